@@ -0,0 +1,316 @@
+//! Talks the wire protocol with a controller, over any byte stream.
+
+use protocol::config::Config;
+use protocol::events::LoggedEvent;
+use protocol::frame::{self, FrameError, Receiver};
+use protocol::message::{ErrorCode, Message, Request, Response, Telemetry, PROTOCOL_VERSION};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a response.
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// Events asked for with each request, the controller sends them all before the next one.
+const EVENTS_PER_REQUEST: u8 = 16;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Frame(FrameError),
+    /// The controller rejected the request.
+    Rejected(ErrorCode),
+    /// Something else came back than the request asks for.
+    Unexpected(Response),
+    Timeout,
+}
+
+pub struct Device<P> {
+    port: P,
+    receiver: Receiver,
+    /// Telemetry which arrived while waiting for a response.
+    telemetry: VecDeque<Telemetry>,
+}
+
+impl<P: Read + Write> Device<P> {
+    /// Starts a session, the controller leaves its shell and speaks frames from here on.
+    pub fn connect(port: P) -> Result<Self, Error> {
+        let mut device = Self {
+            port,
+            receiver: Receiver::new(),
+            telemetry: VecDeque::new(),
+        };
+
+        // Ends whatever has been typed into the shell before.
+        device.port.write_all(&[0])?;
+        device.send(&Request::Hello {
+            version: PROTOCOL_VERSION,
+        })?;
+        // The shell echoes the hello before it switches, which arrives as broken frames.
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match device.receive(deadline) {
+                Ok(Message::Response(Response::Hello { .. })) => return Ok(device),
+                Ok(Message::Response(Response::Error(code))) => return Err(Error::Rejected(code)),
+                Ok(_) | Err(Error::Frame(_)) => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Telemetry, Error> {
+        match self.request(&Request::GetStatus)? {
+            Response::Status(telemetry) => Ok(telemetry),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    pub fn config(&mut self) -> Result<Config, Error> {
+        match self.request(&Request::GetConfig)? {
+            Response::Config(config) => Ok(config),
+            response => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// Replaces the configuration, `save` stores it in flash as well.
+    pub fn set_config(&mut self, config: Config, save: bool) -> Result<(), Error> {
+        let response = self.request(&Request::SetConfig { config, save })?;
+        expect_ok(response)
+    }
+
+    /// Adds the brew temperature measured at the group to the samples of the brew model.
+    pub fn brew_sample(&mut self, brew_temp: f32) -> Result<(), Error> {
+        let response = self.request(&Request::BrewSample { brew_temp })?;
+        expect_ok(response)
+    }
+
+    /// Fits the brew model through the samples and applies it.
+    pub fn fit_brew_model(&mut self) -> Result<(), Error> {
+        let response = self.request(&Request::FitBrewModel)?;
+        expect_ok(response)
+    }
+
+    /// Sets the time of day (seconds since midnight) of the controller.
+    pub fn set_time(&mut self, seconds_of_day: u32) -> Result<(), Error> {
+        let response = self.request(&Request::SetTime { seconds_of_day })?;
+        expect_ok(response)
+    }
+
+    /// Turns the telemetry after every measurement on or off, see [`next_telemetry`](Self::next_telemetry).
+    pub fn stream(&mut self, on: bool) -> Result<(), Error> {
+        let response = self.request(&Request::Stream(on))?;
+        expect_ok(response)
+    }
+
+    /// Waits for the next streamed telemetry.
+    pub fn next_telemetry(&mut self) -> Result<Telemetry, Error> {
+        if let Some(telemetry) = self.telemetry.pop_front() {
+            return Ok(telemetry);
+        }
+        match self.receive(Instant::now() + TIMEOUT)? {
+            Message::Telemetry(telemetry) => Ok(telemetry),
+            Message::Response(response) => Err(Error::Unexpected(response)),
+        }
+    }
+
+    /// Reads the event log starting at the sequence number `from`, as far as it goes.
+    pub fn events(&mut self, mut from: u32) -> Result<Vec<LoggedEvent>, Error> {
+        let mut events = Vec::new();
+        loop {
+            self.send(&Request::ReadEvents {
+                from,
+                count: EVENTS_PER_REQUEST,
+            })?;
+            let next = loop {
+                match self.response()? {
+                    Response::Event(event) => events.push(event),
+                    Response::EventsEnd { next } => break next,
+                    Response::Error(code) => return Err(Error::Rejected(code)),
+                    response => return Err(Error::Unexpected(response)),
+                }
+            };
+            // Nothing left once the controller does not move on.
+            if next <= from {
+                return Ok(events);
+            }
+            from = next;
+        }
+    }
+
+    /// Sends `request` and waits for the answer, which is not an error.
+    fn request(&mut self, request: &Request) -> Result<Response, Error> {
+        self.send(request)?;
+        match self.response()? {
+            Response::Error(code) => Err(Error::Rejected(code)),
+            response => Ok(response),
+        }
+    }
+
+    fn send(&mut self, request: &Request) -> Result<(), Error> {
+        let frame = frame::encode_vec(request)?;
+        self.port.write_all(&frame)?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    fn response(&mut self) -> Result<Response, Error> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match self.receive(deadline)? {
+                Message::Response(response) => return Ok(response),
+                Message::Telemetry(telemetry) => self.telemetry.push_back(telemetry),
+            }
+        }
+    }
+
+    fn receive(&mut self, deadline: Instant) -> Result<Message, Error> {
+        let mut byte = [0];
+        loop {
+            if Instant::now() > deadline {
+                return Err(Error::Timeout);
+            }
+            match self.port.read(&mut byte) {
+                Ok(0) => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+            if let Some(frame) = self.receiver.push(byte[0]) {
+                return Ok(frame::decode(frame)?);
+            }
+        }
+    }
+}
+
+fn expect_ok(response: Response) -> Result<(), Error> {
+    match response {
+        Response::Ok => Ok(()),
+        response => Err(Error::Unexpected(response)),
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(error: FrameError) -> Self {
+        Error::Frame(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Frame(error) => write!(f, "broken frame: {}", error),
+            Error::Rejected(code) => write!(f, "rejected by the controller: {:?}", code),
+            Error::Unexpected(response) => write!(f, "unexpected response: {:?}", response),
+            Error::Timeout => f.write_str("the controller did not answer"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulator;
+    use protocol::config::TargetKind;
+    use protocol::events::Event;
+    use protocol::status::Mode;
+
+    fn connect() -> Device<Simulator> {
+        Device::connect(Simulator::new()).unwrap()
+    }
+
+    #[test]
+    fn connects_and_answers() {
+        let mut device = connect();
+        let telemetry = device.status().unwrap();
+        assert_eq!(telemetry.status.mode, Mode::Coldstart);
+        assert!(!telemetry.ready);
+    }
+
+    #[test]
+    fn requests_need_the_handshake() {
+        let mut device = Device {
+            port: Simulator::new(),
+            receiver: Receiver::new(),
+            telemetry: VecDeque::new(),
+        };
+        assert!(matches!(
+            device.status(),
+            Err(Error::Rejected(ErrorCode::NoHandshake))
+        ));
+    }
+
+    #[test]
+    fn changes_the_config() {
+        let mut device = connect();
+        let mut config = device.config().unwrap();
+        config.target_temp = 93.5;
+        config.target_kind = TargetKind::Brew;
+        config.wake_at = Some(6 * 60 * 60);
+        device.set_config(config, true).unwrap();
+        assert_eq!(device.config().unwrap(), config);
+
+        let rejected = Config {
+            target_temp: 150.0,
+            ..config
+        };
+        assert!(matches!(
+            device.set_config(rejected, false),
+            Err(Error::Rejected(ErrorCode::OutOfRange))
+        ));
+        assert_eq!(device.config().unwrap(), config);
+    }
+
+    #[test]
+    fn streams_telemetry() {
+        let mut device = connect();
+        device.stream(true).unwrap();
+        let first = device.next_telemetry().unwrap();
+        let second = device.next_telemetry().unwrap();
+        assert_eq!(second.uptime, first.uptime + 500);
+
+        // Telemetry which arrives while waiting for an answer is kept for later.
+        assert_eq!(device.config().unwrap().target_temp, 95.0);
+        device.stream(false).unwrap();
+        while let Some(telemetry) = device.telemetry.pop_front() {
+            assert!(telemetry.uptime > second.uptime);
+        }
+        assert!(matches!(device.next_telemetry(), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn reads_the_events_across_requests() {
+        let mut device = connect();
+        let mut config = device.config().unwrap();
+        let changes = 2 * usize::from(EVENTS_PER_REQUEST);
+        for i in 0..changes {
+            config.target_temp = 90.0 + i as f32 / 10.0;
+            device.set_config(config, false).unwrap();
+        }
+
+        let events = device.events(0).unwrap();
+        // The boot and the start of the cold start come first.
+        assert_eq!(events.len(), changes + 2);
+        assert!(matches!(events[0].event, Event::Boot { .. }));
+        for (seq, event) in events.iter().enumerate() {
+            assert_eq!(event.seq, seq as u32);
+        }
+        assert!(events[2..]
+            .iter()
+            .all(|event| event.event == Event::ConfigChanged));
+
+        let last = events.len() as u32 - 1;
+        assert_eq!(device.events(last).unwrap(), &events[last as usize..]);
+        assert!(device.events(last + 1).unwrap().is_empty());
+        assert!(device.events(u32::MAX).unwrap().is_empty());
+    }
+}
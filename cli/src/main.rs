@@ -0,0 +1,556 @@
+//! `rusty-pid`, watches and configures the controller over its USB serial console.
+
+mod device;
+mod sim;
+
+use device::Device;
+use protocol::config::{Calibration, Filter, Gains, TargetKind};
+use protocol::events::{Event, LoggedEvent};
+use protocol::message::Telemetry;
+use sim::Simulator;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Rows of `watch` between two headers.
+const HEADER_EVERY: usize = 20;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "rusty-pid",
+    about = "Monitor, configure and record the controller"
+)]
+struct Opt {
+    /// The serial port of the controller.
+    #[structopt(short, long, default_value = "/dev/ttyACM0")]
+    port: String,
+    /// Talk to a simulated controller instead.
+    #[structopt(long)]
+    simulate: bool,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Show the current values.
+    Status,
+    /// Show the values after every measurement, until interrupted.
+    Watch,
+    /// Show the settings.
+    Get,
+    /// Change the settings, the others stay as they are.
+    Set {
+        /// The brew target in °C.
+        #[structopt(long)]
+        target: Option<f32>,
+        /// What the target is the temperature of: the boiler or the brew water at the group.
+        #[structopt(long, possible_values = &["boiler", "brew"], parse(try_from_str = parse_target_kind))]
+        target_kind: Option<TargetKind>,
+        /// The gains used while heating up from cold.
+        #[structopt(long, number_of_values = 3, value_names = &["KP", "KI", "KD"])]
+        cold: Option<Vec<f32>>,
+        /// The gains used once the boiler reached the setpoint.
+        #[structopt(long, number_of_values = 3, value_names = &["KP", "KI", "KD"])]
+        warm: Option<Vec<f32>>,
+        /// The gains used in steam mode.
+        #[structopt(long, number_of_values = 3, value_names = &["KP", "KI", "KD"])]
+        steam: Option<Vec<f32>>,
+        /// The steam temperature in °C.
+        #[structopt(long)]
+        steam_temp: Option<f32>,
+        /// End steam mode after this many seconds, 0 keeps it on until turned off.
+        #[structopt(long)]
+        steam_timeout: Option<u32>,
+        /// The PID window in ms.
+        #[structopt(long)]
+        window: Option<u32>,
+        /// Correct the sensor reading by a fixed offset in °C.
+        #[structopt(long, conflicts_with_all = &["two-point", "uncalibrated"])]
+        offset: Option<f32>,
+        /// Correct the sensor reading by a line through two pairs of sensor reading and
+        /// reference temperature.
+        #[structopt(
+            long,
+            number_of_values = 4,
+            value_names = &["RAW_LOW", "REF_LOW", "RAW_HIGH", "REF_HIGH"],
+            conflicts_with = "uncalibrated"
+        )]
+        two_point: Option<Vec<f32>>,
+        /// Use the sensor reading as is.
+        #[structopt(long)]
+        uncalibrated: bool,
+        /// Filter the sensor reading: a median over MEDIAN readings (1 for none), an EMA
+        /// (ALPHA 1 for none) and a gate dropping jumps above MAX_DELTA °C (0 for none), which
+        /// gives in after MAX_REJECTS readings in a row.
+        #[structopt(
+            long,
+            number_of_values = 4,
+            value_names = &["MEDIAN", "ALPHA", "MAX_DELTA", "MAX_REJECTS"]
+        )]
+        filter: Option<Vec<f32>>,
+        /// Go to standby after this many seconds without a shot or steam, 0 never does.
+        #[structopt(long)]
+        idle_timeout: Option<u32>,
+        /// The boiler temperature in °C kept in standby, 0 turns the heater off.
+        #[structopt(long)]
+        standby_temp: Option<f32>,
+        /// Leave standby at this time of day, HH:MM.
+        #[structopt(long, parse(try_from_str = parse_time_of_day), conflicts_with = "no-wake")]
+        wake_at: Option<u32>,
+        /// Do not leave standby at a time of day.
+        #[structopt(long)]
+        no_wake: bool,
+        /// Store the settings in flash, so they survive a reset.
+        #[structopt(long)]
+        save: bool,
+    },
+    /// Write the measurements as CSV, until interrupted or for the given time.
+    Record {
+        /// The file to write to, standard output if not given.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// How long to record, in seconds.
+        #[structopt(short, long)]
+        duration: Option<u64>,
+    },
+    /// Add the brew water temperature measured at the group to the samples of the brew model,
+    /// once the machine is ready.
+    BrewSample {
+        /// The measured temperature in °C.
+        temp: f32,
+    },
+    /// Fit the brew model through the samples and use it.
+    BrewFit,
+    /// Set the time of day of the controller, e.g. `set-time $(date +%T)`, which waking up
+    /// from standby at a set time needs.
+    SetTime {
+        /// HH:MM or HH:MM:SS.
+        #[structopt(parse(try_from_str = parse_time_of_day))]
+        time: u32,
+    },
+    /// Show the event log.
+    Events {
+        /// The sequence number of the first event to show.
+        #[structopt(long, default_value = "0")]
+        from: u32,
+    },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let result = if opt.simulate {
+        run(Simulator::new(), opt.command)
+    } else {
+        serialport::new(&opt.port, 115_200)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .map_err(|error| format!("could not open {}: {}", opt.port, error).into())
+            .and_then(|port| run(port, opt.command))
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run<P: Read + Write>(port: P, command: Command) -> Result<(), Box<dyn Error>> {
+    let mut device = Device::connect(port)?;
+    match command {
+        Command::Status => print_status(&device.status()?),
+        Command::Watch => watch(&mut device)?,
+        Command::Get => {
+            let config = device.config()?;
+            println!(
+                "target  {:.1} °C ({})",
+                config.target_temp,
+                match config.target_kind {
+                    TargetKind::Boiler => "boiler",
+                    TargetKind::Brew => "brew water",
+                }
+            );
+            println!("cold    {}", format_gains(&config.cold_gains));
+            println!("warm    {}", format_gains(&config.warm_gains));
+            println!(
+                "steam   {:.1} °C, {}, {}",
+                config.steam_temp,
+                format_gains(&config.steam_gains),
+                match config.steam_timeout {
+                    Some(timeout) => format!("off after {} s", timeout / 1000),
+                    None => "no timeout".to_string(),
+                }
+            );
+            println!("window  {} ms", config.window_size);
+            println!("calib   {}", format_calibration(&config.calibration));
+            println!("filter  {}", format_filter(&config.filter));
+            println!(
+                "standby {}, {}, {}",
+                match config.idle_timeout {
+                    Some(timeout) => format!("after {} s", timeout / 1000),
+                    None => "never".to_string(),
+                },
+                match config.standby_temp {
+                    Some(temp) => format!("at {:.1} °C", temp),
+                    None => "heater off".to_string(),
+                },
+                match config.wake_at {
+                    Some(at) => format!("wake at {:02}:{:02}", at / 3600, at / 60 % 60),
+                    None => "no wake-up".to_string(),
+                }
+            );
+        }
+        Command::Set {
+            target,
+            target_kind,
+            cold,
+            warm,
+            steam,
+            steam_temp,
+            steam_timeout,
+            window,
+            offset,
+            two_point,
+            uncalibrated,
+            filter,
+            idle_timeout,
+            standby_temp,
+            wake_at,
+            no_wake,
+            save,
+        } => {
+            let mut config = device.config()?;
+            if let Some(target) = target {
+                config.target_temp = target;
+            }
+            if let Some(kind) = target_kind {
+                config.target_kind = kind;
+            }
+            if let Some(gains) = cold {
+                config.cold_gains = to_gains(&gains);
+            }
+            if let Some(gains) = warm {
+                config.warm_gains = to_gains(&gains);
+            }
+            if let Some(gains) = steam {
+                config.steam_gains = to_gains(&gains);
+            }
+            if let Some(temp) = steam_temp {
+                config.steam_temp = temp;
+            }
+            if let Some(seconds) = steam_timeout {
+                config.steam_timeout = match seconds {
+                    0 => None,
+                    seconds => Some(seconds.saturating_mul(1000)),
+                };
+            }
+            if let Some(window) = window {
+                config.window_size = window;
+            }
+            if let Some(offset) = offset {
+                config.calibration = Calibration::Offset(offset);
+            }
+            if let Some(values) = two_point {
+                config.calibration = Calibration::TwoPoint {
+                    raw_low: values[0],
+                    reference_low: values[1],
+                    raw_high: values[2],
+                    reference_high: values[3],
+                };
+            }
+            if uncalibrated {
+                config.calibration = Calibration::None;
+            }
+            if let Some(values) = filter {
+                config.filter = to_filter(&values)?;
+            }
+            if let Some(seconds) = idle_timeout {
+                config.idle_timeout = match seconds {
+                    0 => None,
+                    seconds => Some(seconds.saturating_mul(1000)),
+                };
+            }
+            if let Some(temp) = standby_temp {
+                config.standby_temp = if temp == 0.0 { None } else { Some(temp) };
+            }
+            if let Some(wake_at) = wake_at {
+                config.wake_at = Some(wake_at);
+            }
+            if no_wake {
+                config.wake_at = None;
+            }
+            device.set_config(config, save)?;
+        }
+        Command::Record { output, duration } => {
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            record(&mut device, &mut out, duration)?;
+        }
+        Command::BrewSample { temp } => device.brew_sample(temp)?,
+        Command::BrewFit => device.fit_brew_model()?,
+        Command::SetTime { time } => device.set_time(time)?,
+        Command::Events { from } => {
+            for event in device.events(from)? {
+                println!("{}", format_event(&event));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn watch<P: Read + Write>(device: &mut Device<P>) -> Result<(), Box<dyn Error>> {
+    device.stream(true)?;
+    for row in 0.. {
+        let telemetry = device.next_telemetry()?;
+        if row % HEADER_EVERY == 0 {
+            println!(
+                "{:>9}  {:>7}  {:>7}  {:>7}  {:>6}  {:>7}  {:<11}  {:>5}  {:>6}",
+                "uptime", "temp", "brew", "target", "heater", "pid", "mode", "ready", "faults"
+            );
+        }
+        let status = &telemetry.status;
+        println!(
+            "{:>8.1}s  {:>7.2}  {:>7.2}  {:>7.1}  {:>6}  {:>7.1}  {:<11}  {:>5}  {:#06x}",
+            telemetry.uptime as f64 / 1000.0,
+            status.current_temp,
+            telemetry.brew_temp,
+            status.target_temp,
+            if status.heater_on { "on" } else { "off" },
+            status.pid_output,
+            format!("{:?}", status.mode),
+            if telemetry.ready { "yes" } else { "no" },
+            status.faults.0
+        );
+    }
+    Ok(())
+}
+
+/// Writes the same `time,output` columns as the recordings in `controller/data`, `time` in seconds
+/// since the start, plus what the heater did.
+fn record<P: Read + Write>(
+    device: &mut Device<P>,
+    out: &mut dyn Write,
+    duration: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    device.stream(true)?;
+    writeln!(out, "time,output,heater,pid_output,target")?;
+
+    let mut start = None;
+    loop {
+        let telemetry = device.next_telemetry()?;
+        let start = *start.get_or_insert(telemetry.uptime);
+        let elapsed = telemetry.uptime.saturating_sub(start);
+        if matches!(duration, Some(duration) if elapsed > duration * 1000) {
+            break;
+        }
+
+        let status = &telemetry.status;
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            elapsed as f64 / 1000.0,
+            status.current_temp,
+            status.heater_on as u8,
+            status.pid_output,
+            status.target_temp
+        )?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn print_status(telemetry: &Telemetry) {
+    let status = &telemetry.status;
+    println!("uptime  {:.1} s", telemetry.uptime as f64 / 1000.0);
+    println!("mode    {:?}", status.mode);
+    println!(
+        "temp    {:.2} °C (raw {:.2}, brew {:.2})",
+        status.current_temp, telemetry.raw_temp, telemetry.brew_temp
+    );
+    println!("target  {:.1} °C", status.target_temp);
+    println!(
+        "heater  {} (pid {:.1} ms)",
+        if status.heater_on { "on" } else { "off" },
+        status.pid_output
+    );
+    println!(
+        "gains   {}",
+        format_gains(&Gains {
+            kp: status.kp,
+            ki: status.ki,
+            kd: status.kd,
+        })
+    );
+    println!("ready   {}", if telemetry.ready { "yes" } else { "no" });
+    println!("faults  {:#06x}", status.faults.0);
+}
+
+fn parse_target_kind(value: &str) -> Result<TargetKind, String> {
+    match value {
+        "boiler" => Ok(TargetKind::Boiler),
+        "brew" => Ok(TargetKind::Brew),
+        _ => Err(format!("unknown target kind {}", value)),
+    }
+}
+
+/// `HH:MM` or `HH:MM:SS` as seconds since midnight.
+fn parse_time_of_day(value: &str) -> Result<u32, String> {
+    let parts = value
+        .split(':')
+        .map(str::parse::<u32>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid time {}", value))?;
+    match parts[..] {
+        [hours, minutes] if hours < 24 && minutes < 60 => Ok((hours * 60 + minutes) * 60),
+        [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+            Ok((hours * 60 + minutes) * 60 + seconds)
+        }
+        _ => Err(format!("invalid time {}, expected HH:MM[:SS]", value)),
+    }
+}
+
+fn format_gains(gains: &Gains) -> String {
+    format!("kp {} ki {} kd {}", gains.kp, gains.ki, gains.kd)
+}
+
+fn format_calibration(calibration: &Calibration) -> String {
+    match calibration {
+        Calibration::None => "none".to_string(),
+        Calibration::Offset(offset) => format!("offset {:+} °C", offset),
+        Calibration::TwoPoint {
+            raw_low,
+            reference_low,
+            raw_high,
+            reference_high,
+        } => format!(
+            "{} -> {} °C, {} -> {} °C",
+            raw_low, reference_low, raw_high, reference_high
+        ),
+    }
+}
+
+fn format_filter(filter: &Filter) -> String {
+    format!(
+        "median of {}, ema alpha {}, max delta {} °C, max rejects {}",
+        filter.median_len, filter.ema_alpha, filter.max_delta, filter.max_rejects
+    )
+}
+
+/// `number_of_values` makes sure there are four, the counts have to be whole numbers.
+fn to_filter(values: &[f32]) -> Result<Filter, String> {
+    let count = |value: f32| {
+        if value.fract() == 0.0 && (0.0..=255.0).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(format!("invalid count {} in the filter", value))
+        }
+    };
+    Ok(Filter {
+        median_len: count(values[0])?,
+        ema_alpha: values[1],
+        max_delta: values[2],
+        max_rejects: count(values[3])?,
+    })
+}
+
+/// `number_of_values` makes sure there are three.
+fn to_gains(values: &[f32]) -> Gains {
+    Gains {
+        kp: values[0],
+        ki: values[1],
+        kd: values[2],
+    }
+}
+
+fn format_event(event: &LoggedEvent) -> String {
+    let what = match &event.event {
+        Event::Boot { resetreas } => format!("boot (RESETREAS {:#010x})", resetreas),
+        Event::SensorFailure { error } => format!("sensor failure: {}", error.as_str()),
+        Event::ModeChange { from, to } => format!("mode {:?} -> {:?}", from, to),
+        Event::FaultLatched(cutoff) => format!("heater cut off: {:?}", cutoff),
+        Event::FaultCleared => "heater cutoff cleared".to_string(),
+        Event::ConfigChanged => "settings changed".to_string(),
+        Event::ConfigSaved { version } => format!("settings saved (version {})", version),
+        Event::ConfigDefaults(reason) => format!("running on defaults: {:?}", reason),
+        Event::Panic { file, line } => format!("panic at {}:{}", file.as_str(), line),
+        Event::HardFault { pc, lr } => format!("hard fault at pc {:#010x} (lr {:#010x})", pc, lr),
+        Event::TaskStuck(task) => format!("task stuck: {:?}", task),
+    };
+    format!(
+        "#{:<5} {:>10.1}s  {}",
+        event.seq,
+        event.at as f64 / 1000.0,
+        what
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_csv() {
+        let mut device = Device::connect(Simulator::new()).unwrap();
+        let mut out = Vec::new();
+        record(&mut device, &mut out, Some(1)).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("time,output,heater,pid_output,target"));
+        let rows: Vec<Vec<f64>> = lines
+            .map(|line| {
+                line.split(',')
+                    .map(|value| value.parse().unwrap())
+                    .collect()
+            })
+            .collect();
+        // A measurement every 500ms, up to the end of the second.
+        let times: Vec<f64> = rows.iter().map(|row| row[0]).collect();
+        assert_eq!(times, [0.0, 0.5, 1.0]);
+        for row in rows.iter() {
+            assert_eq!(row.len(), 5);
+            assert!(row[2] == 0.0 || row[2] == 1.0);
+            assert_eq!(row[4], 95.0);
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse_time_of_day("06:30"), Ok(23_400));
+        assert_eq!(parse_time_of_day("23:59:59"), Ok(86_399));
+        for invalid in ["24:00", "12:60", "12:00:60", "12", "12:00:00:00", "six"].iter() {
+            assert!(parse_time_of_day(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn filter_counts_are_whole_numbers() {
+        assert_eq!(
+            to_filter(&[5.0, 0.25, 2.5, 7.0]),
+            Ok(Filter {
+                median_len: 5,
+                ema_alpha: 0.25,
+                max_delta: 2.5,
+                max_rejects: 7,
+            })
+        );
+        assert!(to_filter(&[2.5, 0.25, 2.5, 7.0]).is_err());
+        assert!(to_filter(&[5.0, 0.25, 2.5, 256.0]).is_err());
+        assert!(to_filter(&[-1.0, 0.25, 2.5, 7.0]).is_err());
+    }
+
+    #[test]
+    fn formats_events() {
+        let event = LoggedEvent {
+            seq: 7,
+            at: 1500,
+            event: Event::ConfigChanged,
+        };
+        assert_eq!(format_event(&event), "#7            1.5s  settings changed");
+    }
+}
@@ -0,0 +1,413 @@
+//! A pretend controller which speaks the wire protocol, so the tool can be tried without hardware.
+//!
+//! It heats a crude boiler model with a PID on the configured gains, in real time. The numbers
+//! are roughly those of a Silvia, good enough to see something happen but not to tune against.
+
+use protocol::config::{Calibration, Config, Filter, Gains, TargetKind};
+use protocol::events::{Event, LoggedEvent};
+use protocol::frame::{self, Receiver};
+use protocol::gatt::Command;
+use protocol::message::{self, ErrorCode, Message, Request, Response, Telemetry};
+use protocol::status::{Faults, Mode, Status};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The controller measures every 500ms.
+const TICK_MS: u64 = 500;
+const AMBIENT: f32 = 22.0;
+/// How fast (°C/s) the boiler heats with the heater on all the time.
+const HEATING_RATE: f32 = 0.6;
+/// The share of the difference to the ambient temperature lost every second.
+const LOSS: f32 = 0.002;
+/// How close (°C) to the target the boiler counts as ready.
+const READY_BAND: f32 = 0.5;
+/// How long a read waits for something to arrive, the timeout the serial port is opened with.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct Simulator {
+    started: Instant,
+    /// The uptime (ms) of the last measurement.
+    uptime: u64,
+    receiver: Receiver,
+    output: VecDeque<u8>,
+    handshake: bool,
+    streaming: bool,
+    config: Config,
+    mode: Mode,
+    /// The uptime (ms) the current mode was entered at.
+    mode_since: u64,
+    temp: f32,
+    integral: f32,
+    pid_output: f32,
+    events: Vec<LoggedEvent>,
+    /// The (boiler, brew) samples for the brew model.
+    brew_samples: Vec<(f32, f32)>,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        let gains = Gains {
+            kp: 69.0,
+            ki: 0.17,
+            kd: 0.0,
+        };
+        let mut simulator = Self {
+            started: Instant::now(),
+            uptime: 0,
+            receiver: Receiver::new(),
+            output: VecDeque::new(),
+            handshake: false,
+            streaming: false,
+            config: Config {
+                target_temp: 95.0,
+                target_kind: TargetKind::Boiler,
+                cold_gains: Gains {
+                    kp: 250.0,
+                    ki: 0.03,
+                    kd: 0.0,
+                },
+                warm_gains: gains,
+                steam_temp: 125.0,
+                steam_gains: gains,
+                steam_timeout: Some(5 * 60 * 1000),
+                window_size: 1000,
+                calibration: Calibration::None,
+                filter: Filter {
+                    median_len: 3,
+                    ema_alpha: 0.5,
+                    max_delta: 5.0,
+                    max_rejects: 3,
+                },
+                idle_timeout: Some(30 * 60 * 1000),
+                standby_temp: None,
+                wake_at: None,
+            },
+            mode: Mode::Booting,
+            mode_since: 0,
+            temp: AMBIENT,
+            integral: 0.0,
+            pid_output: 0.0,
+            events: Vec::new(),
+            brew_samples: Vec::new(),
+        };
+        simulator.log(Event::Boot { resetreas: 1 });
+        simulator.change_mode(Mode::Coldstart);
+        simulator
+    }
+
+    /// Runs the boiler up to now, streaming the measurements on the way.
+    fn catch_up(&mut self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        while self.uptime + TICK_MS <= now {
+            self.uptime += TICK_MS;
+            self.tick();
+            if self.streaming {
+                let telemetry = self.telemetry();
+                self.send(&Message::Telemetry(telemetry));
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        let dt = TICK_MS as f32 / 1000.0;
+        let target = self.target();
+        let window = self.config.window_size as f32;
+
+        let error = target - self.temp;
+        let gains = self.gains();
+        self.integral = (self.integral + gains.ki * error * dt * 1000.0)
+            .max(0.0)
+            .min(window);
+        self.pid_output = match self.mode {
+            Mode::Standby | Mode::Fault => 0.0,
+            _ => (gains.kp * error + self.integral).max(0.0).min(window),
+        };
+
+        let duty = self.pid_output / window;
+        self.temp += (HEATING_RATE * duty - LOSS * (self.temp - AMBIENT)) * dt;
+
+        let steam_over = matches!(self.config.steam_timeout,
+            Some(timeout) if self.uptime - self.mode_since >= u64::from(timeout));
+        let next = match self.mode {
+            Mode::Coldstart if self.temp > target => Some(Mode::Stabilizing),
+            Mode::Stabilizing if (self.temp - target).abs() < READY_BAND => Some(Mode::Ready),
+            Mode::Ready if (self.temp - target).abs() > READY_BAND => Some(Mode::Stabilizing),
+            Mode::Steam if steam_over => Some(Mode::Stabilizing),
+            _ => None,
+        };
+        if let Some(mode) = next {
+            self.change_mode(mode);
+        }
+    }
+
+    fn target(&self) -> f32 {
+        match self.mode {
+            Mode::Steam => self.config.steam_temp,
+            _ => self.config.target_temp,
+        }
+    }
+
+    fn gains(&self) -> Gains {
+        match self.mode {
+            Mode::Coldstart => self.config.cold_gains,
+            Mode::Steam => self.config.steam_gains,
+            _ => self.config.warm_gains,
+        }
+    }
+
+    fn telemetry(&self) -> Telemetry {
+        let gains = self.gains();
+        // The heater runs at the start of every window, for as long as the PID asks.
+        let in_window = (self.uptime % u64::from(self.config.window_size)) as f32;
+        Telemetry {
+            uptime: self.uptime,
+            status: Status {
+                current_temp: self.temp,
+                target_temp: self.target(),
+                heater_on: in_window < self.pid_output,
+                pid_output: self.pid_output,
+                kp: gains.kp,
+                ki: gains.ki,
+                kd: gains.kd,
+                mode: self.mode,
+                faults: Faults::empty(),
+            },
+            raw_temp: self.temp,
+            // The group runs a few degrees below the boiler.
+            brew_temp: self.temp - 4.0,
+            ready: self.mode == Mode::Ready,
+        }
+    }
+
+    fn change_mode(&mut self, to: Mode) {
+        let from = self.mode;
+        self.mode = to;
+        self.mode_since = self.uptime;
+        self.log(Event::ModeChange { from, to });
+    }
+
+    fn log(&mut self, event: Event) {
+        self.events.push(LoggedEvent {
+            seq: self.events.len() as u32,
+            at: self.uptime,
+            event,
+        });
+    }
+
+    fn serve(&mut self, request: Request) {
+        let response = match request {
+            Request::Hello { version } => match message::negotiate(version) {
+                Some(version) => {
+                    self.handshake = true;
+                    Response::Hello { version }
+                }
+                None => Response::Error(ErrorCode::Incompatible),
+            },
+            _ if !self.handshake => Response::Error(ErrorCode::NoHandshake),
+            Request::GetStatus => Response::Status(self.telemetry()),
+            Request::GetConfig => Response::Config(self.config),
+            Request::SetConfig { config, save } => {
+                if !valid(&config) {
+                    Response::Error(ErrorCode::OutOfRange)
+                } else {
+                    if config != self.config {
+                        self.log(Event::ConfigChanged);
+                    }
+                    self.config = config;
+                    if save {
+                        self.log(Event::ConfigSaved { version: 1 });
+                    }
+                    Response::Ok
+                }
+            }
+            Request::Command(command) => self.command(command),
+            Request::Stream(on) => {
+                self.streaming = on;
+                Response::Ok
+            }
+            Request::BrewSample { .. } if self.mode != Mode::Ready => {
+                Response::Error(ErrorCode::NotAllowed)
+            }
+            Request::BrewSample { brew_temp } => {
+                self.brew_samples.push((self.temp, brew_temp));
+                Response::Ok
+            }
+            Request::SetTime { seconds_of_day } if seconds_of_day >= 24 * 60 * 60 => {
+                Response::Error(ErrorCode::OutOfRange)
+            }
+            // There is no wall clock to set, nothing happens at the wake time.
+            Request::SetTime { .. } => Response::Ok,
+            // There is no brew model to fit, it only checks there is something to fit.
+            Request::FitBrewModel => {
+                let first = self.brew_samples.first().map(|sample| sample.0);
+                if self
+                    .brew_samples
+                    .iter()
+                    .any(|sample| Some(sample.0) != first)
+                {
+                    self.brew_samples.clear();
+                    Response::Ok
+                } else {
+                    Response::Error(ErrorCode::NotAllowed)
+                }
+            }
+            Request::ReadEvents { from, count } => {
+                let end = (from as usize)
+                    .saturating_add(usize::from(count))
+                    .min(self.events.len());
+                let from = (from as usize).min(end);
+                for event in &self.events[from..end] {
+                    push_frame(
+                        &mut self.output,
+                        &Message::Response(Response::Event(*event)),
+                    );
+                }
+                Response::EventsEnd { next: end as u32 }
+            }
+        };
+        self.send(&Message::Response(response));
+    }
+
+    fn command(&mut self, command: Command) -> Response {
+        let next = match (command, self.mode) {
+            (Command::Save, _) => {
+                self.log(Event::ConfigSaved { version: 1 });
+                return Response::Ok;
+            }
+            (_, Mode::Fault) => None,
+            (Command::Standby, Mode::Coldstart)
+            | (Command::Standby, Mode::Stabilizing)
+            | (Command::Standby, Mode::Ready) => Some(Mode::Standby),
+            (Command::Wake, Mode::Standby) => Some(Mode::Coldstart),
+            (Command::SteamOn, Mode::Coldstart)
+            | (Command::SteamOn, Mode::Stabilizing)
+            | (Command::SteamOn, Mode::Ready)
+            | (Command::SteamOn, Mode::Standby) => Some(Mode::Steam),
+            (Command::SteamOff, Mode::Steam) => Some(Mode::Stabilizing),
+            _ => None,
+        };
+        match next {
+            Some(mode) => {
+                self.change_mode(mode);
+                Response::Ok
+            }
+            None => Response::Error(ErrorCode::NotAllowed),
+        }
+    }
+
+    fn send(&mut self, message: &Message) {
+        push_frame(&mut self.output, message);
+    }
+}
+
+fn push_frame(output: &mut VecDeque<u8>, message: &Message) {
+    let frame = frame::encode_vec(message).expect("messages fit into a frame");
+    output.extend(frame);
+}
+
+/// The same ranges the controller accepts.
+fn valid(config: &Config) -> bool {
+    let gains = |gains: &Gains| {
+        [gains.kp, gains.ki, gains.kd]
+            .iter()
+            .all(|gain| gain.is_finite() && *gain >= 0.0)
+    };
+    (20.0..=110.0).contains(&config.target_temp)
+        && gains(&config.cold_gains)
+        && gains(&config.warm_gains)
+        && (100.0..=140.0).contains(&config.steam_temp)
+        && gains(&config.steam_gains)
+        && match config.steam_timeout {
+            Some(timeout) => (60_000..=3_600_000).contains(&timeout),
+            None => true,
+        }
+        && (100..=10_000).contains(&config.window_size)
+        && calibration(&config.calibration)
+        && (1..=9).contains(&config.filter.median_len)
+        && (0.001..=1.0).contains(&config.filter.ema_alpha)
+        && (0.0..=50.0).contains(&config.filter.max_delta)
+        && config.filter.max_rejects <= 20
+        && match config.idle_timeout {
+            Some(timeout) => (60_000..=86_400_000).contains(&timeout),
+            None => true,
+        }
+        && match config.standby_temp {
+            Some(temp) => (20.0..=110.0).contains(&temp),
+            None => true,
+        }
+        && match config.wake_at {
+            Some(at) => at < 24 * 60 * 60,
+            None => true,
+        }
+}
+
+/// At most 10°C off and a slope close to 1, like the controller's check.
+fn calibration(calibration: &Calibration) -> bool {
+    let small = |offset: f32| offset.is_finite() && offset.abs() <= 10.0;
+    match *calibration {
+        Calibration::None => true,
+        Calibration::Offset(offset) => small(offset),
+        Calibration::TwoPoint {
+            raw_low,
+            reference_low,
+            raw_high,
+            reference_high,
+        } => {
+            let slope = (reference_high - reference_low) / (raw_high - raw_low);
+            small(reference_low - raw_low)
+                && small(reference_high - raw_high)
+                && (0.8..=1.25).contains(&slope)
+        }
+    }
+}
+
+impl Read for Simulator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.catch_up();
+        if self.output.is_empty() {
+            // Waits for the next measurement if one is coming, like a port with a timeout would,
+            // so the reader does not spin.
+            let wait = if self.streaming {
+                let next = Duration::from_millis(self.uptime + TICK_MS);
+                next.saturating_sub(self.started.elapsed())
+                    .min(READ_TIMEOUT)
+            } else {
+                READ_TIMEOUT
+            };
+            thread::sleep(wait);
+            self.catch_up();
+            if self.output.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+
+        let len = buf.len().min(self.output.len());
+        for (slot, byte) in buf.iter_mut().zip(self.output.drain(..len)) {
+            *slot = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Simulator {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.catch_up();
+        for byte in buf {
+            let request = self.receiver.push(*byte).map(frame::decode::<Request>);
+            match request {
+                Some(Ok(request)) => self.serve(request),
+                Some(Err(_)) => {
+                    self.send(&Message::Response(Response::Error(ErrorCode::Malformed)))
+                }
+                None => {}
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
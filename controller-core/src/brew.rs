@@ -0,0 +1,321 @@
+//! Estimates the water temperature at the group from the boiler shell temperature.
+//!
+//! The model has two parts: a static offset curve (shell minus water at steady state, as a
+//! function of the shell temperature) and a first order lag which accounts for the water
+//! trailing the shell, plus a correction for how hard the heater has been working recently.
+
+/// Number of points the offset curve can hold.
+pub const MAX_CURVE_POINTS: usize = 4;
+/// Number of measurements [`BrewSamples`] keeps.
+pub const MAX_SAMPLES: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BrewModel {
+    /// (boiler temperature, offset) pairs, sorted by boiler temperature.
+    curve: [(f32, f32); MAX_CURVE_POINTS],
+    curve_len: usize,
+    /// Time constant (ms) of the lag between the shell and the water.
+    lag_ms: u32,
+    /// How much hotter (°C) the shell reads than the water while the heater runs at 100% duty.
+    heater_gain: f32,
+}
+
+impl BrewModel {
+    /// A model where the water is assumed to be at the shell temperature.
+    pub const fn identity() -> Self {
+        Self {
+            curve: [(0.0, 0.0); MAX_CURVE_POINTS],
+            curve_len: 1,
+            lag_ms: 0,
+            heater_gain: 0.0,
+        }
+    }
+
+    /// Creates a model from an offset curve, `None` if the curve is empty, too long or unsorted.
+    pub fn new(curve: &[(f32, f32)], lag_ms: u32, heater_gain: f32) -> Option<Self> {
+        if curve.is_empty() || curve.len() > MAX_CURVE_POINTS {
+            return None;
+        }
+        if curve.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return None;
+        }
+
+        let mut model = Self::identity();
+        model.curve[..curve.len()].copy_from_slice(curve);
+        model.curve_len = curve.len();
+        model.lag_ms = lag_ms;
+        model.heater_gain = heater_gain;
+        Some(model)
+    }
+
+    /// Fits a linear offset curve through steady state (boiler, brew) measurements.
+    pub fn fit(measurements: &[(f32, f32)], lag_ms: u32, heater_gain: f32) -> Option<Self> {
+        if measurements.len() < 2 {
+            return None;
+        }
+
+        let n = measurements.len() as f32;
+        let (mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0);
+        let (mut min_x, mut max_x) = (measurements[0].0, measurements[0].0);
+        for &(boiler, brew) in measurements {
+            let offset = boiler - brew;
+            sum_x += boiler;
+            sum_y += offset;
+            sum_xx += boiler * boiler;
+            sum_xy += boiler * offset;
+            min_x = if boiler < min_x { boiler } else { min_x };
+            max_x = if boiler > max_x { boiler } else { max_x };
+        }
+
+        let denominator = n * sum_xx - sum_x * sum_x;
+        if denominator == 0.0 {
+            return None;
+        }
+        let slope = (n * sum_xy - sum_x * sum_y) / denominator;
+        let intercept = (sum_y - slope * sum_x) / n;
+
+        Self::new(
+            &[
+                (min_x, intercept + slope * min_x),
+                (max_x, intercept + slope * max_x),
+            ],
+            lag_ms,
+            heater_gain,
+        )
+    }
+
+    pub fn curve(&self) -> &[(f32, f32)] {
+        &self.curve[..self.curve_len]
+    }
+
+    pub fn lag_ms(&self) -> u32 {
+        self.lag_ms
+    }
+
+    pub fn heater_gain(&self) -> f32 {
+        self.heater_gain
+    }
+
+    /// The steady state offset between shell and water, interpolated along the curve.
+    pub fn offset(&self, boiler: f32) -> f32 {
+        let curve = &self.curve[..self.curve_len];
+        if boiler <= curve[0].0 {
+            return curve[0].1;
+        }
+        for w in curve.windows(2) {
+            let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+            if boiler <= x1 {
+                return y0 + (boiler - x0) * (y1 - y0) / (x1 - x0);
+            }
+        }
+        curve[self.curve_len - 1].1
+    }
+
+    /// The steady state brew temperature for a boiler temperature.
+    pub fn steady_brew_temp(&self, boiler: f32) -> f32 {
+        boiler - self.offset(boiler)
+    }
+
+    /// The boiler setpoint which results in the given brew temperature at steady state.
+    pub fn boiler_setpoint_for(&self, brew: f32) -> f32 {
+        // The curve is short and monotonic in practice, a bisection is plenty. It goes down to
+        // neighbouring floats, so a model without an offset gives back `brew` itself and a
+        // target at the edge of its range stays within it.
+        let (mut low, mut high) = (0.0f32, 150.0f32);
+        loop {
+            let mid = (low + high) / 2.0;
+            if mid <= low || mid >= high {
+                return high;
+            }
+            if self.steady_brew_temp(mid) < brew {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+    }
+}
+
+/// Tracks the estimated brew temperature over time.
+pub struct BrewEstimator {
+    model: BrewModel,
+    estimate: Option<f32>,
+}
+
+impl BrewEstimator {
+    pub fn new(model: BrewModel) -> Self {
+        Self {
+            model,
+            estimate: None,
+        }
+    }
+
+    pub fn model(&self) -> &BrewModel {
+        &self.model
+    }
+
+    pub fn set_model(&mut self, model: BrewModel) {
+        self.model = model;
+        self.estimate = None;
+    }
+
+    /// Feeds the current boiler temperature and the heater duty (0.0 - 1.0) of the last
+    /// `elapsed_ms` and returns the new estimate.
+    pub fn update(&mut self, boiler: f32, heater_duty: f32, elapsed_ms: u32) -> f32 {
+        let target = self.model.steady_brew_temp(boiler) - self.model.heater_gain * heater_duty;
+
+        let estimate = match self.estimate {
+            Some(estimate) if self.model.lag_ms > 0 => {
+                let dt = elapsed_ms as f32;
+                estimate + (target - estimate) * dt / (self.model.lag_ms as f32 + dt)
+            }
+            _ => target,
+        };
+        self.estimate = Some(estimate);
+        estimate
+    }
+
+    pub fn estimate(&self) -> Option<f32> {
+        self.estimate
+    }
+}
+
+/// Collects steady state (boiler, brew) measurements to fit a [`BrewModel`] through.
+///
+/// Once full, a new measurement replaces the oldest one.
+pub struct BrewSamples {
+    samples: [(f32, f32); MAX_SAMPLES],
+    len: usize,
+    next: usize,
+}
+
+impl BrewSamples {
+    pub const fn new() -> Self {
+        Self {
+            samples: [(0.0, 0.0); MAX_SAMPLES],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds the boiler temperature and the brew temperature measured at the group alongside.
+    pub fn push(&mut self, boiler: f32, brew: f32) {
+        self.samples[self.next] = (boiler, brew);
+        self.next = (self.next + 1) % MAX_SAMPLES;
+        self.len = (self.len + 1).min(MAX_SAMPLES);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    /// Fits a model through the measurements, taking the lag and heater gain over from `model`.
+    ///
+    /// `None` unless there are measurements at two different boiler temperatures.
+    pub fn fit(&self, model: &BrewModel) -> Option<BrewModel> {
+        BrewModel::fit(
+            &self.samples[..self.len],
+            model.lag_ms(),
+            model.heater_gain(),
+        )
+    }
+}
+
+impl Default for BrewSamples {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn fit_recovers_a_linear_offset() {
+        // The group runs 8°C below the boiler at 90°C and 10°C below at 110°C.
+        let measurements = [(90.0, 82.0), (100.0, 91.0), (110.0, 100.0)];
+        let model = BrewModel::fit(&measurements, 2000, 1.5).unwrap();
+
+        assert_eq!(model.curve().len(), 2);
+        assert!(close(model.offset(90.0), 8.0));
+        assert!(close(model.offset(110.0), 10.0));
+        assert!(close(model.steady_brew_temp(100.0), 91.0));
+        assert!(close(model.boiler_setpoint_for(91.0), 100.0));
+        assert_eq!(model.lag_ms(), 2000);
+        assert_eq!(model.heater_gain(), 1.5);
+    }
+
+    #[test]
+    fn without_an_offset_the_setpoint_is_the_brew_temperature() {
+        let model = BrewModel::identity();
+        for brew in [20.0, 93.3, 110.0].iter() {
+            assert_eq!(model.boiler_setpoint_for(*brew), *brew);
+        }
+    }
+
+    #[test]
+    fn fit_averages_noisy_measurements() {
+        let measurements = [(95.0, 86.5), (95.0, 87.5), (105.0, 95.5), (105.0, 96.5)];
+        let model = BrewModel::fit(&measurements, 0, 0.0).unwrap();
+
+        assert!(close(model.offset(95.0), 8.0));
+        assert!(close(model.offset(105.0), 9.0));
+    }
+
+    #[test]
+    fn fit_needs_two_boiler_temperatures() {
+        assert!(BrewModel::fit(&[], 0, 0.0).is_none());
+        assert!(BrewModel::fit(&[(95.0, 87.0)], 0, 0.0).is_none());
+        assert!(BrewModel::fit(&[(95.0, 87.0), (95.0, 88.0)], 0, 0.0).is_none());
+    }
+
+    #[test]
+    fn samples_keep_the_latest_and_the_model_parameters() {
+        let mut samples = BrewSamples::new();
+        let current = BrewModel::new(&[(0.0, 0.0)], 1500, 2.0).unwrap();
+        assert!(samples.fit(&current).is_none());
+
+        // Wrong measurements which get pushed out.
+        for _ in 0..MAX_SAMPLES {
+            samples.push(95.0, 50.0);
+        }
+        for _ in 0..MAX_SAMPLES / 2 {
+            samples.push(90.0, 82.0);
+            samples.push(110.0, 100.0);
+        }
+        assert_eq!(samples.len(), MAX_SAMPLES);
+
+        let model = samples.fit(&current).unwrap();
+        assert!(close(model.offset(90.0), 8.0));
+        assert!(close(model.offset(110.0), 10.0));
+        assert_eq!(model.lag_ms(), 1500);
+        assert_eq!(model.heater_gain(), 2.0);
+
+        samples.clear();
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn estimate_follows_the_model_with_lag() {
+        let model = BrewModel::new(&[(0.0, 5.0)], 1000, 0.0).unwrap();
+        let mut estimator = BrewEstimator::new(model);
+
+        assert_eq!(estimator.update(95.0, 0.0, 500), 90.0);
+        // Half way to the new steady state after one time constant.
+        assert!(close(estimator.update(97.0, 0.0, 1000), 91.0));
+    }
+}
@@ -0,0 +1,165 @@
+//! Maps the sensor reading onto the real temperature.
+//!
+//! The TSIC sits on top of the boiler shell and every unit is slightly off, so the value is
+//! corrected either by a fixed offset or by a line through two reference measurements (for
+//! example boiling water and a reference thermometer at brew temperature).
+
+/// The largest correction (°C) accepted, a sensor which is further off is broken or loose.
+pub const MAX_OFFSET: f32 = 10.0;
+/// The flattest line a two-point calibration may describe.
+pub const MIN_SLOPE: f32 = 0.8;
+/// The steepest line a two-point calibration may describe.
+pub const MAX_SLOPE: f32 = 1.25;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Calibration {
+    /// Use the sensor reading as is.
+    None,
+    /// Add a fixed offset (°C) to every reading.
+    Offset(f32),
+    /// Linear fit through two reference measurements.
+    TwoPoint(TwoPoint),
+}
+
+/// Two (sensor reading, reference temperature) pairs, only created through [`TwoPoint::new`]
+/// so the readings never are the same.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TwoPoint {
+    raw_low: f32,
+    reference_low: f32,
+    raw_high: f32,
+    reference_high: f32,
+}
+
+impl TwoPoint {
+    /// `None` if a value is not finite or both sensor readings are the same.
+    pub fn new(
+        raw_low: f32,
+        reference_low: f32,
+        raw_high: f32,
+        reference_high: f32,
+    ) -> Option<Self> {
+        let finite = [raw_low, reference_low, raw_high, reference_high]
+            .iter()
+            .all(|value| value.is_finite());
+        if !finite || raw_high - raw_low == 0.0 {
+            return None;
+        }
+        Some(Self {
+            raw_low,
+            reference_low,
+            raw_high,
+            reference_high,
+        })
+    }
+
+    /// The pairs in the order [`new`](Self::new) takes them.
+    pub fn points(&self) -> [f32; 4] {
+        [
+            self.raw_low,
+            self.reference_low,
+            self.raw_high,
+            self.reference_high,
+        ]
+    }
+
+    pub fn slope(&self) -> f32 {
+        (self.reference_high - self.reference_low) / (self.raw_high - self.raw_low)
+    }
+
+    pub fn apply(&self, raw: f32) -> f32 {
+        self.reference_low + (raw - self.raw_low) * self.slope()
+    }
+}
+
+impl Calibration {
+    /// Creates a two-point calibration, `None` if both sensor readings are the same.
+    pub fn two_point(
+        raw_low: f32,
+        reference_low: f32,
+        raw_high: f32,
+        reference_high: f32,
+    ) -> Option<Self> {
+        TwoPoint::new(raw_low, reference_low, raw_high, reference_high).map(Calibration::TwoPoint)
+    }
+
+    pub fn apply(&self, raw: f32) -> f32 {
+        match self {
+            Calibration::None => raw,
+            Calibration::Offset(offset) => raw + offset,
+            Calibration::TwoPoint(two_point) => two_point.apply(raw),
+        }
+    }
+
+    /// Whether the correction is one a working sensor could need: at most [`MAX_OFFSET`] at
+    /// the reference points and a slope between [`MIN_SLOPE`] and [`MAX_SLOPE`].
+    pub fn is_plausible(&self) -> bool {
+        let small = |offset: f32| offset.is_finite() && offset.abs() <= MAX_OFFSET;
+        match self {
+            Calibration::None => true,
+            Calibration::Offset(offset) => small(*offset),
+            Calibration::TwoPoint(two_point) => {
+                let [raw_low, reference_low, raw_high, reference_high] = two_point.points();
+                let slope = two_point.slope();
+                small(reference_low - raw_low)
+                    && small(reference_high - raw_high)
+                    && (MIN_SLOPE..=MAX_SLOPE).contains(&slope)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_is_added() {
+        assert_eq!(Calibration::None.apply(93.0), 93.0);
+        assert_eq!(Calibration::Offset(-1.5).apply(93.0), 91.5);
+    }
+
+    #[test]
+    fn two_point_goes_through_both_references() {
+        // Reads 1°C low in boiling water and 3°C low at brew temperature.
+        let calibration = Calibration::two_point(99.0, 100.0, 90.0, 93.0).unwrap();
+
+        assert!((calibration.apply(99.0) - 100.0).abs() < 1e-4);
+        assert!((calibration.apply(90.0) - 93.0).abs() < 1e-4);
+        assert!((calibration.apply(94.5) - 96.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn two_point_needs_two_readings() {
+        assert_eq!(Calibration::two_point(95.0, 94.0, 95.0, 96.0), None);
+        assert_eq!(Calibration::two_point(f32::NAN, 94.0, 95.0, 96.0), None);
+        assert_eq!(
+            Calibration::two_point(90.0, 94.0, f32::INFINITY, 96.0),
+            None
+        );
+    }
+
+    #[test]
+    fn implausible_corrections_are_flagged() {
+        assert!(Calibration::None.is_plausible());
+        assert!(Calibration::Offset(MAX_OFFSET).is_plausible());
+        assert!(!Calibration::Offset(MAX_OFFSET + 0.1).is_plausible());
+        assert!(!Calibration::Offset(f32::NAN).is_plausible());
+
+        assert!(Calibration::two_point(99.0, 100.0, 90.0, 92.0)
+            .unwrap()
+            .is_plausible());
+        // Far off at the high point.
+        assert!(!Calibration::two_point(20.0, 20.0, 90.0, 110.0)
+            .unwrap()
+            .is_plausible());
+        // The readings are close together, so the slope blows up.
+        assert!(!Calibration::two_point(93.0, 93.0, 93.1, 95.0)
+            .unwrap()
+            .is_plausible());
+        // Falling instead of rising.
+        assert!(!Calibration::two_point(90.0, 93.0, 99.0, 92.0)
+            .unwrap()
+            .is_plausible());
+    }
+}
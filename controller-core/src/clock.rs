@@ -0,0 +1,66 @@
+//! Time as the logic sees it, the firmware provides the actual clock.
+
+/// Milliseconds since boot.
+pub type Millis = u64;
+
+pub const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// The time of day, anchored to the uptime once it has been synced from the outside.
+#[derive(Default)]
+pub struct WallClock {
+    /// Seconds since midnight at the uptime `synced_at`.
+    synced: Option<(u32, Millis)>,
+}
+
+impl WallClock {
+    pub fn new() -> Self {
+        Self { synced: None }
+    }
+
+    /// Sets the current time of day in seconds since midnight.
+    pub fn sync(&mut self, seconds_of_day: u32, now: Millis) {
+        self.synced = Some((seconds_of_day % SECONDS_PER_DAY, now));
+    }
+
+    /// Seconds since midnight, `None` until the clock has been synced.
+    pub fn seconds_of_day(&self, now: Millis) -> Option<u32> {
+        self.synced.map(|(seconds, synced_at)| {
+            let elapsed = (now.saturating_sub(synced_at) / 1000) as u32;
+            (seconds + elapsed % SECONDS_PER_DAY) % SECONDS_PER_DAY
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_until_synced() {
+        let mut clock = WallClock::new();
+        assert_eq!(clock.seconds_of_day(5_000), None);
+
+        clock.sync(8 * 3600, 5_000);
+        assert_eq!(clock.seconds_of_day(5_000), Some(8 * 3600));
+        assert_eq!(clock.seconds_of_day(65_999), Some(8 * 3600 + 60));
+    }
+
+    #[test]
+    fn wraps_at_midnight() {
+        let mut clock = WallClock::new();
+        clock.sync(SECONDS_PER_DAY - 10, 0);
+
+        assert_eq!(clock.seconds_of_day(9_000), Some(SECONDS_PER_DAY - 1));
+        assert_eq!(clock.seconds_of_day(10_000), Some(0));
+        // Days later it still is the same time of day.
+        let days = 3 * Millis::from(SECONDS_PER_DAY) * 1000;
+        assert_eq!(clock.seconds_of_day(days + 20_000), Some(10));
+    }
+
+    #[test]
+    fn sync_takes_the_time_modulo_a_day() {
+        let mut clock = WallClock::new();
+        clock.sync(SECONDS_PER_DAY + 5, 0);
+        assert_eq!(clock.seconds_of_day(0), Some(5));
+    }
+}
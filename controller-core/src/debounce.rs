@@ -0,0 +1,94 @@
+//! Debouncing of switch inputs (brew switch and friends), the firmware samples the pins.
+
+/// A change of the debounced switch state.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+/// Turns raw samples of a switch into edges once they have settled.
+pub struct Debounce {
+    pressed: bool,
+    /// Number of polls the raw level has been different from `pressed`.
+    changed_for: u8,
+    debounce_polls: u8,
+}
+
+impl Debounce {
+    /// The raw level has to be stable for `debounce_polls` polls before it counts, the switch
+    /// starts out released.
+    pub fn new(debounce_polls: u8) -> Self {
+        Self {
+            pressed: false,
+            changed_for: 0,
+            debounce_polls,
+        }
+    }
+
+    /// Feeds a raw sample, returns the edge once the new level is stable.
+    pub fn update(&mut self, raw_pressed: bool) -> Option<Edge> {
+        if raw_pressed == self.pressed {
+            self.changed_for = 0;
+            return None;
+        }
+
+        self.changed_for += 1;
+        if self.changed_for < self.debounce_polls {
+            return None;
+        }
+
+        self.changed_for = 0;
+        self.pressed = raw_pressed;
+        if self.pressed {
+            Some(Edge::Pressed)
+        } else {
+            Some(Edge::Released)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The edges for a sequence of raw samples, in the order they come out.
+    fn edges(debounce: &mut Debounce, samples: &[bool]) -> Vec<(usize, Edge)> {
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, raw)| debounce.update(*raw).map(|edge| (i, edge)))
+            .collect()
+    }
+
+    #[test]
+    fn a_stable_level_counts_after_the_debounce_polls() {
+        let mut debounce = Debounce::new(3);
+        assert_eq!(edges(&mut debounce, &[true; 5]), vec![(2, Edge::Pressed)]);
+        assert_eq!(edges(&mut debounce, &[false; 5]), vec![(2, Edge::Released)]);
+    }
+
+    #[test]
+    fn bounces_start_the_count_over() {
+        let mut debounce = Debounce::new(3);
+        let samples = [true, true, false, true, true, false, true, true, true, true];
+        assert_eq!(edges(&mut debounce, &samples), vec![(8, Edge::Pressed)]);
+    }
+
+    #[test]
+    fn a_released_switch_gives_no_edges() {
+        let mut debounce = Debounce::new(3);
+        assert_eq!(edges(&mut debounce, &[false; 10]), vec![]);
+    }
+
+    #[test]
+    fn zero_or_one_poll_takes_the_first_sample() {
+        for polls in [0, 1].iter() {
+            let mut debounce = Debounce::new(*polls);
+            assert_eq!(
+                edges(&mut debounce, &[true, false, false]),
+                vec![(0, Edge::Pressed), (1, Edge::Released)]
+            );
+        }
+    }
+}
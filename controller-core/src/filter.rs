@@ -0,0 +1,248 @@
+//! Filters which smooth the raw sensor readings before they reach the PID.
+
+/// The longest median window which can be configured.
+pub const MAX_MEDIAN_LEN: usize = 9;
+/// The most rejections in a row which can be configured, the gate has to give in at some point.
+pub const MAX_REJECTS: u8 = 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FilterConfig {
+    /// Number of samples the median is taken over, 1 disables the median.
+    pub median_len: usize,
+    /// Smoothing factor of the EMA in (0, 1], 1 disables the EMA.
+    pub ema_alpha: f32,
+    /// Maximum plausible change (°C) between two samples, 0 disables the gate.
+    pub max_delta: f32,
+    /// After this many rejections in a row the gate accepts the new level.
+    pub max_rejects: u8,
+}
+
+impl FilterConfig {
+    pub const fn new(median_len: usize, ema_alpha: f32, max_delta: f32, max_rejects: u8) -> Self {
+        Self {
+            median_len,
+            ema_alpha,
+            max_delta,
+            max_rejects,
+        }
+    }
+}
+
+/// A sample that did not pass the plausibility gate.
+pub struct Rejected;
+
+/// Runs every sample through the plausibility gate, the median and the EMA (in that order).
+pub struct FilterChain {
+    config: FilterConfig,
+    window: [f32; MAX_MEDIAN_LEN],
+    window_len: usize,
+    window_pos: usize,
+    ema: Option<f32>,
+    last_accepted: Option<f32>,
+    rejects_in_row: u8,
+}
+
+impl FilterChain {
+    pub fn new(config: FilterConfig) -> Self {
+        let mut chain = Self {
+            config,
+            window: [0.0; MAX_MEDIAN_LEN],
+            window_len: 0,
+            window_pos: 0,
+            ema: None,
+            last_accepted: None,
+            rejects_in_row: 0,
+        };
+        chain.set_config(config);
+        chain
+    }
+
+    /// Replaces the configuration and starts over with an empty history.
+    pub fn set_config(&mut self, mut config: FilterConfig) {
+        if config.median_len == 0 {
+            config.median_len = 1;
+        } else if config.median_len > MAX_MEDIAN_LEN {
+            config.median_len = MAX_MEDIAN_LEN;
+        }
+        if config.ema_alpha <= 0.0 || config.ema_alpha > 1.0 {
+            config.ema_alpha = 1.0;
+        }
+        if config.max_rejects > MAX_REJECTS {
+            config.max_rejects = MAX_REJECTS;
+        }
+        self.config = config;
+        self.reset();
+    }
+
+    pub fn config(&self) -> FilterConfig {
+        self.config
+    }
+
+    pub fn reset(&mut self) {
+        self.window_len = 0;
+        self.window_pos = 0;
+        self.ema = None;
+        self.last_accepted = None;
+        self.rejects_in_row = 0;
+    }
+
+    /// Feeds a raw sample and returns the filtered value, or `Rejected` if the gate dropped it.
+    pub fn push(&mut self, raw: f32) -> Result<f32, Rejected> {
+        if !self.passes_gate(raw) {
+            return Err(Rejected);
+        }
+        self.last_accepted = Some(raw);
+
+        self.window[self.window_pos] = raw;
+        self.window_pos = (self.window_pos + 1) % self.config.median_len;
+        if self.window_len < self.config.median_len {
+            self.window_len += 1;
+        }
+        let median = self.median();
+
+        let filtered = match self.ema {
+            Some(ema) => ema + self.config.ema_alpha * (median - ema),
+            None => median,
+        };
+        self.ema = Some(filtered);
+
+        Ok(filtered)
+    }
+
+    fn passes_gate(&mut self, raw: f32) -> bool {
+        let last = match self.last_accepted {
+            Some(last) => last,
+            None => return true,
+        };
+        if self.config.max_delta <= 0.0 {
+            return true;
+        }
+
+        let delta = if raw > last { raw - last } else { last - raw };
+        if delta <= self.config.max_delta {
+            self.rejects_in_row = 0;
+            return true;
+        }
+
+        self.rejects_in_row = self.rejects_in_row.saturating_add(1);
+        if self.rejects_in_row > self.config.max_rejects {
+            // The new level is consistent, so it is real and the history is outdated.
+            self.reset();
+            return true;
+        }
+        false
+    }
+
+    fn median(&self) -> f32 {
+        let mut sorted = [0.0; MAX_MEDIAN_LEN];
+        let samples = &mut sorted[..self.window_len];
+        samples.copy_from_slice(&self.window[..self.window_len]);
+
+        // Insertion sort, the window is tiny.
+        for i in 1..samples.len() {
+            let mut j = i;
+            while j > 0 && samples[j - 1] > samples[j] {
+                samples.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        let mid = samples.len() / 2;
+        if samples.len() % 2 == 0 {
+            (samples[mid - 1] + samples[mid]) / 2.0
+        } else {
+            samples[mid]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the gate, no median and no EMA.
+    fn gate(max_delta: f32, max_rejects: u8) -> FilterChain {
+        FilterChain::new(FilterConfig::new(1, 1.0, max_delta, max_rejects))
+    }
+
+    #[test]
+    fn median_drops_a_single_spike() {
+        let mut chain = FilterChain::new(FilterConfig::new(3, 1.0, 0.0, 0));
+
+        assert_eq!(chain.push(90.0).ok(), Some(90.0));
+        assert_eq!(chain.push(91.0).ok(), Some(90.5));
+        assert_eq!(chain.push(120.0).ok(), Some(91.0));
+        assert_eq!(chain.push(92.0).ok(), Some(92.0));
+    }
+
+    #[test]
+    fn ema_smooths_steps() {
+        let mut chain = FilterChain::new(FilterConfig::new(1, 0.5, 0.0, 0));
+
+        assert_eq!(chain.push(90.0).ok(), Some(90.0));
+        assert_eq!(chain.push(92.0).ok(), Some(91.0));
+        assert_eq!(chain.push(92.0).ok(), Some(91.5));
+    }
+
+    #[test]
+    fn gate_rejects_implausible_jumps() {
+        let mut chain = gate(5.0, 3);
+
+        assert_eq!(chain.push(90.0).ok(), Some(90.0));
+        assert!(chain.push(96.0).is_err());
+        assert!(chain.push(84.0).is_err());
+        // Compared against the last accepted sample, not the rejected ones.
+        assert_eq!(chain.push(94.0).ok(), Some(94.0));
+    }
+
+    #[test]
+    fn gate_accepts_a_new_level_after_max_rejects() {
+        let mut chain = FilterChain::new(FilterConfig::new(3, 0.5, 5.0, 3));
+        chain.push(90.0).ok();
+        chain.push(90.0).ok();
+
+        for _ in 0..3 {
+            assert!(chain.push(110.0).is_err());
+        }
+        // The history is dropped, so the new level comes through unfiltered.
+        assert_eq!(chain.push(110.0).ok(), Some(110.0));
+        assert_eq!(chain.push(110.0).ok(), Some(110.0));
+    }
+
+    #[test]
+    fn good_sample_restarts_the_reject_count() {
+        let mut chain = gate(5.0, 2);
+        chain.push(90.0).ok();
+
+        for _ in 0..10 {
+            assert!(chain.push(110.0).is_err());
+            assert!(chain.push(110.0).is_err());
+            assert!(chain.push(91.0).is_ok());
+            chain.push(90.0).ok();
+        }
+    }
+
+    #[test]
+    fn max_rejects_is_bounded() {
+        let mut chain = gate(5.0, u8::MAX);
+        assert_eq!(chain.config().max_rejects, MAX_REJECTS);
+        chain.push(90.0).ok();
+
+        for _ in 0..MAX_REJECTS {
+            assert!(chain.push(20.0).is_err());
+        }
+        assert_eq!(chain.push(20.0).ok(), Some(20.0));
+    }
+
+    #[test]
+    fn reject_count_does_not_overflow() {
+        let mut chain = gate(5.0, MAX_REJECTS);
+        // Past the clamp, to run the counter into its limit.
+        chain.config.max_rejects = u8::MAX;
+        chain.push(90.0).ok();
+
+        for _ in 0..1000 {
+            assert!(chain.push(20.0).is_err());
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! The parts of the controller which do not touch the hardware.
+//!
+//! The safety limits, the filters, the state machine, the settings and their flash layout, the
+//! `embedded-hal` sensor drivers and friends only ever see plain values, generic buses or the
+//! [`storage::Flash`] trait, so they live in their own crate which builds and tests on the host
+//! as well:
+//!
+//! ```sh
+//! cargo test -p controller-core --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! The `defmt` feature derives `defmt::Format` for the types the firmware logs.
+
+#![cfg_attr(not(test), no_std)]
+// The firmware does not require a toolchain with `is_multiple_of` and `div_ceil`, newer clippy
+// versions suggest them over the plain arithmetic anyway.
+#![allow(unknown_lints, clippy::manual_is_multiple_of, clippy::manual_div_ceil)]
+
+pub mod brew;
+pub mod calibration;
+pub mod clock;
+pub mod debounce;
+pub mod filter;
+pub mod machine;
+pub mod ready;
+pub mod reset;
+pub mod safety;
+pub mod self_test;
+pub mod sensor;
+pub mod settings;
+pub mod shell;
+pub mod shot;
+pub mod standby;
+pub mod storage;
+pub mod supervisor;
@@ -0,0 +1,348 @@
+//! The life cycle of the machine as an explicit state machine.
+//!
+//! Everything in here is plain data so the transitions can be exercised without hardware: the
+//! firmware tasks translate what they observe into [`Event`]s and apply whatever the new
+//! [`MachineState`] asks for.
+
+use crate::clock::Millis;
+use protocol::gatt::Command;
+use protocol::status::Mode;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MachineState {
+    /// Waiting for the first good temperature reading.
+    Booting,
+    /// Heating up from cold with the aggressive gains.
+    Coldstart,
+    /// Reached the setpoint, waiting for the temperature to settle.
+    Stabilizing,
+    /// Stable at the setpoint, ready to pull a shot.
+    Ready,
+    /// A shot is being pulled.
+    Brewing,
+    /// Holding the steam setpoint.
+    Steam,
+    /// Idle, the boiler is kept at the eco temperature or the heater is off.
+    Standby,
+    /// The sensor failed, the heater is off until it recovers.
+    Fault,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// The first good reading arrived, `cold` if it is below the setpoint.
+    Booted {
+        cold: bool,
+    },
+    /// The boiler reached the setpoint.
+    TemperatureReached,
+    /// The temperature settled around the setpoint.
+    Stable,
+    /// The temperature left the band around the setpoint.
+    Unstable,
+    BrewStarted,
+    BrewStopped,
+    SteamOn,
+    SteamOff,
+    /// The current state has been active for longer than it is allowed to.
+    Timeout,
+    StandbyRequested,
+    Wake,
+    SensorFault,
+    SensorRecovered,
+}
+
+/// The gains a state runs with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GainSet {
+    Cold,
+    Warm,
+    Steam,
+}
+
+/// What a state allows the heater to do.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HeaterPolicy {
+    /// The heater is forced off.
+    Off,
+    /// The PID drives the heater towards the brew setpoint.
+    Brew,
+    /// The PID drives the heater towards the steam setpoint.
+    Steam,
+    /// Either the eco setpoint or off, depending on the standby settings.
+    Standby,
+}
+
+impl MachineState {
+    pub fn gain_set(self) -> GainSet {
+        match self {
+            MachineState::Booting | MachineState::Coldstart => GainSet::Cold,
+            MachineState::Steam => GainSet::Steam,
+            _ => GainSet::Warm,
+        }
+    }
+
+    pub fn heater_policy(self) -> HeaterPolicy {
+        match self {
+            MachineState::Booting | MachineState::Fault => HeaterPolicy::Off,
+            MachineState::Steam => HeaterPolicy::Steam,
+            MachineState::Standby => HeaterPolicy::Standby,
+            _ => HeaterPolicy::Brew,
+        }
+    }
+
+    /// The state `event` leads to, `None` if the event does not apply in this state.
+    pub fn next(self, event: Event) -> Option<MachineState> {
+        use MachineState::*;
+
+        let next = match (self, event) {
+            (Fault, Event::SensorRecovered) => Coldstart,
+            (Fault, _) => return None,
+            (_, Event::SensorFault) => Fault,
+
+            (Booting, Event::Booted { cold: true }) => Coldstart,
+            (Booting, Event::Booted { cold: false }) => Stabilizing,
+            (Booting, _) => return None,
+
+            (Coldstart, Event::TemperatureReached) => Stabilizing,
+            // The ready detector runs during the cold start as well, after a small change of
+            // the setpoint it can settle without the setpoint ever being crossed.
+            (Coldstart, Event::Stable) | (Stabilizing, Event::Stable) => Ready,
+            (Ready, Event::Unstable) => Stabilizing,
+
+            (Coldstart, Event::BrewStarted)
+            | (Stabilizing, Event::BrewStarted)
+            | (Ready, Event::BrewStarted) => Brewing,
+            (Brewing, Event::BrewStopped) => Stabilizing,
+
+            (Coldstart, Event::SteamOn)
+            | (Stabilizing, Event::SteamOn)
+            | (Ready, Event::SteamOn)
+            | (Standby, Event::SteamOn) => Steam,
+            (Steam, Event::SteamOff) | (Steam, Event::Timeout) => Stabilizing,
+
+            (Coldstart, Event::StandbyRequested)
+            | (Stabilizing, Event::StandbyRequested)
+            | (Ready, Event::StandbyRequested) => Standby,
+            (Standby, Event::Wake) | (Standby, Event::BrewStarted) => Coldstart,
+
+            _ => return None,
+        };
+        Some(next)
+    }
+}
+
+impl Event {
+    /// The event a remote command asks for, `None` if it is not about the machine state.
+    pub fn from_command(command: Command) -> Option<Self> {
+        match command {
+            Command::Standby => Some(Event::StandbyRequested),
+            Command::Wake => Some(Event::Wake),
+            Command::SteamOn => Some(Event::SteamOn),
+            Command::SteamOff => Some(Event::SteamOff),
+            Command::Save => None,
+        }
+    }
+}
+
+impl From<MachineState> for Mode {
+    fn from(machine_state: MachineState) -> Self {
+        match machine_state {
+            MachineState::Booting => Mode::Booting,
+            MachineState::Coldstart => Mode::Coldstart,
+            MachineState::Stabilizing => Mode::Stabilizing,
+            MachineState::Ready => Mode::Ready,
+            MachineState::Brewing => Mode::Brewing,
+            MachineState::Steam => Mode::Steam,
+            MachineState::Standby => Mode::Standby,
+            MachineState::Fault => Mode::Fault,
+        }
+    }
+}
+
+pub struct Machine {
+    state: MachineState,
+    entered_at: Millis,
+}
+
+impl Machine {
+    pub fn new(now: Millis) -> Self {
+        Self {
+            state: MachineState::Booting,
+            entered_at: now,
+        }
+    }
+
+    /// Feeds an event, returns the new state if it caused a transition.
+    pub fn handle(&mut self, event: Event, now: Millis) -> Option<MachineState> {
+        let next = self.state.next(event)?;
+        if next == self.state {
+            return None;
+        }
+        self.state = next;
+        self.entered_at = now;
+        Some(next)
+    }
+
+    pub fn state(&self) -> MachineState {
+        self.state
+    }
+
+    /// How long the machine has been in the current state.
+    pub fn time_in_state(&self, now: Millis) -> Millis {
+        now.saturating_sub(self.entered_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MachineState::*;
+
+    const STATES: [MachineState; 8] = [
+        Booting,
+        Coldstart,
+        Stabilizing,
+        Ready,
+        Brewing,
+        Steam,
+        Standby,
+        Fault,
+    ];
+
+    const EVENTS: [Event; 14] = [
+        Event::Booted { cold: true },
+        Event::Booted { cold: false },
+        Event::TemperatureReached,
+        Event::Stable,
+        Event::Unstable,
+        Event::BrewStarted,
+        Event::BrewStopped,
+        Event::SteamOn,
+        Event::SteamOff,
+        Event::Timeout,
+        Event::StandbyRequested,
+        Event::Wake,
+        Event::SensorFault,
+        Event::SensorRecovered,
+    ];
+
+    /// Every transition there is, anything else has to be ignored.
+    const TRANSITIONS: &[(MachineState, Event, MachineState)] = &[
+        (Booting, Event::Booted { cold: true }, Coldstart),
+        (Booting, Event::Booted { cold: false }, Stabilizing),
+        (Booting, Event::SensorFault, Fault),
+        (Coldstart, Event::TemperatureReached, Stabilizing),
+        (Coldstart, Event::Stable, Ready),
+        (Coldstart, Event::BrewStarted, Brewing),
+        (Coldstart, Event::SteamOn, Steam),
+        (Coldstart, Event::StandbyRequested, Standby),
+        (Coldstart, Event::SensorFault, Fault),
+        (Stabilizing, Event::Stable, Ready),
+        (Stabilizing, Event::BrewStarted, Brewing),
+        (Stabilizing, Event::SteamOn, Steam),
+        (Stabilizing, Event::StandbyRequested, Standby),
+        (Stabilizing, Event::SensorFault, Fault),
+        (Ready, Event::Unstable, Stabilizing),
+        (Ready, Event::BrewStarted, Brewing),
+        (Ready, Event::SteamOn, Steam),
+        (Ready, Event::StandbyRequested, Standby),
+        (Ready, Event::SensorFault, Fault),
+        (Brewing, Event::BrewStopped, Stabilizing),
+        (Brewing, Event::SensorFault, Fault),
+        (Steam, Event::SteamOff, Stabilizing),
+        (Steam, Event::Timeout, Stabilizing),
+        (Steam, Event::SensorFault, Fault),
+        (Standby, Event::Wake, Coldstart),
+        (Standby, Event::BrewStarted, Coldstart),
+        (Standby, Event::SteamOn, Steam),
+        (Standby, Event::SensorFault, Fault),
+        (Fault, Event::SensorRecovered, Coldstart),
+    ];
+
+    #[test]
+    fn transitions_match_the_table() {
+        for &from in STATES.iter() {
+            for &event in EVENTS.iter() {
+                let expected = TRANSITIONS
+                    .iter()
+                    .find(|(state, on, _)| *state == from && *on == event)
+                    .map(|(_, _, to)| *to);
+                assert_eq!(from.next(event), expected, "{:?} on {:?}", from, event);
+            }
+        }
+    }
+
+    #[test]
+    fn states_pick_gains_and_heater_policy() {
+        let expected = [
+            (Booting, GainSet::Cold, HeaterPolicy::Off),
+            (Coldstart, GainSet::Cold, HeaterPolicy::Brew),
+            (Stabilizing, GainSet::Warm, HeaterPolicy::Brew),
+            (Ready, GainSet::Warm, HeaterPolicy::Brew),
+            (Brewing, GainSet::Warm, HeaterPolicy::Brew),
+            (Steam, GainSet::Steam, HeaterPolicy::Steam),
+            (Standby, GainSet::Warm, HeaterPolicy::Standby),
+            (Fault, GainSet::Warm, HeaterPolicy::Off),
+        ];
+        for &(state, gains, policy) in expected.iter() {
+            assert_eq!(state.gain_set(), gains, "{:?}", state);
+            assert_eq!(state.heater_policy(), policy, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn commands_map_to_events() {
+        assert_eq!(
+            Event::from_command(Command::Standby),
+            Some(Event::StandbyRequested)
+        );
+        assert_eq!(Event::from_command(Command::Wake), Some(Event::Wake));
+        assert_eq!(Event::from_command(Command::SteamOn), Some(Event::SteamOn));
+        assert_eq!(
+            Event::from_command(Command::SteamOff),
+            Some(Event::SteamOff)
+        );
+        assert_eq!(Event::from_command(Command::Save), None);
+    }
+
+    #[test]
+    fn machine_tracks_the_time_in_state() {
+        let mut machine = Machine::new(1_000);
+        assert_eq!(machine.state(), Booting);
+
+        assert_eq!(
+            machine.handle(Event::Booted { cold: true }, 2_000),
+            Some(Coldstart)
+        );
+        assert_eq!(machine.time_in_state(2_500), 500);
+
+        // Ignored events leave the clock alone.
+        assert_eq!(machine.handle(Event::BrewStopped, 3_000), None);
+        assert_eq!(machine.time_in_state(3_000), 1_000);
+
+        assert_eq!(machine.handle(Event::SteamOn, 4_000), Some(Steam));
+        assert_eq!(machine.handle(Event::Timeout, 9_000), Some(Stabilizing));
+        assert_eq!(machine.time_in_state(9_000), 0);
+    }
+
+    #[test]
+    fn a_cold_start_reaches_ready() {
+        let mut machine = Machine::new(0);
+        for (at, event) in [
+            Event::Booted { cold: true },
+            Event::TemperatureReached,
+            Event::Stable,
+        ]
+        .iter()
+        .enumerate()
+        {
+            machine.handle(*event, at as Millis);
+        }
+        assert_eq!(machine.state(), Ready);
+    }
+}
@@ -0,0 +1,309 @@
+//! Decides when the machine is ready to pull a shot.
+//!
+//! Reaching the setpoint is not enough, the boiler walls and the group need time to soak up
+//! the heat. The machine counts as ready once the temperature stayed in a band around the
+//! setpoint for the dwell time and the heater duty stopped moving around while doing so.
+
+use crate::clock::Millis;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReadyConfig {
+    /// Allowed deviation (°C) from the setpoint.
+    pub band: f32,
+    /// How long (in ms) the temperature has to stay within the band.
+    pub dwell_ms: Millis,
+    /// Maximum spread (0.0 - 1.0) of the heater duty during the dwell time.
+    pub duty_tolerance: f32,
+}
+
+/// A change of the ready flag.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReadyChange {
+    BecameReady,
+    LostReady,
+}
+
+/// Smoothing of the temperature slope used for the estimate.
+const SLOPE_ALPHA: f32 = 0.2;
+
+pub struct ReadyDetector {
+    config: ReadyConfig,
+    in_band_since: Option<Millis>,
+    duty_min: f32,
+    duty_max: f32,
+    ready: bool,
+    last_sample: Option<(Millis, f32)>,
+    /// Smoothed temperature slope in °C per ms.
+    slope: f32,
+    eta: Option<Millis>,
+}
+
+impl ReadyDetector {
+    pub fn new(config: ReadyConfig) -> Self {
+        Self {
+            config,
+            in_band_since: None,
+            duty_min: 0.0,
+            duty_max: 0.0,
+            ready: false,
+            last_sample: None,
+            slope: 0.0,
+            eta: None,
+        }
+    }
+
+    /// Forgets the history, for example after the setpoint moved.
+    pub fn reset(&mut self) {
+        self.in_band_since = None;
+        self.ready = false;
+        self.last_sample = None;
+        self.slope = 0.0;
+        self.eta = None;
+    }
+
+    /// Feeds a new temperature and the current heater duty (0.0 - 1.0).
+    pub fn update(
+        &mut self,
+        now: Millis,
+        temp: f32,
+        setpoint: f32,
+        duty: f32,
+    ) -> Option<ReadyChange> {
+        self.update_slope(now, temp);
+
+        let deviation = if temp > setpoint {
+            temp - setpoint
+        } else {
+            setpoint - temp
+        };
+
+        if deviation > self.config.band {
+            self.in_band_since = None;
+        } else {
+            match self.in_band_since {
+                Some(_) => {
+                    self.duty_min = if duty < self.duty_min {
+                        duty
+                    } else {
+                        self.duty_min
+                    };
+                    self.duty_max = if duty > self.duty_max {
+                        duty
+                    } else {
+                        self.duty_max
+                    };
+                    if self.duty_max - self.duty_min > self.config.duty_tolerance {
+                        // The heater is still hunting, start the dwell over.
+                        self.start_dwell(now, duty);
+                    }
+                }
+                None => self.start_dwell(now, duty),
+            }
+        }
+
+        let ready = match self.in_band_since {
+            Some(since) => now.saturating_sub(since) >= self.config.dwell_ms,
+            None => false,
+        };
+        self.eta = self.estimate(now, temp, setpoint);
+
+        let change = match (self.ready, ready) {
+            (false, true) => Some(ReadyChange::BecameReady),
+            (true, false) => Some(ReadyChange::LostReady),
+            _ => None,
+        };
+        self.ready = ready;
+        change
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// The estimated time (in ms) until ready, `None` if it cannot be estimated.
+    pub fn eta(&self) -> Option<Millis> {
+        self.eta
+    }
+
+    fn start_dwell(&mut self, now: Millis, duty: f32) {
+        self.in_band_since = Some(now);
+        self.duty_min = duty;
+        self.duty_max = duty;
+    }
+
+    fn update_slope(&mut self, now: Millis, temp: f32) {
+        if let Some((last_at, last_temp)) = self.last_sample {
+            let dt = now.saturating_sub(last_at);
+            if dt > 0 {
+                let slope = (temp - last_temp) / dt as f32;
+                self.slope += SLOPE_ALPHA * (slope - self.slope);
+            }
+        }
+        self.last_sample = Some((now, temp));
+    }
+
+    fn estimate(&self, now: Millis, temp: f32, setpoint: f32) -> Option<Millis> {
+        if self.ready {
+            return Some(0);
+        }
+
+        if let Some(since) = self.in_band_since {
+            return Some(
+                self.config
+                    .dwell_ms
+                    .saturating_sub(now.saturating_sub(since)),
+            );
+        }
+
+        // Outside of the band: follow the current trajectory to the band edge, then dwell.
+        let distance = if temp < setpoint {
+            setpoint - self.config.band - temp
+        } else {
+            temp - setpoint - self.config.band
+        };
+        let closing = if temp < setpoint {
+            self.slope
+        } else {
+            -self.slope
+        };
+        if closing <= 0.0 {
+            return None;
+        }
+        Some((distance / closing) as Millis + self.config.dwell_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETPOINT: f32 = 93.0;
+    const SAMPLE_MS: Millis = 500;
+
+    fn detector() -> ReadyDetector {
+        ReadyDetector::new(ReadyConfig {
+            band: 0.5,
+            dwell_ms: 10_000,
+            duty_tolerance: 0.1,
+        })
+    }
+
+    /// Feeds a sample every [`SAMPLE_MS`] from `from` until before `to`, returning the changes.
+    fn feed(
+        detector: &mut ReadyDetector,
+        from: Millis,
+        to: Millis,
+        sample: impl Fn(Millis) -> (f32, f32),
+    ) -> Vec<(Millis, ReadyChange)> {
+        (from..to)
+            .step_by(SAMPLE_MS as usize)
+            .filter_map(|now| {
+                let (temp, duty) = sample(now);
+                detector
+                    .update(now, temp, SETPOINT, duty)
+                    .map(|change| (now, change))
+            })
+            .collect()
+    }
+
+    /// `even` on every other sample starting with the first, `odd` in between.
+    fn alternate(now: Millis, even: f32, odd: f32) -> f32 {
+        [even, odd][(now / SAMPLE_MS % 2) as usize]
+    }
+
+    #[test]
+    fn becomes_ready_after_dwelling_in_the_band() {
+        let mut detector = detector();
+        let wobble = |now: Millis| (SETPOINT + alternate(now, 0.4, -0.4), 0.3);
+
+        assert_eq!(feed(&mut detector, 0, 5000, wobble), vec![]);
+        assert!(!detector.is_ready());
+        assert_eq!(detector.eta(), Some(5500));
+
+        assert_eq!(
+            feed(&mut detector, 5000, 20_000, wobble),
+            vec![(10_000, ReadyChange::BecameReady)]
+        );
+        assert!(detector.is_ready());
+        assert_eq!(detector.eta(), Some(0));
+    }
+
+    #[test]
+    fn leaving_the_band_loses_ready_and_starts_the_dwell_over() {
+        let mut detector = detector();
+        feed(&mut detector, 0, 11_000, |_| (SETPOINT, 0.3));
+        assert!(detector.is_ready());
+
+        assert_eq!(
+            detector.update(11_000, SETPOINT - 1.0, SETPOINT, 0.3),
+            Some(ReadyChange::LostReady)
+        );
+        assert!(!detector.is_ready());
+
+        assert_eq!(
+            feed(&mut detector, 11_500, 30_000, |_| (SETPOINT, 0.3)),
+            vec![(21_500, ReadyChange::BecameReady)]
+        );
+    }
+
+    #[test]
+    fn a_hunting_heater_is_not_ready() {
+        let mut detector = detector();
+        let hunting = |now: Millis| (SETPOINT, alternate(now, 0.2, 0.4));
+        assert_eq!(feed(&mut detector, 0, 30_000, hunting), vec![]);
+        assert!(!detector.is_ready());
+
+        // The last swing at 29.5s started the dwell, the settled duty is within the tolerance.
+        assert_eq!(
+            feed(&mut detector, 30_000, 50_000, |_| (SETPOINT, 0.3)),
+            vec![(39_500, ReadyChange::BecameReady)]
+        );
+    }
+
+    #[test]
+    fn a_duty_drifting_within_the_tolerance_stays_ready() {
+        let mut detector = detector();
+        let drifting = |now: Millis| (SETPOINT, 0.3 + 0.09 * now as f32 / 30_000.0);
+        assert_eq!(
+            feed(&mut detector, 0, 30_000, drifting),
+            vec![(10_000, ReadyChange::BecameReady)]
+        );
+    }
+
+    #[test]
+    fn the_estimate_follows_a_linear_heat_up() {
+        let mut detector = detector();
+        // 0.5°C per second from 60°C, 80°C are reached after 40s.
+        let heat_up = |now: Millis| (60.0 + 0.0005 * now as f32, 1.0);
+        assert_eq!(detector.update(0, 60.0, SETPOINT, 1.0), None);
+        assert_eq!(detector.eta(), None);
+
+        feed(&mut detector, SAMPLE_MS, 40_500, heat_up);
+        // 12.5°C to the band edge at 0.5°C per second and the dwell.
+        let expected = 25_000.0 + 10_000.0;
+        let eta = detector.eta().unwrap() as f32;
+        assert!((eta - expected).abs() < expected * 0.01, "{}", eta);
+    }
+
+    #[test]
+    fn there_is_no_estimate_while_moving_away() {
+        let mut detector = detector();
+        feed(&mut detector, 0, 20_000, |now| {
+            (80.0 - 0.0005 * now as f32, 0.0)
+        });
+        assert_eq!(detector.eta(), None);
+
+        detector.reset();
+        feed(&mut detector, 0, 20_000, |now| {
+            (100.0 + 0.0005 * now as f32, 0.0)
+        });
+        assert_eq!(detector.eta(), None);
+
+        // Cooling down towards the setpoint from above is fine though.
+        detector.reset();
+        feed(&mut detector, 0, 20_000, |now| {
+            (110.0 - 0.0005 * now as f32, 0.0)
+        });
+        assert!(detector.eta().is_some());
+    }
+}
@@ -0,0 +1,143 @@
+//! Why the controller (re)started and how much of the previous run can be trusted.
+
+// The bits of the POWER.RESETREAS register.
+const RESETPIN: u32 = 1 << 0;
+const DOG: u32 = 1 << 1;
+const SREQ: u32 = 1 << 2;
+const LOCKUP: u32 = 1 << 3;
+/// Wake-up from System OFF through GPIO, LPCOMP, debug interface, NFC or VBUS.
+const WAKE_UP: u32 = 0b1_1111 << 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    /// Power was applied or dropped below the brown-out level, both leave no bit behind.
+    PowerOn,
+    /// The reset pin was pulled.
+    Pin,
+    /// A task stopped petting the watchdog.
+    Watchdog,
+    /// The firmware asked for the reset, usually from the panic handler.
+    SoftReset,
+    /// The CPU locked up, e.g. on a fault inside the HardFault handler.
+    Lockup,
+    /// Woke up from System OFF.
+    WakeUp,
+}
+
+/// How to treat the boot after a given [`ResetReason`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ResetPolicy {
+    /// A boiler which is still hot may skip the coldstart.
+    ///
+    /// Off after resets which hint at a firmware problem, since the state the previous run
+    /// left behind is not trusted.
+    pub warm_start: bool,
+    /// The reset was not asked for by the user and is reported on the display.
+    pub unexpected: bool,
+}
+
+impl ResetReason {
+    /// Classifies the raw RESETREAS register.
+    ///
+    /// The register accumulates until it is cleared, so the most severe reason wins if several
+    /// bits are set.
+    pub fn from_resetreas(bits: u32) -> Self {
+        if bits & LOCKUP != 0 {
+            ResetReason::Lockup
+        } else if bits & DOG != 0 {
+            ResetReason::Watchdog
+        } else if bits & SREQ != 0 {
+            ResetReason::SoftReset
+        } else if bits & RESETPIN != 0 {
+            ResetReason::Pin
+        } else if bits & WAKE_UP != 0 {
+            ResetReason::WakeUp
+        } else {
+            ResetReason::PowerOn
+        }
+    }
+
+    pub fn policy(self) -> ResetPolicy {
+        match self {
+            ResetReason::PowerOn | ResetReason::Pin | ResetReason::WakeUp => ResetPolicy {
+                warm_start: true,
+                unexpected: false,
+            },
+            // Most likely a panic, which leaves its own crash record behind.
+            ResetReason::SoftReset => ResetPolicy {
+                warm_start: true,
+                unexpected: true,
+            },
+            ResetReason::Watchdog | ResetReason::Lockup => ResetPolicy {
+                warm_start: false,
+                unexpected: true,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_bit_has_its_reason() {
+        assert_eq!(ResetReason::from_resetreas(0), ResetReason::PowerOn);
+        assert_eq!(ResetReason::from_resetreas(RESETPIN), ResetReason::Pin);
+        assert_eq!(ResetReason::from_resetreas(DOG), ResetReason::Watchdog);
+        assert_eq!(ResetReason::from_resetreas(SREQ), ResetReason::SoftReset);
+        assert_eq!(ResetReason::from_resetreas(LOCKUP), ResetReason::Lockup);
+        // GPIO, LPCOMP, debug interface, NFC and VBUS.
+        for bit in 16..21 {
+            assert_eq!(ResetReason::from_resetreas(1 << bit), ResetReason::WakeUp);
+        }
+        // Bits the classification does not know about, like the reserved ones.
+        for bit in (4..16).chain(21..32) {
+            assert_eq!(
+                ResetReason::from_resetreas(1 << bit),
+                ResetReason::PowerOn,
+                "bit {}",
+                bit
+            );
+        }
+    }
+
+    #[test]
+    fn the_most_severe_of_several_bits_wins() {
+        let by_severity = [LOCKUP, DOG, SREQ, RESETPIN, 1 << 16];
+        for (i, bit) in by_severity.iter().enumerate() {
+            let expected = ResetReason::from_resetreas(*bit);
+            // Together with any of the less severe ones.
+            for less in &by_severity[i..] {
+                assert_eq!(ResetReason::from_resetreas(bit | less), expected);
+            }
+            let all_less = by_severity[i..].iter().fold(0, |bits, b| bits | b);
+            assert_eq!(ResetReason::from_resetreas(all_less), expected);
+        }
+        assert_eq!(ResetReason::from_resetreas(!0), ResetReason::Lockup);
+    }
+
+    #[test]
+    fn only_resets_hinting_at_a_firmware_problem_distrust_the_warm_state() {
+        let table = [
+            (ResetReason::PowerOn, true, false),
+            (ResetReason::Pin, true, false),
+            (ResetReason::WakeUp, true, false),
+            (ResetReason::SoftReset, true, true),
+            (ResetReason::Watchdog, false, true),
+            (ResetReason::Lockup, false, true),
+        ];
+        for (reason, warm_start, unexpected) in table.iter() {
+            assert_eq!(
+                reason.policy(),
+                ResetPolicy {
+                    warm_start: *warm_start,
+                    unexpected: *unexpected,
+                },
+                "{:?}",
+                reason
+            );
+        }
+    }
+}
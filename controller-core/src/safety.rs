@@ -0,0 +1,463 @@
+//! Hard safety limits which are enforced on the heater independently of the PID.
+//!
+//! The limits are compile-time constants on purpose: neither a gain change nor a new setpoint
+//! can move them, and every limit only re-enables the heater once its own hysteresis has been
+//! cleared.
+
+/// The boiler is never heated above this temperature (°C), no matter what the PID asks for.
+const MAX_TEMP: f32 = 145.0;
+/// Once tripped, the over-temperature cutoff only clears below `MAX_TEMP - MAX_TEMP_HYSTERESIS`.
+const MAX_TEMP_HYSTERESIS: f32 = 10.0;
+
+/// The window (in ms) over which the rate of rise is measured.
+const RISE_WINDOW_MS: u32 = 1000;
+/// The maximum temperature rise (°C) per `RISE_WINDOW_MS` before the heater is cut off.
+const MAX_RISE: f32 = 2.0;
+/// A full window with a rise below `MAX_RISE - MAX_RISE_HYSTERESIS` clears the rate cutoff.
+const MAX_RISE_HYSTERESIS: f32 = 1.0;
+
+/// A reading which moves less than this (°C) is considered to be the same value.
+const STUCK_EPSILON: f32 = 0.01;
+/// Accumulated heater on-time (in ms) after which an unchanged reading counts as stuck.
+const STUCK_ON_TIME_MS: u32 = 20_000;
+/// Once tripped, the stuck cutoff only clears after the reading moved this far (°C) away from
+/// the value it got stuck at, a dead sensor jittering by a few bits does not count.
+const STUCK_HYSTERESIS: f32 = 1.0;
+
+/// The limits enforced by the [`Cutoff`].
+#[derive(Clone, Copy)]
+pub struct SafetyLimits {
+    max_temp: f32,
+    max_temp_hysteresis: f32,
+    rise_window_ms: u32,
+    max_rise: f32,
+    max_rise_hysteresis: f32,
+    stuck_epsilon: f32,
+    stuck_on_time_ms: u32,
+    stuck_hysteresis: f32,
+}
+
+impl SafetyLimits {
+    /// The limits for the Silvia boiler.
+    ///
+    /// There is deliberately no way to construct other limits at runtime.
+    pub const fn boiler() -> Self {
+        Self {
+            max_temp: MAX_TEMP,
+            max_temp_hysteresis: MAX_TEMP_HYSTERESIS,
+            rise_window_ms: RISE_WINDOW_MS,
+            max_rise: MAX_RISE,
+            max_rise_hysteresis: MAX_RISE_HYSTERESIS,
+            stuck_epsilon: STUCK_EPSILON,
+            stuck_on_time_ms: STUCK_ON_TIME_MS,
+            stuck_hysteresis: STUCK_HYSTERESIS,
+        }
+    }
+}
+
+/// Why the heater got cut off.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CutoffReason {
+    /// The boiler is above the absolute maximum temperature.
+    OverTemperature,
+    /// The boiler heats up faster than physically plausible.
+    RateOfRise,
+    /// The reading did not change while the heater was on for a long time.
+    StuckSensor,
+}
+
+/// Tracks the safety limits and decides if the heater must be forced off.
+pub struct Cutoff {
+    limits: SafetyLimits,
+    over_temp: bool,
+    rate_of_rise: bool,
+    stuck: bool,
+    window_elapsed_ms: u32,
+    window_start_temp: Option<f32>,
+    stuck_temp: Option<f32>,
+    stuck_on_time_ms: u32,
+}
+
+impl Cutoff {
+    pub fn new(limits: SafetyLimits) -> Self {
+        Self {
+            limits,
+            over_temp: false,
+            rate_of_rise: false,
+            stuck: false,
+            window_elapsed_ms: 0,
+            window_start_temp: None,
+            stuck_temp: None,
+            stuck_on_time_ms: 0,
+        }
+    }
+
+    /// Feeds the current temperature into all limits.
+    ///
+    /// `elapsed_ms` is the time since the last check and `heater_on` tells if the heater was
+    /// on during that time. Returns the reason if the heater must stay off.
+    pub fn check(
+        &mut self,
+        temperature: f32,
+        elapsed_ms: u32,
+        heater_on: bool,
+    ) -> Option<CutoffReason> {
+        self.check_over_temp(temperature);
+        self.check_rate_of_rise(temperature, elapsed_ms);
+        self.check_stuck(temperature, elapsed_ms, heater_on);
+        self.tripped()
+    }
+
+    /// Returns the currently active cutoff, if any.
+    pub fn tripped(&self) -> Option<CutoffReason> {
+        if self.over_temp {
+            Some(CutoffReason::OverTemperature)
+        } else if self.rate_of_rise {
+            Some(CutoffReason::RateOfRise)
+        } else if self.stuck {
+            Some(CutoffReason::StuckSensor)
+        } else {
+            None
+        }
+    }
+
+    fn check_over_temp(&mut self, temperature: f32) {
+        if temperature >= self.limits.max_temp {
+            self.over_temp = true;
+        } else if temperature <= self.limits.max_temp - self.limits.max_temp_hysteresis {
+            self.over_temp = false;
+        }
+    }
+
+    fn check_rate_of_rise(&mut self, temperature: f32, elapsed_ms: u32) {
+        let start = match self.window_start_temp {
+            Some(start) => start,
+            None => {
+                self.window_start_temp = Some(temperature);
+                self.window_elapsed_ms = 0;
+                return;
+            }
+        };
+
+        self.window_elapsed_ms += elapsed_ms;
+        if self.window_elapsed_ms < self.limits.rise_window_ms {
+            return;
+        }
+
+        let rise = temperature - start;
+        if rise > self.limits.max_rise {
+            self.rate_of_rise = true;
+        } else if rise <= self.limits.max_rise - self.limits.max_rise_hysteresis {
+            self.rate_of_rise = false;
+        }
+
+        self.window_start_temp = Some(temperature);
+        self.window_elapsed_ms = 0;
+    }
+
+    fn check_stuck(&mut self, temperature: f32, elapsed_ms: u32, heater_on: bool) {
+        let moved = match self.stuck_temp {
+            Some(stuck_temp) if temperature > stuck_temp => temperature - stuck_temp,
+            Some(stuck_temp) => stuck_temp - temperature,
+            None => {
+                self.stuck_temp = Some(temperature);
+                return;
+            }
+        };
+
+        if self.stuck {
+            // The reference stays where the sensor got stuck, so a slow drift adds up.
+            if moved > self.limits.stuck_hysteresis {
+                self.stuck_temp = Some(temperature);
+                self.stuck_on_time_ms = 0;
+                self.stuck = false;
+            }
+            return;
+        }
+
+        if moved > self.limits.stuck_epsilon {
+            self.stuck_temp = Some(temperature);
+            self.stuck_on_time_ms = 0;
+            return;
+        }
+
+        if heater_on {
+            self.stuck_on_time_ms = self.stuck_on_time_ms.saturating_add(elapsed_ms);
+        }
+        if self.stuck_on_time_ms >= self.limits.stuck_on_time_ms {
+            self.stuck = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Heater::control` checks the limits every 20ms.
+    const CHECK_MS: u32 = 20;
+    /// The sensor only delivers a new reading every 500ms, the checks in between see the same.
+    const SENSOR_MS: u32 = 500;
+    const AMBIENT: f32 = 22.0;
+    /// How fast (°C/s) the boiler heats with the heater on all the time.
+    const HEATING_RATE: f32 = 0.6;
+    /// The share of the difference to the ambient temperature lost every second.
+    const LOSS: f32 = 0.002;
+
+    /// A crude boiler, roughly a Silvia, with a sensor which can get stuck.
+    struct SimulatedBoiler {
+        temp: f32,
+        /// Once set, the sensor reports this no matter what the boiler does.
+        stuck_at: Option<f32>,
+        /// The last value the sensor delivered.
+        reading: f32,
+        since_reading_ms: u32,
+    }
+
+    impl SimulatedBoiler {
+        fn new(temp: f32) -> Self {
+            Self {
+                temp,
+                stuck_at: None,
+                reading: temp,
+                since_reading_ms: 0,
+            }
+        }
+
+        fn step(&mut self, heater_on: bool, elapsed_ms: u32) {
+            let dt = elapsed_ms as f32 / 1000.0;
+            let heating = if heater_on { HEATING_RATE } else { 0.0 };
+            self.temp += (heating - LOSS * (self.temp - AMBIENT)) * dt;
+
+            self.since_reading_ms += elapsed_ms;
+            if self.since_reading_ms >= SENSOR_MS {
+                self.since_reading_ms = 0;
+                self.reading = self.stuck_at.unwrap_or(self.temp);
+            }
+        }
+    }
+
+    /// Runs the boiler for `duration_ms`, checking the limits every [`CHECK_MS`] and heating
+    /// whenever `heat` asks for it and the cutoff allows it. Returns the cutoff of every check.
+    fn run(
+        cutoff: &mut Cutoff,
+        boiler: &mut SimulatedBoiler,
+        duration_ms: u32,
+        heat: bool,
+    ) -> Vec<Option<CutoffReason>> {
+        let mut heater_on = heat && cutoff.tripped().is_none();
+        (0..duration_ms / CHECK_MS)
+            .map(|_| {
+                boiler.step(heater_on, CHECK_MS);
+                let tripped = cutoff.check(boiler.reading, CHECK_MS, heater_on);
+                heater_on = heat && tripped.is_none();
+                tripped
+            })
+            .collect()
+    }
+
+    /// Checks the same reading for `duration_ms`, returning the last result.
+    fn hold(
+        cutoff: &mut Cutoff,
+        temp: f32,
+        duration_ms: u32,
+        heater_on: bool,
+    ) -> Option<CutoffReason> {
+        (0..duration_ms / CHECK_MS)
+            .map(|_| cutoff.check(temp, CHECK_MS, heater_on))
+            .last()
+            .unwrap()
+    }
+
+    fn cutoff() -> Cutoff {
+        Cutoff::new(SafetyLimits::boiler())
+    }
+
+    #[test]
+    fn heating_up_from_cold_never_trips() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(AMBIENT);
+
+        let checks = run(&mut cutoff, &mut boiler, 120_000, true);
+
+        assert!(checks.iter().all(Option::is_none));
+        assert!(boiler.temp > 80.0);
+    }
+
+    #[test]
+    fn runaway_heater_is_held_below_max_temp() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(130.0);
+
+        let checks = run(&mut cutoff, &mut boiler, 300_000, true);
+
+        assert!(checks.contains(&Some(CutoffReason::OverTemperature)));
+        // Off at the limit, then it has to cool down by the hysteresis before heating again.
+        assert!(boiler.temp < MAX_TEMP + 0.5);
+    }
+
+    #[test]
+    fn over_temperature_clears_below_hysteresis() {
+        let mut cutoff = cutoff();
+
+        assert_eq!(
+            cutoff.check(MAX_TEMP, CHECK_MS, false),
+            Some(CutoffReason::OverTemperature)
+        );
+        // Slowly cooling, so the other limits stay quiet.
+        let mut temp = MAX_TEMP;
+        while temp > MAX_TEMP - MAX_TEMP_HYSTERESIS + 0.5 {
+            temp -= 0.5;
+            assert_eq!(
+                hold(&mut cutoff, temp, SENSOR_MS, false),
+                Some(CutoffReason::OverTemperature)
+            );
+        }
+        assert_eq!(
+            cutoff.check(MAX_TEMP - MAX_TEMP_HYSTERESIS, CHECK_MS, false),
+            None
+        );
+    }
+
+    #[test]
+    fn rate_of_rise_trips_and_needs_a_calm_window_to_clear() {
+        let mut cutoff = cutoff();
+
+        // The window starts at the first check, 50 checks make a second.
+        assert_eq!(cutoff.check(90.0, CHECK_MS, true), None);
+        assert_eq!(hold(&mut cutoff, 90.0, SENSOR_MS - CHECK_MS, true), None);
+        assert_eq!(hold(&mut cutoff, 91.5, SENSOR_MS - CHECK_MS, true), None);
+        assert_eq!(
+            hold(&mut cutoff, 93.0, 2 * CHECK_MS, true),
+            Some(CutoffReason::RateOfRise)
+        );
+
+        // A rise between the clear and the trip level keeps it tripped.
+        hold(&mut cutoff, 93.7, SENSOR_MS, false);
+        assert_eq!(
+            hold(&mut cutoff, 94.5, SENSOR_MS, false),
+            Some(CutoffReason::RateOfRise)
+        );
+
+        hold(&mut cutoff, 94.6, SENSOR_MS, false);
+        assert_eq!(hold(&mut cutoff, 94.7, SENSOR_MS, false), None);
+    }
+
+    #[test]
+    fn stuck_sensor_trips_after_the_heater_ran_long_enough() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(60.0);
+        // Ends right after a fresh reading.
+        run(&mut cutoff, &mut boiler, 10_000, true);
+
+        boiler.stuck_at = Some(boiler.reading);
+        let checks_to_trip = (STUCK_ON_TIME_MS / CHECK_MS) as usize;
+        let checks = run(&mut cutoff, &mut boiler, STUCK_ON_TIME_MS + CHECK_MS, true);
+
+        assert!(checks[..checks_to_trip - 1].iter().all(Option::is_none));
+        assert_eq!(checks[checks_to_trip - 1], Some(CutoffReason::StuckSensor));
+        assert_eq!(checks[checks_to_trip], Some(CutoffReason::StuckSensor));
+    }
+
+    #[test]
+    fn unchanged_reading_with_the_heater_off_is_not_stuck() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(AMBIENT);
+        boiler.stuck_at = Some(AMBIENT);
+
+        let checks = run(&mut cutoff, &mut boiler, 300_000, false);
+
+        assert!(checks.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn stuck_sensor_stays_tripped_while_it_only_jitters() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(90.0);
+        boiler.stuck_at = Some(90.0);
+        let checks = run(&mut cutoff, &mut boiler, 30_000, true);
+        assert_eq!(checks.last(), Some(&Some(CutoffReason::StuckSensor)));
+
+        for (i, noise) in [0.05, -0.1, 0.3, -0.5, 0.8, -0.9]
+            .iter()
+            .cycle()
+            .take(60)
+            .enumerate()
+        {
+            assert_eq!(
+                hold(&mut cutoff, 90.0 + noise, SENSOR_MS, false),
+                Some(CutoffReason::StuckSensor),
+                "cleared by noise at reading {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn stuck_sensor_clears_once_the_reading_really_moves() {
+        let mut cutoff = cutoff();
+        let mut boiler = SimulatedBoiler::new(90.0);
+        boiler.stuck_at = Some(90.0);
+        run(&mut cutoff, &mut boiler, 30_000, true);
+
+        // The sensor comes back while the boiler cools with the heater off.
+        boiler.temp = 90.0;
+        boiler.stuck_at = None;
+        let checks = run(&mut cutoff, &mut boiler, 30_000, false);
+
+        let cleared = checks
+            .iter()
+            .position(Option::is_none)
+            .expect("the cutoff never cleared");
+        // Cooling by the hysteresis takes a few seconds, the first bit of movement is not enough.
+        assert!(cleared as u32 * CHECK_MS > 5_000);
+        assert!(checks[..cleared]
+            .iter()
+            .all(|check| *check == Some(CutoffReason::StuckSensor)));
+        assert!(checks[cleared..].iter().all(Option::is_none));
+    }
+
+    /// When (ms) and why a cutoff trips.
+    type Trip = Option<(u32, CutoffReason)>;
+
+    /// When the cutoff trips within a minute of heating, checking every `check_ms` a reading
+    /// which follows `temp` and is updated every [`SENSOR_MS`].
+    fn trip(check_ms: u32, temp: impl Fn(f32) -> f32) -> Trip {
+        let mut cutoff = cutoff();
+        (0..=60_000).step_by(check_ms as usize).find_map(|time| {
+            let read_at = time / SENSOR_MS * SENSOR_MS;
+            let reading = temp(read_at as f32 / 1000.0);
+            cutoff
+                .check(reading, check_ms, true)
+                .map(|reason| (time, reason))
+        })
+    }
+
+    /// Asserts that the cutoff trips the same at every checking cadence.
+    fn trips_at_every_cadence(temp: fn(f32) -> f32, expected: Trip) {
+        for check_ms in [CHECK_MS, 100, 250, SENSOR_MS].iter() {
+            assert_eq!(
+                trip(*check_ms, temp),
+                expected,
+                "checking every {}ms",
+                check_ms
+            );
+        }
+    }
+
+    #[test]
+    fn limits_do_not_depend_on_how_often_they_are_checked() {
+        // The temperatures are over the time in seconds, the first is a normal heat up.
+        trips_at_every_cadence(|s| 60.0 + 0.6 * s, None);
+        trips_at_every_cadence(|s| 90.0 + 3.0 * s, Some((1000, CutoffReason::RateOfRise)));
+        trips_at_every_cadence(
+            |s| 140.0 + 0.5 * s,
+            Some((10_000, CutoffReason::OverTemperature)),
+        );
+        trips_at_every_cadence(
+            |_| 90.0,
+            Some((STUCK_ON_TIME_MS, CutoffReason::StuckSensor)),
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Checks the sensor, the heater path and the display once after boot, before regular control
+//! takes over.
+//!
+//! The sensor has to deliver a few plausible and steady readings first. Then the heater is
+//! pulsed and the boiler has to show a small rise within a window, which proves the SSR and the
+//! heating element are connected. Control stays inhibited if any of the checks fails.
+
+use crate::clock::Millis;
+
+/// Readings the sensor has to deliver before it counts as plausible.
+const SENSOR_SAMPLES: u8 = 5;
+/// Failed reads tolerated during the sensor check.
+const MAX_READ_FAILURES: u8 = 5;
+/// Plausible boiler temperatures (°C), a machine standing in a cold room included.
+const MIN_TEMP: f32 = 0.0;
+const MAX_TEMP: f32 = 140.0;
+/// How far (°C) the readings may spread before they count as unstable.
+const MAX_SPREAD: f32 = 1.0;
+
+/// How long (in ms) the heater is switched on for the pulse.
+const PULSE_MS: Millis = 5_000;
+/// How long (in ms) after the start of the pulse the rise has to show up.
+const RISE_WINDOW_MS: Millis = 30_000;
+/// The rise (°C) which proves the heater works.
+const MIN_RISE: f32 = 0.5;
+/// Above this temperature (°C) the pulse is skipped, the boiler is obviously heated.
+const SKIP_PULSE_ABOVE: f32 = 80.0;
+
+/// Why a check failed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Failure {
+    /// The sensor did not deliver enough readings.
+    NoReading,
+    /// A reading was outside of the plausible range.
+    OutOfRange,
+    /// The readings jumped around although nothing heats.
+    Unstable,
+    /// The heater pulse did not warm up the boiler.
+    NoRise,
+    /// The display did not respond to the init sequence.
+    InitFailed,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Check {
+    Pending,
+    Passed,
+    /// Not needed on this boot.
+    Skipped,
+    Failed(Failure),
+}
+
+impl Check {
+    fn is_done(self) -> bool {
+        self != Check::Pending
+    }
+
+    fn is_failed(self) -> bool {
+        matches!(self, Check::Failed(_))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfTestReport {
+    pub sensor: Check,
+    pub heater: Check,
+    pub display: Check,
+}
+
+impl SelfTestReport {
+    /// Nothing has been checked yet.
+    pub const fn pending() -> Self {
+        Self {
+            sensor: Check::Pending,
+            heater: Check::Pending,
+            display: Check::Pending,
+        }
+    }
+
+    /// All checks finished, none of them failed.
+    pub fn passed(&self) -> bool {
+        self.is_done() && !self.failed()
+    }
+
+    /// At least one check failed.
+    pub fn failed(&self) -> bool {
+        self.sensor.is_failed() || self.heater.is_failed() || self.display.is_failed()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sensor.is_done() && self.heater.is_done() && self.display.is_done()
+    }
+}
+
+enum Phase {
+    Sensor {
+        samples: u8,
+        failures: u8,
+        min: f32,
+        max: f32,
+    },
+    /// `baseline` is the temperature before the pulse, which started at `started_at`.
+    Heater {
+        baseline: f32,
+        started_at: Millis,
+    },
+    Done,
+}
+
+pub struct SelfTest {
+    phase: Phase,
+    report: SelfTestReport,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::Sensor {
+                samples: 0,
+                failures: 0,
+                min: f32::MAX,
+                max: f32::MIN,
+            },
+            report: SelfTestReport::pending(),
+        }
+    }
+
+    /// Feeds the outcome of a sensor read, `None` if it failed.
+    pub fn sample(&mut self, now: Millis, temp: Option<f32>) {
+        match self.phase {
+            Phase::Sensor {
+                ref mut samples,
+                ref mut failures,
+                ref mut min,
+                ref mut max,
+            } => {
+                let temp = match temp {
+                    Some(temp) => temp,
+                    None => {
+                        *failures += 1;
+                        if *failures > MAX_READ_FAILURES {
+                            self.fail_sensor(Failure::NoReading);
+                        }
+                        return;
+                    }
+                };
+                if !(MIN_TEMP..=MAX_TEMP).contains(&temp) {
+                    self.fail_sensor(Failure::OutOfRange);
+                    return;
+                }
+
+                *samples += 1;
+                *min = min.min(temp);
+                *max = max.max(temp);
+                if *max - *min > MAX_SPREAD {
+                    self.fail_sensor(Failure::Unstable);
+                } else if *samples >= SENSOR_SAMPLES {
+                    self.report.sensor = Check::Passed;
+                    if temp > SKIP_PULSE_ABOVE {
+                        self.report.heater = Check::Skipped;
+                        self.phase = Phase::Done;
+                    } else {
+                        self.phase = Phase::Heater {
+                            baseline: *max,
+                            started_at: now,
+                        };
+                    }
+                }
+            }
+            Phase::Heater {
+                baseline,
+                started_at,
+            } => {
+                if matches!(temp, Some(temp) if temp - baseline >= MIN_RISE) {
+                    self.report.heater = Check::Passed;
+                    self.phase = Phase::Done;
+                } else if now.saturating_sub(started_at) > RISE_WINDOW_MS {
+                    self.report.heater = Check::Failed(Failure::NoRise);
+                    self.phase = Phase::Done;
+                }
+            }
+            Phase::Done => {}
+        }
+    }
+
+    /// Records if the display came up.
+    pub fn display_initialized(&mut self, ok: bool) {
+        self.report.display = if ok {
+            Check::Passed
+        } else {
+            Check::Failed(Failure::InitFailed)
+        };
+    }
+
+    /// `Some(true)` while the heater has to be pulsed, `Some(false)` while it has to stay off
+    /// and `None` once regular control may take over.
+    pub fn heater_demand(&self, now: Millis) -> Option<bool> {
+        match self.phase {
+            Phase::Heater { started_at, .. } => Some(now.saturating_sub(started_at) < PULSE_MS),
+            Phase::Sensor { .. } => Some(false),
+            Phase::Done if self.report.failed() => Some(false),
+            Phase::Done => None,
+        }
+    }
+
+    pub fn report(&self) -> SelfTestReport {
+        self.report
+    }
+
+    fn fail_sensor(&mut self, failure: Failure) {
+        self.report.sensor = Check::Failed(failure);
+        // Without a sensor the heater cannot be checked either.
+        self.report.heater = Check::Skipped;
+        self.phase = Phase::Done;
+    }
+}
+
+impl Default for SelfTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the same reading every second from `from` until before `to`.
+    fn feed(test: &mut SelfTest, from: Millis, to: Millis, temp: Option<f32>) {
+        for now in (from..to).step_by(1000) {
+            test.sample(now, temp);
+        }
+    }
+
+    /// A self test which got through the sensor check at 20°C, the pulse started at 4s.
+    fn pulsing() -> SelfTest {
+        let mut test = SelfTest::new();
+        test.display_initialized(true);
+        feed(&mut test, 0, 5000, Some(20.0));
+        assert_eq!(test.report().sensor, Check::Passed);
+        test
+    }
+
+    #[test]
+    fn passes_once_the_pulse_warms_the_boiler() {
+        let mut test = SelfTest::new();
+        assert_eq!(test.report(), SelfTestReport::pending());
+        test.display_initialized(true);
+
+        // The heater stays off until the sensor has been checked.
+        feed(&mut test, 0, 4000, Some(20.0));
+        assert_eq!(test.heater_demand(3000), Some(false));
+        assert_eq!(test.report().sensor, Check::Pending);
+
+        test.sample(4000, Some(20.4));
+        assert_eq!(test.report().sensor, Check::Passed);
+        assert_eq!(test.heater_demand(4000), Some(true));
+        assert_eq!(test.heater_demand(8999), Some(true));
+        assert_eq!(test.heater_demand(9000), Some(false));
+
+        // The rise counts from the highest reading of the sensor check.
+        test.sample(12_000, Some(20.8));
+        assert_eq!(test.report().heater, Check::Pending);
+        test.sample(13_000, Some(20.9));
+        assert_eq!(test.report().heater, Check::Passed);
+        assert!(test.report().passed());
+        assert_eq!(test.heater_demand(13_000), None);
+    }
+
+    #[test]
+    fn implausible_readings_fail_the_sensor() {
+        for temp in [-5.0, 150.0, f32::NAN].iter() {
+            let mut test = SelfTest::new();
+            test.display_initialized(true);
+            test.sample(0, Some(20.0));
+            test.sample(1000, Some(*temp));
+
+            let report = test.report();
+            assert_eq!(report.sensor, Check::Failed(Failure::OutOfRange));
+            // The heater cannot be checked without a sensor.
+            assert_eq!(report.heater, Check::Skipped);
+            assert!(report.is_done() && report.failed());
+            assert_eq!(test.heater_demand(1000), Some(false));
+        }
+    }
+
+    #[test]
+    fn a_sensor_which_does_not_deliver_fails() {
+        let mut test = SelfTest::new();
+        feed(&mut test, 0, 5000, None);
+        assert_eq!(test.report().sensor, Check::Pending);
+        test.sample(5000, None);
+        assert_eq!(test.report().sensor, Check::Failed(Failure::NoReading));
+    }
+
+    #[test]
+    fn a_few_failed_reads_are_tolerated() {
+        let mut test = SelfTest::new();
+        for now in (0..10_000).step_by(1000) {
+            let temp = if now < 5000 { None } else { Some(20.0) };
+            test.sample(now, temp);
+        }
+        assert_eq!(test.report().sensor, Check::Passed);
+    }
+
+    #[test]
+    fn unstable_readings_fail_the_sensor() {
+        let mut test = SelfTest::new();
+        test.sample(0, Some(20.0));
+        test.sample(1000, Some(21.0));
+        assert_eq!(test.report().sensor, Check::Pending);
+        test.sample(2000, Some(19.9));
+        assert_eq!(test.report().sensor, Check::Failed(Failure::Unstable));
+        assert_eq!(test.report().heater, Check::Skipped);
+    }
+
+    #[test]
+    fn no_rise_within_the_window_fails_the_heater() {
+        let mut test = pulsing();
+        feed(&mut test, 5000, 34_000, Some(20.4));
+        assert_eq!(test.report().heater, Check::Pending);
+
+        test.sample(35_000, Some(20.4));
+        assert_eq!(test.report().heater, Check::Failed(Failure::NoRise));
+        assert!(test.report().failed());
+        // Control stays inhibited.
+        assert_eq!(test.heater_demand(35_000), Some(false));
+
+        // A late rise does not change the verdict.
+        test.sample(36_000, Some(30.0));
+        assert_eq!(test.report().heater, Check::Failed(Failure::NoRise));
+    }
+
+    #[test]
+    fn failed_reads_during_the_pulse_do_not_count_as_a_rise() {
+        let mut test = pulsing();
+        feed(&mut test, 5000, 40_000, None);
+        assert_eq!(test.report().heater, Check::Failed(Failure::NoRise));
+    }
+
+    #[test]
+    fn a_hot_boiler_skips_the_pulse() {
+        let mut test = SelfTest::new();
+        test.display_initialized(true);
+        feed(&mut test, 0, 5000, Some(90.0));
+
+        let report = test.report();
+        assert_eq!(report.sensor, Check::Passed);
+        assert_eq!(report.heater, Check::Skipped);
+        assert!(report.passed());
+        assert_eq!(test.heater_demand(5000), None);
+    }
+
+    #[test]
+    fn a_failed_display_fails_the_self_test() {
+        let mut test = SelfTest::new();
+        feed(&mut test, 0, 5000, Some(90.0));
+        assert!(!test.report().is_done());
+
+        test.display_initialized(false);
+        let report = test.report();
+        assert_eq!(report.display, Check::Failed(Failure::InitFailed));
+        assert!(report.is_done() && report.failed() && !report.passed());
+        assert_eq!(test.heater_demand(5000), Some(false));
+    }
+}
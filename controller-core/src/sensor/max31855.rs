@@ -0,0 +1,156 @@
+//! A type K thermocouple read through the MAX31855 or the older MAX6675 over SPI.
+
+use super::{SpiSensorError, TemperatureSensor};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+#[derive(Clone, Copy)]
+pub enum ThermocoupleChip {
+    /// 32 bit frame, 14 bit signed temperature and three fault bits.
+    Max31855,
+    /// 16 bit frame, 12 bit unsigned temperature and an open circuit bit.
+    Max6675,
+}
+
+pub struct Thermocouple<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    chip: ThermocoupleChip,
+}
+
+impl<SPI, CS> Thermocouple<SPI, CS>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+{
+    pub fn new(spi: SPI, cs: CS, chip: ThermocoupleChip) -> Self {
+        Self { spi, cs, chip }
+    }
+
+    fn read_frame(&mut self, buf: &mut [u8]) -> Result<(), SpiSensorError> {
+        self.cs.set_low().map_err(|_| SpiSensorError::Pin)?;
+        let result = self
+            .spi
+            .transfer(buf)
+            .map(|_| ())
+            .map_err(|_| SpiSensorError::Spi);
+        self.cs.set_high().map_err(|_| SpiSensorError::Pin)?;
+        result
+    }
+}
+
+impl<SPI, CS> TemperatureSensor for Thermocouple<SPI, CS>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+{
+    type Error = SpiSensorError;
+
+    fn read<D: DelayUs<u8>>(&mut self, _delay: &mut D) -> Result<f32, Self::Error> {
+        match self.chip {
+            ThermocoupleChip::Max31855 => {
+                let mut frame = [0u8; 4];
+                self.read_frame(&mut frame)?;
+                let raw = u32::from_be_bytes(frame);
+
+                if raw & 0x0001_0000 != 0 {
+                    return Err(SpiSensorError::Fault((raw & 0x07) as u8));
+                }
+
+                // The upper 14 bits are the signed temperature in 0.25°C steps.
+                let temp = (raw as i32) >> 18;
+                Ok(temp as f32 * 0.25)
+            }
+            ThermocoupleChip::Max6675 => {
+                let mut frame = [0u8; 2];
+                self.read_frame(&mut frame)?;
+                let raw = u16::from_be_bytes(frame);
+
+                if raw & 0x0004 != 0 {
+                    return Err(SpiSensorError::Fault(0x01));
+                }
+
+                Ok((raw >> 3) as f32 * 0.25)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::mock::{Chip, ChipSelect, NoDelay, Spi};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn sensor(chip: ThermocoupleChip) -> (Thermocouple<Spi, ChipSelect>, Rc<RefCell<Chip>>) {
+        let bus = Chip::new();
+        let sensor = Thermocouple::new(Spi(bus.clone()), ChipSelect(bus.clone()), chip);
+        (sensor, bus)
+    }
+
+    fn set_frame(chip: &RefCell<Chip>, frame: &[u8]) {
+        chip.borrow_mut().registers[..frame.len()].copy_from_slice(frame);
+    }
+
+    /// A MAX31855 frame with the thermocouple temperature in 0.25°C steps and a cold junction of
+    /// 25°C in the lower half, which is ignored.
+    fn max31855_frame(quarters: i32) -> [u8; 4] {
+        let cold_junction = (25 * 16) << 4;
+        (((quarters << 18) | cold_junction) as u32).to_be_bytes()
+    }
+
+    #[test]
+    fn converts_max31855_readings() {
+        let (mut sensor, chip) = sensor(ThermocoupleChip::Max31855);
+
+        for &(quarters, temp) in &[(0, 0.0), (100, 25.0), (375, 93.75), (-41, -10.25)] {
+            set_frame(&chip, &max31855_frame(quarters));
+            assert_eq!(sensor.read(&mut NoDelay), Ok(temp));
+        }
+        assert_eq!(chip.borrow().unselected, 0);
+    }
+
+    #[test]
+    fn reports_max31855_faults() {
+        let (mut sensor, chip) = sensor(ThermocoupleChip::Max31855);
+
+        // Fault flag plus short to VCC, short to GND and open circuit.
+        for &bits in &[0x04, 0x02, 0x01] {
+            let frame = u32::from_be_bytes(max31855_frame(375)) | 0x0001_0000 | bits;
+            set_frame(&chip, &frame.to_be_bytes());
+            assert_eq!(
+                sensor.read(&mut NoDelay),
+                Err(SpiSensorError::Fault(bits as u8))
+            );
+        }
+    }
+
+    #[test]
+    fn converts_max6675_readings() {
+        let (mut sensor, chip) = sensor(ThermocoupleChip::Max6675);
+
+        for &(quarters, temp) in &[(0u16, 0.0), (372, 93.0), (1023, 255.75)] {
+            set_frame(&chip, &(quarters << 3).to_be_bytes());
+            assert_eq!(sensor.read(&mut NoDelay), Ok(temp));
+        }
+    }
+
+    #[test]
+    fn reports_an_open_max6675_thermocouple() {
+        let (mut sensor, chip) = sensor(ThermocoupleChip::Max6675);
+        set_frame(&chip, &((372u16 << 3) | 0x04).to_be_bytes());
+
+        assert_eq!(sensor.read(&mut NoDelay), Err(SpiSensorError::Fault(0x01)));
+    }
+
+    #[test]
+    fn reports_bus_failures() {
+        let (mut sensor, chip) = sensor(ThermocoupleChip::Max31855);
+        chip.borrow_mut().broken = true;
+
+        assert_eq!(sensor.read(&mut NoDelay), Err(SpiSensorError::Spi));
+        assert!(!chip.borrow().is_selected());
+    }
+}
@@ -0,0 +1,241 @@
+//! A PT100 or PT1000 RTD read through the MAX31865 over SPI.
+
+use super::{SpiSensorError, TemperatureSensor};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_RTD_MSB: u8 = 0x01;
+const REG_FAULT_STATUS: u8 = 0x07;
+const WRITE: u8 = 0x80;
+
+const CONFIG_VBIAS: u8 = 0x80;
+const CONFIG_AUTO_CONVERSION: u8 = 0x40;
+const CONFIG_THREE_WIRE: u8 = 0x10;
+const CONFIG_FAULT_CLEAR: u8 = 0x02;
+const CONFIG_FILTER_50HZ: u8 = 0x01;
+
+/// Callendar-Van Dusen coefficients for platinum RTDs (IEC 60751).
+const CVD_A: f32 = 3.9083e-3;
+const CVD_B: f32 = -5.775e-7;
+
+#[derive(Clone, Copy)]
+pub enum RtdType {
+    Pt100,
+    Pt1000,
+}
+
+impl RtdType {
+    fn nominal(self) -> f32 {
+        match self {
+            RtdType::Pt100 => 100.0,
+            RtdType::Pt1000 => 1000.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Wiring {
+    TwoOrFourWire,
+    ThreeWire,
+}
+
+pub struct Max31865<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    rtd: RtdType,
+    reference_resistor: f32,
+    config: u8,
+}
+
+impl<SPI, CS, E> Max31865<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// Creates the sensor and puts the chip into automatic conversion mode.
+    ///
+    /// The reference resistor is the one on the breakout board, usually 430Ω for a PT100
+    /// and 4300Ω for a PT1000.
+    pub fn new(
+        spi: SPI,
+        cs: CS,
+        rtd: RtdType,
+        wiring: Wiring,
+        reference_resistor: f32,
+    ) -> Result<Self, SpiSensorError> {
+        let mut config = CONFIG_VBIAS | CONFIG_AUTO_CONVERSION | CONFIG_FILTER_50HZ;
+        if let Wiring::ThreeWire = wiring {
+            config |= CONFIG_THREE_WIRE;
+        }
+
+        let mut sensor = Self {
+            spi,
+            cs,
+            rtd,
+            reference_resistor,
+            config,
+        };
+        sensor.write_register(REG_CONFIG, config | CONFIG_FAULT_CLEAR)?;
+        Ok(sensor)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), SpiSensorError> {
+        self.cs.set_low().map_err(|_| SpiSensorError::Pin)?;
+        let result = self
+            .spi
+            .write(&[register | WRITE, value])
+            .map_err(|_| SpiSensorError::Spi);
+        self.cs.set_high().map_err(|_| SpiSensorError::Pin)?;
+        result
+    }
+
+    fn read_registers(&mut self, register: u8, buf: &mut [u8]) -> Result<(), SpiSensorError> {
+        self.cs.set_low().map_err(|_| SpiSensorError::Pin)?;
+        let result = self
+            .spi
+            .write(&[register])
+            .and_then(|_| self.spi.transfer(buf).map(|_| ()))
+            .map_err(|_| SpiSensorError::Spi);
+        self.cs.set_high().map_err(|_| SpiSensorError::Pin)?;
+        result
+    }
+
+    fn resistance_to_celsius(&self, resistance: f32) -> f32 {
+        // Solves R / R0 = 1 + A·t + B·t² with Newton's method, starting from the linear
+        // approximation. The closed form needs a square root, and micromath's is off by more
+        // than a degree at brew temperature.
+        let ratio = resistance / self.rtd.nominal();
+        let mut temp = (ratio - 1.0) / CVD_A;
+        for _ in 0..3 {
+            let error = 1.0 + CVD_A * temp + CVD_B * temp * temp - ratio;
+            temp -= error / (CVD_A + 2.0 * CVD_B * temp);
+        }
+        temp
+    }
+}
+
+impl<SPI, CS, E> TemperatureSensor for Max31865<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    type Error = SpiSensorError;
+
+    fn read<D: DelayUs<u8>>(&mut self, _delay: &mut D) -> Result<f32, Self::Error> {
+        let mut rtd = [0u8; 2];
+        self.read_registers(REG_RTD_MSB, &mut rtd)?;
+
+        if rtd[1] & 0x01 != 0 {
+            let mut status = [0u8; 1];
+            self.read_registers(REG_FAULT_STATUS, &mut status)?;
+            let config = self.config;
+            self.write_register(REG_CONFIG, config | CONFIG_FAULT_CLEAR)?;
+            return Err(SpiSensorError::Fault(status[0]));
+        }
+
+        let raw = (((rtd[0] as u16) << 8) | rtd[1] as u16) >> 1;
+        let resistance = raw as f32 * self.reference_resistor / 32768.0;
+        Ok(self.resistance_to_celsius(resistance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::mock::{Chip, ChipSelect, NoDelay, Spi};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const PT100_REFERENCE: f32 = 430.0;
+    const PT1000_REFERENCE: f32 = 4300.0;
+
+    fn sensor(
+        rtd: RtdType,
+        wiring: Wiring,
+        reference: f32,
+    ) -> (Max31865<Spi, ChipSelect>, Rc<RefCell<Chip>>) {
+        let chip = Chip::new();
+        let sensor = Max31865::new(
+            Spi(chip.clone()),
+            ChipSelect(chip.clone()),
+            rtd,
+            wiring,
+            reference,
+        )
+        .unwrap();
+        (sensor, chip)
+    }
+
+    /// Puts the ADC value for `temp` into the RTD registers, the way the chip would.
+    fn set_temperature(chip: &RefCell<Chip>, rtd: RtdType, reference: f32, temp: f32) {
+        let resistance = rtd.nominal() * (1.0 + CVD_A * temp + CVD_B * temp * temp);
+        let raw = (resistance / reference * 32768.0).round() as u16;
+        let [msb, lsb] = (raw << 1).to_be_bytes();
+        let mut chip = chip.borrow_mut();
+        chip.registers[usize::from(REG_RTD_MSB)] = msb;
+        chip.registers[usize::from(REG_RTD_MSB) + 1] = lsb;
+    }
+
+    #[test]
+    fn configures_the_chip_on_creation() {
+        let (_, chip) = sensor(RtdType::Pt100, Wiring::TwoOrFourWire, PT100_REFERENCE);
+        assert_eq!(chip.borrow().writes, [(REG_CONFIG | WRITE, 0xc3)]);
+
+        let (_, chip) = sensor(RtdType::Pt100, Wiring::ThreeWire, PT100_REFERENCE);
+        assert_eq!(chip.borrow().writes, [(REG_CONFIG | WRITE, 0xd3)]);
+    }
+
+    #[test]
+    fn converts_pt100_readings() {
+        let (mut sensor, chip) = sensor(RtdType::Pt100, Wiring::ThreeWire, PT100_REFERENCE);
+
+        for &temp in &[0.0, 25.0, 93.0, 100.0, 125.0, 145.0] {
+            set_temperature(&chip, RtdType::Pt100, PT100_REFERENCE, temp);
+            let read = sensor.read(&mut NoDelay).unwrap();
+            assert!((read - temp).abs() < 0.05, "{} read as {}", temp, read);
+        }
+
+        let chip = chip.borrow();
+        assert_eq!(chip.unselected, 0);
+        assert!(!chip.is_selected());
+    }
+
+    #[test]
+    fn converts_pt1000_readings() {
+        let (mut sensor, chip) = sensor(RtdType::Pt1000, Wiring::TwoOrFourWire, PT1000_REFERENCE);
+
+        for &temp in &[20.0, 93.0, 125.0] {
+            set_temperature(&chip, RtdType::Pt1000, PT1000_REFERENCE, temp);
+            let read = sensor.read(&mut NoDelay).unwrap();
+            assert!((read - temp).abs() < 0.05, "{} read as {}", temp, read);
+        }
+    }
+
+    #[test]
+    fn reports_and_clears_faults() {
+        let (mut sensor, chip) = sensor(RtdType::Pt100, Wiring::ThreeWire, PT100_REFERENCE);
+        set_temperature(&chip, RtdType::Pt100, PT100_REFERENCE, 93.0);
+        {
+            let mut chip = chip.borrow_mut();
+            chip.registers[usize::from(REG_RTD_MSB) + 1] |= 0x01;
+            // RTD high threshold and open RTD.
+            chip.registers[usize::from(REG_FAULT_STATUS)] = 0x84;
+            chip.writes.clear();
+        }
+
+        assert_eq!(sensor.read(&mut NoDelay), Err(SpiSensorError::Fault(0x84)));
+        assert_eq!(chip.borrow().writes, [(REG_CONFIG | WRITE, 0xd3)]);
+    }
+
+    #[test]
+    fn reports_bus_failures() {
+        let (mut sensor, chip) = sensor(RtdType::Pt100, Wiring::ThreeWire, PT100_REFERENCE);
+        chip.borrow_mut().broken = true;
+
+        assert_eq!(sensor.read(&mut NoDelay), Err(SpiSensorError::Spi));
+        // The chip is released even though the transfer failed.
+        assert!(!chip.borrow().is_selected());
+    }
+}
@@ -0,0 +1,143 @@
+//! Temperature sensors which can be used to measure the boiler.
+//!
+//! The firmware's `Boiler` only talks to the [`TemperatureSensor`] trait, the backend is picked
+//! through a `sensor-*` feature of the controller. The backends in here only need
+//! `embedded-hal`, the TSIC lives in the controller since it drives the nRF pins directly.
+
+pub mod max31855;
+pub mod max31865;
+pub mod ntc;
+
+use core::fmt::Debug;
+use embedded_hal::blocking::delay::DelayUs;
+
+/// A sensor which returns the temperature in °C.
+pub trait TemperatureSensor {
+    /// Printed into the event log when the sensor starts failing.
+    type Error: Debug;
+
+    /// Performs a single blocking measurement.
+    fn read<D: DelayUs<u8>>(&mut self, delay: &mut D) -> Result<f32, Self::Error>;
+}
+
+/// When the firmware gives up on a sensor which keeps failing, and when it trusts it again.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HealthConfig {
+    /// Consecutive failed reads after which the sensor is `Failed`.
+    pub failed_after: u8,
+    /// Consecutive good reads a `Failed` sensor needs before it is `Ok` again.
+    pub recover_after: u8,
+}
+
+/// Errors shared by the sensors which are attached through SPI.
+#[derive(Debug, PartialEq)]
+pub enum SpiSensorError {
+    /// The SPI transfer itself failed.
+    Spi,
+    /// Could not toggle the chip select pin.
+    Pin,
+    /// The chip reported a fault, the raw fault bits are attached.
+    Fault(u8),
+}
+
+/// Stand-ins for the buses, so the drivers can be checked against what the datasheets say.
+#[cfg(test)]
+mod mock {
+    use core::convert::Infallible;
+    use embedded_hal::blocking::delay::DelayUs;
+    use embedded_hal::blocking::spi::{Transfer, Write};
+    use embedded_hal::digital::v2::OutputPin;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A chip behind a chip select, shared by the bus and the pin.
+    #[derive(Default)]
+    pub struct Chip {
+        /// What the chip shifts out, starting at the addressed register.
+        pub registers: [u8; 8],
+        /// Register writes, address and value.
+        pub writes: Vec<(u8, u8)>,
+        /// Fails every transfer while set.
+        pub broken: bool,
+        /// Transfers which happened without the chip being selected.
+        pub unselected: usize,
+        selected: bool,
+        address: usize,
+    }
+
+    impl Chip {
+        pub fn new() -> Rc<RefCell<Self>> {
+            Rc::new(RefCell::new(Self::default()))
+        }
+
+        pub fn is_selected(&self) -> bool {
+            self.selected
+        }
+    }
+
+    pub struct Spi(pub Rc<RefCell<Chip>>);
+
+    impl Write<u8> for Spi {
+        type Error = ();
+
+        fn write(&mut self, words: &[u8]) -> Result<(), ()> {
+            let mut chip = self.0.borrow_mut();
+            if chip.broken {
+                return Err(());
+            }
+            if !chip.selected {
+                chip.unselected += 1;
+            }
+            match *words {
+                [address, value] if address & 0x80 != 0 => chip.writes.push((address, value)),
+                [address] => chip.address = usize::from(address),
+                _ => panic!("unexpected write {:?}", words),
+            }
+            Ok(())
+        }
+    }
+
+    impl Transfer<u8> for Spi {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            let mut chip = self.0.borrow_mut();
+            if chip.broken {
+                return Err(());
+            }
+            if !chip.selected {
+                chip.unselected += 1;
+            }
+            for word in words.iter_mut() {
+                *word = chip.registers[chip.address];
+                chip.address += 1;
+            }
+            Ok(words)
+        }
+    }
+
+    pub struct ChipSelect(pub Rc<RefCell<Chip>>);
+
+    impl OutputPin for ChipSelect {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            let mut chip = self.0.borrow_mut();
+            chip.selected = true;
+            // Reads without an address start at the top, like the thermocouple chips.
+            chip.address = 0;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().selected = false;
+            Ok(())
+        }
+    }
+
+    pub struct NoDelay;
+
+    impl DelayUs<u8> for NoDelay {
+        fn delay_us(&mut self, _us: u8) {}
+    }
+}
@@ -0,0 +1,162 @@
+//! An NTC thermistor in a voltage divider, read through the SAADC.
+//!
+//! The thermistor sits between the analog pin and ground, the series resistor between VDD and
+//! the analog pin.
+
+use super::TemperatureSensor;
+use core::marker::PhantomData;
+use embedded_hal::adc::{Channel, OneShot};
+use embedded_hal::blocking::delay::DelayUs;
+use micromath::F32Ext;
+
+const KELVIN: f32 = 273.15;
+
+pub struct NtcConfig {
+    /// The resistor between VDD and the measured node, in Ω.
+    pub series_resistor: f32,
+    /// The value the ADC returns at full scale (16384 for the default 14 bit SAADC setup).
+    pub adc_max: f32,
+    /// Steinhart-Hart coefficients A, B and C.
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+}
+
+pub struct Ntc<A, ADC, PIN> {
+    adc: ADC,
+    pin: PIN,
+    config: NtcConfig,
+    _adc: PhantomData<A>,
+}
+
+impl<A, ADC, PIN> Ntc<A, ADC, PIN>
+where
+    ADC: OneShot<A, i16, PIN>,
+    PIN: Channel<A>,
+{
+    pub fn new(adc: ADC, pin: PIN, config: NtcConfig) -> Self {
+        Self {
+            adc,
+            pin,
+            config,
+            _adc: PhantomData,
+        }
+    }
+}
+
+/// Errors which can happen when reading the thermistor.
+#[derive(Debug, PartialEq)]
+pub enum NtcError {
+    /// The ADC conversion failed.
+    Adc,
+    /// The reading is at one of the rails, the thermistor is open or shorted.
+    OutOfRange,
+}
+
+impl<A, ADC, PIN> TemperatureSensor for Ntc<A, ADC, PIN>
+where
+    ADC: OneShot<A, i16, PIN>,
+    PIN: Channel<A>,
+{
+    type Error = NtcError;
+
+    fn read<D: DelayUs<u8>>(&mut self, _delay: &mut D) -> Result<f32, Self::Error> {
+        let raw = nb::block!(self.adc.read(&mut self.pin)).map_err(|_| NtcError::Adc)? as f32;
+        if raw <= 0.0 || raw >= self.config.adc_max {
+            return Err(NtcError::OutOfRange);
+        }
+
+        let resistance = self.config.series_resistor * raw / (self.config.adc_max - raw);
+        // micromath's, not the one of std which the host tests would pick otherwise.
+        let ln_r = F32Ext::ln(resistance);
+        let inv_kelvin = self.config.a + self.config.b * ln_r + self.config.c * ln_r * ln_r * ln_r;
+        Ok(1.0 / inv_kelvin - KELVIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::mock::NoDelay;
+
+    const SERIES_RESISTOR: f32 = 10_000.0;
+    const ADC_MAX: f32 = 16384.0;
+    /// A 10kΩ thermistor with a B value of 3950, as Steinhart-Hart coefficients.
+    const R25: f32 = 10_000.0;
+    const BETA: f32 = 3950.0;
+
+    struct Adc {
+        /// Conversions still in flight before the value is ready.
+        busy: usize,
+        value: Result<i16, ()>,
+    }
+
+    struct Input;
+
+    impl Channel<Adc> for Input {
+        type ID = u8;
+
+        fn channel() -> u8 {
+            0
+        }
+    }
+
+    impl OneShot<Adc, i16, Input> for Adc {
+        type Error = ();
+
+        fn read(&mut self, _pin: &mut Input) -> nb::Result<i16, ()> {
+            if self.busy > 0 {
+                self.busy -= 1;
+                return Err(nb::Error::WouldBlock);
+            }
+            self.value.map_err(nb::Error::Other)
+        }
+    }
+
+    fn config() -> NtcConfig {
+        NtcConfig {
+            series_resistor: SERIES_RESISTOR,
+            adc_max: ADC_MAX,
+            a: 1.0 / (25.0 + KELVIN) - R25.ln() / BETA,
+            b: 1.0 / BETA,
+            c: 0.0,
+        }
+    }
+
+    fn read(busy: usize, value: Result<i16, ()>) -> Result<f32, NtcError> {
+        Ntc::new(Adc { busy, value }, Input, config()).read(&mut NoDelay)
+    }
+
+    /// The ADC value the divider produces at `temp`.
+    fn adc_value(temp: f32) -> i16 {
+        let resistance = R25 * (BETA * (1.0 / (temp + KELVIN) - 1.0 / (25.0 + KELVIN))).exp();
+        (ADC_MAX * resistance / (resistance + SERIES_RESISTOR)).round() as i16
+    }
+
+    #[test]
+    fn converts_the_divider_voltage() {
+        for &temp in &[25.0, 60.0, 93.0, 125.0] {
+            let read = read(0, Ok(adc_value(temp))).unwrap();
+            assert!((read - temp).abs() < 0.5, "{} read as {}", temp, read);
+        }
+    }
+
+    #[test]
+    fn waits_for_the_conversion() {
+        let read = read(3, Ok(adc_value(93.0))).unwrap();
+        assert!((read - 93.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn rejects_readings_at_the_rails() {
+        // Shorted thermistor, a bit of negative offset and an open thermistor.
+        assert_eq!(read(0, Ok(0)), Err(NtcError::OutOfRange));
+        assert_eq!(read(0, Ok(-3)), Err(NtcError::OutOfRange));
+        assert_eq!(read(0, Ok(ADC_MAX as i16)), Err(NtcError::OutOfRange));
+    }
+
+    #[test]
+    fn reports_adc_failures() {
+        assert_eq!(read(0, Err(())), Err(NtcError::Adc));
+    }
+}
@@ -0,0 +1,635 @@
+//! The runtime configuration of the controller.
+
+use crate::brew::BrewModel;
+use crate::calibration::Calibration;
+use crate::clock::SECONDS_PER_DAY;
+use crate::filter::{FilterConfig, MAX_MEDIAN_LEN, MAX_REJECTS};
+use crate::ready::ReadyConfig;
+use crate::sensor::HealthConfig;
+use crate::standby::{StandbyAction, StandbyConfig};
+use protocol::config::{self, Config, TargetKind};
+
+/// One set of PID gains.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Gains {
+    pub const fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self { kp, ki, kd }
+    }
+}
+
+/// What the user wants to hit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    /// A boiler temperature (°C), used as the PID setpoint directly.
+    Boiler(f32),
+    /// A brew water temperature (°C), converted to a setpoint through the brew model.
+    Brew(f32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub target: Target,
+    /// Gains used while heating up from cold.
+    pub cold_gains: Gains,
+    /// Gains used once the boiler reached the setpoint.
+    pub warm_gains: Gains,
+    /// The boiler setpoint (°C) in steam mode.
+    pub steam_temp: f32,
+    /// Gains used in steam mode.
+    pub steam_gains: Gains,
+    /// Steam mode ends on its own after this long (in ms), `None` keeps it on.
+    pub steam_timeout: Option<u64>,
+    /// The PID window (and sample time) in ms.
+    pub window_size: u32,
+    /// The heater refuses to run on a reading older than this (in ms).
+    pub max_reading_age: u64,
+    pub filter: FilterConfig,
+    pub sensor_health: HealthConfig,
+    pub calibration: Calibration,
+    pub brew_model: BrewModel,
+    pub ready: ReadyConfig,
+    pub standby: StandbyConfig,
+}
+
+/// A setting which is out of its allowed range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SettingsError {
+    Target,
+    Gains,
+    SteamTemp,
+    SteamTimeout,
+    WindowSize,
+    MaxReadingAge,
+    Filter,
+    SensorHealth,
+    Calibration,
+    Ready,
+    Standby,
+}
+
+const MIN_TEMP: f32 = 20.0;
+const MAX_BREW_TEMP: f32 = 110.0;
+const MIN_STEAM_TEMP: f32 = 100.0;
+/// Stays clear of the hard cutoff in the safety module.
+const MAX_STEAM_TEMP: f32 = 140.0;
+const MIN_STEAM_TIMEOUT: u64 = 60 * 1000;
+const MAX_STEAM_TIMEOUT: u64 = 60 * 60 * 1000;
+const MIN_IDLE_TIMEOUT: u64 = 60 * 1000;
+const MAX_IDLE_TIMEOUT: u64 = SECONDS_PER_DAY as u64 * 1000;
+
+impl Gains {
+    /// Gains have to be finite and, just like `Pid::set_tunings` demands, not negative.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let valid = |v: f32| v.is_finite() && v >= 0.0;
+        if valid(self.kp) && valid(self.ki) && valid(self.kd) {
+            Ok(())
+        } else {
+            Err(SettingsError::Gains)
+        }
+    }
+}
+
+fn in_range(value: f32, min: f32, max: f32) -> bool {
+    value.is_finite() && value >= min && value <= max
+}
+
+impl Settings {
+    /// Checks every setting against its allowed range.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let target = match self.target {
+            Target::Boiler(temp) | Target::Brew(temp) => temp,
+        };
+        if !in_range(target, MIN_TEMP, MAX_BREW_TEMP)
+            || !in_range(self.boiler_setpoint(), MIN_TEMP, MAX_BREW_TEMP)
+        {
+            return Err(SettingsError::Target);
+        }
+
+        self.cold_gains.validate()?;
+        self.warm_gains.validate()?;
+        self.steam_gains.validate()?;
+
+        if !in_range(self.steam_temp, MIN_STEAM_TEMP, MAX_STEAM_TEMP) {
+            return Err(SettingsError::SteamTemp);
+        }
+        if let Some(timeout) = self.steam_timeout {
+            if !(MIN_STEAM_TIMEOUT..=MAX_STEAM_TIMEOUT).contains(&timeout) {
+                return Err(SettingsError::SteamTimeout);
+            }
+        }
+        if self.window_size < 100 || self.window_size > 10_000 {
+            return Err(SettingsError::WindowSize);
+        }
+        if self.max_reading_age < 500 || self.max_reading_age > 10_000 {
+            return Err(SettingsError::MaxReadingAge);
+        }
+
+        if self.filter.median_len == 0
+            || self.filter.median_len > MAX_MEDIAN_LEN
+            || !in_range(self.filter.ema_alpha, 0.001, 1.0)
+            || !in_range(self.filter.max_delta, 0.0, 50.0)
+            || self.filter.max_rejects > MAX_REJECTS
+        {
+            return Err(SettingsError::Filter);
+        }
+
+        if self.sensor_health.failed_after == 0 || self.sensor_health.recover_after == 0 {
+            return Err(SettingsError::SensorHealth);
+        }
+
+        if !self.calibration.is_plausible() {
+            return Err(SettingsError::Calibration);
+        }
+
+        if !in_range(self.ready.band, 0.1, 5.0) || !in_range(self.ready.duty_tolerance, 0.0, 1.0) {
+            return Err(SettingsError::Ready);
+        }
+
+        if let Some(timeout) = self.standby.idle_timeout {
+            if !(MIN_IDLE_TIMEOUT..=MAX_IDLE_TIMEOUT).contains(&timeout) {
+                return Err(SettingsError::Standby);
+            }
+        }
+        if let StandbyAction::Eco(temp) = self.standby.action {
+            if !in_range(temp, MIN_TEMP, MAX_BREW_TEMP) {
+                return Err(SettingsError::Standby);
+            }
+        }
+        if matches!(self.standby.wake_at, Some(at) if at >= SECONDS_PER_DAY) {
+            return Err(SettingsError::Standby);
+        }
+
+        Ok(())
+    }
+
+    /// The part of the settings which can be changed by remotes.
+    pub fn config(&self) -> Config {
+        let gains = |gains: Gains| config::Gains {
+            kp: gains.kp,
+            ki: gains.ki,
+            kd: gains.kd,
+        };
+        Config {
+            target_temp: match self.target {
+                Target::Boiler(temp) | Target::Brew(temp) => temp,
+            },
+            target_kind: match self.target {
+                Target::Boiler(_) => TargetKind::Boiler,
+                Target::Brew(_) => TargetKind::Brew,
+            },
+            cold_gains: gains(self.cold_gains),
+            warm_gains: gains(self.warm_gains),
+            steam_temp: self.steam_temp,
+            steam_gains: gains(self.steam_gains),
+            // At most an hour, see `validate`.
+            steam_timeout: self.steam_timeout.map(|timeout| timeout as u32),
+            window_size: self.window_size,
+            calibration: match self.calibration {
+                Calibration::None => config::Calibration::None,
+                Calibration::Offset(offset) => config::Calibration::Offset(offset),
+                Calibration::TwoPoint(two_point) => {
+                    let [raw_low, reference_low, raw_high, reference_high] = two_point.points();
+                    config::Calibration::TwoPoint {
+                        raw_low,
+                        reference_low,
+                        raw_high,
+                        reference_high,
+                    }
+                }
+            },
+            filter: config::Filter {
+                // At most MAX_MEDIAN_LEN, see `validate`.
+                median_len: self.filter.median_len as u8,
+                ema_alpha: self.filter.ema_alpha,
+                max_delta: self.filter.max_delta,
+                max_rejects: self.filter.max_rejects,
+            },
+            // At most a day, see `validate`.
+            idle_timeout: self.standby.idle_timeout.map(|timeout| timeout as u32),
+            standby_temp: match self.standby.action {
+                StandbyAction::Eco(temp) => Some(temp),
+                StandbyAction::Off => None,
+            },
+            wake_at: self.standby.wake_at,
+        }
+    }
+
+    /// These settings with `config` taken over and validated.
+    pub fn with_config(&self, config: &Config) -> Result<Self, SettingsError> {
+        let gains = |gains: config::Gains| Gains::new(gains.kp, gains.ki, gains.kd);
+        let calibration = match config.calibration {
+            config::Calibration::None => Calibration::None,
+            config::Calibration::Offset(offset) => Calibration::Offset(offset),
+            config::Calibration::TwoPoint {
+                raw_low,
+                reference_low,
+                raw_high,
+                reference_high,
+            } => Calibration::two_point(raw_low, reference_low, raw_high, reference_high)
+                .ok_or(SettingsError::Calibration)?,
+        };
+        let settings = Self {
+            target: match config.target_kind {
+                TargetKind::Boiler => Target::Boiler(config.target_temp),
+                TargetKind::Brew => Target::Brew(config.target_temp),
+            },
+            cold_gains: gains(config.cold_gains),
+            warm_gains: gains(config.warm_gains),
+            steam_temp: config.steam_temp,
+            steam_gains: gains(config.steam_gains),
+            steam_timeout: config.steam_timeout.map(u64::from),
+            window_size: config.window_size,
+            calibration,
+            filter: FilterConfig::new(
+                usize::from(config.filter.median_len),
+                config.filter.ema_alpha,
+                config.filter.max_delta,
+                config.filter.max_rejects,
+            ),
+            standby: StandbyConfig {
+                idle_timeout: config.idle_timeout.map(u64::from),
+                action: match config.standby_temp {
+                    Some(temp) => StandbyAction::Eco(temp),
+                    None => StandbyAction::Off,
+                },
+                wake_at: config.wake_at,
+            },
+            ..*self
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// The setpoint handed to the PID outside of steam and standby.
+    pub fn boiler_setpoint(&self) -> f32 {
+        match self.target {
+            Target::Boiler(temp) => temp,
+            Target::Brew(temp) => self.brew_model.boiler_setpoint_for(temp),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            target: Target::Boiler(95.0),
+            cold_gains: Gains::new(250.0, 0.03, 0.0),
+            warm_gains: Gains::new(69.0, 0.17, 0.0),
+            steam_temp: 125.0,
+            steam_gains: Gains::new(69.0, 0.17, 0.0),
+            steam_timeout: Some(5 * 60 * 1000),
+            window_size: 1000,
+            max_reading_age: 1000,
+            // Median of 3, light EMA and at most 5°C between two readings (half a second apart).
+            filter: FilterConfig::new(3, 0.5, 5.0, 3),
+            // Degraded on the first failed read, failed after five in a row, three good reads to
+            // recover.
+            sensor_health: HealthConfig {
+                failed_after: 5,
+                recover_after: 3,
+            },
+            calibration: Calibration::None,
+            brew_model: BrewModel::identity(),
+            // Within half a degree for five minutes, duty moving less than 10%.
+            ready: ReadyConfig {
+                band: 0.5,
+                dwell_ms: 5 * 60 * 1000,
+                duty_tolerance: 0.1,
+            },
+            // Same as the stock Silvia V6 E: off after 30 minutes without use.
+            standby: StandbyConfig {
+                idle_timeout: Some(30 * 60 * 1000),
+                action: StandbyAction::Off,
+                wake_at: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validates the defaults with `change` applied.
+    fn validate(change: impl FnOnce(&mut Settings)) -> Result<(), SettingsError> {
+        let mut settings = Settings::default();
+        change(&mut settings);
+        settings.validate()
+    }
+
+    /// Checks that every value of `valid` passes and every value of `invalid` gives `error`.
+    fn check<T: Copy + core::fmt::Debug>(
+        set: impl Fn(&mut Settings, T),
+        valid: &[T],
+        invalid: &[T],
+        error: SettingsError,
+    ) {
+        for value in valid {
+            assert_eq!(validate(|s| set(s, *value)), Ok(()), "{:?}", value);
+        }
+        for value in invalid {
+            assert_eq!(validate(|s| set(s, *value)), Err(error), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        assert_eq!(Settings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn targets_are_brewing_temperatures() {
+        let temps = (
+            &[20.0, 95.0, 110.0],
+            &[19.9, 110.1, f32::NAN, f32::INFINITY],
+        );
+        check(
+            |s, t| s.target = Target::Boiler(t),
+            temps.0,
+            temps.1,
+            SettingsError::Target,
+        );
+        check(
+            |s, t| s.target = Target::Brew(t),
+            temps.0,
+            temps.1,
+            SettingsError::Target,
+        );
+
+        // A brew target which needs a boiler setpoint out of range.
+        let brew_model = BrewModel::new(&[(90.0, 10.0)], 0, 0.0).unwrap();
+        let set = |s: &mut Settings, temp| {
+            s.brew_model = brew_model;
+            s.target = Target::Brew(temp);
+        };
+        check(set, &[100.0], &[105.0], SettingsError::Target);
+    }
+
+    #[test]
+    fn gains_are_finite_and_not_negative() {
+        let valid = [Gains::new(0.0, 0.0, 0.0), Gains::new(250.0, 0.03, 1.5)];
+        let invalid = [
+            Gains::new(-1.0, 0.0, 0.0),
+            Gains::new(0.0, -0.01, 0.0),
+            Gains::new(0.0, 0.0, -0.5),
+            Gains::new(f32::NAN, 0.0, 0.0),
+            Gains::new(0.0, f32::INFINITY, 0.0),
+            Gains::new(0.0, 0.0, f32::NAN),
+        ];
+        check(
+            |s, g| s.cold_gains = g,
+            &valid,
+            &invalid,
+            SettingsError::Gains,
+        );
+        check(
+            |s, g| s.warm_gains = g,
+            &valid,
+            &invalid,
+            SettingsError::Gains,
+        );
+        check(
+            |s, g| s.steam_gains = g,
+            &valid,
+            &invalid,
+            SettingsError::Gains,
+        );
+    }
+
+    #[test]
+    fn steam_stays_between_boiling_and_the_cutoff() {
+        check(
+            |s, t| s.steam_temp = t,
+            &[100.0, 125.0, 140.0],
+            &[99.9, 140.1, f32::NAN],
+            SettingsError::SteamTemp,
+        );
+        check(
+            |s, t| s.steam_timeout = t,
+            &[None, Some(60_000), Some(60 * 60 * 1000)],
+            &[Some(0), Some(59_999), Some(60 * 60 * 1000 + 1)],
+            SettingsError::SteamTimeout,
+        );
+    }
+
+    #[test]
+    fn the_window_and_the_reading_age_have_bounds() {
+        check(
+            |s, w| s.window_size = w,
+            &[100, 1000, 10_000],
+            &[0, 99, 10_001],
+            SettingsError::WindowSize,
+        );
+        check(
+            |s, a| s.max_reading_age = a,
+            &[500, 1000, 10_000],
+            &[0, 499, 10_001],
+            SettingsError::MaxReadingAge,
+        );
+    }
+
+    #[test]
+    fn every_filter_stage_has_bounds() {
+        let valid = [
+            FilterConfig::new(1, 1.0, 0.0, 0),
+            FilterConfig::new(MAX_MEDIAN_LEN, 0.001, 50.0, MAX_REJECTS),
+        ];
+        let invalid = [
+            FilterConfig::new(0, 0.5, 5.0, 3),
+            FilterConfig::new(MAX_MEDIAN_LEN + 1, 0.5, 5.0, 3),
+            FilterConfig::new(3, 0.0, 5.0, 3),
+            FilterConfig::new(3, 1.1, 5.0, 3),
+            FilterConfig::new(3, f32::NAN, 5.0, 3),
+            FilterConfig::new(3, 0.5, -1.0, 3),
+            FilterConfig::new(3, 0.5, 50.1, 3),
+            FilterConfig::new(3, 0.5, 5.0, MAX_REJECTS + 1),
+        ];
+        check(|s, f| s.filter = f, &valid, &invalid, SettingsError::Filter);
+    }
+
+    #[test]
+    fn sensor_health_needs_at_least_one_read() {
+        let health = |failed_after, recover_after| HealthConfig {
+            failed_after,
+            recover_after,
+        };
+        check(
+            |s, h| s.sensor_health = h,
+            &[health(1, 1), health(255, 255)],
+            &[health(0, 3), health(5, 0)],
+            SettingsError::SensorHealth,
+        );
+    }
+
+    #[test]
+    fn calibrations_have_to_be_plausible() {
+        check(
+            |s, c| s.calibration = c,
+            &[
+                Calibration::None,
+                Calibration::Offset(-10.0),
+                Calibration::Offset(10.0),
+                Calibration::two_point(99.0, 100.0, 90.0, 92.0).unwrap(),
+            ],
+            &[
+                Calibration::Offset(10.1),
+                Calibration::Offset(f32::NAN),
+                // Off by more than the largest offset at a reference point.
+                Calibration::two_point(80.0, 100.0, 90.0, 110.0).unwrap(),
+                // A slope of 2, the readings would run away from the real temperature.
+                Calibration::two_point(90.0, 90.0, 95.0, 100.0).unwrap(),
+                // Falling, a sensor wired the wrong way round.
+                Calibration::two_point(90.0, 95.0, 95.0, 90.0).unwrap(),
+            ],
+            SettingsError::Calibration,
+        );
+    }
+
+    #[test]
+    fn ready_needs_a_band_and_a_duty_tolerance() {
+        let ready = |band, duty_tolerance| ReadyConfig {
+            band,
+            dwell_ms: 60_000,
+            duty_tolerance,
+        };
+        check(
+            |s, r| s.ready = r,
+            &[ready(0.1, 0.0), ready(5.0, 1.0)],
+            &[
+                ready(0.09, 0.1),
+                ready(5.1, 0.1),
+                ready(f32::NAN, 0.1),
+                ready(0.5, -0.1),
+                ready(0.5, 1.1),
+            ],
+            SettingsError::Ready,
+        );
+    }
+
+    #[test]
+    fn standby_times_and_temperatures_have_bounds() {
+        let day = SECONDS_PER_DAY as u64 * 1000;
+        check(
+            |s, t| s.standby.idle_timeout = t,
+            &[None, Some(60_000), Some(day)],
+            &[Some(0), Some(59_999), Some(day + 1)],
+            SettingsError::Standby,
+        );
+        check(
+            |s, a| s.standby.action = a,
+            &[
+                StandbyAction::Off,
+                StandbyAction::Eco(20.0),
+                StandbyAction::Eco(110.0),
+            ],
+            &[
+                StandbyAction::Eco(19.9),
+                StandbyAction::Eco(110.1),
+                StandbyAction::Eco(f32::NAN),
+            ],
+            SettingsError::Standby,
+        );
+        check(
+            |s, w| s.standby.wake_at = w,
+            &[None, Some(0), Some(SECONDS_PER_DAY - 1)],
+            &[Some(SECONDS_PER_DAY), Some(u32::MAX)],
+            SettingsError::Standby,
+        );
+    }
+
+    #[test]
+    fn the_config_round_trips() {
+        let settings = Settings {
+            target: Target::Brew(92.5),
+            steam_timeout: None,
+            calibration: Calibration::two_point(99.0, 100.0, 90.0, 92.0).unwrap(),
+            filter: FilterConfig::new(5, 0.25, 2.5, 7),
+            standby: StandbyConfig {
+                idle_timeout: Some(10 * 60 * 1000),
+                action: StandbyAction::Eco(70.0),
+                wake_at: Some(6 * 60 * 60),
+            },
+            ..Settings::default()
+        };
+        assert_eq!(settings.validate(), Ok(()));
+        assert_eq!(
+            Settings::default().with_config(&settings.config()),
+            Ok(settings)
+        );
+    }
+
+    #[test]
+    fn settings_outside_of_the_config_are_kept() {
+        let settings = Settings {
+            max_reading_age: 2000,
+            sensor_health: HealthConfig {
+                failed_after: 9,
+                recover_after: 9,
+            },
+            brew_model: BrewModel::new(&[(90.0, 4.0)], 1500, 2.0).unwrap(),
+            ..Settings::default()
+        };
+        let config = Config {
+            window_size: 2000,
+            ..Settings::default().config()
+        };
+        assert_eq!(
+            settings.with_config(&config),
+            Ok(Settings {
+                window_size: 2000,
+                ..settings
+            })
+        );
+    }
+
+    #[test]
+    fn configs_are_validated() {
+        let defaults = Settings::default();
+        let with = |change: fn(&mut Config)| {
+            let mut config = defaults.config();
+            change(&mut config);
+            defaults.with_config(&config)
+        };
+
+        assert_eq!(with(|c| c.target_temp = 150.0), Err(SettingsError::Target));
+        assert_eq!(with(|c| c.warm_gains.kp = -1.0), Err(SettingsError::Gains));
+        assert_eq!(
+            with(|c| c.steam_temp = f32::NAN),
+            Err(SettingsError::SteamTemp)
+        );
+        assert_eq!(
+            with(|c| c.filter.median_len = 0),
+            Err(SettingsError::Filter)
+        );
+        assert_eq!(
+            with(|c| c.wake_at = Some(SECONDS_PER_DAY)),
+            Err(SettingsError::Standby)
+        );
+        // Two readings which are the same do not make a line at all.
+        assert_eq!(
+            with(|c| c.calibration = config::Calibration::TwoPoint {
+                raw_low: 90.0,
+                reference_low: 92.0,
+                raw_high: 90.0,
+                reference_high: 100.0,
+            }),
+            Err(SettingsError::Calibration)
+        );
+        // A line, but not one a working sensor could need.
+        assert_eq!(
+            with(|c| c.calibration = config::Calibration::TwoPoint {
+                raw_low: 90.0,
+                reference_low: 90.0,
+                raw_high: 95.0,
+                reference_high: 100.0,
+            }),
+            Err(SettingsError::Calibration)
+        );
+    }
+}
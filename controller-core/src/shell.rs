@@ -0,0 +1,397 @@
+//! Parses the lines typed into the serial console.
+//!
+//! Only depends on the `protocol` crate, so the parsing can be checked on the host.
+
+use core::fmt;
+use core::str::FromStr;
+use protocol::config::{Calibration, Filter, Gains, TargetKind};
+use protocol::gatt::Command;
+
+pub const HELP: &str = "\
+status                      show the current values
+config                      show the settings
+set target <temp> [boiler|brew]
+                            set the brew target in C, of the boiler or the water
+set pid <cold|warm|steam> <kp> <ki> <kd>
+                            set the gains of a gain set
+set window <ms>             set the PID window
+set steam <temp>            set the steam temperature in C
+set steam-timeout <s|off>   end steam mode after this long
+set calibration none | offset <c> | points <raw> <ref> <raw> <ref>
+                            correct the sensor reading
+set filter <median> <alpha> <max-delta> <max-rejects>
+                            filter the sensor reading
+set idle-timeout <s|off>    go to standby after this long without use
+set standby-temp <temp|off> keep the boiler at this temperature in standby
+set wake-at <hh:mm|off>     leave standby at this time of day
+time <hh:mm[:ss]>           set the time of day
+save                        store the settings in flash
+brew sample <temp>          add the water temperature measured at the group
+brew fit                    fit the brew model through the samples
+standby | wake              enter or leave standby
+steam <on|off>              switch steam mode
+log dump                    print the event log
+stream <on|off>             print the status after every measurement
+help                        show this text";
+
+/// A command typed into the console.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line {
+    Help,
+    Status,
+    Config,
+    /// The kind stays as it is if not given.
+    SetTarget {
+        temp: f32,
+        kind: Option<TargetKind>,
+    },
+    SetGains {
+        set: GainSet,
+        gains: Gains,
+    },
+    SetWindow(u32),
+    SetSteamTemp(f32),
+    /// In ms, `None` keeps steam mode on.
+    SetSteamTimeout(Option<u32>),
+    SetCalibration(Calibration),
+    SetFilter(Filter),
+    /// In ms, `None` never goes to standby.
+    SetIdleTimeout(Option<u32>),
+    /// `None` turns the heater off in standby.
+    SetStandbyTemp(Option<f32>),
+    /// Seconds since midnight, `None` does not wake up on its own.
+    SetWakeAt(Option<u32>),
+    /// Seconds since midnight.
+    SetTime(u32),
+    Command(Command),
+    BrewSample(f32),
+    FitBrewModel,
+    LogDump,
+    Stream(bool),
+}
+
+/// Which gains `set pid` changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GainSet {
+    Cold,
+    Warm,
+    Steam,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    /// Nothing but whitespace.
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    TooManyArguments,
+    /// An argument is not one of the words the command takes.
+    InvalidArgument,
+    InvalidNumber,
+}
+
+pub fn parse(line: &str) -> Result<Line, ParseError> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or(ParseError::Empty)?;
+
+    let parsed = match command {
+        "help" | "?" => Line::Help,
+        "status" => Line::Status,
+        "config" => Line::Config,
+        "save" => Line::Command(Command::Save),
+        "standby" => Line::Command(Command::Standby),
+        "wake" => Line::Command(Command::Wake),
+        "steam" => Line::Command(if on_off(&mut words)? {
+            Command::SteamOn
+        } else {
+            Command::SteamOff
+        }),
+        "stream" => Line::Stream(on_off(&mut words)?),
+        "time" => Line::SetTime(time_of_day(next(&mut words)?)?),
+        "brew" => match next(&mut words)? {
+            "sample" => Line::BrewSample(number(&mut words)?),
+            "fit" => Line::FitBrewModel,
+            _ => return Err(ParseError::InvalidArgument),
+        },
+        "log" => match next(&mut words)? {
+            "dump" => Line::LogDump,
+            _ => return Err(ParseError::InvalidArgument),
+        },
+        "set" => match next(&mut words)? {
+            "target" => Line::SetTarget {
+                temp: number(&mut words)?,
+                kind: match words.next() {
+                    None => None,
+                    Some("boiler") => Some(TargetKind::Boiler),
+                    Some("brew") => Some(TargetKind::Brew),
+                    Some(_) => return Err(ParseError::InvalidArgument),
+                },
+            },
+            "window" => Line::SetWindow(number(&mut words)?),
+            "steam" => Line::SetSteamTemp(number(&mut words)?),
+            "steam-timeout" => Line::SetSteamTimeout(unless_off(&mut words, seconds_as_ms)?),
+            "idle-timeout" => Line::SetIdleTimeout(unless_off(&mut words, seconds_as_ms)?),
+            "standby-temp" => Line::SetStandbyTemp(unless_off(&mut words, |word| {
+                word.parse().map_err(|_| ParseError::InvalidNumber)
+            })?),
+            "wake-at" => Line::SetWakeAt(unless_off(&mut words, time_of_day)?),
+            "pid" => {
+                let set = match next(&mut words)? {
+                    "cold" => GainSet::Cold,
+                    "warm" => GainSet::Warm,
+                    "steam" => GainSet::Steam,
+                    _ => return Err(ParseError::InvalidArgument),
+                };
+                let gains = Gains {
+                    kp: number(&mut words)?,
+                    ki: number(&mut words)?,
+                    kd: number(&mut words)?,
+                };
+                Line::SetGains { set, gains }
+            }
+            "calibration" => Line::SetCalibration(match next(&mut words)? {
+                "none" => Calibration::None,
+                "offset" => Calibration::Offset(number(&mut words)?),
+                "points" => Calibration::TwoPoint {
+                    raw_low: number(&mut words)?,
+                    reference_low: number(&mut words)?,
+                    raw_high: number(&mut words)?,
+                    reference_high: number(&mut words)?,
+                },
+                _ => return Err(ParseError::InvalidArgument),
+            }),
+            "filter" => Line::SetFilter(Filter {
+                median_len: number(&mut words)?,
+                ema_alpha: number(&mut words)?,
+                max_delta: number(&mut words)?,
+                max_rejects: number(&mut words)?,
+            }),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+        _ => return Err(ParseError::UnknownCommand),
+    };
+
+    if words.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(parsed)
+}
+
+fn next<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, ParseError> {
+    words.next().ok_or(ParseError::MissingArgument)
+}
+
+fn number<'a, T: FromStr>(words: &mut impl Iterator<Item = &'a str>) -> Result<T, ParseError> {
+    next(words)?.parse().map_err(|_| ParseError::InvalidNumber)
+}
+
+/// `None` for `off`, anything else goes through `parse`.
+fn unless_off<'a, T>(
+    words: &mut impl Iterator<Item = &'a str>,
+    parse: impl FnOnce(&str) -> Result<T, ParseError>,
+) -> Result<Option<T>, ParseError> {
+    match next(words)? {
+        "off" => Ok(None),
+        word => parse(word).map(Some),
+    }
+}
+
+/// Seconds, turned into ms.
+fn seconds_as_ms(word: &str) -> Result<u32, ParseError> {
+    word.parse::<u32>()
+        .ok()
+        .and_then(|seconds| seconds.checked_mul(1000))
+        .ok_or(ParseError::InvalidNumber)
+}
+
+/// `hh:mm` or `hh:mm:ss`, turned into seconds since midnight.
+fn time_of_day(word: &str) -> Result<u32, ParseError> {
+    let mut parts = word.split(':');
+    let mut part = |max: u32| -> Result<u32, ParseError> {
+        match parts.next().map(str::parse::<u32>) {
+            Some(Ok(value)) if value <= max => Ok(value),
+            _ => Err(ParseError::InvalidNumber),
+        }
+    };
+    let hours = part(23)?;
+    let minutes = part(59)?;
+    let seconds = match word.matches(':').count() {
+        1 => 0,
+        2 => part(59)?,
+        _ => return Err(ParseError::InvalidNumber),
+    };
+    Ok((hours * 60 + minutes) * 60 + seconds)
+}
+
+fn on_off<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<bool, ParseError> {
+    match next(words)? {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::Empty => "empty line",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::TooManyArguments => "too many arguments",
+            ParseError::InvalidArgument => "invalid argument",
+            ParseError::InvalidNumber => "invalid number",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let cases = [
+            ("help", Line::Help),
+            ("?", Line::Help),
+            ("status", Line::Status),
+            ("config", Line::Config),
+            (
+                "set target 94",
+                Line::SetTarget {
+                    temp: 94.0,
+                    kind: None,
+                },
+            ),
+            (
+                "set target 92.5 brew",
+                Line::SetTarget {
+                    temp: 92.5,
+                    kind: Some(TargetKind::Brew),
+                },
+            ),
+            (
+                "set pid warm 69 0.17 0",
+                Line::SetGains {
+                    set: GainSet::Warm,
+                    gains: Gains {
+                        kp: 69.0,
+                        ki: 0.17,
+                        kd: 0.0,
+                    },
+                },
+            ),
+            ("set window 1000", Line::SetWindow(1000)),
+            ("set steam 125", Line::SetSteamTemp(125.0)),
+            (
+                "set steam-timeout 300",
+                Line::SetSteamTimeout(Some(300_000)),
+            ),
+            ("set steam-timeout off", Line::SetSteamTimeout(None)),
+            (
+                "set idle-timeout 1800",
+                Line::SetIdleTimeout(Some(1_800_000)),
+            ),
+            ("set standby-temp 70", Line::SetStandbyTemp(Some(70.0))),
+            ("set standby-temp off", Line::SetStandbyTemp(None)),
+            ("set wake-at 06:30", Line::SetWakeAt(Some(23_400))),
+            ("set wake-at off", Line::SetWakeAt(None)),
+            (
+                "set calibration none",
+                Line::SetCalibration(Calibration::None),
+            ),
+            (
+                "set calibration offset -1.5",
+                Line::SetCalibration(Calibration::Offset(-1.5)),
+            ),
+            (
+                "set calibration points 99 100 90 92",
+                Line::SetCalibration(Calibration::TwoPoint {
+                    raw_low: 99.0,
+                    reference_low: 100.0,
+                    raw_high: 90.0,
+                    reference_high: 92.0,
+                }),
+            ),
+            (
+                "set filter 3 0.5 5 3",
+                Line::SetFilter(Filter {
+                    median_len: 3,
+                    ema_alpha: 0.5,
+                    max_delta: 5.0,
+                    max_rejects: 3,
+                }),
+            ),
+            ("time 23:59:59", Line::SetTime(86_399)),
+            ("time 0:00", Line::SetTime(0)),
+            ("save", Line::Command(Command::Save)),
+            ("standby", Line::Command(Command::Standby)),
+            ("wake", Line::Command(Command::Wake)),
+            ("steam on", Line::Command(Command::SteamOn)),
+            ("steam off", Line::Command(Command::SteamOff)),
+            ("brew sample 93.2", Line::BrewSample(93.2)),
+            ("brew fit", Line::FitBrewModel),
+            ("log dump", Line::LogDump),
+            ("stream on", Line::Stream(true)),
+            ("stream off", Line::Stream(false)),
+            // Whitespace is whitespace.
+            (
+                "  set\ttarget   94  ",
+                Line::SetTarget {
+                    temp: 94.0,
+                    kind: None,
+                },
+            ),
+        ];
+        for (line, expected) in cases.iter() {
+            assert_eq!(parse(line), Ok(*expected), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_does_not_understand() {
+        let cases = [
+            ("", ParseError::Empty),
+            ("   ", ParseError::Empty),
+            ("reboot", ParseError::UnknownCommand),
+            ("Status", ParseError::UnknownCommand),
+            ("set", ParseError::MissingArgument),
+            ("set target", ParseError::MissingArgument),
+            ("set pid warm 69 0.17", ParseError::MissingArgument),
+            (
+                "set calibration points 99 100 90",
+                ParseError::MissingArgument,
+            ),
+            ("stream", ParseError::MissingArgument),
+            ("log", ParseError::MissingArgument),
+            ("status now", ParseError::TooManyArguments),
+            ("set target 94 brew now", ParseError::TooManyArguments),
+            ("set pid warm 69 0.17 0 1", ParseError::TooManyArguments),
+            ("log dump all", ParseError::TooManyArguments),
+            ("set colour red", ParseError::InvalidArgument),
+            ("set target 94 group", ParseError::InvalidArgument),
+            ("set pid hot 69 0.17 0", ParseError::InvalidArgument),
+            ("set calibration linear", ParseError::InvalidArgument),
+            ("stream yes", ParseError::InvalidArgument),
+            ("log clear", ParseError::InvalidArgument),
+            ("brew now", ParseError::InvalidArgument),
+            ("set target hot", ParseError::InvalidNumber),
+            ("set pid warm 69 0,17 0", ParseError::InvalidNumber),
+            ("set window -1", ParseError::InvalidNumber),
+            ("set window 1.5", ParseError::InvalidNumber),
+            ("set filter 3 0.5 5", ParseError::MissingArgument),
+            ("set filter 300 0.5 5 3", ParseError::InvalidNumber),
+            ("set standby-temp warm", ParseError::InvalidNumber),
+            // Too many seconds to be counted in ms.
+            ("set idle-timeout 4294968", ParseError::InvalidNumber),
+            ("set wake-at 24:00", ParseError::InvalidNumber),
+            ("set wake-at 6", ParseError::InvalidNumber),
+            ("time 12:60", ParseError::InvalidNumber),
+            ("time 12:00:60", ParseError::InvalidNumber),
+            ("time 12:00:00:00", ParseError::InvalidNumber),
+            ("time 12:", ParseError::InvalidNumber),
+        ];
+        for (line, expected) in cases.iter() {
+            assert_eq!(parse(line), Err(*expected), "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Times a shot from brew switch on to brew switch off.
+
+use crate::clock::Millis;
+
+/// A completed shot.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Shot {
+    pub started_at: Millis,
+    pub stopped_at: Millis,
+    pub duration_ms: u32,
+    pub min_temp: f32,
+    pub max_temp: f32,
+    pub avg_temp: f32,
+}
+
+struct RunningShot {
+    started_at: Millis,
+    min_temp: f32,
+    max_temp: f32,
+    temp_sum: f32,
+    samples: u32,
+}
+
+#[derive(Default)]
+pub struct ShotTimer {
+    running: Option<RunningShot>,
+}
+
+impl ShotTimer {
+    pub fn new() -> Self {
+        Self { running: None }
+    }
+
+    /// Starts a new shot, a shot which is already running is discarded.
+    pub fn start(&mut self, now: Millis, temp: f32) {
+        self.running = Some(RunningShot {
+            started_at: now,
+            min_temp: temp,
+            max_temp: temp,
+            temp_sum: temp,
+            samples: 1,
+        });
+    }
+
+    /// Records a temperature while the shot is running.
+    pub fn sample(&mut self, temp: f32) {
+        if let Some(shot) = self.running.as_mut() {
+            if temp < shot.min_temp {
+                shot.min_temp = temp;
+            }
+            if temp > shot.max_temp {
+                shot.max_temp = temp;
+            }
+            shot.temp_sum += temp;
+            shot.samples += 1;
+        }
+    }
+
+    /// Stops the running shot and returns it, `None` if no shot was running.
+    pub fn stop(&mut self, now: Millis) -> Option<Shot> {
+        self.running.take().map(|shot| Shot {
+            started_at: shot.started_at,
+            stopped_at: now,
+            duration_ms: now.saturating_sub(shot.started_at) as u32,
+            min_temp: shot.min_temp,
+            max_temp: shot.max_temp,
+            avg_temp: shot.temp_sum / shot.samples as f32,
+        })
+    }
+
+    /// How long the current shot has been running, `None` if there is none.
+    pub fn elapsed(&self, now: Millis) -> Option<Millis> {
+        self.running
+            .as_ref()
+            .map(|shot| now.saturating_sub(shot.started_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_a_shot_and_its_temperatures() {
+        let mut timer = ShotTimer::new();
+        assert_eq!(timer.elapsed(1000), None);
+
+        timer.start(1000, 93.0);
+        for temp in [92.0, 94.0, 93.0].iter() {
+            timer.sample(*temp);
+        }
+        assert_eq!(timer.elapsed(26_000), Some(25_000));
+
+        assert_eq!(
+            timer.stop(29_000),
+            Some(Shot {
+                started_at: 1000,
+                stopped_at: 29_000,
+                duration_ms: 28_000,
+                min_temp: 92.0,
+                max_temp: 94.0,
+                avg_temp: 93.0,
+            })
+        );
+        assert_eq!(timer.elapsed(30_000), None);
+    }
+
+    #[test]
+    fn stopping_without_a_shot_gives_nothing() {
+        let mut timer = ShotTimer::new();
+        timer.sample(93.0);
+        assert_eq!(timer.stop(1000), None);
+
+        timer.start(1000, 93.0);
+        timer.stop(2000);
+        assert_eq!(timer.stop(3000), None);
+    }
+
+    #[test]
+    fn a_new_start_discards_the_running_shot() {
+        let mut timer = ShotTimer::new();
+        timer.start(1000, 80.0);
+        timer.sample(70.0);
+        timer.start(5000, 93.0);
+
+        let shot = timer.stop(6000).unwrap();
+        assert_eq!(shot.started_at, 5000);
+        assert_eq!(shot.duration_ms, 1000);
+        assert_eq!(shot.min_temp, 93.0);
+        assert_eq!(shot.avg_temp, 93.0);
+    }
+
+    #[test]
+    fn a_clock_going_backwards_gives_a_zero_duration() {
+        let mut timer = ShotTimer::new();
+        timer.start(5000, 93.0);
+        assert_eq!(timer.elapsed(4000), Some(0));
+        assert_eq!(timer.stop(4000).unwrap().duration_ms, 0);
+    }
+}
@@ -0,0 +1,240 @@
+//! Puts the machine into standby when it is not used and wakes it up again.
+
+use crate::clock::{Millis, WallClock};
+use crate::machine::{Event, MachineState};
+
+/// What happens to the boiler in standby.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StandbyAction {
+    /// Keep the boiler at a lower temperature (°C).
+    Eco(f32),
+    /// Turn the heater off completely.
+    Off,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StandbyConfig {
+    /// Time (in ms) without brewing or steaming before going to standby, `None` never does.
+    pub idle_timeout: Option<Millis>,
+    pub action: StandbyAction,
+    /// Time of day (seconds since midnight) to wake up, needs a synced wall clock.
+    pub wake_at: Option<u32>,
+}
+
+pub struct Standby {
+    last_activity: Millis,
+    last_checked: Option<u32>,
+}
+
+impl Standby {
+    pub fn new(now: Millis) -> Self {
+        Self {
+            last_activity: now,
+            last_checked: None,
+        }
+    }
+
+    /// Records a brew or steam event, which restarts the idle timer.
+    pub fn activity(&mut self, now: Millis) {
+        self.last_activity = now;
+    }
+
+    /// Returns the event the standby logic wants to raise, if any.
+    pub fn check(
+        &mut self,
+        now: Millis,
+        machine_state: MachineState,
+        wall_clock: &WallClock,
+        config: &StandbyConfig,
+    ) -> Option<Event> {
+        let seconds = wall_clock.seconds_of_day(now);
+        let previous = self.last_checked;
+        self.last_checked = seconds;
+
+        match machine_state {
+            MachineState::Coldstart | MachineState::Stabilizing | MachineState::Ready => {
+                match config.idle_timeout {
+                    Some(timeout) if now.saturating_sub(self.last_activity) >= timeout => {
+                        Some(Event::StandbyRequested)
+                    }
+                    _ => None,
+                }
+            }
+            MachineState::Standby => match (config.wake_at, previous, seconds) {
+                (Some(wake_at), Some(previous), Some(current))
+                    if crossed(previous, current, wake_at) =>
+                {
+                    self.last_activity = now;
+                    Some(Event::Wake)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// True if `target` lies in `(previous, current]`, taking the wrap at midnight into account.
+fn crossed(previous: u32, current: u32, target: u32) -> bool {
+    if previous <= current {
+        previous < target && target <= current
+    } else {
+        target > previous || target <= current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SECONDS_PER_DAY;
+
+    const MINUTE: Millis = 60 * 1000;
+
+    /// Runs the standby logic once a second, like the switch polling does more often.
+    struct Harness {
+        now: Millis,
+        standby: Standby,
+        wall_clock: WallClock,
+        config: StandbyConfig,
+        state: MachineState,
+    }
+
+    impl Harness {
+        fn new(config: StandbyConfig) -> Self {
+            Self {
+                now: 0,
+                standby: Standby::new(0),
+                wall_clock: WallClock::new(),
+                config,
+                state: MachineState::Ready,
+            }
+        }
+
+        /// Advances the virtual clock, returns the uptime of the first event and the event.
+        fn run_for(&mut self, duration: Millis) -> Option<(Millis, Event)> {
+            let end = self.now + duration;
+            while self.now < end {
+                self.now += 1000;
+                let event =
+                    self.standby
+                        .check(self.now, self.state, &self.wall_clock, &self.config);
+                if let Some(event) = event {
+                    self.state = self.state.next(event).unwrap_or(self.state);
+                    return Some((self.now, event));
+                }
+            }
+            None
+        }
+    }
+
+    fn config(idle_timeout: Option<Millis>, wake_at: Option<u32>) -> StandbyConfig {
+        StandbyConfig {
+            idle_timeout,
+            action: StandbyAction::Off,
+            wake_at,
+        }
+    }
+
+    #[test]
+    fn idle_timeout_requests_standby() {
+        let mut harness = Harness::new(config(Some(30 * MINUTE), None));
+
+        assert_eq!(
+            harness.run_for(31 * MINUTE),
+            Some((30 * MINUTE, Event::StandbyRequested))
+        );
+        assert_eq!(harness.state, MachineState::Standby);
+        // Without a wake-up time it stays there.
+        assert_eq!(harness.run_for(24 * 60 * MINUTE), None);
+    }
+
+    #[test]
+    fn activity_restarts_the_idle_timer() {
+        let mut harness = Harness::new(config(Some(30 * MINUTE), None));
+
+        assert_eq!(harness.run_for(20 * MINUTE), None);
+        harness.standby.activity(harness.now);
+        assert_eq!(harness.run_for(29 * MINUTE), None);
+        assert_eq!(
+            harness.run_for(2 * MINUTE),
+            Some((50 * MINUTE, Event::StandbyRequested))
+        );
+    }
+
+    #[test]
+    fn no_timeout_never_idles_and_busy_states_are_left_alone() {
+        let mut harness = Harness::new(config(None, None));
+        assert_eq!(harness.run_for(24 * 60 * MINUTE), None);
+
+        let mut harness = Harness::new(config(Some(MINUTE), None));
+        for &state in [
+            MachineState::Booting,
+            MachineState::Brewing,
+            MachineState::Steam,
+            MachineState::Fault,
+        ]
+        .iter()
+        {
+            harness.state = state;
+            assert_eq!(harness.run_for(10 * MINUTE), None, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn wakes_at_the_time_of_day() {
+        let mut harness = Harness::new(config(None, Some(7 * 3600)));
+        harness.state = MachineState::Standby;
+        harness.wall_clock.sync(6 * 3600, 0);
+
+        let (at, event) = harness.run_for(2 * 60 * MINUTE).unwrap();
+        assert_eq!(event, Event::Wake);
+        assert_eq!(at, 60 * MINUTE);
+        assert_eq!(harness.state, MachineState::Coldstart);
+    }
+
+    #[test]
+    fn wakes_across_midnight() {
+        let mut harness = Harness::new(config(None, Some(10)));
+        harness.state = MachineState::Standby;
+        harness.wall_clock.sync(SECONDS_PER_DAY - 30, 0);
+
+        assert_eq!(harness.run_for(40 * 1000), Some((40 * 1000, Event::Wake)));
+    }
+
+    #[test]
+    fn wakes_at_midnight_and_once_a_day() {
+        let mut harness = Harness::new(config(None, Some(0)));
+        harness.state = MachineState::Standby;
+        harness.wall_clock.sync(SECONDS_PER_DAY - 5, 0);
+
+        assert_eq!(harness.run_for(MINUTE), Some((5 * 1000, Event::Wake)));
+
+        // Back in standby right after, the next wake-up is a day later.
+        harness.state = MachineState::Standby;
+        assert_eq!(harness.run_for(24 * 60 * MINUTE - 10 * 1000), None);
+        assert!(harness.run_for(MINUTE).is_some());
+    }
+
+    #[test]
+    fn never_wakes_without_the_time() {
+        let mut harness = Harness::new(config(None, Some(7 * 3600)));
+        harness.state = MachineState::Standby;
+
+        assert_eq!(harness.run_for(2 * 24 * 60 * MINUTE), None);
+    }
+
+    #[test]
+    fn crossed_handles_the_wrap() {
+        assert!(crossed(100, 200, 200));
+        assert!(!crossed(100, 200, 100));
+        assert!(!crossed(100, 200, 201));
+        assert!(!crossed(100, 100, 100));
+
+        let last = SECONDS_PER_DAY - 1;
+        assert!(crossed(last - 1, 1, last));
+        assert!(crossed(last - 1, 1, 0));
+        assert!(crossed(last - 1, 1, 1));
+        assert!(!crossed(last - 1, 1, 2));
+        assert!(!crossed(last - 1, 1, last - 1));
+    }
+}
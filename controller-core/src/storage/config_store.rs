@@ -0,0 +1,709 @@
+//! Keeps the [`Settings`] in flash.
+//!
+//! Two pages are split into fixed slots and every save goes into the next free slot, so a page
+//! is only erased once all of its slots are used up. Each record carries a sequence number and
+//! a CRC, the valid record with the highest sequence number wins when loading. Since the page
+//! with the newest record is never erased, a power loss during a save loses at most that save.
+
+use super::codec::{Reader, Writer};
+use super::{crc32, Flash, PAGE_SIZE};
+use crate::brew::{BrewModel, MAX_CURVE_POINTS};
+use crate::calibration::Calibration;
+use crate::filter::FilterConfig;
+use crate::ready::ReadyConfig;
+use crate::sensor::HealthConfig;
+use crate::settings::{Gains, Settings, Target};
+use crate::standby::{StandbyAction, StandbyConfig};
+
+/// The current layout of the encoded settings.
+///
+/// - 1: the layout of the first firmware which stored settings. The sensor health thresholds
+///   were constants back then and are not part of it.
+/// - 2: sensor health thresholds inserted after the filter parameters.
+///
+/// Every version has been written to controllers out there, so the layout of a version never
+/// changes once it is released and older ones stay readable through [`MIGRATIONS`]. A layout
+/// change gets a new version and a migration step.
+pub const CONFIG_VERSION: u16 = 2;
+
+/// Upgrades a payload from version `n + 1` to `n + 2` in place, returning the new length.
+type Migration = fn(&mut [u8], usize) -> Option<usize>;
+
+/// The upgrade steps, `MIGRATIONS[0]` takes a version 1 payload to version 2 and so on.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize - 1] = [migrate_v1_to_v2];
+
+/// Where the sensor health thresholds start in a version 2 payload.
+const V2_SENSOR_HEALTH_OFFSET: usize = 76;
+
+const MAGIC: u32 = 0x4449_5052; // "RPID"
+const SLOT_SIZE: usize = 256;
+const SLOTS_PER_PAGE: u32 = PAGE_SIZE / SLOT_SIZE as u32;
+/// magic, sequence, version and payload length.
+const HEADER_SIZE: usize = 12;
+const CRC_SIZE: usize = 4;
+const MAX_PAYLOAD: usize = SLOT_SIZE - HEADER_SIZE - CRC_SIZE;
+
+/// Why the defaults are used instead of the stored settings.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DefaultsReason {
+    /// Nothing has been saved yet.
+    Empty,
+    /// There are records, but none of them has a valid CRC and layout.
+    Corrupt,
+    /// The newest record was written in a layout this firmware does not know.
+    UnknownVersion(u16),
+}
+
+/// Errors which can happen when saving.
+#[derive(Debug)]
+pub enum StoreError<E> {
+    Flash(E),
+    /// The settings do not fit into a slot.
+    TooLarge,
+}
+
+pub struct ConfigStore {
+    base: u32,
+    /// The slot the next record goes to (0 .. 2 * SLOTS_PER_PAGE).
+    next_slot: u32,
+    next_seq: u32,
+    /// No slot has been written to, as opposed to only holding broken records.
+    empty: bool,
+}
+
+struct Record {
+    slot: u32,
+    seq: u32,
+    version: u16,
+    len: usize,
+}
+
+impl ConfigStore {
+    /// Creates the store on the two pages starting at `base`.
+    pub fn new<F: Flash>(flash: &F, base: u32) -> Self {
+        let mut store = Self {
+            base,
+            next_slot: 0,
+            next_seq: 0,
+            empty: true,
+        };
+        store.scan(flash);
+        store
+    }
+
+    /// Loads the newest valid settings and the layout version they were stored in.
+    ///
+    /// If nothing usable is stored, the reason is returned and the caller falls back to the
+    /// defaults.
+    pub fn load<F: Flash>(&self, flash: &F) -> Result<(Settings, u16), DefaultsReason> {
+        let record = match self.newest_record(flash) {
+            Some(record) => record,
+            None if self.empty => return Err(DefaultsReason::Empty),
+            None => return Err(DefaultsReason::Corrupt),
+        };
+
+        let mut slot = [0u8; SLOT_SIZE];
+        if flash
+            .read(self.slot_address(record.slot), &mut slot)
+            .is_err()
+        {
+            return Err(DefaultsReason::Corrupt);
+        }
+        let payload = &mut slot[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD];
+
+        let len = migrate(record.version, payload, record.len)?;
+        decode(&payload[..len])
+            .map(|settings| (settings, record.version))
+            .ok_or(DefaultsReason::Corrupt)
+    }
+
+    /// Appends the settings as a new record.
+    pub fn save<F: Flash>(
+        &mut self,
+        flash: &mut F,
+        settings: &Settings,
+    ) -> Result<(), StoreError<F::Error>> {
+        let mut slot = [0xFFu8; SLOT_SIZE];
+        let len = encode(settings, &mut slot[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD])
+            .ok_or(StoreError::TooLarge)?;
+
+        let seq = self.next_seq;
+        slot[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        slot[4..8].copy_from_slice(&seq.to_le_bytes());
+        slot[8..10].copy_from_slice(&CONFIG_VERSION.to_le_bytes());
+        slot[10..12].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = crc32(&slot[4..HEADER_SIZE + len]);
+        slot[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        if self.next_slot % SLOTS_PER_PAGE == 0 {
+            // Starting a page, wipe whatever old records it still holds.
+            flash
+                .erase_page(self.base + (self.next_slot / SLOTS_PER_PAGE) * PAGE_SIZE)
+                .map_err(StoreError::Flash)?;
+        }
+
+        self.empty = false;
+        let mut words = [0u32; SLOT_SIZE / 4];
+        for (word, chunk) in words.iter_mut().zip(slot.chunks(4)) {
+            let mut raw = [0; 4];
+            raw.copy_from_slice(chunk);
+            *word = u32::from_le_bytes(raw);
+        }
+        let used_words = (HEADER_SIZE + len + CRC_SIZE + 3) / 4;
+        flash
+            .write(self.slot_address(self.next_slot), &words[..used_words])
+            .map_err(StoreError::Flash)?;
+
+        self.next_slot = (self.next_slot + 1) % (2 * SLOTS_PER_PAGE);
+        self.next_seq = seq.wrapping_add(1);
+        Ok(())
+    }
+
+    fn slot_address(&self, slot: u32) -> u32 {
+        self.base + slot * SLOT_SIZE as u32
+    }
+
+    /// Finds where the next record goes.
+    fn scan<F: Flash>(&mut self, flash: &F) {
+        self.empty = (0..2 * SLOTS_PER_PAGE).all(|slot| self.is_blank(flash, slot));
+
+        // Only complete records count, the sequence number of a torn or corrupt one can be
+        // anything and a too high one would make every later save lose against older records.
+        if let Some(record) = self.newest_record(flash) {
+            self.next_slot = (record.slot + 1) % (2 * SLOTS_PER_PAGE);
+            self.next_seq = record.seq.wrapping_add(1);
+        }
+
+        // A torn or corrupt record behind the newest one left its slot dirty, writing over it
+        // would break the next record as well. Slots starting a page are erased before they
+        // are written.
+        while self.next_slot % SLOTS_PER_PAGE != 0 && !self.is_blank(flash, self.next_slot) {
+            self.next_slot = (self.next_slot + 1) % (2 * SLOTS_PER_PAGE);
+        }
+    }
+
+    fn is_blank<F: Flash>(&self, flash: &F, slot: u32) -> bool {
+        let mut data = [0u8; SLOT_SIZE];
+        flash.read(self.slot_address(slot), &mut data).is_ok() && data.iter().all(|b| *b == 0xFF)
+    }
+
+    /// The valid record with the highest sequence number.
+    fn newest_record<F: Flash>(&self, flash: &F) -> Option<Record> {
+        let mut newest: Option<Record> = None;
+        for slot in 0..2 * SLOTS_PER_PAGE {
+            let mut data = [0u8; SLOT_SIZE];
+            if flash.read(self.slot_address(slot), &mut data).is_err() {
+                continue;
+            }
+            let record = match parse_record(slot, &data) {
+                Some(record) => record,
+                None => continue,
+            };
+            let newer = match newest {
+                Some(ref n) => record.seq > n.seq,
+                None => true,
+            };
+            if newer {
+                newest = Some(record);
+            }
+        }
+        newest
+    }
+}
+
+fn parse_record(slot: u32, data: &[u8; SLOT_SIZE]) -> Option<Record> {
+    let mut reader = Reader::new(&data[..HEADER_SIZE]);
+    if reader.u32()? != MAGIC {
+        return None;
+    }
+    let seq = reader.u32()?;
+    let version = reader.u16()?;
+    let len = reader.u16()? as usize;
+    if len > MAX_PAYLOAD {
+        return None;
+    }
+
+    let mut reader = Reader::new(&data[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE]);
+    if reader.u32()? != crc32(&data[4..HEADER_SIZE + len]) {
+        return None;
+    }
+
+    Some(Record {
+        slot,
+        seq,
+        version,
+        len,
+    })
+}
+
+/// Brings a payload of `version` up to [`CONFIG_VERSION`], returning its new length.
+pub fn migrate(version: u16, payload: &mut [u8], len: usize) -> Result<usize, DefaultsReason> {
+    if version == 0 {
+        return Err(DefaultsReason::Corrupt);
+    }
+    if version > CONFIG_VERSION {
+        return Err(DefaultsReason::UnknownVersion(version));
+    }
+
+    let mut len = len;
+    for step in &MIGRATIONS[version as usize - 1..] {
+        len = step(payload, len).ok_or(DefaultsReason::Corrupt)?;
+    }
+    Ok(len)
+}
+
+/// Version 2 made the sensor health thresholds configurable, version 1 records get the
+/// defaults, which are the constants the firmware used before.
+fn migrate_v1_to_v2(payload: &mut [u8], len: usize) -> Option<usize> {
+    let defaults = Settings::default().sensor_health;
+    insert(
+        payload,
+        len,
+        V2_SENSOR_HEALTH_OFFSET,
+        &[defaults.failed_after, defaults.recover_after],
+    )
+}
+
+/// Inserts `data` at `offset`, moving everything behind it back.
+fn insert(payload: &mut [u8], len: usize, offset: usize, data: &[u8]) -> Option<usize> {
+    let new_len = len + data.len();
+    if offset > len || new_len > payload.len() {
+        return None;
+    }
+    payload.copy_within(offset..len, offset + data.len());
+    payload[offset..offset + data.len()].copy_from_slice(data);
+    Some(new_len)
+}
+
+/// Encodes the settings in the current layout, `None` if the buffer is too small.
+pub fn encode(settings: &Settings, buf: &mut [u8]) -> Option<usize> {
+    let mut w = Writer::new(buf);
+
+    match settings.target {
+        Target::Boiler(temp) => {
+            w.u8(0);
+            w.f32(temp);
+        }
+        Target::Brew(temp) => {
+            w.u8(1);
+            w.f32(temp);
+        }
+    }
+    put_gains(&mut w, settings.cold_gains);
+    put_gains(&mut w, settings.warm_gains);
+    w.f32(settings.steam_temp);
+    put_gains(&mut w, settings.steam_gains);
+    put_opt_u64(&mut w, settings.steam_timeout);
+    w.u32(settings.window_size);
+    w.u64(settings.max_reading_age);
+
+    w.u8(settings.filter.median_len as u8);
+    w.f32(settings.filter.ema_alpha);
+    w.f32(settings.filter.max_delta);
+    w.u8(settings.filter.max_rejects);
+    w.u8(settings.sensor_health.failed_after);
+    w.u8(settings.sensor_health.recover_after);
+
+    let (tag, values) = match settings.calibration {
+        Calibration::None => (0, [0.0; 4]),
+        Calibration::Offset(offset) => (1, [offset, 0.0, 0.0, 0.0]),
+        Calibration::TwoPoint(two_point) => (2, two_point.points()),
+    };
+    w.u8(tag);
+    for value in values.iter() {
+        w.f32(*value);
+    }
+
+    let curve = settings.brew_model.curve();
+    w.u8(curve.len() as u8);
+    for i in 0..MAX_CURVE_POINTS {
+        let (boiler, offset) = curve.get(i).copied().unwrap_or((0.0, 0.0));
+        w.f32(boiler);
+        w.f32(offset);
+    }
+    w.u32(settings.brew_model.lag_ms());
+    w.f32(settings.brew_model.heater_gain());
+
+    w.f32(settings.ready.band);
+    w.u64(settings.ready.dwell_ms);
+    w.f32(settings.ready.duty_tolerance);
+
+    put_opt_u64(&mut w, settings.standby.idle_timeout);
+    match settings.standby.action {
+        StandbyAction::Off => {
+            w.u8(0);
+            w.f32(0.0);
+        }
+        StandbyAction::Eco(temp) => {
+            w.u8(1);
+            w.f32(temp);
+        }
+    }
+    put_opt_u64(&mut w, settings.standby.wake_at.map(u64::from));
+
+    w.finish()
+}
+
+/// Decodes settings in the current layout, `None` if the data is truncated, has trailing bytes
+/// or is out of range.
+pub fn decode(buf: &[u8]) -> Option<Settings> {
+    let mut r = Reader::new(buf);
+
+    let target = match (r.u8()?, r.f32()?) {
+        (0, temp) => Target::Boiler(temp),
+        (1, temp) => Target::Brew(temp),
+        _ => return None,
+    };
+    let cold_gains = get_gains(&mut r)?;
+    let warm_gains = get_gains(&mut r)?;
+    let steam_temp = r.f32()?;
+    let steam_gains = get_gains(&mut r)?;
+    let steam_timeout = get_opt_u64(&mut r)?;
+    let window_size = r.u32()?;
+    let max_reading_age = r.u64()?;
+
+    let filter = FilterConfig::new(r.u8()? as usize, r.f32()?, r.f32()?, r.u8()?);
+    let sensor_health = HealthConfig {
+        failed_after: r.u8()?,
+        recover_after: r.u8()?,
+    };
+
+    let tag = r.u8()?;
+    let values = [r.f32()?, r.f32()?, r.f32()?, r.f32()?];
+    let calibration = match tag {
+        0 => Calibration::None,
+        1 => Calibration::Offset(values[0]),
+        2 => Calibration::two_point(values[0], values[1], values[2], values[3])?,
+        _ => return None,
+    };
+
+    let curve_len = r.u8()? as usize;
+    let mut curve = [(0.0, 0.0); MAX_CURVE_POINTS];
+    for point in curve.iter_mut() {
+        *point = (r.f32()?, r.f32()?);
+    }
+    if curve_len > MAX_CURVE_POINTS {
+        return None;
+    }
+    let brew_model = BrewModel::new(&curve[..curve_len], r.u32()?, r.f32()?)?;
+
+    let ready = ReadyConfig {
+        band: r.f32()?,
+        dwell_ms: r.u64()?,
+        duty_tolerance: r.f32()?,
+    };
+
+    let idle_timeout = get_opt_u64(&mut r)?;
+    let action = match (r.u8()?, r.f32()?) {
+        (0, _) => StandbyAction::Off,
+        (1, temp) => StandbyAction::Eco(temp),
+        _ => return None,
+    };
+    let wake_at = get_opt_u64(&mut r)?.map(|w| w as u32);
+    if r.remaining() != 0 {
+        return None;
+    }
+
+    let settings = Settings {
+        target,
+        cold_gains,
+        warm_gains,
+        steam_temp,
+        steam_gains,
+        steam_timeout,
+        window_size,
+        max_reading_age,
+        filter,
+        sensor_health,
+        calibration,
+        brew_model,
+        ready,
+        standby: StandbyConfig {
+            idle_timeout,
+            action,
+            wake_at,
+        },
+    };
+    settings.validate().ok()?;
+    Some(settings)
+}
+
+fn put_gains(w: &mut Writer, gains: Gains) {
+    w.f32(gains.kp);
+    w.f32(gains.ki);
+    w.f32(gains.kd);
+}
+
+fn get_gains(r: &mut Reader) -> Option<Gains> {
+    Some(Gains::new(r.f32()?, r.f32()?, r.f32()?))
+}
+
+fn put_opt_u64(w: &mut Writer, value: Option<u64>) {
+    w.bool(value.is_some());
+    w.u64(value.unwrap_or(0));
+}
+
+fn get_opt_u64(r: &mut Reader) -> Option<Option<u64>> {
+    let present = r.bool()?;
+    let value = r.u64()?;
+    Some(if present { Some(value) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mock::{MockError, MockFlash};
+    use crate::storage::CONFIG_PAGES;
+
+    const SLOTS: u32 = 2 * SLOTS_PER_PAGE;
+
+    fn flash() -> MockFlash {
+        MockFlash::new(CONFIG_PAGES, 2)
+    }
+
+    /// Settings which differ from the defaults wherever they can.
+    fn custom() -> Settings {
+        Settings {
+            target: Target::Brew(92.5),
+            warm_gains: Gains::new(60.0, 0.2, 1.5),
+            steam_timeout: None,
+            calibration: Calibration::two_point(99.0, 100.0, 90.0, 92.0).unwrap(),
+            brew_model: BrewModel::new(&[(90.0, -4.0), (100.0, -6.0)], 20_000, 0.1).unwrap(),
+            standby: StandbyConfig {
+                idle_timeout: Some(10 * 60 * 1000),
+                action: StandbyAction::Eco(70.0),
+                wake_at: Some(6 * 60 * 60),
+            },
+            ..Settings::default()
+        }
+    }
+
+    /// The settings with the window size doubling as a marker.
+    fn numbered(n: u32) -> Settings {
+        Settings {
+            window_size: 1000 + n,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let mut buf = [0u8; MAX_PAYLOAD];
+        for settings in [Settings::default(), custom()].iter() {
+            let len = encode(settings, &mut buf).unwrap();
+            assert_eq!(decode(&buf[..len]), Some(*settings));
+            assert_eq!(decode(&buf[..len - 1]), None);
+        }
+    }
+
+    #[test]
+    fn saved_settings_survive_a_restart() {
+        let mut flash = flash();
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash), Err(DefaultsReason::Empty));
+
+        let mut store = store;
+        store.save(&mut flash, &custom()).unwrap();
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash), Ok((custom(), CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn the_newest_record_wins() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        for n in 0..5 {
+            store.save(&mut flash, &numbered(n)).unwrap();
+        }
+        assert_eq!(store.load(&flash).unwrap().0, numbered(4));
+
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(5)).unwrap();
+        assert_eq!(store.load(&flash).unwrap().0, numbered(5));
+    }
+
+    #[test]
+    fn a_torn_save_keeps_the_previous_settings() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(1)).unwrap();
+
+        // The header made it, the payload and the CRC did not.
+        flash.cut_power_after(3);
+        assert!(matches!(
+            store.save(&mut flash, &numbered(2)),
+            Err(StoreError::Flash(MockError::PowerLost))
+        ));
+        flash.power_on();
+
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash).unwrap().0, numbered(1));
+
+        // The torn slot is skipped, the next save goes behind it.
+        store.save(&mut flash, &numbered(3)).unwrap();
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash).unwrap().0, numbered(3));
+    }
+
+    #[test]
+    fn a_corrupt_record_is_not_loaded() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(1)).unwrap();
+        store.save(&mut flash, &numbered(2)).unwrap();
+
+        flash.corrupt(CONFIG_PAGES + SLOT_SIZE as u32 + 20, 0x01);
+        assert_eq!(store.load(&flash).unwrap().0, numbered(1));
+
+        flash.corrupt(CONFIG_PAGES + 20, 0x01);
+        assert_eq!(store.load(&flash), Err(DefaultsReason::Corrupt));
+    }
+
+    #[test]
+    fn a_corrupt_sequence_number_does_not_outrank_later_saves() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(1)).unwrap();
+        store.save(&mut flash, &numbered(2)).unwrap();
+
+        // Flip the newest sequence number from 1 to 0xFFFFFFFF, which breaks its CRC. Were it
+        // trusted anyway, the next sequence number would wrap to 0 and the following save would
+        // lose against numbered(1).
+        let seq_address = CONFIG_PAGES + SLOT_SIZE as u32 + 4;
+        for (offset, mask) in [0xFE, 0xFF, 0xFF, 0xFF].iter().enumerate() {
+            flash.corrupt(seq_address + offset as u32, *mask);
+        }
+
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash).unwrap().0, numbered(1));
+        store.save(&mut flash, &numbered(3)).unwrap();
+
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash).unwrap().0, numbered(3));
+    }
+
+    #[test]
+    fn only_broken_records_are_corrupt_not_empty() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(1)).unwrap();
+        flash.corrupt(CONFIG_PAGES + 20, 0x01);
+
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(store.load(&flash), Err(DefaultsReason::Corrupt));
+    }
+
+    #[test]
+    fn saves_wear_every_slot_evenly() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        let laps = 3;
+        for n in 0..laps * SLOTS {
+            // Reopening in between must not change where the records go.
+            if n % 7 == 0 {
+                store = ConfigStore::new(&flash, CONFIG_PAGES);
+            }
+            store.save(&mut flash, &numbered(n)).unwrap();
+            assert_eq!(store.load(&flash).unwrap().0, numbered(n));
+        }
+
+        assert_eq!(flash.erases, vec![laps; 2]);
+        for slot in 0..SLOTS {
+            let word = (slot * SLOT_SIZE as u32 / 4) as usize;
+            assert_eq!(flash.writes[word], laps, "slot {}", slot);
+        }
+    }
+
+    #[test]
+    fn records_of_newer_firmware_are_not_loaded() {
+        let mut flash = flash();
+        let mut store = ConfigStore::new(&flash, CONFIG_PAGES);
+        store.save(&mut flash, &numbered(1)).unwrap();
+
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let len = encode(&numbered(2), &mut payload).unwrap();
+        write_record(&mut flash, 1, 1, CONFIG_VERSION + 1, &payload[..len]);
+
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        assert_eq!(
+            store.load(&flash),
+            Err(DefaultsReason::UnknownVersion(CONFIG_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn version_1_records_are_migrated() {
+        let stored = Settings {
+            sensor_health: HealthConfig {
+                failed_after: 9,
+                recover_after: 9,
+            },
+            ..custom()
+        };
+        // Version 1 is the current layout without the sensor health thresholds.
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let len = encode(&stored, &mut payload).unwrap();
+        payload.copy_within(V2_SENSOR_HEALTH_OFFSET + 2..len, V2_SENSOR_HEALTH_OFFSET);
+        let len = len - 2;
+
+        let mut flash = flash();
+        write_record(&mut flash, 0, 0, 1, &payload[..len]);
+
+        let store = ConfigStore::new(&flash, CONFIG_PAGES);
+        let (settings, version) = store.load(&flash).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(settings.sensor_health, Settings::default().sensor_health);
+        assert_eq!(
+            settings,
+            Settings {
+                sensor_health: Settings::default().sensor_health,
+                ..stored
+            }
+        );
+
+        // Saving again stores the current layout.
+        let mut store = store;
+        store.save(&mut flash, &settings).unwrap();
+        assert_eq!(store.load(&flash), Ok((settings, CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn migrations_reject_bad_versions_and_payloads() {
+        let mut payload = [0u8; MAX_PAYLOAD];
+        assert_eq!(migrate(0, &mut payload, 10), Err(DefaultsReason::Corrupt));
+        assert_eq!(
+            migrate(CONFIG_VERSION + 1, &mut payload, 10),
+            Err(DefaultsReason::UnknownVersion(CONFIG_VERSION + 1))
+        );
+        // Too short to hold the fields in front of the inserted ones.
+        assert_eq!(migrate(1, &mut payload, 10), Err(DefaultsReason::Corrupt));
+        // No room left to grow.
+        assert_eq!(
+            migrate(1, &mut payload, MAX_PAYLOAD),
+            Err(DefaultsReason::Corrupt)
+        );
+        assert_eq!(migrate(CONFIG_VERSION, &mut payload, 10), Ok(10));
+    }
+
+    /// Writes a record like an older or newer firmware would have.
+    fn write_record(flash: &mut MockFlash, slot: u32, seq: u32, version: u16, payload: &[u8]) {
+        let mut raw = [0xFFu8; SLOT_SIZE];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..8].copy_from_slice(&seq.to_le_bytes());
+        raw[8..10].copy_from_slice(&version.to_le_bytes());
+        raw[10..12].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        raw[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        let crc = crc32(&raw[4..HEADER_SIZE + payload.len()]);
+        let end = HEADER_SIZE + payload.len();
+        raw[end..end + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        let words: Vec<u32> = raw
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        flash
+            .write(CONFIG_PAGES + slot * SLOT_SIZE as u32, &words)
+            .unwrap();
+    }
+}
@@ -0,0 +1,674 @@
+//! An append-only log of what happened to the controller, kept in flash across resets.
+//!
+//! Records have a fixed size and go into a ring of pages. Every record carries a sequence
+//! number which also determines its slot, so the ring position never has to be stored
+//! separately. Once the ring is full, the page holding the oldest records is erased.
+//!
+//! Records are only queued by [`EventLog::record`], writing them out is left to
+//! [`EventLog::flush`] so the control tasks never wait for a page erase.
+
+use super::codec::{Reader, Writer};
+use super::config_store::DefaultsReason;
+use super::{crc32, Flash, PAGE_SIZE};
+use crate::clock::Millis;
+use crate::machine::MachineState;
+use crate::safety::CutoffReason;
+use crate::supervisor::Task;
+use core::fmt::{self, Write};
+use heapless::consts::U8;
+use heapless::Vec;
+use protocol::events::{self, Event, LoggedEvent, Text};
+
+/// The pages holding the log, 0xE7000 up to the configuration pages at 0xEB000.
+pub const EVENT_LOG_PAGES: u32 = 0x000E_7000;
+/// The number of pages in the ring.
+pub const EVENT_LOG_PAGE_COUNT: u32 = 4;
+
+pub const RECORD_SIZE: usize = 32;
+const RECORDS_PER_PAGE: u32 = PAGE_SIZE / RECORD_SIZE as u32;
+const RECORDS: u32 = EVENT_LOG_PAGE_COUNT * RECORDS_PER_PAGE;
+/// sequence number, timestamp and tag.
+const HEADER_SIZE: usize = 13;
+const CRC_SIZE: usize = 4;
+const DATA_SIZE: usize = RECORD_SIZE - HEADER_SIZE - CRC_SIZE;
+
+/// The longest text kept in a record.
+pub const MAX_SHORT_TEXT: usize = DATA_SIZE - 3;
+
+/// A bit of text which fits into a record, cut off at [`MAX_SHORT_TEXT`] bytes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ShortText {
+    len: u8,
+    text: [u8; MAX_SHORT_TEXT],
+}
+
+impl ShortText {
+    fn empty() -> Self {
+        Self {
+            len: 0,
+            text: [0; MAX_SHORT_TEXT],
+        }
+    }
+
+    /// The name of an error, as printed by its `Debug` implementation.
+    pub fn of_debug<E: fmt::Debug>(error: &E) -> Self {
+        let mut text = Self::empty();
+        // Running out of space only cuts the name short.
+        write!(text, "{:?}", error).ok();
+        text
+    }
+
+    /// The end of `s`, which is the interesting part of a file path.
+    pub fn tail(s: &str) -> Self {
+        let mut start = s.len().saturating_sub(MAX_SHORT_TEXT);
+        while !s.is_char_boundary(start) {
+            start += 1;
+        }
+        let mut text = Self::empty();
+        text.write_str(&s[start..]).ok();
+        text
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.text[..self.len as usize]).unwrap_or("?")
+    }
+}
+
+impl Write for ShortText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = self.len as usize;
+            if len + c.len_utf8() > MAX_SHORT_TEXT {
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.text[len..]);
+            self.len += c.len_utf8() as u8;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ShortText {
+    fn format(&self, f: &mut defmt::Formatter) {
+        defmt::write!(f, "{:str}", self.as_str());
+    }
+}
+
+/// Everything which ends up in the log.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogEvent {
+    /// The controller started, with the raw `RESETREAS` register telling why.
+    Boot { resetreas: u32 },
+    /// The sensor started failing, logged once per run of failed reads.
+    SensorFailure { error: ShortText },
+    /// The machine changed its state.
+    ModeChange {
+        from: MachineState,
+        to: MachineState,
+    },
+    /// A safety limit cut the heater off.
+    FaultLatched(CutoffReason),
+    /// The safety cutoff cleared again.
+    FaultCleared,
+    /// The settings changed at runtime, they are not stored before the next save.
+    ConfigChanged,
+    /// Settings have been written to flash in the given layout version.
+    ConfigSaved { version: u16 },
+    /// The stored settings were unusable and the defaults are used.
+    ConfigDefaults(DefaultsReason),
+    /// The previous run panicked, see [`crate::crash`] for the full message.
+    Panic { file: ShortText, line: u16 },
+    /// The previous run ended in a HardFault at `pc`.
+    HardFault { pc: u32, lr: u32 },
+    /// The previous run was reset by the watchdog because the task stopped checking in.
+    TaskStuck(Task),
+}
+
+/// A single entry of the log.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogRecord {
+    pub seq: u32,
+    /// Milliseconds since the boot the record was taken in.
+    pub at: Millis,
+    pub event: LogEvent,
+}
+
+impl From<LogRecord> for LoggedEvent {
+    fn from(record: LogRecord) -> Self {
+        let event = match record.event {
+            LogEvent::Boot { resetreas } => Event::Boot { resetreas },
+            LogEvent::SensorFailure { error } => Event::SensorFailure {
+                error: Text::new(error.as_str()),
+            },
+            LogEvent::ModeChange { from, to } => Event::ModeChange {
+                from: from.into(),
+                to: to.into(),
+            },
+            LogEvent::FaultLatched(reason) => Event::FaultLatched(match reason {
+                CutoffReason::OverTemperature => events::Cutoff::OverTemperature,
+                CutoffReason::RateOfRise => events::Cutoff::RateOfRise,
+                CutoffReason::StuckSensor => events::Cutoff::StuckSensor,
+            }),
+            LogEvent::FaultCleared => Event::FaultCleared,
+            LogEvent::ConfigChanged => Event::ConfigChanged,
+            LogEvent::ConfigSaved { version } => Event::ConfigSaved { version },
+            LogEvent::ConfigDefaults(reason) => Event::ConfigDefaults(match reason {
+                DefaultsReason::Empty => events::DefaultsReason::Empty,
+                DefaultsReason::Corrupt => events::DefaultsReason::Corrupt,
+                DefaultsReason::UnknownVersion(version) => {
+                    events::DefaultsReason::UnknownVersion(version)
+                }
+            }),
+            LogEvent::Panic { file, line } => Event::Panic {
+                file: Text::new(file.as_str()),
+                line,
+            },
+            LogEvent::HardFault { pc, lr } => Event::HardFault { pc, lr },
+            LogEvent::TaskStuck(task) => Event::TaskStuck(match task {
+                Task::Measure => events::Task::Measure,
+                Task::HeaterDrive => events::Task::HeaterDrive,
+                Task::Display => events::Task::Display,
+            }),
+        };
+        LoggedEvent {
+            seq: record.seq,
+            at: record.at,
+            event,
+        }
+    }
+}
+
+pub struct EventLog {
+    base: u32,
+    next_seq: u32,
+    pending: Vec<LogRecord, U8>,
+    dropped: u32,
+}
+
+impl EventLog {
+    /// Opens the log on the pages starting at `base`, continuing after the newest record.
+    pub fn new<F: Flash>(flash: &F, base: u32) -> Self {
+        let mut log = Self {
+            base,
+            next_seq: 0,
+            pending: Vec::new(),
+            dropped: 0,
+        };
+        for slot in 0..RECORDS {
+            let mut raw = [0u8; RECORD_SIZE];
+            if flash.read(log.address(slot), &mut raw).is_err() {
+                continue;
+            }
+            // Only complete records count, the sequence number of a torn one can be anything,
+            // including the 0xFFFFFFFF of a slot which was erased.
+            if let Some(seq) = checked_seq(&raw) {
+                if seq >= log.next_seq {
+                    log.next_seq = seq.saturating_add(1);
+                }
+            }
+        }
+
+        // A torn record behind the newest one left its slot dirty, writing over it would tear
+        // the next record as well. Slots starting a page are erased before they are written.
+        while log.next_seq % RECORDS_PER_PAGE != 0 && !log.is_blank(flash, log.next_seq % RECORDS) {
+            log.next_seq += 1;
+        }
+        log
+    }
+
+    /// Queues an event, it is written with the next [`EventLog::flush`].
+    pub fn record(&mut self, at: Millis, event: LogEvent) {
+        let record = LogRecord {
+            seq: self.next_seq,
+            at,
+            event,
+        };
+        if self.pending.push(record).is_err() {
+            self.dropped += 1;
+            return;
+        }
+        self.next_seq += 1;
+    }
+
+    /// Writes all queued records to flash.
+    ///
+    /// A record which fails to write is dropped, its sequence number stays unused.
+    pub fn flush<F: Flash>(&mut self, flash: &mut F) -> Result<(), F::Error> {
+        let mut result = Ok(());
+        for record in self.pending.iter() {
+            let slot = record.seq % RECORDS;
+            let address = self.address(slot);
+            if slot % RECORDS_PER_PAGE == 0 {
+                // Entering a page, which drops the oldest records in the ring.
+                if let Err(e) = flash.erase_page(address) {
+                    result = Err(e);
+                    continue;
+                }
+            }
+
+            let raw = encode(record);
+            let mut words = [0u32; RECORD_SIZE / 4];
+            for (word, chunk) in words.iter_mut().zip(raw.chunks(4)) {
+                *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+            if let Err(e) = flash.write(address, &words) {
+                result = Err(e);
+            }
+        }
+        // Not `clear`, heapless 0.5 indexes past the end while truncating, which debug builds
+        // stop on as undefined behaviour. Dropping the old queue is sound.
+        self.pending = Vec::new();
+        result
+    }
+
+    /// Reads the record with the given sequence number, if it is still in the ring.
+    pub fn get<F: Flash>(&self, flash: &F, seq: u32) -> Option<LogRecord> {
+        if seq >= self.next_seq {
+            return None;
+        }
+        if let Some(record) = self.pending.iter().find(|r| r.seq == seq) {
+            return Some(*record);
+        }
+
+        let mut raw = [0u8; RECORD_SIZE];
+        flash.read(self.address(seq % RECORDS), &mut raw).ok()?;
+        decode(&raw).filter(|record| record.seq == seq)
+    }
+
+    /// The oldest sequence number which can still be in flash.
+    ///
+    /// Records in between can be missing if their write failed.
+    pub fn oldest_seq(&self) -> u32 {
+        if self.next_seq == 0 {
+            return 0;
+        }
+        let last = self.next_seq - 1;
+        let page_start = last - last % RECORDS_PER_PAGE;
+        page_start.saturating_sub(RECORDS - RECORDS_PER_PAGE)
+    }
+
+    /// The sequence number the next record gets.
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    /// Records lost because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    fn address(&self, slot: u32) -> u32 {
+        self.base + slot * RECORD_SIZE as u32
+    }
+
+    fn is_blank<F: Flash>(&self, flash: &F, slot: u32) -> bool {
+        let mut raw = [0u8; RECORD_SIZE];
+        flash.read(self.address(slot), &mut raw).is_ok() && raw.iter().all(|b| *b == 0xFF)
+    }
+}
+
+/// Encodes a record into its flash layout.
+pub fn encode(record: &LogRecord) -> [u8; RECORD_SIZE] {
+    let mut raw = [0xFFu8; RECORD_SIZE];
+    let mut data = [0u8; DATA_SIZE];
+    let tag = encode_event(&record.event, &mut data);
+
+    let mut w = Writer::new(&mut raw[..HEADER_SIZE + DATA_SIZE]);
+    w.u32(record.seq);
+    w.u64(record.at);
+    w.u8(tag);
+    w.bytes(&data);
+
+    let crc = crc32(&raw[..HEADER_SIZE + DATA_SIZE]);
+    raw[HEADER_SIZE + DATA_SIZE..].copy_from_slice(&crc.to_le_bytes());
+    raw
+}
+
+/// Decodes a record, `None` if the slot is blank, torn or holds an unknown event.
+pub fn decode(raw: &[u8; RECORD_SIZE]) -> Option<LogRecord> {
+    let seq = checked_seq(raw)?;
+    let mut r = Reader::new(&raw[4..HEADER_SIZE + DATA_SIZE]);
+    let at = r.u64()?;
+    let tag = r.u8()?;
+    let event = decode_event(tag, r.bytes(DATA_SIZE)?)?;
+    Some(LogRecord { seq, at, event })
+}
+
+/// The sequence number of a record with a valid CRC.
+fn checked_seq(raw: &[u8; RECORD_SIZE]) -> Option<u32> {
+    let mut r = Reader::new(&raw[HEADER_SIZE + DATA_SIZE..]);
+    if r.u32()? != crc32(&raw[..HEADER_SIZE + DATA_SIZE]) {
+        return None;
+    }
+    Reader::new(raw).u32()
+}
+
+fn encode_event(event: &LogEvent, data: &mut [u8]) -> u8 {
+    let mut w = Writer::new(data);
+    match *event {
+        LogEvent::Boot { resetreas } => {
+            w.u32(resetreas);
+            0
+        }
+        LogEvent::SensorFailure { error } => {
+            put_text(&mut w, &error);
+            1
+        }
+        LogEvent::ModeChange { from, to } => {
+            w.u8(machine_state_to_u8(from));
+            w.u8(machine_state_to_u8(to));
+            2
+        }
+        LogEvent::FaultLatched(reason) => {
+            w.u8(match reason {
+                CutoffReason::OverTemperature => 0,
+                CutoffReason::RateOfRise => 1,
+                CutoffReason::StuckSensor => 2,
+            });
+            3
+        }
+        LogEvent::FaultCleared => 4,
+        LogEvent::ConfigSaved { version } => {
+            w.u16(version);
+            5
+        }
+        LogEvent::ConfigDefaults(reason) => {
+            match reason {
+                DefaultsReason::Empty => w.u8(0),
+                DefaultsReason::Corrupt => w.u8(1),
+                DefaultsReason::UnknownVersion(version) => {
+                    w.u8(2);
+                    w.u16(version);
+                }
+            }
+            6
+        }
+        LogEvent::Panic { file, line } => {
+            put_text(&mut w, &file);
+            w.u16(line);
+            7
+        }
+        LogEvent::HardFault { pc, lr } => {
+            w.u32(pc);
+            w.u32(lr);
+            8
+        }
+        LogEvent::TaskStuck(task) => {
+            w.u8(task.id());
+            9
+        }
+        LogEvent::ConfigChanged => 10,
+    }
+}
+
+fn decode_event(tag: u8, data: &[u8]) -> Option<LogEvent> {
+    let mut r = Reader::new(data);
+    let event = match tag {
+        0 => LogEvent::Boot {
+            resetreas: r.u32()?,
+        },
+        1 => LogEvent::SensorFailure {
+            error: get_text(&mut r)?,
+        },
+        2 => LogEvent::ModeChange {
+            from: machine_state_from_u8(r.u8()?)?,
+            to: machine_state_from_u8(r.u8()?)?,
+        },
+        3 => LogEvent::FaultLatched(match r.u8()? {
+            0 => CutoffReason::OverTemperature,
+            1 => CutoffReason::RateOfRise,
+            2 => CutoffReason::StuckSensor,
+            _ => return None,
+        }),
+        4 => LogEvent::FaultCleared,
+        5 => LogEvent::ConfigSaved { version: r.u16()? },
+        6 => LogEvent::ConfigDefaults(match r.u8()? {
+            0 => DefaultsReason::Empty,
+            1 => DefaultsReason::Corrupt,
+            2 => DefaultsReason::UnknownVersion(r.u16()?),
+            _ => return None,
+        }),
+        7 => LogEvent::Panic {
+            file: get_text(&mut r)?,
+            line: r.u16()?,
+        },
+        8 => LogEvent::HardFault {
+            pc: r.u32()?,
+            lr: r.u32()?,
+        },
+        9 => LogEvent::TaskStuck(Task::from_id(r.u8()?)?),
+        10 => LogEvent::ConfigChanged,
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn put_text(w: &mut Writer, text: &ShortText) {
+    w.u8(text.len);
+    w.bytes(&text.text);
+}
+
+fn get_text(r: &mut Reader) -> Option<ShortText> {
+    let len = r.u8()?;
+    if len as usize > MAX_SHORT_TEXT {
+        return None;
+    }
+    let mut text = [0; MAX_SHORT_TEXT];
+    text.copy_from_slice(r.bytes(MAX_SHORT_TEXT)?);
+    Some(ShortText { len, text })
+}
+
+fn machine_state_to_u8(state: MachineState) -> u8 {
+    match state {
+        MachineState::Booting => 0,
+        MachineState::Coldstart => 1,
+        MachineState::Stabilizing => 2,
+        MachineState::Ready => 3,
+        MachineState::Brewing => 4,
+        MachineState::Steam => 5,
+        MachineState::Standby => 6,
+        MachineState::Fault => 7,
+    }
+}
+
+fn machine_state_from_u8(raw: u8) -> Option<MachineState> {
+    Some(match raw {
+        0 => MachineState::Booting,
+        1 => MachineState::Coldstart,
+        2 => MachineState::Stabilizing,
+        3 => MachineState::Ready,
+        4 => MachineState::Brewing,
+        5 => MachineState::Steam,
+        6 => MachineState::Standby,
+        7 => MachineState::Fault,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::mock::MockFlash;
+
+    fn flash() -> MockFlash {
+        MockFlash::new(EVENT_LOG_PAGES, EVENT_LOG_PAGE_COUNT)
+    }
+
+    /// Appends `count` boot records, each carrying its sequence number.
+    fn append(log: &mut EventLog, flash: &mut MockFlash, count: u32) {
+        for _ in 0..count {
+            let seq = log.next_seq();
+            log.record(u64::from(seq) * 10, LogEvent::Boot { resetreas: seq });
+            log.flush(flash).unwrap();
+        }
+    }
+
+    fn assert_boot(log: &EventLog, flash: &MockFlash, seq: u32) {
+        let record = log.get(flash, seq).unwrap();
+        assert_eq!(record.seq, seq);
+        assert_eq!(record.event, LogEvent::Boot { resetreas: seq });
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let events = [
+            LogEvent::Boot { resetreas: 0x0004 },
+            LogEvent::SensorFailure {
+                error: ShortText::of_debug(&"Timeout"),
+            },
+            LogEvent::ModeChange {
+                from: MachineState::Coldstart,
+                to: MachineState::Fault,
+            },
+            LogEvent::FaultLatched(CutoffReason::OverTemperature),
+            LogEvent::FaultLatched(CutoffReason::RateOfRise),
+            LogEvent::FaultLatched(CutoffReason::StuckSensor),
+            LogEvent::FaultCleared,
+            LogEvent::ConfigChanged,
+            LogEvent::ConfigSaved { version: 2 },
+            LogEvent::ConfigDefaults(DefaultsReason::Empty),
+            LogEvent::ConfigDefaults(DefaultsReason::Corrupt),
+            LogEvent::ConfigDefaults(DefaultsReason::UnknownVersion(7)),
+            LogEvent::Panic {
+                file: ShortText::tail("controller/src/peripherals/boiler.rs"),
+                line: 123,
+            },
+            LogEvent::HardFault {
+                pc: 0x0000_1234,
+                lr: 0xFFFF_FFF9,
+            },
+            LogEvent::TaskStuck(Task::Measure),
+            LogEvent::TaskStuck(Task::HeaterDrive),
+            LogEvent::TaskStuck(Task::Display),
+        ];
+        for (seq, event) in events.iter().enumerate() {
+            let record = LogRecord {
+                seq: seq as u32,
+                at: u64::MAX - seq as u64,
+                event: *event,
+            };
+            let mut raw = encode(&record);
+            assert_eq!(decode(&raw), Some(record), "{:?}", event);
+
+            raw[HEADER_SIZE] ^= 0x80;
+            assert_eq!(decode(&raw), None, "{:?}", event);
+        }
+        assert_eq!(decode(&[0xFF; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn short_text_keeps_what_fits() {
+        let path = ShortText::tail("controller/src/peripherals/boiler.rs");
+        assert_eq!(path.as_str(), "ls/boiler.rs");
+        assert_eq!(path.as_str().len(), MAX_SHORT_TEXT);
+
+        // Cut at a character, not in the middle of one.
+        assert_eq!(ShortText::tail("ßßßßßßßß").as_str(), "ßßßßßß");
+        assert_eq!(ShortText::of_debug(&"Open").as_str(), "\"Open\"");
+    }
+
+    #[test]
+    fn records_survive_a_restart() {
+        let mut flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        assert_eq!(log.next_seq(), 0);
+        assert_eq!(log.get(&flash, 0), None);
+
+        append(&mut log, &mut flash, 3);
+        let log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        assert_eq!(log.next_seq(), 3);
+        assert_eq!(log.oldest_seq(), 0);
+        for seq in 0..3 {
+            assert_boot(&log, &flash, seq);
+        }
+        assert_eq!(log.get(&flash, 3), None);
+    }
+
+    #[test]
+    fn queued_records_are_readable_before_the_flush() {
+        let mut flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        log.record(5, LogEvent::FaultCleared);
+        assert_eq!(log.get(&flash, 0).unwrap().event, LogEvent::FaultCleared);
+        // Not in flash yet.
+        assert_eq!(EventLog::new(&flash, EVENT_LOG_PAGES).next_seq(), 0);
+
+        log.flush(&mut flash).unwrap();
+        assert_eq!(EventLog::new(&flash, EVENT_LOG_PAGES).next_seq(), 1);
+    }
+
+    #[test]
+    fn a_full_queue_drops_records() {
+        let flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        for _ in 0..10 {
+            log.record(0, LogEvent::FaultCleared);
+        }
+        assert_eq!(log.next_seq(), 8);
+        assert_eq!(log.dropped(), 2);
+    }
+
+    #[test]
+    fn the_ring_wraps_around() {
+        let mut flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        let total = RECORDS + RECORDS_PER_PAGE / 2;
+        append(&mut log, &mut flash, total);
+
+        let log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        assert_eq!(log.next_seq(), total);
+        // Writing into the first page again erased its records from the first lap.
+        assert_eq!(log.oldest_seq(), RECORDS_PER_PAGE);
+        for seq in log.oldest_seq()..total {
+            assert_boot(&log, &flash, seq);
+        }
+        assert_eq!(log.get(&flash, log.oldest_seq() - 1), None);
+        assert_eq!(log.get(&flash, 0), None);
+
+        assert_eq!(flash.erases, vec![2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn a_torn_record_is_skipped() {
+        let mut flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        append(&mut log, &mut flash, 3);
+
+        // Only the sequence number made it.
+        flash.cut_power_after(1);
+        log.record(30, LogEvent::FaultCleared);
+        assert!(log.flush(&mut flash).is_err());
+        flash.power_on();
+
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        assert_eq!(log.next_seq(), 4);
+        assert_eq!(log.get(&flash, 3), None);
+
+        append(&mut log, &mut flash, 1);
+        assert_boot(&EventLog::new(&flash, EVENT_LOG_PAGES), &flash, 4);
+    }
+
+    #[test]
+    fn garbage_sequence_numbers_are_ignored() {
+        let mut flash = flash();
+        let mut log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        append(&mut log, &mut flash, 5);
+
+        // A torn record right behind the newest one, whose sequence number has not been
+        // written completely, and one far away which looks erased.
+        let mut torn = [0u32; RECORD_SIZE / 4];
+        torn[0] = 0xFFFF_FF05;
+        torn[1] = 1234;
+        flash.write(log.address(5), &torn[..2]).unwrap();
+        torn[0] = 0xFFFF_FFFF;
+        flash.write(log.address(200), &torn[..2]).unwrap();
+
+        let log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        assert_eq!(log.next_seq(), 6);
+        assert_eq!(log.oldest_seq(), 0);
+        assert_boot(&log, &flash, 4);
+    }
+}
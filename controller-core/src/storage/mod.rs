@@ -0,0 +1,186 @@
+//! Persistent storage in the internal flash of the nRF52840.
+//!
+//! The layout of the records lives here, the firmware brings the NVMC behind the [`Flash`]
+//! trait. The pages are kept out of the firmware image by the `memory.x` of the controller.
+
+pub mod codec;
+pub mod config_store;
+pub mod event_log;
+
+/// The size of a flash page, which is also the unit of erase.
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Two pages holding the configuration records, 0xEB000 up to 0xED000.
+///
+/// The top of the flash belongs to the Adafruit boards: the 28KiB of their InternalFS start at
+/// 0xED000, followed by the 48KiB of the UF2 bootloader at 0xF4000. The configuration ends right
+/// where the InternalFS starts, so going back to an Arduino sketch keeps its files, and the
+/// event log sits right below it, see [`event_log::EVENT_LOG_PAGES`].
+pub const CONFIG_PAGES: u32 = 0x000E_B000;
+
+/// Access to a region of flash.
+///
+/// Addresses are absolute, writes have to be word aligned and can only flip bits from 1 to 0,
+/// so a region has to be erased before it is written again.
+pub trait Flash {
+    type Error;
+
+    fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    fn write(&mut self, address: u32, data: &[u32]) -> Result<(), Self::Error>;
+
+    fn erase_page(&mut self, address: u32) -> Result<(), Self::Error>;
+}
+
+/// CRC-32 (IEEE 802.3) over `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// A flash in RAM which behaves like the NVMC: writes only clear bits and the power can be cut
+/// in the middle of a write.
+#[cfg(test)]
+pub mod mock {
+    use super::{Flash, PAGE_SIZE};
+
+    #[derive(Debug, PartialEq)]
+    pub enum MockError {
+        OutOfRange,
+        Unaligned,
+        /// The power was cut, nothing is written until it comes back.
+        PowerLost,
+    }
+
+    pub struct MockFlash {
+        base: u32,
+        memory: Vec<u8>,
+        /// How often each page was erased.
+        pub erases: Vec<u32>,
+        /// How often each word was written.
+        pub writes: Vec<u32>,
+        /// Words which are still written before the power is cut.
+        power_left: Option<usize>,
+    }
+
+    impl MockFlash {
+        /// `pages` erased pages starting at `base`.
+        pub fn new(base: u32, pages: u32) -> Self {
+            Self {
+                base,
+                memory: vec![0xFF; (pages * PAGE_SIZE) as usize],
+                erases: vec![0; pages as usize],
+                writes: vec![0; (pages * PAGE_SIZE / 4) as usize],
+                power_left: None,
+            }
+        }
+
+        /// Cuts the power once `words` more words have been written.
+        pub fn cut_power_after(&mut self, words: usize) {
+            self.power_left = Some(words);
+        }
+
+        pub fn power_on(&mut self) {
+            self.power_left = None;
+        }
+
+        /// Flips the bits of `mask` at `address`, like a flash which lost a bit.
+        pub fn corrupt(&mut self, address: u32, mask: u8) {
+            let offset = self.offset(address, 1).unwrap();
+            self.memory[offset] ^= mask;
+        }
+
+        fn offset(&self, address: u32, len: usize) -> Result<usize, MockError> {
+            let offset = address
+                .checked_sub(self.base)
+                .ok_or(MockError::OutOfRange)? as usize;
+            if offset + len > self.memory.len() {
+                return Err(MockError::OutOfRange);
+            }
+            Ok(offset)
+        }
+    }
+
+    impl Flash for MockFlash {
+        type Error = MockError;
+
+        fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = self.offset(address, buf.len())?;
+            buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, address: u32, data: &[u32]) -> Result<(), Self::Error> {
+            if address % 4 != 0 {
+                return Err(MockError::Unaligned);
+            }
+            let offset = self.offset(address, data.len() * 4)?;
+            for (i, word) in data.iter().enumerate() {
+                match self.power_left {
+                    Some(0) => return Err(MockError::PowerLost),
+                    Some(ref mut left) => *left -= 1,
+                    None => {}
+                }
+                let at = offset + i * 4;
+                for (byte, new) in self.memory[at..at + 4].iter_mut().zip(&word.to_le_bytes()) {
+                    *byte &= new;
+                }
+                self.writes[at / 4] += 1;
+            }
+            Ok(())
+        }
+
+        fn erase_page(&mut self, address: u32) -> Result<(), Self::Error> {
+            if address % PAGE_SIZE != 0 {
+                return Err(MockError::Unaligned);
+            }
+            if self.power_left == Some(0) {
+                return Err(MockError::PowerLost);
+            }
+            let offset = self.offset(address, PAGE_SIZE as usize)?;
+            self.memory[offset..offset + PAGE_SIZE as usize]
+                .iter_mut()
+                .for_each(|byte| *byte = 0xFF);
+            self.erases[offset / PAGE_SIZE as usize] += 1;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn the_firmware_ends_below_the_storage_pages() {
+        let memory_x = include_str!("../../../controller/memory.x");
+        let flash = memory_x
+            .lines()
+            .find(|line| line.trim_start().starts_with("FLASH"))
+            .unwrap();
+        let length = flash.rsplit("0x").next().unwrap().trim();
+        assert_eq!(
+            u32::from_str_radix(length, 16).unwrap(),
+            event_log::EVENT_LOG_PAGES
+        );
+
+        let event_log_end =
+            event_log::EVENT_LOG_PAGES + event_log::EVENT_LOG_PAGE_COUNT * PAGE_SIZE;
+        assert_eq!(event_log_end, CONFIG_PAGES);
+        // The Adafruit InternalFS starts right behind the configuration.
+        assert_eq!(CONFIG_PAGES + 2 * PAGE_SIZE, 0x000E_D000);
+    }
+}
@@ -0,0 +1,224 @@
+//! Makes sure every critical task keeps running before the watchdog gets fed.
+//!
+//! The tasks only check in, the [`Supervisor`] runs at a higher priority than all of them, so a
+//! task which hangs cannot keep it from noticing. Once a task misses too many windows, the
+//! supervisor stops feeding the watchdog and the next boot learns which task it was.
+//!
+//! Tasks taking turns at missing a window starve the watchdog just as well, without any of them
+//! missing enough windows on its own. So once the watchdog went unfed for as long as it allows,
+//! the task which checked in the longest ago is the one reported.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// The tasks which have to check in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Task {
+    Measure,
+    HeaterDrive,
+    Display,
+}
+
+const TASKS: [Task; 3] = [Task::Measure, Task::HeaterDrive, Task::Display];
+const ALL_CHECKED_IN: u8 = (1 << TASKS.len()) - 1;
+
+/// Windows in a row a task may miss before it counts as stuck, so jitter on a task which runs
+/// as often as the supervisor is tolerated.
+const MAX_MISSED: u8 = 2;
+
+/// The windows (of a second) the watchdog waits for a feed before it resets the controller.
+pub const WATCHDOG_WINDOWS: u8 = 3;
+/// Windows without a feed after which the supervisor gives up, one before the watchdog would.
+const MAX_UNFED: u8 = WATCHDOG_WINDOWS - 1;
+
+static CHECK_INS: AtomicU8 = AtomicU8::new(0);
+
+impl Task {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        TASKS.get(id as usize).copied()
+    }
+}
+
+/// Reports that `task` is still alive, called once per run of the task.
+pub fn check_in(task: Task) {
+    CHECK_INS.fetch_or(task.bit(), Ordering::Relaxed);
+}
+
+/// What the supervisor found in a window.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Verdict {
+    /// Every task checked in, the watchdog can be fed.
+    Feed,
+    /// Some task did not check in yet, but is still within its allowance.
+    Wait,
+    /// The task missed too many windows, or checked in the longest ago once the watchdog went
+    /// unfed for too long.
+    Stuck(Task),
+}
+
+pub struct Supervisor {
+    missed: [u8; TASKS.len()],
+    /// Windows since the watchdog was fed.
+    unfed: u8,
+    /// Once a task got stuck, the watchdog is never fed again, even if the task recovers.
+    stuck: Option<Task>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            missed: [0; TASKS.len()],
+            unfed: 0,
+            stuck: None,
+        }
+    }
+
+    /// Closes the current window, called periodically from the supervising task.
+    pub fn check(&mut self) -> Verdict {
+        self.close_window(CHECK_INS.swap(0, Ordering::Relaxed))
+    }
+
+    fn close_window(&mut self, check_ins: u8) -> Verdict {
+        if let Some(task) = self.stuck {
+            return Verdict::Stuck(task);
+        }
+
+        let mut stuck = None;
+        for (task, missed) in TASKS.iter().zip(self.missed.iter_mut()) {
+            if check_ins & task.bit() != 0 {
+                *missed = 0;
+                continue;
+            }
+            *missed = missed.saturating_add(1);
+            if *missed >= MAX_MISSED && stuck.is_none() {
+                stuck = Some(*task);
+            }
+        }
+
+        if check_ins == ALL_CHECKED_IN {
+            self.unfed = 0;
+        } else {
+            self.unfed = self.unfed.saturating_add(1);
+        }
+        if stuck.is_none() && self.unfed >= MAX_UNFED {
+            stuck = self.longest_missing();
+        }
+
+        self.stuck = stuck;
+        match stuck {
+            Some(task) => Verdict::Stuck(task),
+            None if check_ins == ALL_CHECKED_IN => Verdict::Feed,
+            None => Verdict::Wait,
+        }
+    }
+
+    /// The task which checked in the longest ago, the first one listed on a tie.
+    fn longest_missing(&self) -> Option<Task> {
+        let mut longest: Option<(Task, u8)> = None;
+        for (task, missed) in TASKS.iter().zip(self.missed.iter()) {
+            if *missed > longest.map_or(0, |(_, most)| most) {
+                longest = Some((*task, *missed));
+            }
+        }
+        longest.map(|(task, _)| task)
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: u8 = ALL_CHECKED_IN;
+
+    fn without(task: Task) -> u8 {
+        ALL & !task.bit()
+    }
+
+    #[test]
+    fn task_ids_round_trip() {
+        for task in TASKS.iter() {
+            assert_eq!(Task::from_id(task.id()), Some(*task));
+        }
+        assert_eq!(Task::from_id(TASKS.len() as u8), None);
+    }
+
+    #[test]
+    fn feeds_once_every_task_checked_in() {
+        let mut supervisor = Supervisor::new();
+        assert_eq!(supervisor.close_window(ALL), Verdict::Feed);
+        assert_eq!(supervisor.close_window(0), Verdict::Wait);
+        assert_eq!(supervisor.close_window(ALL), Verdict::Feed);
+        assert_eq!(
+            supervisor.close_window(without(Task::Display)),
+            Verdict::Wait
+        );
+        assert_eq!(supervisor.close_window(ALL), Verdict::Feed);
+    }
+
+    #[test]
+    fn a_task_missing_too_many_windows_is_stuck_for_good() {
+        let mut supervisor = Supervisor::new();
+        let check_ins = without(Task::HeaterDrive);
+        assert_eq!(supervisor.close_window(check_ins), Verdict::Wait);
+        assert_eq!(
+            supervisor.close_window(check_ins),
+            Verdict::Stuck(Task::HeaterDrive)
+        );
+        // Recovering does not bring the feed back.
+        assert_eq!(
+            supervisor.close_window(ALL),
+            Verdict::Stuck(Task::HeaterDrive)
+        );
+    }
+
+    #[test]
+    fn tasks_taking_turns_are_caught_before_the_watchdog() {
+        let mut supervisor = Supervisor::new();
+        assert_eq!(supervisor.close_window(ALL), Verdict::Feed);
+        assert_eq!(
+            supervisor.close_window(without(Task::Measure)),
+            Verdict::Wait
+        );
+        // Nobody missed two windows in a row, but the watchdog went unfed for two. Display
+        // checked in a window before Measure did.
+        assert_eq!(
+            supervisor.close_window(without(Task::Display)),
+            Verdict::Stuck(Task::Display)
+        );
+    }
+
+    #[test]
+    fn never_waits_past_the_watchdog() {
+        // Every pattern of check-ins over the windows the watchdog allows.
+        let patterns = 1 << (TASKS.len() * WATCHDOG_WINDOWS as usize);
+        for pattern in 0..patterns {
+            let mut supervisor = Supervisor::new();
+            assert_eq!(supervisor.close_window(ALL), Verdict::Feed);
+
+            let mut unfed = 0;
+            for window in 0..WATCHDOG_WINDOWS as usize {
+                let check_ins = (pattern >> (window * TASKS.len())) as u8 & ALL;
+                match supervisor.close_window(check_ins) {
+                    Verdict::Feed => unfed = 0,
+                    Verdict::Wait => unfed += 1,
+                    Verdict::Stuck(_) => break,
+                }
+                assert!(unfed < MAX_UNFED, "pattern {:#b}", pattern);
+            }
+        }
+    }
+}
@@ -27,7 +27,6 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 use display_interface::DataFormat::U8Iter;
 use groundhog_nrf52::GlobalRollingTimer;
 use nrf52840_hal::gpio::p0;
-use panic_probe as _;
 
 #[rtic::app(device = nrf52840_hal::pac, peripherals = true, monotonic = groundhog_nrf52::GlobalRollingTimer)]
 const APP: () = {
@@ -115,6 +114,12 @@ fn timestamp() -> u64 {
     n as u64
 }
 
+#[panic_handler]
+fn core_panic(_: &core::panic::PanicInfo) -> ! {
+    defmt::error!("Panicked");
+    cortex_m::asm::udf()
+}
+
 #[defmt::panic_handler]
 fn panic() -> ! {
     cortex_m::asm::udf()
@@ -0,0 +1,311 @@
+//! Generates the `PinConfig` of the selected board from its description in `boards/`.
+//!
+//! The board is picked through the `board-*` feature, `BOARD_FILE` can point to another
+//! description for a differently wired board of the same kind. Only the pins of the boiler
+//! sensor picked through the `sensor-*` feature are taken. Conflicting pins and missing roles
+//! fail the build here instead of panicking in `init`.
+//!
+//! It also puts `memory.x` on the linker search path, which keeps the pages the storage writes
+//! to out of the firmware image.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How a role needs its pin to be configured.
+#[derive(Clone, Copy)]
+enum Mode {
+    Output,
+    /// An output which starts out high, like a chip select.
+    IdleHighOutput,
+    FloatingInput,
+    PullUpInput,
+    /// One of the SAADC inputs, which keeps its own type instead of being degraded.
+    Analog,
+}
+
+/// Every role the controller needs besides the sensor, in the order of the `PinConfig` fields.
+const ROLES: &[(&str, Mode)] = &[
+    ("heater_signal", Mode::Output),
+    ("display_rst", Mode::Output),
+    ("display_dc", Mode::Output),
+    ("display_cs", Mode::Output),
+    ("display_sck", Mode::Output),
+    ("display_mosi", Mode::Output),
+    ("brew_switch", Mode::PullUpInput),
+    ("steam_switch", Mode::PullUpInput),
+];
+
+/// The roles of each boiler sensor, in the order of its `SensorPins` fields.
+const SENSORS: &[(&str, &[(&str, Mode)])] = &[
+    (
+        "tsic",
+        &[("vdd", Mode::Output), ("signal", Mode::FloatingInput)],
+    ),
+    (
+        "max31865",
+        &[
+            ("sck", Mode::Output),
+            ("mosi", Mode::Output),
+            ("miso", Mode::FloatingInput),
+            ("cs", Mode::IdleHighOutput),
+        ],
+    ),
+    (
+        "max31855",
+        &[
+            ("sck", Mode::Output),
+            ("miso", Mode::FloatingInput),
+            ("cs", Mode::IdleHighOutput),
+        ],
+    ),
+    ("ntc", &[("analog", Mode::Analog)]),
+];
+
+/// The pins the SAADC can sample, all of them on port 0.
+const ANALOG_PINS: &[u8] = &[2, 3, 4, 5, 28, 29, 30, 31];
+
+/// A single GPIO, like `1_09`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Gpio {
+    port: u8,
+    pin: u8,
+}
+
+impl Gpio {
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = value.splitn(2, '_');
+        let port = parts.next().and_then(|p| p.parse::<u8>().ok());
+        let pin = parts.next().and_then(|p| p.parse::<u8>().ok());
+
+        match (port, pin) {
+            (Some(0), Some(pin)) if pin < 32 => Ok(Self { port: 0, pin }),
+            (Some(1), Some(pin)) if pin < 16 => Ok(Self { port: 1, pin }),
+            _ => Err(format!("`{}` is not a valid nRF52840 gpio", value)),
+        }
+    }
+
+    fn field(&self) -> String {
+        format!("p{}_{:02}", self.port, self.pin)
+    }
+
+    /// Moves the pin out of its port and configures it for `mode`.
+    fn take(&self, mode: Mode) -> String {
+        let port = if self.port == 0 { "$p0" } else { "$p1" };
+        let (into, degrade) = match mode {
+            Mode::Output => (
+                "into_push_pull_output(nrf52840_hal::gpio::Level::Low)",
+                true,
+            ),
+            Mode::IdleHighOutput => (
+                "into_push_pull_output(nrf52840_hal::gpio::Level::High)",
+                true,
+            ),
+            Mode::FloatingInput => ("into_floating_input()", true),
+            Mode::PullUpInput => ("into_pullup_input()", true),
+            Mode::Analog => ("into_floating_input()", false),
+        };
+        let pin = format!("{}.{}.{}", port, self.field(), into);
+        if degrade {
+            format!("{}.degrade()", pin)
+        } else {
+            pin
+        }
+    }
+}
+
+fn main() {
+    let path = board_file();
+    println!("cargo:rerun-if-env-changed=BOARD_FILE");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let generated = match generate(&path) {
+        Ok(generated) => generated,
+        Err(e) => panic!("Invalid board description {}: {}", path.display(), e),
+    };
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out.join("pin_config.rs"), generated).unwrap();
+
+    println!("cargo:rerun-if-changed=memory.x");
+    fs::copy("memory.x", out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+}
+
+fn board_file() -> PathBuf {
+    if let Ok(file) = env::var("BOARD_FILE") {
+        return PathBuf::from(file);
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let board = if env::var_os("CARGO_FEATURE_BOARD_DK").is_some() {
+        "dk"
+    } else {
+        // Also covers a build without any board, `src/board` reports that one.
+        "bluefruit"
+    };
+    manifest_dir.join("boards").join(format!("{}.toml", board))
+}
+
+/// The sensor picked through the `sensor-*` feature.
+fn selected_sensor() -> &'static str {
+    SENSORS
+        .iter()
+        .map(|(sensor, _)| *sensor)
+        .find(|sensor| {
+            let feature = format!("CARGO_FEATURE_SENSOR_{}", sensor.to_uppercase());
+            env::var_os(feature).is_some()
+        })
+        // Also covers a build without any sensor, `src/peripherals/sensor` reports that one.
+        .unwrap_or("tsic")
+}
+
+fn generate(path: &Path) -> Result<String, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let board: toml::Value = content
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+
+    let name = board
+        .get("name")
+        .and_then(|n| n.as_str())
+        .ok_or("missing `name`")?;
+    let pins = read_table(board.get("pins"), "pins")?;
+    let reserved = read_table(board.get("reserved"), "reserved")?;
+
+    for role in pins.keys() {
+        if !ROLES.iter().any(|(r, _)| r == role) {
+            return Err(format!("unknown role `{}`", role));
+        }
+    }
+
+    let sensors = match board.get("sensor") {
+        Some(value) => value.as_table().ok_or("`sensor` must be a table")?.clone(),
+        None => Default::default(),
+    };
+    for (sensor, sensor_pins) in &sensors {
+        let roles = SENSORS
+            .iter()
+            .find(|(s, _)| s == sensor)
+            .map(|(_, roles)| *roles)
+            .ok_or_else(|| format!("unknown sensor `{}`", sensor))?;
+        let key = format!("sensor.{}", sensor);
+        for role in read_table(Some(sensor_pins), &key)?.keys() {
+            if !roles.iter().any(|(r, _)| r == role) {
+                return Err(format!("unknown role `{}.{}`", key, role));
+            }
+        }
+    }
+
+    let sensor = selected_sensor();
+    let sensor_key = format!("sensor.{}", sensor);
+    let sensor_pins = read_table(sensors.get(sensor), &sensor_key)?;
+
+    let mut used: BTreeMap<Gpio, String> = BTreeMap::new();
+    let sensor_roles = sensor_pins
+        .iter()
+        .map(|(role, gpio)| (format!("{}.{}", sensor_key, role), gpio));
+    let roles = pins
+        .iter()
+        .chain(reserved.iter())
+        .map(|(role, gpio)| (role.clone(), gpio))
+        .chain(sensor_roles);
+    for (role, gpio) in roles {
+        if let Some(other) = used.insert(*gpio, role.clone()) {
+            return Err(format!(
+                "gpio {}_{:02} is assigned to both `{}` and `{}`",
+                gpio.port, gpio.pin, other, role
+            ));
+        }
+    }
+
+    let sensor_modes = SENSORS
+        .iter()
+        .find(|(s, _)| *s == sensor)
+        .map(|(_, roles)| *roles)
+        .unwrap();
+    let mut sensor_fields = String::new();
+    let mut analog_alias = String::new();
+    for (role, mode) in sensor_modes {
+        let gpio = sensor_pins.get(*role).ok_or_else(|| {
+            format!(
+                "missing required role `{}.{}` of the selected sensor",
+                sensor_key, role
+            )
+        })?;
+        if let Mode::Analog = mode {
+            if gpio.port != 0 || !ANALOG_PINS.contains(&gpio.pin) {
+                return Err(format!(
+                    "`{}.{}` has to be one of the analog inputs",
+                    sensor_key, role
+                ));
+            }
+            analog_alias = format!(
+                "\n\
+                 /// The analog input of the NTC, the SAADC needs its concrete type.\n\
+                 pub type SensorAnalogPin = nrf52840_hal::gpio::p0::P0_{:02}<\n\
+                 \x20   nrf52840_hal::gpio::Input<nrf52840_hal::gpio::Floating>,\n\
+                 >;\n",
+                gpio.pin
+            );
+        }
+        sensor_fields.push_str(&format!(
+            "                {}: {},\n",
+            role,
+            gpio.take(*mode)
+        ));
+    }
+
+    let mut fields = format!(
+        "            sensor: $crate::config::SensorPins {{\n{}            }},\n",
+        sensor_fields
+    );
+    for (role, mode) in ROLES {
+        let gpio = pins
+            .get(*role)
+            .ok_or_else(|| format!("missing required role `{}`", role))?;
+        fields.push_str(&format!("            {}: {},\n", role, gpio.take(*mode)));
+    }
+
+    Ok(format!(
+        "// Generated by build.rs from {}, do not edit.\n\
+         \n\
+         pub const BOARD_NAME: &str = {:?};\n\
+         {}\
+         \n\
+         /// Moves the pins of the board description out of the two gpio ports.\n\
+         ///\n\
+         /// The ports are only partially moved, so the board can still take its extras.\n\
+         macro_rules! board_pin_config {{\n\
+         \x20   ($p0:ident, $p1:ident) => {{\n\
+         \x20       $crate::config::PinConfig {{\n\
+         {}\
+         \x20       }}\n\
+         \x20   }};\n\
+         }}\n",
+        path.display(),
+        name,
+        analog_alias,
+        fields
+    ))
+}
+
+fn read_table(value: Option<&toml::Value>, key: &str) -> Result<BTreeMap<String, Gpio>, String> {
+    let table = match value {
+        Some(value) => value
+            .as_table()
+            .ok_or_else(|| format!("`{}` must be a table", key))?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    table
+        .iter()
+        .map(|(role, value)| {
+            let value = value
+                .as_str()
+                .ok_or_else(|| format!("`{}.{}` must be a string", key, role))?;
+            Ok((role.clone(), Gpio::parse(value)?))
+        })
+        .collect()
+}
@@ -0,0 +1,15 @@
+/* nRF52840 with 1MiB of flash and 256KiB of RAM.
+ *
+ * The firmware only gets the flash below the pages kept by `controller_core::storage`, so the
+ * linker fails instead of placing code where the settings and the event log are written:
+ *
+ *   0x000E7000 - 0x000EB000  event log (4 pages)
+ *   0x000EB000 - 0x000ED000  settings (2 pages)
+ *   0x000ED000 - 0x000F4000  Adafruit InternalFS, left alone
+ *   0x000F4000 - 0x00100000  Adafruit UF2 bootloader
+ */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 0xE7000
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+}
@@ -0,0 +1,132 @@
+//! Bluetooth Low Energy peripheral, so the app can watch and configure the controller.
+//!
+//! Runs the [rubble](https://github.com/jonas-schievink/rubble) stack. The radio and its timer
+//! are serviced by interrupts above all other tasks, the packets they queue up are processed in
+//! the `ble_worker` task.
+
+pub mod service;
+
+use crate::settings::Settings;
+use crate::state::State;
+use nrf52840_hal::pac::{FICR, RADIO, TIMER2};
+use protocol::gatt::MAX_VALUE_LEN;
+use rubble::config::Config;
+use rubble::l2cap::{BleChannelMap, L2CAPState};
+use rubble::link::ad_structure::AdStructure;
+use rubble::link::queue::{PacketQueue, SimpleQueue};
+use rubble::link::{LinkLayer, Responder};
+use rubble::security::NoSecurity;
+use rubble::time::{Duration, Timer};
+use rubble_nrf5x::radio::{BleRadio, PacketBuffer};
+use rubble_nrf5x::timer::BleTimer;
+use rubble_nrf5x::utils::get_device_address;
+use service::{ControllerService, COUNT, WRITE_RESULT_INDEX};
+
+/// The name the controller advertises with.
+pub const DEVICE_NAME: &str = "Rusty PID";
+
+/// How often (in ms) the controller advertises while nobody is connected.
+const ADVERTISING_INTERVAL_MS: u32 = 200;
+
+pub enum BleConfig {}
+
+impl Config for BleConfig {
+    type Timer = BleTimer<TIMER2>;
+    type Transmitter = BleRadio;
+    type ChannelMapper = BleChannelMap<ControllerService, NoSecurity>;
+    type PacketQueue = &'static mut SimpleQueue;
+}
+
+pub type BleLinkLayer = LinkLayer<BleConfig>;
+pub type BleResponder = Responder<BleConfig>;
+
+/// The buffers the stack works in, they have to live as long as the program.
+pub struct BleBuffers {
+    pub tx: &'static mut PacketBuffer,
+    pub rx: &'static mut PacketBuffer,
+    pub tx_queue: &'static mut SimpleQueue,
+    pub rx_queue: &'static mut SimpleQueue,
+}
+
+/// Sets up the stack and starts advertising, writes are checked against `settings`.
+///
+/// Needs the external high frequency oscillator running, the radio does not work off the
+/// internal one.
+pub fn start(
+    radio: RADIO,
+    timer: TIMER2,
+    ficr: &FICR,
+    buffers: BleBuffers,
+    settings: &Settings,
+) -> (BleLinkLayer, BleResponder, BleRadio) {
+    let timer = BleTimer::init(timer);
+    let address = get_device_address();
+    let mut radio = BleRadio::new(radio, ficr, buffers.tx, buffers.rx);
+
+    let (tx, tx_cons) = buffers.tx_queue.split();
+    let (rx_prod, rx) = buffers.rx_queue.split();
+
+    let mut link_layer = LinkLayer::<BleConfig>::new(address, timer);
+    let responder = Responder::new(
+        tx,
+        rx,
+        L2CAPState::new(BleChannelMap::with_attributes(ControllerService::new(
+            settings,
+        ))),
+    );
+
+    let next_update = link_layer
+        .start_advertise(
+            Duration::from_millis(ADVERTISING_INTERVAL_MS),
+            &[AdStructure::CompleteLocalName(DEVICE_NAME)],
+            &mut radio,
+            tx_cons,
+            rx_prod,
+        )
+        .unwrap();
+    link_layer.timer().configure_interrupt(next_update);
+
+    (link_layer, responder, radio)
+}
+
+/// Refreshes the values of the service and notifies subscribed clients about them.
+pub fn publish(responder: &mut BleResponder, state: &State, settings: &Settings) {
+    service(responder).update(state, settings);
+
+    for index in 0..COUNT {
+        if index != WRITE_RESULT_INDEX && !notify(responder, index) {
+            break;
+        }
+    }
+}
+
+/// Notifies subscribed clients about the result of the last write, if it is new.
+pub fn publish_write_result(responder: &mut BleResponder) {
+    if service(responder).take_write_result_changed() {
+        notify(responder, WRITE_RESULT_INDEX);
+    }
+}
+
+pub fn service(responder: &mut BleResponder) -> &mut ControllerService {
+    responder.l2cap().channel_mapper().attribute_provider()
+}
+
+/// Returns `false` if the notification could not be queued.
+fn notify(responder: &mut BleResponder, index: usize) -> bool {
+    let mut value = [0; MAX_VALUE_LEN];
+    let (handle, len) = match service(responder).notification(index, &mut value) {
+        Some(notification) => notification,
+        None => return true,
+    };
+    // A full queue only drops this round, the next measurement notifies again.
+    if responder
+        .l2cap()
+        .att()
+        .notify_raw(handle, &value[..len])
+        .is_err()
+    {
+        defmt::debug!("BLE: notification queue full");
+        return false;
+    }
+    true
+}
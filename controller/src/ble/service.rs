@@ -0,0 +1,364 @@
+//! The GATT service which mirrors the [`State`](crate::state::State) and takes new settings.
+//!
+//! The encoding of the values lives in the `protocol` crate, so the app and the host tools decode
+//! exactly what is sent here. Every characteristic takes a declaration and a value handle, those
+//! which notify a client configuration handle as well.
+//!
+//! Writes are checked right away against a copy of the settings, so a rejected write gets an
+//! error response. Accepted writes are queued as [`Change`]s, the `ble_worker` task applies them
+//! to the controller.
+
+use crate::machine::{Event, MachineState};
+use crate::settings::Settings;
+use crate::state::State;
+use heapless::consts::U4;
+use heapless::spsc::Queue;
+use protocol::gatt::{
+    Characteristic, Command, Setting, WriteResult, COMMAND_ID, MAX_VALUE_LEN, SERVICE_UUID,
+    WRITE_RESULT_ID,
+};
+use rubble::att::{
+    AttUuid, Attribute, AttributeAccessPermissions, AttributeProvider, Handle, HandleRange,
+};
+use rubble::uuid::{Uuid128, Uuid16};
+use rubble::Error;
+
+const STATUS_COUNT: usize = Characteristic::ALL.len();
+const SETTING_COUNT: usize = Setting::ALL.len();
+/// The number of characteristics in the service, the command and the write result come last.
+pub const COUNT: usize = STATUS_COUNT + SETTING_COUNT + 2;
+/// The index of the write result characteristic, for [`ControllerService::notification`].
+pub const WRITE_RESULT_INDEX: usize = COUNT - 1;
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+const CLIENT_CONFIGURATION: Uuid16 = Uuid16(0x2902);
+
+const READ: u8 = 0x02;
+const WRITE: u8 = 0x08;
+const NOTIFY: u8 = 0x10;
+const NOTIFY_ENABLED: u8 = 0x01;
+
+const SERVICE_HANDLE: u16 = 1;
+
+/// What sits behind a characteristic of the service.
+#[derive(Clone, Copy)]
+enum Entry {
+    Status(Characteristic),
+    Setting(Setting),
+    Command,
+    WriteResult,
+}
+
+impl Entry {
+    fn at(index: usize) -> Self {
+        if index < STATUS_COUNT {
+            Entry::Status(Characteristic::ALL[index])
+        } else if index < STATUS_COUNT + SETTING_COUNT {
+            Entry::Setting(Setting::ALL[index - STATUS_COUNT])
+        } else if index == STATUS_COUNT + SETTING_COUNT {
+            Entry::Command
+        } else {
+            Entry::WriteResult
+        }
+    }
+
+    fn id(self) -> u16 {
+        match self {
+            Entry::Status(characteristic) => characteristic.id(),
+            Entry::Setting(setting) => setting.id(),
+            Entry::Command => COMMAND_ID,
+            Entry::WriteResult => WRITE_RESULT_ID,
+        }
+    }
+
+    fn properties(self) -> u8 {
+        match self {
+            Entry::Status(_) | Entry::WriteResult => READ | NOTIFY,
+            Entry::Setting(_) => READ | WRITE | NOTIFY,
+            Entry::Command => WRITE,
+        }
+    }
+
+    fn notifies(self) -> bool {
+        self.properties() & NOTIFY != 0
+    }
+
+    fn value_len(self) -> usize {
+        match self {
+            Entry::Status(characteristic) => characteristic.value_len(),
+            Entry::Setting(setting) => setting.value_len(),
+            Entry::Command => 0,
+            Entry::WriteResult => 3,
+        }
+    }
+}
+
+/// Where a handle points to within the service.
+enum Slot {
+    Service,
+    Declaration(usize),
+    Value(usize),
+    ClientConfiguration(usize),
+}
+
+/// An accepted write, to be applied to the controller.
+pub enum Change {
+    /// The settings with the written value, already validated.
+    Settings(Settings),
+    Event(Event),
+    Save,
+}
+
+pub struct ControllerService {
+    /// The service UUID as it is sent, in little endian.
+    service_uuid: [u8; 16],
+    declarations: [[u8; 19]; COUNT],
+    value_handles: [u16; COUNT],
+    values: [[u8; MAX_VALUE_LEN]; COUNT],
+    client_configurations: [[u8; 2]; COUNT],
+    group_end: Attribute<'static>,
+    last_handle: u16,
+    /// The settings and machine state writes are checked against, kept in sync by `update`.
+    settings: Settings,
+    machine_state: MachineState,
+    changes: Queue<Change, U4>,
+    /// Set until the new write result has been notified.
+    write_result_changed: bool,
+}
+
+impl ControllerService {
+    pub fn new(settings: &Settings) -> Self {
+        let mut service_uuid = SERVICE_UUID;
+        service_uuid.reverse();
+
+        let mut declarations = [[0; 19]; COUNT];
+        let mut value_handles = [0; COUNT];
+        let mut last_handle = SERVICE_HANDLE;
+        for (index, declaration) in declarations.iter_mut().enumerate() {
+            let entry = Entry::at(index);
+            let value_handle = last_handle + 2;
+            value_handles[index] = value_handle;
+            last_handle = if entry.notifies() {
+                value_handle + 1
+            } else {
+                value_handle
+            };
+
+            let mut uuid = protocol::gatt::uuid(entry.id());
+            uuid.reverse();
+            declaration[0] = entry.properties();
+            declaration[1..3].copy_from_slice(&value_handle.to_le_bytes());
+            declaration[3..].copy_from_slice(&uuid);
+        }
+
+        let mut service = Self {
+            service_uuid,
+            declarations,
+            value_handles,
+            values: [[0; MAX_VALUE_LEN]; COUNT],
+            client_configurations: [[0; 2]; COUNT],
+            group_end: Attribute::new(PRIMARY_SERVICE.into(), Handle::from_raw(last_handle), &[]),
+            last_handle,
+            settings: *settings,
+            machine_state: MachineState::Booting,
+            changes: Queue::new(),
+            write_result_changed: false,
+        };
+        // Nothing has been written yet.
+        service.set_write_result(0, WriteResult::Ok);
+        service.write_result_changed = false;
+        service
+    }
+
+    /// Encodes the new values, they are read and notified from here on.
+    pub fn update(&mut self, state: &State, settings: &Settings) {
+        let status = state.status();
+        let config = settings.config();
+        for (index, value) in self.values.iter_mut().enumerate() {
+            match Entry::at(index) {
+                Entry::Status(characteristic) => {
+                    characteristic.encode(&status, value);
+                }
+                Entry::Setting(setting) => {
+                    setting.encode(&config, value);
+                }
+                Entry::Command | Entry::WriteResult => {}
+            }
+        }
+        self.settings = *settings;
+        self.machine_state = state.machine_state();
+    }
+
+    /// Copies the value of the characteristic at `index` into `value` if a client wants to be
+    /// notified about it, returns its handle and length.
+    pub fn notification(
+        &self,
+        index: usize,
+        value: &mut [u8; MAX_VALUE_LEN],
+    ) -> Option<(u16, usize)> {
+        if self.client_configurations[index][0] & NOTIFY_ENABLED == 0 {
+            return None;
+        }
+        let len = Entry::at(index).value_len();
+        value[..len].copy_from_slice(&self.values[index][..len]);
+        Some((self.value_handles[index], len))
+    }
+
+    /// Reports how a write to the characteristic with the short id `id` went.
+    pub fn set_write_result(&mut self, id: u16, result: WriteResult) {
+        let encoded = result.encode(id);
+        self.values[WRITE_RESULT_INDEX][..encoded.len()].copy_from_slice(&encoded);
+        self.write_result_changed = true;
+    }
+
+    /// `true` once after each new write result.
+    pub fn take_write_result_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.write_result_changed, false)
+    }
+
+    /// The next accepted write which has not been applied yet.
+    pub fn take_change(&mut self) -> Option<Change> {
+        self.changes.dequeue()
+    }
+
+    fn slot(&self, handle: u16) -> Option<Slot> {
+        match handle {
+            0 => return None,
+            SERVICE_HANDLE => return Some(Slot::Service),
+            _ => {}
+        }
+        let index = self
+            .value_handles
+            .iter()
+            .position(|value_handle| handle <= *value_handle + 1)?;
+        let value_handle = self.value_handles[index];
+        Some(if handle < value_handle {
+            Slot::Declaration(index)
+        } else if handle == value_handle {
+            Slot::Value(index)
+        } else if Entry::at(index).notifies() {
+            Slot::ClientConfiguration(index)
+        } else {
+            // The declaration of the next characteristic.
+            Slot::Declaration(index + 1)
+        })
+    }
+
+    fn attribute(&self, handle: u16) -> Option<Attribute<'_>> {
+        let (att_type, value): (AttUuid, &[u8]) = match self.slot(handle)? {
+            Slot::Service => (PRIMARY_SERVICE.into(), &self.service_uuid),
+            Slot::Declaration(index) => (CHARACTERISTIC.into(), &self.declarations[index]),
+            Slot::Value(index) => {
+                let entry = Entry::at(index);
+                (
+                    AttUuid::Uuid128(Uuid128::from_bytes(protocol::gatt::uuid(entry.id()))),
+                    &self.values[index][..entry.value_len()],
+                )
+            }
+            Slot::ClientConfiguration(index) => (
+                CLIENT_CONFIGURATION.into(),
+                &self.client_configurations[index],
+            ),
+        };
+        Some(Attribute::new(att_type, Handle::from_raw(handle), value))
+    }
+
+    /// Checks a written value and queues it, unless it is rejected.
+    fn write(&mut self, entry: Entry, data: &[u8]) -> Result<(), WriteResult> {
+        let change = match entry {
+            Entry::Setting(setting) => {
+                let mut config = self.settings.config();
+                setting.decode(data, &mut config)?;
+                let settings = self
+                    .settings
+                    .with_config(&config)
+                    .map_err(|_| WriteResult::OutOfRange)?;
+                Change::Settings(settings)
+            }
+            Entry::Command => {
+                let command = Command::from_value(data)?;
+                match Event::from_command(command) {
+                    Some(event) => {
+                        let next = self
+                            .machine_state
+                            .next(event)
+                            .ok_or(WriteResult::NotAllowed)?;
+                        self.machine_state = next;
+                        Change::Event(event)
+                    }
+                    None => Change::Save,
+                }
+            }
+            Entry::Status(_) | Entry::WriteResult => return Err(WriteResult::NotAllowed),
+        };
+
+        // Later writes are checked against the settings including this one.
+        let settings = match change {
+            Change::Settings(settings) => Some(settings),
+            _ => None,
+        };
+        self.changes
+            .enqueue(change)
+            .map_err(|_| WriteResult::Busy)?;
+        if let Some(settings) = settings {
+            self.settings = settings;
+        }
+        Ok(())
+    }
+}
+
+impl AttributeProvider for ControllerService {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, &Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let start = range.start().as_u16().max(SERVICE_HANDLE);
+        let end = range.end().as_u16().min(self.last_handle);
+        for handle in start..=end {
+            if let Some(attribute) = self.attribute(handle) {
+                f(self, &attribute)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == PRIMARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>> {
+        if handle.as_u16() == SERVICE_HANDLE {
+            Some(&self.group_end)
+        } else {
+            None
+        }
+    }
+
+    fn attr_access_permissions(&self, handle: Handle) -> AttributeAccessPermissions {
+        match self.slot(handle.as_u16()) {
+            Some(Slot::ClientConfiguration(_)) => AttributeAccessPermissions::ReadableAndWriteable,
+            Some(Slot::Value(index)) if Entry::at(index).properties() & WRITE != 0 => {
+                AttributeAccessPermissions::ReadableAndWriteable
+            }
+            _ => AttributeAccessPermissions::Readable,
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, data: &[u8]) -> Result<(), Error> {
+        match self.slot(handle.as_u16()) {
+            Some(Slot::ClientConfiguration(index)) if data.len() == 2 => {
+                self.client_configurations[index].copy_from_slice(data);
+                Ok(())
+            }
+            Some(Slot::Value(index)) => {
+                let entry = Entry::at(index);
+                let result = self.write(entry, data);
+                self.set_write_result(entry.id(), result.err().unwrap_or(WriteResult::Ok));
+                result.map_err(|_| Error::InvalidValue)
+            }
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
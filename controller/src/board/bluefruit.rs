@@ -0,0 +1,45 @@
+//! The Adafruit Feather nRF52840 Express, which sits in the machine.
+
+use super::Board;
+use crate::config::PinConfig;
+use nrf52840_hal::gpio::p0::Parts as Parts0;
+use nrf52840_hal::gpio::p1::Parts as Parts1;
+use nrf52840_hal::gpio::{Input, Level, Output, Pin, PullUp, PushPull};
+use nrf52840_hal::prelude::*;
+
+pub struct Bluefruit;
+
+pub struct BluefruitExtras {
+    /// The red LED next to the USB port (D3), active high.
+    pub red_led: Pin<Output<PushPull>>,
+    /// The blue "connection" LED (D4), active high.
+    pub blue_led: Pin<Output<PushPull>>,
+    /// The user switch (D7), closes to ground.
+    pub user_switch: Pin<Input<PullUp>>,
+}
+
+impl Board for Bluefruit {
+    const NAME: &'static str = super::BOARD_NAME;
+
+    type Extras = BluefruitExtras;
+
+    fn split(p0: Parts0, p1: Parts1) -> (PinConfig, Self::Extras) {
+        let pins = board_pin_config!(p0, p1);
+
+        let extras = BluefruitExtras {
+            red_led: p1.p1_15.into_push_pull_output(Level::Low).degrade(),
+            blue_led: p1.p1_10.into_push_pull_output(Level::Low).degrade(),
+            user_switch: p1.p1_02.into_pullup_input().degrade(),
+        };
+
+        (pins, extras)
+    }
+
+    fn indicate_heater(extras: &mut Self::Extras, on: bool) {
+        if on {
+            extras.red_led.set_high().ok();
+        } else {
+            extras.red_led.set_low().ok();
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! The nRF52840 DK used during development.
+//!
+//! Buttons 1 and 2 stand in for the brew and steam switches, so both can be tried on the desk.
+
+use super::Board;
+use crate::config::PinConfig;
+use nrf52840_hal::gpio::p0::Parts as Parts0;
+use nrf52840_hal::gpio::p1::Parts as Parts1;
+use nrf52840_hal::gpio::{Input, Level, Output, Pin, PullUp, PushPull};
+use nrf52840_hal::prelude::*;
+
+pub struct Dk;
+
+pub struct DkExtras {
+    /// LED 1 to 4, active low.
+    pub leds: [Pin<Output<PushPull>>; 4],
+    /// Button 3 and 4, button 1 and 2 are the brew and steam switches.
+    pub buttons: [Pin<Input<PullUp>>; 2],
+}
+
+impl Board for Dk {
+    const NAME: &'static str = super::BOARD_NAME;
+
+    type Extras = DkExtras;
+
+    fn split(p0: Parts0, p1: Parts1) -> (PinConfig, Self::Extras) {
+        let pins = board_pin_config!(p0, p1);
+
+        let extras = DkExtras {
+            leds: [
+                p0.p0_13.into_push_pull_output(Level::High).degrade(),
+                p0.p0_14.into_push_pull_output(Level::High).degrade(),
+                p0.p0_15.into_push_pull_output(Level::High).degrade(),
+                p0.p0_16.into_push_pull_output(Level::High).degrade(),
+            ],
+            buttons: [
+                p0.p0_24.into_pullup_input().degrade(),
+                p0.p0_25.into_pullup_input().degrade(),
+            ],
+        };
+
+        (pins, extras)
+    }
+
+    fn indicate_heater(extras: &mut Self::Extras, on: bool) {
+        if on {
+            extras.leds[0].set_low().ok();
+        } else {
+            extras.leds[0].set_high().ok();
+        }
+    }
+}
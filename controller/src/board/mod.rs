@@ -0,0 +1,41 @@
+//! The boards the controller runs on, selected through exactly one `board-*` cargo feature.
+//!
+//! The pins of each board are described in `boards/<board>.toml` and turned into code by
+//! `build.rs`, everything else a board has to offer lives in its module here.
+
+#[cfg(all(feature = "board-dk", feature = "board-bluefruit"))]
+compile_error!("Only one board can be selected, enable either `board-dk` or `board-bluefruit`.");
+
+#[cfg(not(any(feature = "board-dk", feature = "board-bluefruit")))]
+compile_error!("No board selected, enable either `board-dk` or `board-bluefruit`.");
+
+// Provides `BOARD_NAME` and `board_pin_config!`, which the boards below use to take their pins.
+include!(concat!(env!("OUT_DIR"), "/pin_config.rs"));
+
+#[cfg(feature = "board-bluefruit")]
+mod bluefruit;
+#[cfg(feature = "board-dk")]
+mod dk;
+
+#[cfg(feature = "board-bluefruit")]
+pub use bluefruit::Bluefruit as SelectedBoard;
+#[cfg(feature = "board-dk")]
+pub use dk::Dk as SelectedBoard;
+
+use crate::config::PinConfig;
+use nrf52840_hal::gpio::p0::Parts as Parts0;
+use nrf52840_hal::gpio::p1::Parts as Parts1;
+
+pub trait Board {
+    /// Shown in the logs on boot, taken from the board description.
+    const NAME: &'static str;
+
+    /// Whatever else the board has on it, like LEDs and buttons.
+    type Extras;
+
+    /// Splits the GPIO ports into the pins the controller needs and the board extras.
+    fn split(p0: Parts0, p1: Parts1) -> (PinConfig, Self::Extras);
+
+    /// Mirrors the heater state on a board LED, if there is one.
+    fn indicate_heater(extras: &mut Self::Extras, on: bool);
+}
@@ -0,0 +1,35 @@
+//! A monotonic uptime clock on top of the 32 bit rolling timer.
+
+use groundhog::RollingTimer;
+use groundhog_nrf52::GlobalRollingTimer;
+
+pub use controller_core::clock::{Millis, WallClock, SECONDS_PER_DAY};
+
+/// Extends the rolling microsecond ticks into a 64 bit uptime.
+///
+/// The rolling timer wraps after roughly 71 minutes, so `now` must be called more often than
+/// that, which every periodic task does anyway.
+pub struct Clock {
+    timer: GlobalRollingTimer,
+    last_ticks: u32,
+    micros: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let timer = GlobalRollingTimer::new();
+        let last_ticks = timer.get_ticks();
+        Self {
+            timer,
+            last_ticks,
+            micros: 0,
+        }
+    }
+
+    pub fn now(&mut self) -> Millis {
+        let ticks = self.timer.get_ticks();
+        self.micros += ticks.wrapping_sub(self.last_ticks) as u64;
+        self.last_ticks = ticks;
+        self.micros / 1000
+    }
+}
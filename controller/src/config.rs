@@ -1,38 +1,45 @@
-use nrf52840_hal::gpio::p0::Parts as Parts0;
-use nrf52840_hal::gpio::p1::Parts as Parts1;
-use nrf52840_hal::gpio::{Floating, Input, Level, Output, Pin, PushPull};
+use nrf52840_hal::gpio::{Floating, Input, Output, Pin, PullUp, PushPull};
 
+/// The pins every board has to provide, generated from the description in `boards/`.
 pub struct PinConfig {
-    pub sensor_vdd: Option<Pin<Output<PushPull>>>,
-    pub sensor_signal: Option<Pin<Input<Floating>>>,
-    pub heater_signal: Option<Pin<Output<PushPull>>>,
-    pub display_rst_pin: Option<Pin<Output<PushPull>>>,
-    pub display_dc_pin: Option<Pin<Output<PushPull>>>,
-    pub display_cs_pin: Option<Pin<Output<PushPull>>>,
-    pub display_sck_pin: Option<Pin<Output<PushPull>>>,
-    pub display_mosi_pin: Option<Pin<Output<PushPull>>>,
+    pub sensor: SensorPins,
+    pub heater_signal: Pin<Output<PushPull>>,
+    pub display_rst: Pin<Output<PushPull>>,
+    pub display_dc: Pin<Output<PushPull>>,
+    pub display_cs: Pin<Output<PushPull>>,
+    pub display_sck: Pin<Output<PushPull>>,
+    pub display_mosi: Pin<Output<PushPull>>,
+    pub brew_switch: Pin<Input<PullUp>>,
+    pub steam_switch: Pin<Input<PullUp>>,
 }
 
-impl PinConfig {
-    pub fn new(p0: Parts0, p1: Parts1) -> Self {
-        let sensor_vdd = Some(p1.p1_09.into_push_pull_output(Level::Low).degrade());
-        let sensor_signal = Some(p0.p0_08.into_floating_input().degrade());
-        let heater_signal = Some(p0.p0_06.into_push_pull_output(Level::Low).degrade());
-        let display_rst_pin = Some(p0.p0_28.into_push_pull_output(Level::Low).degrade());
-        let display_dc_pin = Some(p0.p0_02.into_push_pull_output(Level::Low).degrade());
-        let display_cs_pin = Some(p0.p0_03.into_push_pull_output(Level::Low).degrade());
-        let display_sck_pin = Some(p0.p0_14.into_push_pull_output(Level::Low).degrade());
-        let display_mosi_pin = Some(p0.p0_13.into_push_pull_output(Level::Low).degrade());
+/// The pins of the boiler sensor selected through the `sensor-*` feature.
+#[cfg(feature = "sensor-tsic")]
+pub struct SensorPins {
+    pub vdd: Pin<Output<PushPull>>,
+    pub signal: Pin<Input<Floating>>,
+}
+
+/// The pins of the boiler sensor selected through the `sensor-*` feature.
+#[cfg(feature = "sensor-max31865")]
+pub struct SensorPins {
+    pub sck: Pin<Output<PushPull>>,
+    pub mosi: Pin<Output<PushPull>>,
+    pub miso: Pin<Input<Floating>>,
+    pub cs: Pin<Output<PushPull>>,
+}
+
+/// The pins of the boiler sensor selected through the `sensor-*` feature.
+#[cfg(feature = "sensor-max31855")]
+pub struct SensorPins {
+    pub sck: Pin<Output<PushPull>>,
+    pub miso: Pin<Input<Floating>>,
+    pub cs: Pin<Output<PushPull>>,
+}
 
-        Self {
-            sensor_signal,
-            sensor_vdd,
-            heater_signal,
-            display_rst_pin,
-            display_dc_pin,
-            display_cs_pin,
-            display_sck_pin,
-            display_mosi_pin,
-        }
-    }
+/// The pins of the boiler sensor selected through the `sensor-*` feature.
+#[cfg(feature = "sensor-ntc")]
+pub struct SensorPins {
+    /// Has to be one of the SAADC inputs, so it keeps its own type.
+    pub analog: crate::board::SensorAnalogPin,
 }
@@ -0,0 +1,508 @@
+//! A serial console on the native USB port (CDC-ACM).
+//!
+//! It starts out as a line shell for humans, see [`crate::shell`]. A host tool switches it to the
+//! framed wire protocol by sending a [`Request::Hello`] frame, closing the port switches back.
+//! Requests which read or change the controller are handed out through
+//! [`next_request`](Console::next_request), the console takes care of the session itself:
+//! the handshake, streaming and dumping the event log.
+
+use crate::shell::{self, GainSet, Line, ParseError};
+use crate::storage::event_log::EventLog;
+use crate::storage::nvmc::NvmcFlash;
+use core::fmt::{self, Write};
+use core::str;
+use heapless::consts::{U1024, U256, U64};
+use heapless::spsc::Queue;
+use heapless::Vec;
+use nrf52840_hal::usbd::{UsbPeripheral, Usbd};
+use protocol::config::Config;
+use protocol::events::LoggedEvent;
+use protocol::frame::{self, Receiver, MAX_FRAME};
+use protocol::message::{self, ErrorCode, Message, Request, Response, Telemetry};
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+pub type UsbBus = Usbd<UsbPeripheral<'static>>;
+
+/// The pid.codes test VID/PID, fine for a device which is not sold.
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+/// Bytes which have to be free in the output before the next event of a dump is written.
+const DUMP_ROOM: usize = MAX_FRAME;
+const PROMPT: &str = "> ";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Session {
+    Text,
+    Binary,
+}
+
+/// An event log dump in progress.
+#[derive(Clone, Copy)]
+struct Dump {
+    next: u32,
+    end: u32,
+}
+
+pub struct Console {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+    connected: bool,
+    session: Session,
+    rx: Queue<u8, U256>,
+    tx: Queue<u8, U1024>,
+    line: Vec<u8, U64>,
+    /// Set when the current line did not fit, it is rejected once it ends.
+    line_overflow: bool,
+    receiver: Receiver,
+    streaming: bool,
+    dump: Option<Dump>,
+}
+
+impl Console {
+    pub fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("daschl")
+            .product("Rusty PID")
+            .serial_number("1")
+            .device_class(USB_CLASS_CDC)
+            .max_packet_size_0(64)
+            .build();
+
+        Self {
+            device,
+            serial,
+            connected: false,
+            session: Session::Text,
+            rx: Queue::new(),
+            tx: Queue::new(),
+            line: Vec::new(),
+            line_overflow: false,
+            receiver: Receiver::new(),
+            streaming: false,
+            dump: None,
+        }
+    }
+
+    /// Services the USB device and takes the received bytes, called from the USB interrupt.
+    pub fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.serial]);
+
+        // Only what fits is read, the rest waits in the serial port, which holds the host off.
+        let mut buf = [0; 64];
+        loop {
+            let room = (self.rx.capacity() - self.rx.len()).min(buf.len());
+            if room == 0 {
+                break;
+            }
+            match self.serial.read(&mut buf[..room]) {
+                Ok(count) if count > 0 => {
+                    for byte in &buf[..count] {
+                        // Cannot fail, there is room for all of them.
+                        self.rx.enqueue(*byte).ok();
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        // Closing the port drops DTR, the next one to open it starts over.
+        let connected = self.serial.dtr();
+        if self.connected && !connected {
+            self.reset();
+        }
+        self.connected = connected;
+    }
+
+    /// The next request the controller has to answer through [`respond`](Self::respond).
+    ///
+    /// `config` are the current settings, which a shell `set` command changes a part of.
+    pub fn next_request(&mut self, config: &Config, log: &EventLog) -> Option<Request> {
+        while let Some(byte) = self.rx.dequeue() {
+            if let Some(request) = self.take(byte, config, log) {
+                return Some(request);
+            }
+        }
+        None
+    }
+
+    pub fn respond(&mut self, response: Response) {
+        match self.session {
+            Session::Binary => self.send_frame(&Message::Response(response)),
+            Session::Text => {
+                self.write_response(&response);
+                self.write_str(PROMPT);
+            }
+        }
+    }
+
+    /// Sends the telemetry if streaming has been turned on.
+    pub fn stream(&mut self, telemetry: &Telemetry) {
+        if !self.streaming {
+            return;
+        }
+        match self.session {
+            Session::Binary => self.send_frame(&Message::Telemetry(*telemetry)),
+            Session::Text => self.write_telemetry(telemetry),
+        }
+        self.flush_tx();
+    }
+
+    /// Sends what is queued up, continuing a dump of the event log.
+    pub fn flush(&mut self, log: &EventLog, flash: &NvmcFlash) {
+        while let Some(mut dump) = self.dump.take() {
+            if self.room() < DUMP_ROOM {
+                self.dump = Some(dump);
+                break;
+            }
+
+            if dump.next >= dump.end {
+                match self.session {
+                    Session::Binary => {
+                        self.send_frame(&Message::Response(Response::EventsEnd { next: dump.next }))
+                    }
+                    Session::Text => {
+                        self.write_str("end of log\r\n");
+                        self.write_str(PROMPT);
+                    }
+                }
+                break;
+            }
+
+            let seq = dump.next;
+            dump.next += 1;
+            self.dump = Some(dump);
+            // Records which got overwritten in the meantime are skipped.
+            if let Some(record) = log.get(flash, seq) {
+                let event = LoggedEvent::from(record);
+                match self.session {
+                    Session::Binary => self.send_frame(&Message::Response(Response::Event(event))),
+                    Session::Text => {
+                        write!(
+                            self.output(),
+                            "#{} {}ms {:?}\r\n",
+                            event.seq,
+                            event.at,
+                            event.event
+                        )
+                        .ok();
+                    }
+                }
+            }
+        }
+
+        self.flush_tx();
+    }
+
+    fn reset(&mut self) {
+        self.session = Session::Text;
+        self.streaming = false;
+        self.dump = None;
+        self.clear_line();
+        while self.tx.dequeue().is_some() {}
+    }
+
+    fn clear_line(&mut self) {
+        // Not `clear`, heapless 0.5 indexes past the end while truncating, which debug builds
+        // stop on as undefined behaviour. Dropping the old line is sound.
+        self.line = Vec::new();
+        self.line_overflow = false;
+    }
+
+    fn take(&mut self, byte: u8, config: &Config, log: &EventLog) -> Option<Request> {
+        // Frames are looked for in both sessions, since a hello frame starts the binary one.
+        match self.receiver.push(byte).map(frame::decode::<Request>) {
+            Some(Ok(request)) => {
+                // What the shell collected was the frame.
+                self.clear_line();
+                return self.frame_request(request, log);
+            }
+            Some(Err(_)) if self.session == Session::Binary => {
+                self.send_frame(&Message::Response(Response::Error(ErrorCode::Malformed)));
+                return None;
+            }
+            _ => {}
+        }
+        if self.session == Session::Binary {
+            return None;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                let request = if self.line_overflow {
+                    self.write_str("\r\nerror: line too long\r\n");
+                    self.write_str(PROMPT);
+                    None
+                } else if self.line.is_empty() {
+                    // The second half of a CRLF.
+                    None
+                } else {
+                    self.write_str("\r\n");
+                    let line = self.line.clone();
+                    self.line_request(str::from_utf8(&line).unwrap_or(""), config, log)
+                };
+                self.clear_line();
+                request
+            }
+            // Backspace and delete.
+            0x08 | 0x7F => {
+                if self.line.pop().is_some() {
+                    self.write_str("\x08 \x08");
+                }
+                None
+            }
+            0x20..=0x7E => {
+                if self.line.push(byte).is_ok() {
+                    self.push_bytes(&[byte]);
+                } else {
+                    self.line_overflow = true;
+                }
+                None
+            }
+            // A frame delimiter ends whatever came before, anything else is not typed by hand.
+            0 => {
+                self.clear_line();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn frame_request(&mut self, request: Request, log: &EventLog) -> Option<Request> {
+        let response = match request {
+            Request::Hello { version } => match message::negotiate(version) {
+                Some(version) => {
+                    self.session = Session::Binary;
+                    // Ends whatever the shell echoed before, the host drops it as a broken frame.
+                    self.push_bytes(&[0]);
+                    Response::Hello { version }
+                }
+                None => Response::Error(ErrorCode::Incompatible),
+            },
+            _ if self.session == Session::Text => Response::Error(ErrorCode::NoHandshake),
+            Request::Stream(on) => {
+                self.streaming = on;
+                Response::Ok
+            }
+            Request::ReadEvents { from, count } => {
+                let from = from.max(log.oldest_seq());
+                self.dump = Some(Dump {
+                    next: from,
+                    end: from.saturating_add(u32::from(count)).min(log.next_seq()),
+                });
+                return None;
+            }
+            request => return Some(request),
+        };
+        self.send_frame(&Message::Response(response));
+        None
+    }
+
+    fn line_request(&mut self, line: &str, config: &Config, log: &EventLog) -> Option<Request> {
+        let set = |config: Config| Request::SetConfig {
+            config,
+            save: false,
+        };
+        let request = match shell::parse(line) {
+            Ok(Line::Help) => {
+                self.write_str(shell::HELP);
+                self.write_str("\r\n");
+                None
+            }
+            Ok(Line::Status) => Some(Request::GetStatus),
+            Ok(Line::Config) => Some(Request::GetConfig),
+            Ok(Line::SetTarget { temp, kind }) => Some(set(Config {
+                target_temp: temp,
+                target_kind: kind.unwrap_or(config.target_kind),
+                ..*config
+            })),
+            Ok(Line::SetGains {
+                set: gain_set,
+                gains,
+            }) => {
+                let mut config = *config;
+                match gain_set {
+                    GainSet::Cold => config.cold_gains = gains,
+                    GainSet::Warm => config.warm_gains = gains,
+                    GainSet::Steam => config.steam_gains = gains,
+                }
+                Some(set(config))
+            }
+            Ok(Line::SetWindow(window_size)) => Some(set(Config {
+                window_size,
+                ..*config
+            })),
+            Ok(Line::SetSteamTemp(steam_temp)) => Some(set(Config {
+                steam_temp,
+                ..*config
+            })),
+            Ok(Line::SetSteamTimeout(steam_timeout)) => Some(set(Config {
+                steam_timeout,
+                ..*config
+            })),
+            Ok(Line::SetIdleTimeout(idle_timeout)) => Some(set(Config {
+                idle_timeout,
+                ..*config
+            })),
+            Ok(Line::SetStandbyTemp(standby_temp)) => Some(set(Config {
+                standby_temp,
+                ..*config
+            })),
+            Ok(Line::SetWakeAt(wake_at)) => Some(set(Config { wake_at, ..*config })),
+            Ok(Line::SetTime(seconds_of_day)) => Some(Request::SetTime { seconds_of_day }),
+            Ok(Line::SetCalibration(calibration)) => Some(set(Config {
+                calibration,
+                ..*config
+            })),
+            Ok(Line::SetFilter(filter)) => Some(set(Config { filter, ..*config })),
+            Ok(Line::Command(command)) => Some(Request::Command(command)),
+            Ok(Line::BrewSample(brew_temp)) => Some(Request::BrewSample { brew_temp }),
+            Ok(Line::FitBrewModel) => Some(Request::FitBrewModel),
+            Ok(Line::LogDump) => {
+                self.dump = Some(Dump {
+                    next: log.oldest_seq(),
+                    end: log.next_seq(),
+                });
+                // The prompt follows the end of the log.
+                return None;
+            }
+            Ok(Line::Stream(on)) => {
+                self.streaming = on;
+                self.write_str("ok\r\n");
+                None
+            }
+            Err(ParseError::Empty) => None,
+            Err(error) => {
+                write!(self.output(), "error: {}\r\n", error).ok();
+                None
+            }
+        };
+        if request.is_none() {
+            self.write_str(PROMPT);
+        }
+        request
+    }
+
+    fn write_response(&mut self, response: &Response) {
+        match response {
+            Response::Status(telemetry) => self.write_telemetry(telemetry),
+            Response::Config(config) => {
+                write!(
+                    self.output(),
+                    "target {:.1} {:?}\r\ncold   {} {} {}\r\nwarm   {} {} {}\r\nsteam  {:.1} {} {} {} timeout {:?}\r\n\
+                     window {}\r\ncalibration {:?}\r\nfilter median {} alpha {} max delta {} max rejects {}\r\n\
+                     standby after {:?} ms at {:?} wake at {:?}\r\n",
+                    config.target_temp,
+                    config.target_kind,
+                    config.cold_gains.kp,
+                    config.cold_gains.ki,
+                    config.cold_gains.kd,
+                    config.warm_gains.kp,
+                    config.warm_gains.ki,
+                    config.warm_gains.kd,
+                    config.steam_temp,
+                    config.steam_gains.kp,
+                    config.steam_gains.ki,
+                    config.steam_gains.kd,
+                    config.steam_timeout,
+                    config.window_size,
+                    config.calibration,
+                    config.filter.median_len,
+                    config.filter.ema_alpha,
+                    config.filter.max_delta,
+                    config.filter.max_rejects,
+                    config.idle_timeout,
+                    config.standby_temp,
+                    config.wake_at
+                )
+                .ok();
+            }
+            Response::Ok => self.write_str("ok\r\n"),
+            Response::Error(code) => {
+                write!(self.output(), "error: {:?}\r\n", code).ok();
+            }
+            // Only sent in the binary session.
+            Response::Hello { .. } | Response::Event(_) | Response::EventsEnd { .. } => {}
+        }
+    }
+
+    fn write_telemetry(&mut self, telemetry: &Telemetry) {
+        let status = &telemetry.status;
+        write!(
+            self.output(),
+            "{}ms temp {:.2} raw {:.2} target {:.1} heater {} pid {:.0} mode {:?} faults {:#06x}{}\r\n",
+            telemetry.uptime,
+            status.current_temp,
+            telemetry.raw_temp,
+            status.target_temp,
+            if status.heater_on { "on" } else { "off" },
+            status.pid_output,
+            status.mode,
+            status.faults.0,
+            if telemetry.ready { " ready" } else { "" }
+        )
+        .ok();
+    }
+
+    fn send_frame(&mut self, message: &Message) {
+        let mut buf = [0; MAX_FRAME];
+        match frame::encode(message, &mut buf) {
+            Ok(len) if len <= self.room() => self.push_bytes(&buf[..len]),
+            _ => defmt::warn!("Console: dropped a frame"),
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        // Running out of room cuts the text short, which is all that can be done.
+        self.output().write_str(s).ok();
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if self.tx.enqueue(*byte).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn output(&mut self) -> Output<'_> {
+        Output(&mut self.tx)
+    }
+
+    fn room(&self) -> usize {
+        self.tx.capacity() - self.tx.len()
+    }
+
+    fn flush_tx(&mut self) {
+        while !self.tx.is_empty() {
+            let mut chunk = [0; 64];
+            let mut len = 0;
+            for (slot, byte) in chunk.iter_mut().zip(self.tx.iter()) {
+                *slot = *byte;
+                len += 1;
+            }
+            match self.serial.write(&chunk[..len]) {
+                Ok(written) => {
+                    for _ in 0..written {
+                        self.tx.dequeue();
+                    }
+                }
+                // The rest goes out once the host picked this up.
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Writes text into the output queue.
+struct Output<'a>(&'a mut Queue<u8, U1024>);
+
+impl<'a> Write for Output<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0.enqueue(byte).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! Keeps the reason for a crash in RAM which survives the reset, so the next boot can report it.
+//!
+//! Besides panics and HardFaults, this also covers a task the supervisor found stuck before the
+//! watchdog reset the controller.
+//!
+//! The record lives in the `.uninit` section, which the runtime neither zeroes nor initializes.
+//! After a power loss the RAM holds garbage, the magic and CRC tell a real record apart.
+
+use crate::storage::crc32;
+use crate::supervisor::Task;
+use core::fmt::{self, Write};
+use core::mem::{size_of, MaybeUninit};
+use core::panic::PanicInfo;
+use core::{ptr, slice, str};
+use defmt::Format;
+
+const MAGIC: u32 = 0x4853_5243; // "CRSH"
+const KIND_PANIC: u32 = 0;
+const KIND_HARD_FAULT: u32 = 1;
+const KIND_STUCK_TASK: u32 = 2;
+
+/// The longest file path kept, longer paths keep their end.
+pub const MAX_FILE: usize = 32;
+/// The longest panic message kept.
+pub const MAX_MESSAGE: usize = 64;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct RawCrash {
+    magic: u32,
+    kind: u32,
+    line: u32,
+    pc: u32,
+    lr: u32,
+    task: u32,
+    file_len: u32,
+    file: [u8; MAX_FILE],
+    message_len: u32,
+    message: [u8; MAX_MESSAGE],
+    crc: u32,
+}
+
+#[link_section = ".uninit.CRASH"]
+static mut CRASH: MaybeUninit<RawCrash> = MaybeUninit::uninit();
+
+impl RawCrash {
+    fn new(kind: u32) -> Self {
+        Self {
+            magic: MAGIC,
+            kind,
+            line: 0,
+            pc: 0,
+            lr: 0,
+            task: 0,
+            file_len: 0,
+            file: [0; MAX_FILE],
+            message_len: 0,
+            message: [0; MAX_MESSAGE],
+            crc: 0,
+        }
+    }
+
+    fn checksum(&self) -> u32 {
+        // Safety: the struct is `repr(C)` and only made of `u32`s and byte arrays sized in
+        // multiples of four, so there is no padding.
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                self as *const Self as *const u8,
+                size_of::<Self>() - size_of::<u32>(),
+            )
+        };
+        crc32(bytes)
+    }
+
+    fn store(mut self) {
+        self.crc = self.checksum();
+        // Safety: only written from the panic and fault handlers, which never return, and the
+        // supervisor, which runs at the highest task priority. Read in `init` before interrupts
+        // are enabled.
+        unsafe { ptr::write_volatile(CRASH.as_mut_ptr(), self) };
+    }
+}
+
+/// What brought the previous run down.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CrashKind {
+    Panic,
+    HardFault,
+    /// The watchdog reset the controller because a task stopped checking in.
+    StuckTask,
+}
+
+/// A crash recorded by the previous run.
+#[derive(Clone, Copy)]
+pub struct CrashReport {
+    raw: RawCrash,
+}
+
+impl CrashReport {
+    pub fn kind(&self) -> CrashKind {
+        match self.raw.kind {
+            KIND_HARD_FAULT => CrashKind::HardFault,
+            KIND_STUCK_TASK => CrashKind::StuckTask,
+            _ => CrashKind::Panic,
+        }
+    }
+
+    /// The file the panic happened in, possibly cut off at the front.
+    pub fn file(&self) -> &str {
+        text(&self.raw.file, self.raw.file_len)
+    }
+
+    pub fn line(&self) -> u32 {
+        self.raw.line
+    }
+
+    /// The panic message, possibly cut off at the end.
+    pub fn message(&self) -> &str {
+        text(&self.raw.message, self.raw.message_len)
+    }
+
+    /// The program counter of the faulting instruction.
+    pub fn pc(&self) -> u32 {
+        self.raw.pc
+    }
+
+    pub fn lr(&self) -> u32 {
+        self.raw.lr
+    }
+
+    /// The task which stopped checking in.
+    pub fn task(&self) -> Option<Task> {
+        Task::from_id(self.raw.task as u8)
+    }
+}
+
+impl Format for CrashReport {
+    fn format(&self, f: &mut defmt::Formatter) {
+        match self.kind() {
+            CrashKind::Panic => defmt::write!(
+                f,
+                "panic at {:str}:{:u32}: {:str}",
+                self.file(),
+                self.line(),
+                self.message()
+            ),
+            CrashKind::HardFault => {
+                defmt::write!(f, "HardFault at pc={:u32} lr={:u32}", self.pc(), self.lr())
+            }
+            CrashKind::StuckTask => defmt::write!(f, "task {:?} got stuck", self.task()),
+        }
+    }
+}
+
+/// Records a panic, called from the panic handler.
+pub fn record_panic(info: &PanicInfo) {
+    let mut raw = RawCrash::new(KIND_PANIC);
+    if let Some(location) = info.location() {
+        let file = location.file();
+        let mut start = file.len().saturating_sub(MAX_FILE);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        raw.file_len = copy(&file[start..], &mut raw.file);
+        raw.line = location.line();
+    }
+
+    let mut message = Truncating {
+        buf: &mut raw.message,
+        len: 0,
+    };
+    // A message longer than the buffer is cut short, which is fine.
+    write!(message, "{}", info).ok();
+    raw.message_len = message.len as u32;
+
+    raw.store();
+}
+
+/// Records a HardFault with the stacked program counter and link register.
+///
+/// A record this run wrote already is kept. The `bkpt` a debug build halts on after a panic
+/// escalates to a HardFault when no debugger is attached, and the panic is what matters.
+pub fn record_hard_fault(pc: u32, lr: u32) {
+    if stored().is_some() {
+        return;
+    }
+    let mut raw = RawCrash::new(KIND_HARD_FAULT);
+    raw.pc = pc;
+    raw.lr = lr;
+    raw.store();
+}
+
+/// Records the task which made the supervisor stop feeding the watchdog.
+pub fn record_stuck_task(task: Task) {
+    let mut raw = RawCrash::new(KIND_STUCK_TASK);
+    raw.task = task.id() as u32;
+    raw.store();
+}
+
+/// Returns the crash recorded by the previous run, if any, and clears it.
+pub fn take() -> Option<CrashReport> {
+    let report = stored();
+    // Safety: called once from `init`, nothing else accesses the record at that point.
+    unsafe { ptr::write_volatile(&mut (*CRASH.as_mut_ptr()).magic, 0) };
+    report
+}
+
+/// The record in RAM, if it is a valid one.
+fn stored() -> Option<CrashReport> {
+    // Safety: only read from `init` and the HardFault handler, which nothing preempts. Any bit
+    // pattern is a valid `RawCrash`.
+    let raw = unsafe { ptr::read_volatile(CRASH.as_ptr()) };
+    let valid = raw.magic == MAGIC
+        && raw.crc == raw.checksum()
+        && raw.file_len as usize <= MAX_FILE
+        && raw.message_len as usize <= MAX_MESSAGE
+        && raw.kind <= KIND_STUCK_TASK;
+    if valid {
+        Some(CrashReport { raw })
+    } else {
+        None
+    }
+}
+
+fn text(buf: &[u8], len: u32) -> &str {
+    str::from_utf8(&buf[..len as usize]).unwrap_or("?")
+}
+
+fn copy(s: &str, buf: &mut [u8]) -> u32 {
+    let len = s.len().min(buf.len());
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    len as u32
+}
+
+/// Writes into a fixed buffer, stopping at the last character which still fits.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Truncating<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len + c.len_utf8() > self.buf.len() {
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += c.len_utf8();
+        }
+        Ok(())
+    }
+}
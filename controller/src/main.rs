@@ -1,88 +1,252 @@
 #![no_main]
 #![cfg_attr(not(test), no_std)]
 
+mod ble;
+mod board;
+mod clock;
 mod config;
+mod console;
+mod crash;
 mod peripherals;
 mod pid;
+mod remote;
 mod state;
-
+mod storage;
+
+use ble::service::Change;
+use ble::{BleBuffers, BleLinkLayer, BleResponder};
+use board::{Board, SelectedBoard};
+use brew::{BrewEstimator, BrewSamples};
+use clock::{Clock, Millis, WallClock};
+use console::{Console, UsbBus};
+use controller_core::{
+    brew, calibration, filter, machine, ready, reset, safety, self_test, settings, shell, shot,
+    standby, supervisor,
+};
+use core::panic::PanicInfo;
 use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+use crash::CrashKind;
 #[allow(unused_imports)]
 use defmt_rtt as _;
-#[allow(unused_imports)]
-use nrf52840_hal as _MemoryLayout;
+use machine::{Event, GainSet, HeaterPolicy, Machine, MachineState};
 use pid::Proportional;
+use protocol::gatt::{WriteResult, COMMAND_ID};
+use ready::{ReadyChange, ReadyDetector};
+use remote::Remote;
+use reset::ResetReason;
+use self_test::SelfTest;
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 use groundhog_nrf52::GlobalRollingTimer;
+use nrf52840_hal::clocks::{Clocks, ExternalOscillator, Internal, LfOscStopped};
 use nrf52840_hal::gpio::{p0, p1};
 use nrf52840_hal::pac::TIMER1;
+use nrf52840_hal::usbd::{UsbPeripheral, Usbd};
 use nrf52840_hal::wdt::{count, handles::Hdl0, Parts, Watchdog, WatchdogHandle};
 use nrf52840_hal::Timer;
-use panic_probe as _;
-use peripherals::boiler::Boiler;
+use peripherals::boiler::{Boiler, BoilerError, SensorHealth};
 use peripherals::display::Display;
 use peripherals::heater::{Heater, HeaterConfig};
+use peripherals::sensor::{boiler_sensor, BoilerSensor};
+use peripherals::switch::{Edge, Switch};
+use rubble::link::queue::SimpleQueue;
+use rubble::link::MIN_PDU_BUF;
+use rubble::time::Timer as _;
+use rubble_nrf5x::radio::{BleRadio, PacketBuffer};
+use settings::Settings;
+use shot::ShotTimer;
+use standby::{Standby, StandbyAction};
 use state::State;
+use storage::config_store::{ConfigStore, DefaultsReason, CONFIG_VERSION};
+use storage::event_log::{EventLog, LogEvent, ShortText, EVENT_LOG_PAGES};
+use storage::nvmc::NvmcFlash;
+use supervisor::{Supervisor, Task, Verdict};
+use usb_device::bus::UsbBusAllocator;
 
 const ONE_SECOND: i32 = 1_000_000; // schedule is in micros
 const HALF_SECOND: i32 = ONE_SECOND / 2;
 const TWENTY_MILLIS: i32 = ONE_SECOND / 1000 * 20; // div by 1000 => 1 millis, * 20 => 20 millis
 
-const TARGET_TEMP: f32 = 95.0;
-
-const START_KP: f32 = 250.0;
-const START_KI: f32 = 0.03;
-const START_KD: f32 = 0.0;
-
-const WARM_KP: f32 = 69.0;
-const WARM_KI: f32 = 0.17;
-const WARM_KD: f32 = 0.0;
-
-const COLD_ENABLED: bool = true;
+/// Polls (20ms apart) a switch has to be stable before it counts.
+const SWITCH_DEBOUNCE_POLLS: u8 = 3;
 
 #[rtic::app(device = nrf52840_hal::pac, peripherals = true, monotonic = groundhog_nrf52::GlobalRollingTimer)]
 const APP: () = {
     struct Resources {
-        boiler: Boiler,
+        #[init([0; MIN_PDU_BUF])]
+        ble_tx_buf: PacketBuffer,
+        #[init([0; MIN_PDU_BUF])]
+        ble_rx_buf: PacketBuffer,
+        #[init(SimpleQueue::new())]
+        ble_tx_queue: SimpleQueue,
+        #[init(SimpleQueue::new())]
+        ble_rx_queue: SimpleQueue,
+        #[init(None)]
+        clocks: Option<Clocks<ExternalOscillator, Internal, LfOscStopped>>,
+        #[init(None)]
+        usb_bus: Option<UsbBusAllocator<UsbBus>>,
+        #[init(BrewSamples::new())]
+        brew_samples: BrewSamples,
+        ble_ll: BleLinkLayer,
+        ble_r: BleResponder,
+        board_extras: <SelectedBoard as Board>::Extras,
+        boiler: Boiler<BoilerSensor>,
         boiler_timer: Timer<TIMER1>,
+        brew_estimator: BrewEstimator,
+        brew_switch: Switch,
+        clock: Clock,
+        config_store: ConfigStore,
+        console: Console,
         heater: Heater,
         display: Display,
+        event_log: EventLog,
+        flash: NvmcFlash,
+        machine: Machine,
+        radio: BleRadio,
+        ready_detector: ReadyDetector,
+        self_test: SelfTest,
+        settings: Settings,
+        shot_timer: ShotTimer,
+        standby: Standby,
         state: State,
+        steam_switch: Switch,
+        supervisor: Supervisor,
+        wall_clock: WallClock,
         watchdog_handle: WatchdogHandle<Hdl0>,
     }
 
-    #[init(spawn = [boiler_measure_temperature, draw_display, heater_drive_on_off])]
+    #[init(resources = [ble_tx_buf, ble_rx_buf, ble_tx_queue, ble_rx_queue, clocks, usb_bus], spawn = [boiler_measure_temperature, draw_display, flush_event_log, heater_drive_on_off, poll_switches, supervise])]
     fn init(ctx: init::Context) -> init::LateResources {
         GlobalRollingTimer::init(ctx.device.TIMER0);
+        let mut clock = Clock::new();
+        // The radio and USB need the external crystal, the internal oscillator is too inaccurate.
+        let clocks = ctx
+            .resources
+            .clocks
+            .get_or_insert(Clocks::new(ctx.device.CLOCK).enable_ext_hfosc());
+        let usb_bus = ctx
+            .resources
+            .usb_bus
+            .get_or_insert(Usbd::new(UsbPeripheral::new(ctx.device.USBD, clocks)));
+        let console = Console::new(usb_bus);
 
         let port0 = p0::Parts::new(ctx.device.P0);
         let port1 = p1::Parts::new(ctx.device.P1);
-        let mut pin_config = config::PinConfig::new(port0, port1);
+        let (pin_config, board_extras) = SelectedBoard::split(port0, port1);
+        defmt::info!("Running on the {:str}", SelectedBoard::NAME);
 
-        // Boiler Sensor Setup
-        let sensor_vdd = pin_config.sensor_vdd.take().unwrap();
-        let sensor_signal = pin_config.sensor_signal.take().unwrap();
+        // Boiler Sensor Setup, the backend is picked through the `sensor-*` feature
+        let sensor = boiler_sensor(pin_config.sensor, ctx.device.SPIM1, ctx.device.SAADC);
 
         // Heater Setup
-        let heater_signal = pin_config.heater_signal.take().unwrap();
+        let heater_signal = pin_config.heater_signal;
 
         // Display Setup
-        let display_rst_pin = pin_config.display_rst_pin.take().unwrap();
-        let display_dc_pin = pin_config.display_dc_pin.take().unwrap();
-        let display_cs_pin = pin_config.display_cs_pin.take().unwrap();
-        let display_sck_pin = pin_config.display_sck_pin.take().unwrap();
-        let display_mosi_pin = pin_config.display_mosi_pin.take().unwrap();
+        let display_rst_pin = pin_config.display_rst;
+        let display_dc_pin = pin_config.display_dc;
+        let display_cs_pin = pin_config.display_cs;
+        let display_sck_pin = pin_config.display_sck;
+        let display_mosi_pin = pin_config.display_mosi;
+
+        // Switch Setup
+        let brew_switch = Switch::new(pin_config.brew_switch, SWITCH_DEBOUNCE_POLLS);
+        let steam_switch = Switch::new(pin_config.steam_switch, SWITCH_DEBOUNCE_POLLS);
+
+        let mut flash = NvmcFlash::new(ctx.device.NVMC);
+        let mut event_log = EventLog::new(&flash, EVENT_LOG_PAGES);
+        let resetreas = ctx.device.POWER.resetreas.read().bits();
+        // The bits are cleared by writing them back, otherwise they pile up over resets.
+        ctx.device
+            .POWER
+            .resetreas
+            .write(|w| unsafe { w.bits(resetreas) });
+        let reset_reason = ResetReason::from_resetreas(resetreas);
+        if reset_reason.policy().unexpected {
+            defmt::warn!("Unexpected reset: {:?}", reset_reason);
+        } else {
+            defmt::info!("Reset reason: {:?}", reset_reason);
+        }
+        event_log.record(clock.now(), LogEvent::Boot { resetreas });
+
+        let last_crash = crash::take();
+        if let Some(report) = last_crash {
+            defmt::error!("The previous run crashed: {:?}", report);
+            let event = match report.kind() {
+                CrashKind::Panic => Some(LogEvent::Panic {
+                    file: ShortText::tail(report.file()),
+                    line: report.line().min(u16::MAX as u32) as u16,
+                }),
+                CrashKind::HardFault => Some(LogEvent::HardFault {
+                    pc: report.pc(),
+                    lr: report.lr(),
+                }),
+                CrashKind::StuckTask => report.task().map(LogEvent::TaskStuck),
+            };
+            if let Some(event) = event {
+                event_log.record(clock.now(), event);
+            }
+        }
 
-        let target_temp = TARGET_TEMP;
-        let kp = START_KP;
-        let ki = START_KI;
-        let kd = START_KD;
+        let mut config_store = ConfigStore::new(&flash, storage::CONFIG_PAGES);
+        let mut config_warning = None;
+        let settings = match config_store.load(&flash) {
+            Ok((settings, version)) => {
+                defmt::info!("Loaded settings (version {:u16}) from flash", version);
+                if version != CONFIG_VERSION {
+                    if config_store.save(&mut flash, &settings).is_ok() {
+                        event_log.record(
+                            clock.now(),
+                            LogEvent::ConfigSaved {
+                                version: CONFIG_VERSION,
+                            },
+                        );
+                    } else {
+                        defmt::warn!("Could not store the migrated settings!");
+                    }
+                }
+                settings
+            }
+            Err(DefaultsReason::Empty) => {
+                defmt::info!("No settings stored, using defaults");
+                Settings::default()
+            }
+            Err(reason) => {
+                defmt::warn!("Stored settings unusable ({:?}), using defaults!", reason);
+                event_log.record(clock.now(), LogEvent::ConfigDefaults(reason));
+                config_warning = Some(reason);
+                Settings::default()
+            }
+        };
+        let target_temp = settings.boiler_setpoint();
+        let kp = settings.cold_gains.kp;
+        let ki = settings.cold_gains.ki;
+        let kd = settings.cold_gains.kd;
 
-        let heater_config = HeaterConfig::new(target_temp, kp, ki, kd, 1000);
+        let heater_config = HeaterConfig::new(
+            target_temp,
+            kp,
+            ki,
+            kd,
+            settings.window_size,
+            settings.max_reading_age,
+        );
 
         let boiler_timer = Timer::new(ctx.device.TIMER1);
 
+        let (ble_ll, ble_r, radio) = ble::start(
+            ctx.device.RADIO,
+            ctx.device.TIMER2,
+            &ctx.device.FICR,
+            BleBuffers {
+                tx: ctx.resources.ble_tx_buf,
+                rx: ctx.resources.ble_rx_buf,
+                tx_queue: ctx.resources.ble_tx_queue,
+                rx_queue: ctx.resources.ble_rx_queue,
+            },
+            &settings,
+        );
+
         let display = Display::new(
             ctx.device.SPIM0,
             display_rst_pin,
@@ -102,15 +266,21 @@ const APP: () = {
             kp,
             ki,
             kd,
-            true,
-            false,
+            reset_reason,
         );
 
-        // Watchdog Setup
+        if let Some(warning) = config_warning {
+            state.set_config_warning(warning);
+        }
+        if let Some(report) = last_crash {
+            state.set_last_crash(report);
+        }
+
+        // Watchdog Setup, only the `supervise` task feeds it once every critical task checked in
         let (watchdog_handle, ..) = match Watchdog::try_new(ctx.device.WDT) {
             Ok(mut watchdog) => {
-                // Set the watchdog to timeout after 3 seconds (in 32.768kHz ticks)
-                watchdog.set_lfosc_ticks(3 * 32768);
+                // One supervisor window is a second, or 32768 ticks of the 32.768kHz clock.
+                watchdog.set_lfosc_ticks(u32::from(supervisor::WATCHDOG_WINDOWS) * 32768);
 
                 let Parts {
                     watchdog: _watchdog,
@@ -136,33 +306,65 @@ const APP: () = {
             },
         };
 
-        if ctx.device.POWER.resetreas.read().dog().is_detected() {
-            ctx.device.POWER.resetreas.modify(|_r, w| {
-                // Clear the watchdog reset reason bit
-                w.dog().set_bit()
-            });
-            defmt::warn!("Controller got restarted by the watchdog!");
-            state.set_watchdog_reset(true);
-        }
-
         ctx.spawn.boiler_measure_temperature().ok();
         ctx.spawn.draw_display(true).ok();
         ctx.spawn.heater_drive_on_off().ok();
+        ctx.spawn.poll_switches().ok();
+        ctx.spawn.flush_event_log().ok();
+        ctx.spawn.supervise().ok();
+
+        let now = clock.now();
+        let machine = Machine::new(now);
 
         init::LateResources {
-            boiler: Boiler::new(sensor_signal, sensor_vdd),
+            ble_ll,
+            ble_r,
+            board_extras,
+            boiler: Boiler::new(
+                sensor,
+                settings.calibration,
+                settings.filter,
+                settings.sensor_health,
+            ),
             boiler_timer,
+            brew_estimator: BrewEstimator::new(settings.brew_model),
+            brew_switch,
+            clock,
+            config_store,
+            console,
             heater,
             display,
+            event_log,
+            flash,
+            machine,
+            radio,
+            ready_detector: ReadyDetector::new(settings.ready),
+            self_test: SelfTest::new(),
+            settings,
+            shot_timer: ShotTimer::new(),
+            standby: Standby::new(now),
             state,
+            steam_switch,
+            supervisor: Supervisor::new(),
+            wall_clock: WallClock::new(),
             watchdog_handle,
         }
     }
 
-    #[task(resources= [display, boiler_timer, state], priority = 2, schedule = [draw_display])]
+    #[task(resources= [display, boiler_timer, self_test, state], priority = 2, schedule = [draw_display])]
     fn draw_display(ctx: draw_display::Context, init: bool) {
+        supervisor::check_in(Task::Display);
+
         if init {
-            ctx.resources.display.init(ctx.resources.boiler_timer);
+            let initialized = ctx
+                .resources
+                .display
+                .init(ctx.resources.boiler_timer)
+                .is_ok();
+            ctx.resources.self_test.display_initialized(initialized);
+            ctx.resources
+                .state
+                .set_self_test(ctx.resources.self_test.report());
         }
 
         ctx.resources.display.draw_screen(ctx.resources.state);
@@ -172,64 +374,443 @@ const APP: () = {
             .unwrap();
     }
 
-    #[task(resources = [boiler, boiler_timer, heater, state, watchdog_handle], priority = 2, schedule = [boiler_measure_temperature])]
+    #[task(resources = [ble_r, boiler, boiler_timer, brew_estimator, clock, console, event_log, heater, machine, ready_detector, self_test, settings, state], priority = 2, schedule = [boiler_measure_temperature])]
     fn boiler_measure_temperature(ctx: boiler_measure_temperature::Context) {
         defmt::debug!("Measuring Temperature");
-
-        if let Ok(t) = ctx
+        supervisor::check_in(Task::Measure);
+
+        let now = ctx.resources.clock.now();
+        // A remote may have changed the calibration, the filter or the brew model since the last
+        // reading.
+        let calibration = ctx.resources.settings.calibration;
+        if ctx.resources.boiler.calibration() != calibration {
+            ctx.resources.boiler.set_calibration(calibration);
+        }
+        let filter = ctx.resources.settings.filter;
+        if ctx.resources.boiler.filter_config() != filter {
+            ctx.resources.boiler.set_filter_config(filter);
+        }
+        let brew_model = ctx.resources.settings.brew_model;
+        if *ctx.resources.brew_estimator.model() != brew_model {
+            ctx.resources.brew_estimator.set_model(brew_model);
+        }
+        let result = ctx
             .resources
             .boiler
-            .read_temperature(ctx.resources.boiler_timer)
-        {
-            ctx.resources.state.set_current_boiler_temp(t);
-
-            if COLD_ENABLED
-                && t > ctx.resources.state.target_boiler_temp()
-                && ctx.resources.state.in_coldstart()
-            {
-                ctx.resources.state.disable_coldstart();
+            .read_temperature(ctx.resources.boiler_timer, now);
+        ctx.resources
+            .self_test
+            .sample(now, result.as_ref().ok().map(|reading| reading.calibrated));
+        ctx.resources
+            .state
+            .set_self_test(ctx.resources.self_test.report());
+
+        match result {
+            Ok(reading) => {
+                let t = reading.filtered;
+                ctx.resources.state.set_raw_boiler_temp(reading.raw);
+                ctx.resources.state.set_current_boiler_temp(t);
+
+                let duty =
+                    ctx.resources.state.last_pid_out() / ctx.resources.settings.window_size as f32;
+                let brew_temp =
+                    ctx.resources
+                        .brew_estimator
+                        .update(t, duty, (HALF_SECOND / 1000) as u32);
+                ctx.resources.state.set_brew_temp_estimate(brew_temp);
+
+                let machine_state = ctx.resources.machine.state();
+                let ready_change = match machine_state {
+                    MachineState::Coldstart | MachineState::Stabilizing | MachineState::Ready => {
+                        ctx.resources.ready_detector.update(
+                            now,
+                            t,
+                            ctx.resources.state.target_boiler_temp(),
+                            duty,
+                        )
+                    }
+                    _ => {
+                        ctx.resources.ready_detector.reset();
+                        None
+                    }
+                };
                 ctx.resources
-                    .heater
-                    .update_pid(WARM_KP, WARM_KI, WARM_KD, Proportional::OnError);
-                ctx.resources.state.set_kp(WARM_KP);
-                ctx.resources.state.set_ki(WARM_KI);
-                ctx.resources.state.set_kd(WARM_KD);
+                    .state
+                    .set_ready(ctx.resources.ready_detector.is_ready());
+                ctx.resources
+                    .state
+                    .set_ready_eta(ctx.resources.ready_detector.eta());
+
+                let event = match (machine_state, ready_change) {
+                    (MachineState::Booting, _) if ctx.resources.self_test.report().passed() => {
+                        Some(Event::Booted {
+                            cold: t < ctx.resources.state.target_boiler_temp()
+                                || !ctx.resources.state.reset_reason().policy().warm_start,
+                        })
+                    }
+                    // Before the setpoint check, so becoming ready is not lost on the way.
+                    (_, Some(ReadyChange::BecameReady)) => {
+                        defmt::info!("Ready to brew");
+                        Some(Event::Stable)
+                    }
+                    (MachineState::Coldstart, _)
+                        if t > ctx.resources.state.target_boiler_temp() =>
+                    {
+                        Some(Event::TemperatureReached)
+                    }
+                    (_, Some(ReadyChange::LostReady)) => Some(Event::Unstable),
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    dispatch(
+                        event,
+                        now,
+                        ctx.resources.machine,
+                        ctx.resources.heater,
+                        ctx.resources.state,
+                        ctx.resources.settings,
+                        ctx.resources.event_log,
+                    );
+                }
             }
-        } else {
-            defmt::warn!("Reading temperature failed!");
-            // Turn the heater off until we get a good new reading for safety reasons.
+            Err(BoilerError::SampleRejected { raw }) => {
+                // A single implausible sample is a soft failure, keep the last filtered value.
+                defmt::warn!("Rejected implausible temperature {:f32}", raw);
+                ctx.resources.state.set_raw_boiler_temp(raw);
+            }
+            Err(BoilerError::TempReadFailed) => {
+                let failures = ctx.resources.boiler.consecutive_failures();
+                defmt::warn!("Reading temperature failed ({:u8} in a row)!", failures);
+                if let (1, Some(error)) = (failures, ctx.resources.boiler.last_error()) {
+                    ctx.resources.event_log.record(
+                        now,
+                        LogEvent::SensorFailure {
+                            error: ShortText::of_debug(error),
+                        },
+                    );
+                }
+            }
+        }
+
+        let health = ctx.resources.boiler.health();
+        if health != ctx.resources.state.sensor_health() {
+            defmt::warn!("Sensor health changed to {:?}", health);
+        }
+        let health_event = match (health, ctx.resources.machine.state()) {
+            (SensorHealth::Failed, MachineState::Fault) => None,
+            (SensorHealth::Failed, _) => Some(Event::SensorFault),
+            (SensorHealth::Ok, MachineState::Fault) => Some(Event::SensorRecovered),
+            _ => None,
+        };
+        if let Some(event) = health_event {
+            dispatch(
+                event,
+                now,
+                ctx.resources.machine,
+                ctx.resources.heater,
+                ctx.resources.state,
+                ctx.resources.settings,
+                ctx.resources.event_log,
+            );
+        }
+        if health == SensorHealth::Failed {
+            // Do not wait for the reading to go stale, a failed sensor stops the heater right away.
             ctx.resources.heater.turn_heater_off().ok();
             ctx.resources.state.set_heater_on(false);
         }
-
-        // Pet the watchdog so it doesn't cause a reset
-        ctx.resources.watchdog_handle.pet();
+        ctx.resources.state.set_sensor_health(health);
+        ctx.resources
+            .state
+            .set_last_reading_at(ctx.resources.boiler.last_good_at());
+        ctx.resources
+            .state
+            .set_rejected_samples(ctx.resources.boiler.soft_failures());
 
         defmt::debug!("{:?}", ctx.resources.state);
+        ble::publish(
+            ctx.resources.ble_r,
+            ctx.resources.state,
+            ctx.resources.settings,
+        );
+        ctx.resources
+            .console
+            .stream(&ctx.resources.state.telemetry(now));
 
         ctx.schedule
             .boiler_measure_temperature(ctx.scheduled + HALF_SECOND)
             .unwrap();
     }
 
-    #[task(resources = [heater, state], priority = 2, schedule = [heater_drive_on_off])]
+    #[task(resources = [board_extras, clock, event_log, heater, machine, self_test, settings, state], priority = 2, schedule = [heater_drive_on_off])]
     fn heater_drive_on_off(ctx: heater_drive_on_off::Context) {
+        supervisor::check_in(Task::HeaterDrive);
+        let now = ctx.resources.clock.now();
+
+        let reading_age = ctx
+            .resources
+            .state
+            .last_reading_at()
+            .map(|at| now.saturating_sub(at));
+        let temp = ctx.resources.state.current_boiler_temp();
+
+        // Until the self test passed, it decides on its own if the heater runs.
+        let forced = match ctx.resources.self_test.heater_demand(now) {
+            Some(pulse) => Some(pulse),
+            None => match ctx.resources.machine.state().heater_policy() {
+                HeaterPolicy::Off => Some(false),
+                HeaterPolicy::Standby
+                    if ctx.resources.settings.standby.action == StandbyAction::Off =>
+                {
+                    Some(false)
+                }
+                HeaterPolicy::Standby | HeaterPolicy::Brew | HeaterPolicy::Steam => None,
+            },
+        };
+        if let Some(pulse) = forced {
+            let heater_on = if pulse {
+                ctx.resources
+                    .heater
+                    .pulse(temp, reading_age)
+                    .unwrap_or(false)
+            } else {
+                ctx.resources.heater.turn_heater_off().ok();
+                false
+            };
+            ctx.resources.state.set_heater_on(heater_on);
+            SelectedBoard::indicate_heater(ctx.resources.board_extras, heater_on);
+            ctx.schedule
+                .heater_drive_on_off(ctx.scheduled + TWENTY_MILLIS)
+                .unwrap();
+            return;
+        }
+
         let heater_on = ctx
             .resources
             .heater
-            .control(ctx.resources.state.current_boiler_temp())
+            .control(temp, reading_age)
             .ok()
             .unwrap();
         ctx.resources.state.set_heater_on(heater_on);
+        SelectedBoard::indicate_heater(ctx.resources.board_extras, heater_on);
         ctx.resources
             .state
             .set_last_pid_out(ctx.resources.heater.last_output());
+        let cutoff = ctx.resources.heater.cutoff();
+        if cutoff != ctx.resources.state.cutoff() {
+            let event = match cutoff {
+                Some(reason) => LogEvent::FaultLatched(reason),
+                None => LogEvent::FaultCleared,
+            };
+            ctx.resources.event_log.record(now, event);
+        }
+        ctx.resources.state.set_cutoff(cutoff);
 
         ctx.schedule
             .heater_drive_on_off(ctx.scheduled + TWENTY_MILLIS)
             .unwrap();
     }
 
+    #[task(resources = [brew_switch, clock, event_log, heater, machine, settings, shot_timer, standby, state, steam_switch, wall_clock], priority = 2, schedule = [poll_switches])]
+    fn poll_switches(ctx: poll_switches::Context) {
+        let now = ctx.resources.clock.now();
+        let temp = ctx.resources.state.current_boiler_temp();
+
+        let mut events: [Option<Event>; 3] = [None, None, None];
+
+        match ctx.resources.brew_switch.poll() {
+            Some(Edge::Pressed) => {
+                defmt::info!("Shot started");
+                ctx.resources.shot_timer.start(now, temp);
+                events[0] = Some(Event::BrewStarted);
+            }
+            Some(Edge::Released) => {
+                if let Some(shot) = ctx.resources.shot_timer.stop(now) {
+                    defmt::info!("Shot finished: {:?}", shot);
+                    ctx.resources.state.set_last_shot(shot);
+                }
+                events[0] = Some(Event::BrewStopped);
+            }
+            None => ctx.resources.shot_timer.sample(temp),
+        }
+        ctx.resources
+            .state
+            .set_shot_elapsed(ctx.resources.shot_timer.elapsed(now));
+
+        events[1] = match ctx.resources.steam_switch.poll() {
+            Some(Edge::Pressed) => Some(Event::SteamOn),
+            Some(Edge::Released) => Some(Event::SteamOff),
+            None => match (
+                ctx.resources.machine.state(),
+                ctx.resources.settings.steam_timeout,
+            ) {
+                (MachineState::Steam, Some(timeout))
+                    if ctx.resources.machine.time_in_state(now) >= timeout =>
+                {
+                    defmt::info!("Steam mode timed out, cooling down");
+                    Some(Event::Timeout)
+                }
+                _ => None,
+            },
+        };
+
+        if events[0] == Some(Event::BrewStarted) || events[1].is_some() {
+            ctx.resources.standby.activity(now);
+        }
+        events[2] = ctx.resources.standby.check(
+            now,
+            ctx.resources.machine.state(),
+            ctx.resources.wall_clock,
+            &ctx.resources.settings.standby,
+        );
+
+        for event in events.iter().flatten() {
+            dispatch(
+                *event,
+                now,
+                ctx.resources.machine,
+                ctx.resources.heater,
+                ctx.resources.state,
+                ctx.resources.settings,
+                ctx.resources.event_log,
+            );
+        }
+
+        ctx.schedule
+            .poll_switches(ctx.scheduled + TWENTY_MILLIS)
+            .unwrap();
+    }
+
+    #[task(resources = [event_log, flash], priority = 2, schedule = [flush_event_log])]
+    fn flush_event_log(ctx: flush_event_log::Context) {
+        if ctx.resources.event_log.flush(ctx.resources.flash).is_err() {
+            defmt::warn!("Could not write the event log!");
+        }
+
+        ctx.schedule
+            .flush_event_log(ctx.scheduled + ONE_SECOND)
+            .unwrap();
+    }
+
+    /// Runs above all other tasks, so it keeps going even if one of them hangs.
+    #[task(resources = [supervisor, watchdog_handle], priority = 3, schedule = [supervise])]
+    fn supervise(ctx: supervise::Context) {
+        match ctx.resources.supervisor.check() {
+            Verdict::Feed => ctx.resources.watchdog_handle.pet(),
+            Verdict::Wait => {}
+            Verdict::Stuck(task) => {
+                // Not feeding the watchdog any more lets it reset the controller.
+                defmt::error!("Task {:?} is stuck, waiting for the watchdog", task);
+                crash::record_stuck_task(task);
+            }
+        }
+
+        ctx.schedule.supervise(ctx.scheduled + ONE_SECOND).unwrap();
+    }
+
+    /// Handles what the radio sent or received.
+    #[task(binds = RADIO, resources = [ble_ll, radio], spawn = [ble_worker], priority = 4)]
+    fn radio(ctx: radio::Context) {
+        let ble_ll: &mut BleLinkLayer = ctx.resources.ble_ll;
+        if let Some(cmd) = ctx
+            .resources
+            .radio
+            .recv_interrupt(ble_ll.timer().now(), ble_ll)
+        {
+            ctx.resources.radio.configure_receiver(cmd.radio);
+            ble_ll.timer().configure_interrupt(cmd.next_update);
+            if cmd.queued_work {
+                ctx.spawn.ble_worker().ok();
+            }
+        }
+    }
+
+    /// Drives the BLE link layer, above all other tasks since the radio timing is tight.
+    #[task(binds = TIMER2, resources = [ble_ll, radio], spawn = [ble_worker], priority = 4)]
+    fn ble_timer(ctx: ble_timer::Context) {
+        let ble_ll: &mut BleLinkLayer = ctx.resources.ble_ll;
+        let timer = ble_ll.timer();
+        if !timer.is_interrupt_pending() {
+            return;
+        }
+        timer.clear_interrupt();
+
+        let cmd = ble_ll.update_timer(ctx.resources.radio);
+        ctx.resources.radio.configure_receiver(cmd.radio);
+        ble_ll.timer().configure_interrupt(cmd.next_update);
+        if cmd.queued_work {
+            ctx.spawn.ble_worker().ok();
+        }
+    }
+
+    /// Answers what the radio received and applies the accepted writes.
+    #[task(resources = [ble_r, brew_samples, clock, config_store, event_log, flash, heater, machine, settings, standby, state, wall_clock], priority = 2)]
+    fn ble_worker(ctx: ble_worker::Context) {
+        while ctx.resources.ble_r.has_work() {
+            if ctx.resources.ble_r.process_one().is_err() {
+                defmt::warn!("BLE: could not process a packet");
+                break;
+            }
+        }
+
+        let mut remote = Remote {
+            now: ctx.resources.clock.now(),
+            machine: ctx.resources.machine,
+            heater: ctx.resources.heater,
+            state: ctx.resources.state,
+            settings: ctx.resources.settings,
+            event_log: ctx.resources.event_log,
+            config_store: ctx.resources.config_store,
+            brew_samples: ctx.resources.brew_samples,
+            wall_clock: ctx.resources.wall_clock,
+            standby: ctx.resources.standby,
+            flash: ctx.resources.flash,
+        };
+        while let Some(change) = ble::service(ctx.resources.ble_r).take_change() {
+            match change {
+                Change::Settings(settings) => {
+                    defmt::info!("BLE: settings changed");
+                    remote.apply_settings(settings);
+                }
+                Change::Event(event) => remote.dispatch(event),
+                Change::Save => {
+                    let result = match remote.save_settings() {
+                        Ok(()) => WriteResult::Ok,
+                        Err(_) => WriteResult::StorageFailed,
+                    };
+                    ble::service(ctx.resources.ble_r).set_write_result(COMMAND_ID, result);
+                }
+            }
+        }
+        ble::publish_write_result(ctx.resources.ble_r);
+    }
+
+    /// Runs the serial console, answering its requests like the BLE writes.
+    #[task(binds = USBD, resources = [brew_samples, clock, config_store, console, event_log, flash, heater, machine, settings, standby, state, wall_clock], priority = 2)]
+    fn usb(ctx: usb::Context) {
+        let console: &mut Console = ctx.resources.console;
+        console.poll();
+
+        let mut remote = Remote {
+            now: ctx.resources.clock.now(),
+            machine: ctx.resources.machine,
+            heater: ctx.resources.heater,
+            state: ctx.resources.state,
+            settings: ctx.resources.settings,
+            event_log: ctx.resources.event_log,
+            config_store: ctx.resources.config_store,
+            brew_samples: ctx.resources.brew_samples,
+            wall_clock: ctx.resources.wall_clock,
+            standby: ctx.resources.standby,
+            flash: ctx.resources.flash,
+        };
+        while let Some(request) = console.next_request(&remote.settings.config(), remote.event_log)
+        {
+            let response = remote.serve(request);
+            console.respond(response);
+        }
+        console.flush(remote.event_log, remote.flash);
+    }
+
     #[idle]
     fn idle(_: idle::Context) -> ! {
         loop {
@@ -243,6 +824,51 @@ const APP: () = {
     }
 };
 
+/// Feeds an event into the machine and applies the new state if it caused a transition.
+fn dispatch(
+    event: Event,
+    now: Millis,
+    machine: &mut Machine,
+    heater: &mut Heater,
+    state: &mut State,
+    settings: &Settings,
+    event_log: &mut EventLog,
+) {
+    let from = machine.state();
+    if let Some(to) = machine.handle(event, now) {
+        defmt::info!("Machine: {:?} -> {:?} on {:?}", from, to, event);
+        event_log.record(now, LogEvent::ModeChange { from, to });
+        enter_state(to, heater, state, settings);
+    }
+}
+
+/// Moves the heater and the state to the setpoint and gains the machine state asks for.
+fn enter_state(
+    machine_state: MachineState,
+    heater: &mut Heater,
+    state: &mut State,
+    settings: &Settings,
+) {
+    let (gains, pon) = match machine_state.gain_set() {
+        GainSet::Cold => (settings.cold_gains, Proportional::OnMeasurement),
+        GainSet::Warm => (settings.warm_gains, Proportional::OnError),
+        GainSet::Steam => (settings.steam_gains, Proportional::OnError),
+    };
+    let setpoint = match (machine_state.heater_policy(), settings.standby.action) {
+        (HeaterPolicy::Steam, _) => settings.steam_temp,
+        (HeaterPolicy::Standby, StandbyAction::Eco(temp)) => temp,
+        _ => settings.boiler_setpoint(),
+    };
+
+    heater.set_setpoint(setpoint);
+    heater.update_pid(gains.kp, gains.ki, gains.kd, pon);
+    state.set_machine_state(machine_state);
+    state.set_target_boiler_temp(setpoint);
+    state.set_kp(gains.kp);
+    state.set_ki(gains.ki);
+    state.set_kd(gains.kd);
+}
+
 #[defmt::timestamp]
 fn timestamp() -> u64 {
     static COUNT: AtomicUsize = AtomicUsize::new(0);
@@ -252,10 +878,41 @@ fn timestamp() -> u64 {
     n as u64
 }
 
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    crash::record_panic(info);
+    if let Some(location) = info.location() {
+        defmt::error!(
+            "Panicked at {:str}:{:u32}",
+            location.file(),
+            location.line()
+        );
+    }
+    halt()
+}
+
 #[defmt::panic_handler]
-fn panic() -> ! {
+fn defmt_panic() -> ! {
+    halt()
+}
+
+#[exception]
+fn HardFault(frame: &ExceptionFrame) -> ! {
+    crash::record_hard_fault(frame.pc, frame.lr);
+    if cfg!(debug_assertions) {
+        exit()
+    } else {
+        SCB::sys_reset()
+    }
+}
+
+/// Stops in the debugger during development, resets the controller in the field.
+///
+/// A breakpoint instead of `udf`, which would raise a HardFault on top of the panic.
+fn halt() -> ! {
     if cfg!(debug_assertions) {
-        cortex_m::asm::udf()
+        exit()
     } else {
         SCB::sys_reset()
     }
@@ -1,44 +1,176 @@
 //! Encapsulates the Boiler Peripheral
 
+use crate::calibration::Calibration;
+use crate::clock::Millis;
+use crate::filter::{FilterChain, FilterConfig};
+use crate::peripherals::sensor::{HealthConfig, TemperatureSensor};
+use defmt::Format;
 use embedded_hal::blocking::delay::DelayUs;
-use nrf52840_hal::gpio::{Floating, Input, Output, Pin, PushPull};
-use tsic::{SensorType, Tsic, TsicError};
 
 pub type BoilerTemperature = f32;
 
-pub struct Boiler {
-    temp_sensor: Tsic<Pin<Input<Floating>>, Pin<Output<PushPull>>>,
+/// A reading which made it through the filter chain.
+#[derive(Clone, Copy)]
+pub struct BoilerReading {
+    /// The value as reported by the sensor, before calibration.
+    pub raw: BoilerTemperature,
+    /// The calibrated value, before filtering.
+    pub calibrated: BoilerTemperature,
+    /// The value after the filter chain, this is what the PID sees.
+    pub filtered: BoilerTemperature,
+}
+
+/// The health of the temperature sensor.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum SensorHealth {
+    /// The last read succeeded.
+    Ok,
+    /// Some reads failed recently, but not enough to give up on the sensor.
+    Degraded,
+    /// Too many reads failed in a row.
+    Failed,
+}
+
+pub struct Boiler<S: TemperatureSensor> {
+    temp_sensor: S,
+    calibration: Calibration,
+    filter: FilterChain,
+    health_config: HealthConfig,
+    health: SensorHealth,
+    consecutive_failures: u8,
+    consecutive_successes: u8,
+    last_good_at: Option<Millis>,
+    last_error: Option<S::Error>,
     last_temp: Option<BoilerTemperature>,
+    soft_failures: u32,
 }
 
-impl Boiler {
-    pub fn new(signal_pin: Pin<Input<Floating>>, vdd_pin: Pin<Output<PushPull>>) -> Self {
-        let temp_sensor = Tsic::with_vdd_control(SensorType::Tsic306, signal_pin, vdd_pin);
+impl<S: TemperatureSensor> Boiler<S> {
+    pub fn new(
+        temp_sensor: S,
+        calibration: Calibration,
+        filter_config: FilterConfig,
+        health_config: HealthConfig,
+    ) -> Self {
         Self {
             temp_sensor,
+            calibration,
+            filter: FilterChain::new(filter_config),
+            health_config,
+            health: SensorHealth::Ok,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            last_good_at: None,
+            last_error: None,
             last_temp: None,
+            soft_failures: 0,
         }
     }
 
     pub fn read_temperature<D: DelayUs<u8>>(
         &mut self,
         delay: &mut D,
-    ) -> Result<BoilerTemperature, BoilerError> {
-        match self.temp_sensor.read(delay) {
-            Ok(t) => {
-                let rounded = t.as_celsius() as BoilerTemperature;
-                self.last_temp = Some(rounded);
-                Ok(rounded)
+        now: Millis,
+    ) -> Result<BoilerReading, BoilerError> {
+        let raw = match self.temp_sensor.read(delay) {
+            Ok(t) => t,
+            Err(e) => {
+                self.record_failure(e);
+                return Err(BoilerError::TempReadFailed);
+            }
+        };
+
+        let calibrated = self.calibration.apply(raw);
+        match self.filter.push(calibrated) {
+            Ok(filtered) => {
+                self.record_success(now);
+                self.last_temp = Some(filtered);
+                Ok(BoilerReading {
+                    raw,
+                    calibrated,
+                    filtered,
+                })
+            }
+            Err(_) => {
+                self.soft_failures = self.soft_failures.wrapping_add(1);
+                Err(BoilerError::SampleRejected { raw })
             }
-            Err(e) => Err(BoilerError::TempReadFailed { cause: e }),
+        }
+    }
+
+    fn record_failure(&mut self, cause: S::Error) {
+        self.last_error = Some(cause);
+        self.consecutive_successes = 0;
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.health = if self.consecutive_failures >= self.health_config.failed_after {
+            SensorHealth::Failed
+        } else {
+            SensorHealth::Degraded
+        };
+    }
+
+    fn record_success(&mut self, now: Millis) {
+        self.consecutive_failures = 0;
+        self.consecutive_successes = self.consecutive_successes.saturating_add(1);
+        self.last_good_at = Some(now);
+        // A failed sensor has to prove itself for a few reads before it is trusted again.
+        if self.health != SensorHealth::Failed
+            || self.consecutive_successes >= self.health_config.recover_after
+        {
+            self.health = SensorHealth::Ok;
         }
     }
 
     pub fn current_temperature(&self) -> Option<BoilerTemperature> {
         self.last_temp
     }
+
+    pub fn health(&self) -> SensorHealth {
+        self.health
+    }
+
+    /// The uptime of the last reading which made it through the filter chain.
+    pub fn last_good_at(&self) -> Option<Millis> {
+        self.last_good_at
+    }
+
+    /// The cause of the most recent failed read.
+    pub fn last_error(&self) -> Option<&S::Error> {
+        self.last_error.as_ref()
+    }
+
+    pub fn consecutive_failures(&self) -> u8 {
+        self.consecutive_failures
+    }
+
+    /// Number of samples the filter chain rejected as implausible.
+    pub fn soft_failures(&self) -> u32 {
+        self.soft_failures
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    /// Replaces the calibration, the filter history is dropped since it is in the old scale.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.filter.reset();
+    }
+
+    pub fn filter_config(&self) -> FilterConfig {
+        self.filter.config()
+    }
+
+    /// Replaces the filter configuration, the filter starts over with an empty history.
+    pub fn set_filter_config(&mut self, config: FilterConfig) {
+        self.filter.set_config(config);
+    }
 }
 
 pub enum BoilerError {
-    TempReadFailed { cause: TsicError },
+    /// The sensor read failed, the cause is kept in [`Boiler::last_error`].
+    TempReadFailed,
+    /// The sensor returned a value, but the plausibility gate dropped it.
+    SampleRejected { raw: BoilerTemperature },
 }
@@ -1,3 +1,10 @@
+use crate::crash::CrashKind;
+use crate::machine::MachineState;
+use crate::peripherals::boiler::SensorHealth;
+use crate::reset::ResetReason;
+use crate::safety::CutoffReason;
+use crate::self_test::{Check, Failure, SelfTestReport};
+use crate::supervisor::Task;
 use crate::State;
 use core::fmt::Write;
 use display_interface_spi::SPIInterface;
@@ -51,9 +58,11 @@ impl Display {
         }
     }
 
-    pub fn init<D: DelayMs<u8>>(&mut self, timer: &mut D) {
-        self.display.reset(&mut self.rst, timer).ok();
-        self.display.init().ok();
+    pub fn init<D: DelayMs<u8>>(&mut self, timer: &mut D) -> Result<(), DisplayError> {
+        self.display
+            .reset(&mut self.rst, timer)
+            .map_err(|_| DisplayError::Reset)?;
+        self.display.init().map_err(|_| DisplayError::Init)
     }
 
     pub fn draw_screen(&mut self, state: &State) {
@@ -81,7 +90,12 @@ impl Display {
             .draw(&mut self.display)
             .ok();
 
-        let mut target_data = String::<U32>::from("Target:  ");
+        let target_label = if state.machine_state() == MachineState::Steam {
+            "Steam:   "
+        } else {
+            "Target:  "
+        };
+        let mut target_data = String::<U32>::from(target_label);
         let _ = write!(target_data, "{}°C", state.target_boiler_temp().round());
 
         let style = TextStyleBuilder::new(Font6x8)
@@ -94,6 +108,14 @@ impl Display {
             .draw(&mut self.display)
             .ok();
 
+        let mut brew_data = String::<U32>::from("Brew:    ~");
+        let _ = write!(brew_data, "{}°C", state.brew_temp_estimate().round());
+
+        Text::new(brew_data.as_str(), Point::new(0, 20))
+            .into_styled(style)
+            .draw(&mut self.display)
+            .ok();
+
         let heater_msg = if state.heater_on() {
             "Heater:  On"
         } else {
@@ -142,6 +164,72 @@ impl Display {
             .draw(&mut self.display)
             .ok();
 
+        let ready_data = if state.is_ready() {
+            Some(String::<U32>::from("Ready!"))
+        } else {
+            state.ready_eta().map(|eta| {
+                let mut data = String::<U32>::from("Ready in ~");
+                let minutes = (eta + 59_999) / 60_000;
+                let _ = write!(data, "{} min", minutes);
+                data
+            })
+        };
+        if let Some(ready_data) = ready_data {
+            Text::new(ready_data.as_str(), Point::new(18, 100))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        }
+
+        if let Some(elapsed) = state.shot_elapsed() {
+            let mut shot_data = String::<U32>::from("Shot:    ");
+            let _ = write!(shot_data, "{}.{}s", elapsed / 1000, elapsed % 1000 / 100);
+            Text::new(shot_data.as_str(), Point::new(0, 70))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        } else if let Some(shot) = state.last_shot() {
+            let mut shot_data = String::<U32>::from("Last:    ");
+            let _ = write!(
+                shot_data,
+                "{}s @ {}°C",
+                shot.duration_ms / 1000,
+                shot.avg_temp.round()
+            );
+            Text::new(shot_data.as_str(), Point::new(0, 70))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        }
+
+        let self_test = state.self_test();
+        let fault_msg = match state.cutoff() {
+            Some(CutoffReason::OverTemperature) => Some("!! CUTOFF: OVER TEMP !!"),
+            Some(CutoffReason::RateOfRise) => Some("!! CUTOFF: RISE RATE !!"),
+            Some(CutoffReason::StuckSensor) => Some("!! CUTOFF: SENSOR STUCK !!"),
+            None if self_test.failed() => Some(self_test_failure(&self_test)),
+            None => None,
+        };
+        if let Some(fault_msg) = fault_msg {
+            Text::new(fault_msg, Point::new(0, 80))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        }
+
+        let sensor_msg = match state.sensor_health() {
+            SensorHealth::Ok if state.config_warning().is_some() => Some("!! CONFIG RESET !!"),
+            SensorHealth::Ok => None,
+            SensorHealth::Degraded => Some("Sensor: Degraded"),
+            SensorHealth::Failed => Some("!! SENSOR FAILED !!"),
+        };
+        if let Some(sensor_msg) = sensor_msg {
+            Text::new(sensor_msg, Point::new(0, 90))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        }
+
         if self.alive_pixel {
             Text::new("<>", Point::new(0, 100))
                 .into_styled(style)
@@ -156,13 +244,82 @@ impl Display {
             self.alive_pixel = true;
         }
 
-        if state.watchdog_reset() {
-            Text::new("!! WOOF !! RESET BY WATCHDOG !!", Point::new(0, 110))
+        if let Some(crash) = state.last_crash() {
+            let mut crash_msg = String::<U32>::new();
+            let _ = match crash.kind() {
+                CrashKind::Panic => {
+                    let file = crash.file().rsplit('/').next().unwrap_or("?");
+                    write!(crash_msg, "!! PANIC {}:{} !!", file, crash.line())
+                }
+                CrashKind::HardFault => write!(crash_msg, "!! FAULT @ {:#010x} !!", crash.pc()),
+                CrashKind::StuckTask => {
+                    let task = match crash.task() {
+                        Some(Task::Measure) => "SENSOR",
+                        Some(Task::HeaterDrive) => "HEATER",
+                        Some(Task::Display) => "DISPLAY",
+                        None => "TASK",
+                    };
+                    write!(crash_msg, "!! WOOF !! {} STUCK !!", task)
+                }
+            };
+            Text::new(crash_msg.as_str(), Point::new(0, 110))
+                .into_styled(style)
+                .draw(&mut self.display)
+                .ok();
+        } else if state.reset_reason().policy().unexpected {
+            let reset_msg = match state.reset_reason() {
+                ResetReason::Watchdog => "!! WOOF !! RESET BY WATCHDOG !!",
+                ResetReason::Lockup => "!! LOCKUP RESET !!",
+                ResetReason::SoftReset => "!! SOFTWARE RESET !!",
+                ResetReason::PowerOn | ResetReason::Pin | ResetReason::WakeUp => "",
+            };
+            Text::new(reset_msg, Point::new(0, 110))
                 .into_styled(style)
                 .draw(&mut self.display)
                 .ok();
         }
 
+        let mode_msg = match state.machine_state() {
+            MachineState::Booting if self_test.failed() => "!! SELF TEST FAILED !!",
+            MachineState::Booting if !self_test.is_done() => "Mode:    Self test",
+            MachineState::Booting => "Mode:    Booting",
+            MachineState::Coldstart => "Mode:    Heating",
+            MachineState::Stabilizing => "Mode:    Stabilizing",
+            MachineState::Ready => "Mode:    Ready",
+            MachineState::Brewing => "Mode:    Brewing",
+            MachineState::Steam => "Mode:    Steam",
+            MachineState::Standby => "Mode:    Standby",
+            MachineState::Fault => "Mode:    FAULT",
+        };
+        Text::new(mode_msg, Point::new(0, 120))
+            .into_styled(style)
+            .draw(&mut self.display)
+            .ok();
+
         self.display.flush().ok();
     }
 }
+
+/// Errors which can happen when bringing up the display.
+pub enum DisplayError {
+    /// Could not toggle the reset pin.
+    Reset,
+    /// The controller did not accept the init sequence.
+    Init,
+}
+
+/// Describes the first check of the report which failed.
+fn self_test_failure(report: &SelfTestReport) -> &'static str {
+    for check in [report.sensor, report.heater, report.display].iter() {
+        if let Check::Failed(failure) = check {
+            return match failure {
+                Failure::NoReading => "Sensor: no reading",
+                Failure::OutOfRange => "Sensor: out of range",
+                Failure::Unstable => "Sensor: unstable",
+                Failure::NoRise => "Heater: no temp rise",
+                Failure::InitFailed => "Display: init failed",
+            };
+        }
+    }
+    "Self test failed"
+}
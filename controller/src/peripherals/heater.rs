@@ -1,20 +1,28 @@
 //! Contains the PID-controlled heater
 
+use crate::clock::Millis;
 use crate::pid::{Direction, Pid, Proportional};
+use crate::safety::{Cutoff, CutoffReason, SafetyLimits};
 use nrf52840_hal::gpio::{Output, Pin, PushPull};
 use nrf52840_hal::prelude::*;
 
+/// How often (in ms) `control` is called by the application.
+const CONTROL_INTERVAL_MS: u32 = 20;
+
 pub struct Heater {
     pin: Pin<Output<PushPull>>,
     pid: Pid,
     window_size: u32,
     isr_counter: u32,
     last_output: f32,
+    cutoff: Cutoff,
+    max_reading_age: Millis,
 }
 
 impl Heater {
     pub fn new(pin: Pin<Output<PushPull>>, config: HeaterConfig) -> Self {
         let window_size = config.window_size;
+        let max_reading_age = config.max_reading_age;
 
         let mut pid = Pid::new(
             config.setpoint,
@@ -33,17 +41,31 @@ impl Heater {
             window_size,
             isr_counter: 0,
             last_output: 0.0,
+            cutoff: Cutoff::new(SafetyLimits::boiler()),
+            max_reading_age,
         }
     }
 
-    pub fn control(&mut self, current_temperature: f32) -> Result<bool, HeaterError> {
+    /// Drives the heater for the current slot of the PID window.
+    ///
+    /// `reading_age` is the time since `current_temperature` was measured, `None` if there has
+    /// not been a good reading yet. The heater refuses to run on data which is too old.
+    pub fn control(
+        &mut self,
+        current_temperature: f32,
+        reading_age: Option<Millis>,
+    ) -> Result<bool, HeaterError> {
+        if !self.allowed(current_temperature, reading_age)? {
+            return Ok(false);
+        }
+
         if self.last_output <= self.isr_counter as f32 {
             self.turn_heater_off()?;
         } else {
             self.turn_heater_on()?;
         }
 
-        self.isr_counter += 20;
+        self.isr_counter += CONTROL_INTERVAL_MS;
         if self.isr_counter > self.window_size {
             self.isr_counter = 0;
             self.last_output = self.pid.compute(current_temperature).unwrap();
@@ -52,10 +74,69 @@ impl Heater {
         Ok(self.is_on()?)
     }
 
+    /// Keeps the heater on regardless of the PID, used for the self test pulse.
+    ///
+    /// The stale reading check and the safety limits still apply, just like in `control`.
+    pub fn pulse(
+        &mut self,
+        current_temperature: f32,
+        reading_age: Option<Millis>,
+    ) -> Result<bool, HeaterError> {
+        if !self.allowed(current_temperature, reading_age)? {
+            return Ok(false);
+        }
+
+        self.turn_heater_on()?;
+        Ok(true)
+    }
+
+    /// Checks the reading age and the safety limits, turning the heater off if either fails.
+    fn allowed(
+        &mut self,
+        current_temperature: f32,
+        reading_age: Option<Millis>,
+    ) -> Result<bool, HeaterError> {
+        match reading_age {
+            Some(age) if age <= self.max_reading_age => {}
+            _ => {
+                self.turn_heater_off()?;
+                self.isr_counter = 0;
+                return Ok(false);
+            }
+        }
+
+        // The safety limits are checked before the PID gets any say.
+        let heater_on = self.is_on()?;
+        if self
+            .cutoff
+            .check(current_temperature, CONTROL_INTERVAL_MS, heater_on)
+            .is_some()
+        {
+            self.turn_heater_off()?;
+            self.isr_counter = 0;
+            self.last_output = 0.0;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
     pub fn update_pid(&mut self, kp: f32, ki: f32, kd: f32, pon: Proportional) {
         self.pid.set_tunings(kp, ki, kd, pon);
     }
 
+    /// Changes the PID window (and sample time), the gains have to be set again afterwards.
+    pub fn set_window_size(&mut self, window_size: u32) {
+        self.window_size = window_size;
+        self.isr_counter = 0;
+        self.pid.set_sample_time(window_size);
+        self.pid.set_output_limits(0.0, window_size as f32);
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: f32) {
+        self.pid.set_setpoint(setpoint);
+    }
+
     pub fn is_on(&self) -> Result<bool, HeaterError> {
         self.pin.is_set_high().map_err(|_| HeaterError::PinError)
     }
@@ -64,6 +145,10 @@ impl Heater {
         self.last_output
     }
 
+    pub fn cutoff(&self) -> Option<CutoffReason> {
+        self.cutoff.tripped()
+    }
+
     fn turn_heater_on(&mut self) -> Result<(), HeaterError> {
         if !self.is_on()? {
             self.pin.set_high().map_err(|_| HeaterError::PinError)?;
@@ -85,16 +170,25 @@ pub struct HeaterConfig {
     kd: f32,
     setpoint: f32,
     window_size: u32,
+    max_reading_age: Millis,
 }
 
 impl HeaterConfig {
-    pub fn new(setpoint: f32, kp: f32, ki: f32, kd: f32, window_size: u32) -> Self {
+    pub fn new(
+        setpoint: f32,
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        window_size: u32,
+        max_reading_age: Millis,
+    ) -> Self {
         Self {
             kp,
             ki,
             kd,
             setpoint,
             window_size,
+            max_reading_age,
         }
     }
 }
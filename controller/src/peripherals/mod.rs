@@ -1,3 +1,5 @@
 pub mod boiler;
 pub mod display;
 pub mod heater;
+pub mod sensor;
+pub mod switch;
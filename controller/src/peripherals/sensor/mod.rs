@@ -0,0 +1,113 @@
+//! The boiler sensor, picked through exactly one `sensor-*` cargo feature.
+//!
+//! The `embedded-hal` backends live in `controller_core::sensor`, the TSIC is here since it
+//! drives the nRF pins directly. Where each sensor is wired is part of the board description in
+//! `boards/`, only the pins of the selected one are taken.
+
+#[cfg(not(any(
+    feature = "sensor-tsic",
+    feature = "sensor-max31865",
+    feature = "sensor-max31855",
+    feature = "sensor-ntc"
+)))]
+compile_error!("No boiler sensor selected, enable one of the `sensor-*` features.");
+
+#[cfg(any(
+    all(feature = "sensor-tsic", feature = "sensor-max31865"),
+    all(feature = "sensor-tsic", feature = "sensor-max31855"),
+    all(feature = "sensor-tsic", feature = "sensor-ntc"),
+    all(feature = "sensor-max31865", feature = "sensor-max31855"),
+    all(feature = "sensor-max31865", feature = "sensor-ntc"),
+    all(feature = "sensor-max31855", feature = "sensor-ntc"),
+))]
+compile_error!("Only one boiler sensor can be selected, enable a single `sensor-*` feature.");
+
+#[cfg(feature = "sensor-tsic")]
+pub mod tsic;
+
+pub use controller_core::sensor::*;
+
+use crate::config::SensorPins;
+use nrf52840_hal::pac::{SAADC, SPIM1};
+
+#[cfg(any(feature = "sensor-max31865", feature = "sensor-max31855"))]
+use nrf52840_hal::{
+    gpio::{Output, Pin, PushPull},
+    spim::{self, Frequency, Spim},
+};
+
+#[cfg(feature = "sensor-tsic")]
+pub type BoilerSensor = tsic::TsicSensor;
+
+#[cfg(feature = "sensor-max31865")]
+pub type BoilerSensor = max31865::Max31865<Spim<SPIM1>, Pin<Output<PushPull>>>;
+/// A three-wire PT100 on the Adafruit breakout, which has a 430Ω reference resistor.
+#[cfg(feature = "sensor-max31865")]
+const RTD: max31865::RtdType = max31865::RtdType::Pt100;
+#[cfg(feature = "sensor-max31865")]
+const WIRING: max31865::Wiring = max31865::Wiring::ThreeWire;
+#[cfg(feature = "sensor-max31865")]
+const REFERENCE_RESISTOR: f32 = 430.0;
+
+#[cfg(feature = "sensor-max31855")]
+pub type BoilerSensor = max31855::Thermocouple<Spim<SPIM1>, Pin<Output<PushPull>>>;
+
+#[cfg(feature = "sensor-ntc")]
+pub type BoilerSensor =
+    ntc::Ntc<nrf52840_hal::saadc::Saadc, nrf52840_hal::saadc::Saadc, crate::board::SensorAnalogPin>;
+/// A 100kΩ (at 25°C) B3950 thermistor with a 4.7kΩ series resistor, a common probe for hot
+/// ends which has its best resolution right around brew and steam temperatures.
+#[cfg(feature = "sensor-ntc")]
+const NTC: ntc::NtcConfig = ntc::NtcConfig {
+    series_resistor: 4700.0,
+    adc_max: 16384.0,
+    a: 0.827_206_9e-3,
+    b: 2.087_897_3e-4,
+    c: 0.806_213_2e-7,
+};
+
+/// Sets up the selected sensor on its pins, the SPI and ADC peripherals are only used by some.
+#[cfg(feature = "sensor-tsic")]
+pub fn boiler_sensor(pins: SensorPins, _spim: SPIM1, _saadc: SAADC) -> BoilerSensor {
+    tsic::TsicSensor::new(pins.signal, pins.vdd)
+}
+
+/// Sets up the selected sensor on its pins, the SPI and ADC peripherals are only used by some.
+#[cfg(feature = "sensor-max31865")]
+pub fn boiler_sensor(pins: SensorPins, spim: SPIM1, _saadc: SAADC) -> BoilerSensor {
+    let spi_pins = spim::Pins {
+        sck: pins.sck,
+        mosi: Some(pins.mosi),
+        miso: Some(pins.miso),
+    };
+    let spi = Spim::new(spim, spi_pins, Frequency::M1, spim::MODE_1, 0);
+    max31865::Max31865::new(spi, pins.cs, RTD, WIRING, REFERENCE_RESISTOR)
+        .expect("The MAX31865 did not take its configuration")
+}
+
+/// Sets up the selected sensor on its pins, the SPI and ADC peripherals are only used by some.
+#[cfg(feature = "sensor-max31855")]
+pub fn boiler_sensor(pins: SensorPins, spim: SPIM1, _saadc: SAADC) -> BoilerSensor {
+    // The chip only talks, there is nothing to send.
+    let spi_pins = spim::Pins {
+        sck: pins.sck,
+        mosi: None,
+        miso: Some(pins.miso),
+    };
+    let spi = Spim::new(spim, spi_pins, Frequency::M1, spim::MODE_0, 0);
+    max31855::Thermocouple::new(spi, pins.cs, max31855::ThermocoupleChip::Max31855)
+}
+
+/// Sets up the selected sensor on its pins, the SPI and ADC peripherals are only used by some.
+#[cfg(feature = "sensor-ntc")]
+pub fn boiler_sensor(pins: SensorPins, _spim: SPIM1, saadc: SAADC) -> BoilerSensor {
+    use nrf52840_hal::saadc::{Gain, Reference, Saadc, SaadcConfig};
+
+    // Measured against VDD, which also feeds the divider, so the full scale is VDD.
+    let config = SaadcConfig {
+        reference: Reference::VDD1_4,
+        gain: Gain::GAIN1_4,
+        ..SaadcConfig::default()
+    };
+    ntc::Ntc::new(Saadc::new(saadc, config), pins.analog, NTC)
+}
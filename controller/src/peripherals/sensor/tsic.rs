@@ -0,0 +1,26 @@
+//! The TSIC 306 which sits on top of the boiler.
+
+use super::TemperatureSensor;
+use embedded_hal::blocking::delay::DelayUs;
+use nrf52840_hal::gpio::{Floating, Input, Output, Pin, PushPull};
+use tsic::{SensorType, Tsic, TsicError};
+
+pub struct TsicSensor {
+    inner: Tsic<Pin<Input<Floating>>, Pin<Output<PushPull>>>,
+}
+
+impl TsicSensor {
+    pub fn new(signal_pin: Pin<Input<Floating>>, vdd_pin: Pin<Output<PushPull>>) -> Self {
+        Self {
+            inner: Tsic::with_vdd_control(SensorType::Tsic306, signal_pin, vdd_pin),
+        }
+    }
+}
+
+impl TemperatureSensor for TsicSensor {
+    type Error = TsicError;
+
+    fn read<D: DelayUs<u8>>(&mut self, delay: &mut D) -> Result<f32, Self::Error> {
+        self.inner.read(delay).map(|t| t.as_celsius() as f32)
+    }
+}
@@ -0,0 +1,28 @@
+//! Switch inputs (brew switch and friends), debounced by [`Debounce`].
+
+use controller_core::debounce::Debounce;
+use nrf52840_hal::gpio::{Input, Pin, PullUp};
+use nrf52840_hal::prelude::*;
+
+pub use controller_core::debounce::Edge;
+
+/// A switch wired between the pin and ground, so it reads low while closed.
+pub struct Switch {
+    pin: Pin<Input<PullUp>>,
+    debounce: Debounce,
+}
+
+impl Switch {
+    /// The raw level has to be stable for `debounce_polls` polls before it counts.
+    pub fn new(pin: Pin<Input<PullUp>>, debounce_polls: u8) -> Self {
+        Self {
+            pin,
+            debounce: Debounce::new(debounce_polls),
+        }
+    }
+
+    /// Samples the pin, returns the edge once the new level is stable.
+    pub fn poll(&mut self) -> Option<Edge> {
+        self.debounce.update(self.pin.is_low().unwrap_or(false))
+    }
+}
@@ -100,6 +100,10 @@ impl Pid {
         }
     }
 
+    pub fn set_setpoint(&mut self, setpoint: f32) {
+        self.setpoint = setpoint;
+    }
+
     pub fn set_sample_time(&mut self, new_sample_time: u32) {
         if new_sample_time > 0 {
             let ratio = (new_sample_time / self.sample_time) as f32;
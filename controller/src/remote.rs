@@ -0,0 +1,159 @@
+//! Changes requested by a remote, over BLE or the serial console.
+//!
+//! Both end up here, so a remote can do exactly the same, no matter how it is connected.
+
+use crate::brew::BrewSamples;
+use crate::clock::{Millis, WallClock, SECONDS_PER_DAY};
+use crate::machine::{Event, Machine};
+use crate::peripherals::heater::Heater;
+use crate::settings::Settings;
+use crate::standby::Standby;
+use crate::state::State;
+use crate::storage::config_store::{ConfigStore, CONFIG_VERSION};
+use crate::storage::event_log::{EventLog, LogEvent};
+use crate::storage::nvmc::NvmcFlash;
+use protocol::gatt::Command;
+use protocol::message::{ErrorCode, Request, Response};
+
+/// The parts of the controller a remote can change, borrowed from the task resources.
+pub struct Remote<'a> {
+    pub now: Millis,
+    pub machine: &'a mut Machine,
+    pub heater: &'a mut Heater,
+    pub state: &'a mut State,
+    pub settings: &'a mut Settings,
+    pub event_log: &'a mut EventLog,
+    pub config_store: &'a mut ConfigStore,
+    pub brew_samples: &'a mut BrewSamples,
+    pub wall_clock: &'a mut WallClock,
+    pub standby: &'a mut Standby,
+    pub flash: &'a mut NvmcFlash,
+}
+
+impl<'a> Remote<'a> {
+    /// Takes over settings which have been validated already, the setpoint and gains apply
+    /// right away.
+    pub fn apply_settings(&mut self, settings: Settings) {
+        if settings != *self.settings {
+            self.event_log.record(self.now, LogEvent::ConfigChanged);
+        }
+        if settings.window_size != self.settings.window_size {
+            self.heater.set_window_size(settings.window_size);
+        }
+        *self.settings = settings;
+        crate::enter_state(self.machine.state(), self.heater, self.state, self.settings);
+    }
+
+    /// Stores the current settings in flash.
+    pub fn save_settings(&mut self) -> Result<(), ErrorCode> {
+        if self.config_store.save(self.flash, self.settings).is_err() {
+            defmt::warn!("Could not store the settings!");
+            return Err(ErrorCode::StorageFailed);
+        }
+        self.event_log.record(
+            self.now,
+            LogEvent::ConfigSaved {
+                version: CONFIG_VERSION,
+            },
+        );
+        Ok(())
+    }
+
+    /// Feeds an event into the machine, the caller made sure it applies in the current state.
+    ///
+    /// Someone is using the machine, just like pressing a switch, so the idle time restarts.
+    pub fn dispatch(&mut self, event: Event) {
+        self.standby.activity(self.now);
+        crate::dispatch(
+            event,
+            self.now,
+            self.machine,
+            self.heater,
+            self.state,
+            self.settings,
+            self.event_log,
+        );
+    }
+
+    pub fn command(&mut self, command: Command) -> Result<(), ErrorCode> {
+        match command {
+            Command::Save => self.save_settings(),
+            _ => {
+                let event = Event::from_command(command).ok_or(ErrorCode::Unsupported)?;
+                self.machine
+                    .state()
+                    .next(event)
+                    .ok_or(ErrorCode::NotAllowed)?;
+                self.dispatch(event);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fits the brew model through the samples and applies it.
+    pub fn fit_brew_model(&mut self) -> Result<(), ErrorCode> {
+        let brew_model = self
+            .brew_samples
+            .fit(&self.settings.brew_model)
+            .ok_or(ErrorCode::NotAllowed)?;
+        let settings = Settings {
+            brew_model,
+            ..*self.settings
+        };
+        settings.validate().map_err(|_| ErrorCode::OutOfRange)?;
+        defmt::info!(
+            "Fitted the brew model through {:usize} samples",
+            self.brew_samples.len()
+        );
+        self.brew_samples.clear();
+        self.apply_settings(settings);
+        Ok(())
+    }
+
+    /// Answers the requests which change or read the controller. The console answers those
+    /// about the session itself.
+    pub fn serve(&mut self, request: Request) -> Response {
+        let result = match request {
+            Request::GetStatus => return Response::Status(self.state.telemetry(self.now)),
+            Request::GetConfig => return Response::Config(self.settings.config()),
+            Request::SetConfig { config, save } => {
+                let settings = match self.settings.with_config(&config) {
+                    Ok(settings) => settings,
+                    Err(_) => return Response::Error(ErrorCode::OutOfRange),
+                };
+                self.apply_settings(settings);
+                if save {
+                    self.save_settings()
+                } else {
+                    Ok(())
+                }
+            }
+            Request::Command(command) => self.command(command),
+            // Only steady state measurements make for a good fit.
+            Request::BrewSample { .. } if !self.state.is_ready() => Err(ErrorCode::NotAllowed),
+            Request::BrewSample { brew_temp } if !brew_temp.is_finite() => {
+                Err(ErrorCode::OutOfRange)
+            }
+            Request::BrewSample { brew_temp } => {
+                self.brew_samples
+                    .push(self.state.current_boiler_temp(), brew_temp);
+                Ok(())
+            }
+            Request::FitBrewModel => self.fit_brew_model(),
+            Request::SetTime { seconds_of_day } if seconds_of_day >= SECONDS_PER_DAY => {
+                Err(ErrorCode::OutOfRange)
+            }
+            Request::SetTime { seconds_of_day } => {
+                self.wall_clock.sync(seconds_of_day, self.now);
+                Ok(())
+            }
+            Request::Hello { .. } | Request::Stream(_) | Request::ReadEvents { .. } => {
+                Err(ErrorCode::Unsupported)
+            }
+        };
+        match result {
+            Ok(()) => Response::Ok,
+            Err(code) => Response::Error(code),
+        }
+    }
+}
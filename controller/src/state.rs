@@ -1,17 +1,41 @@
+use crate::clock::Millis;
+use crate::crash::CrashReport;
+use crate::machine::MachineState;
+use crate::peripherals::boiler::SensorHealth;
+use crate::reset::ResetReason;
+use crate::safety::CutoffReason;
+use crate::self_test::SelfTestReport;
+use crate::shot::Shot;
+use crate::storage::config_store::DefaultsReason;
 use defmt::Format;
+use protocol::message::Telemetry;
+use protocol::status::{Faults, Status};
 
 /// Holds the State for the application.
 #[derive(Format)]
 pub struct State {
     current_boiler_temp: f32,
+    raw_boiler_temp: f32,
+    rejected_samples: u32,
     target_boiler_temp: f32,
+    brew_temp_estimate: f32,
     last_pid_out: f32,
     heater_on: bool,
     kp: f32,
     ki: f32,
     kd: f32,
-    coldstart: bool,
-    watchdog_reset: bool,
+    machine_state: MachineState,
+    reset_reason: ResetReason,
+    cutoff: Option<CutoffReason>,
+    sensor_health: SensorHealth,
+    last_reading_at: Option<Millis>,
+    shot_elapsed: Option<Millis>,
+    last_shot: Option<Shot>,
+    ready: bool,
+    ready_eta: Option<Millis>,
+    config_warning: Option<DefaultsReason>,
+    last_crash: Option<CrashReport>,
+    self_test: SelfTestReport,
 }
 
 impl State {
@@ -21,19 +45,31 @@ impl State {
         kp: f32,
         ki: f32,
         kd: f32,
-        coldstart: bool,
-        watchdog_reset: bool,
+        reset_reason: ResetReason,
     ) -> Self {
         Self {
             current_boiler_temp: 0.0,
+            raw_boiler_temp: 0.0,
+            rejected_samples: 0,
             target_boiler_temp,
+            brew_temp_estimate: 0.0,
             last_pid_out: 0.0,
             heater_on,
             kp,
             ki,
             kd,
-            coldstart,
-            watchdog_reset,
+            machine_state: MachineState::Booting,
+            reset_reason,
+            cutoff: None,
+            sensor_health: SensorHealth::Ok,
+            last_reading_at: None,
+            shot_elapsed: None,
+            last_shot: None,
+            ready: false,
+            ready_eta: None,
+            config_warning: None,
+            last_crash: None,
+            self_test: SelfTestReport::pending(),
         }
     }
 
@@ -41,6 +77,22 @@ impl State {
         self.current_boiler_temp = current_boiler_temp;
     }
 
+    pub fn set_raw_boiler_temp(&mut self, raw_boiler_temp: f32) {
+        self.raw_boiler_temp = raw_boiler_temp;
+    }
+
+    pub fn raw_boiler_temp(&self) -> f32 {
+        self.raw_boiler_temp
+    }
+
+    pub fn set_rejected_samples(&mut self, rejected_samples: u32) {
+        self.rejected_samples = rejected_samples;
+    }
+
+    pub fn rejected_samples(&self) -> u32 {
+        self.rejected_samples
+    }
+
     pub fn set_last_pid_out(&mut self, pid_out: f32) {
         self.last_pid_out = pid_out;
     }
@@ -57,16 +109,21 @@ impl State {
         self.target_boiler_temp
     }
 
-    pub fn set_heater_on(&mut self, heater_on: bool) {
-        self.heater_on = heater_on;
+    pub fn set_brew_temp_estimate(&mut self, brew_temp_estimate: f32) {
+        self.brew_temp_estimate = brew_temp_estimate;
+    }
+
+    /// The estimated water temperature at the group.
+    pub fn brew_temp_estimate(&self) -> f32 {
+        self.brew_temp_estimate
     }
 
-    pub fn set_watchdog_reset(&mut self, reset: bool) {
-        self.watchdog_reset = reset;
+    pub fn set_heater_on(&mut self, heater_on: bool) {
+        self.heater_on = heater_on;
     }
 
-    pub fn watchdog_reset(&self) -> bool {
-        self.watchdog_reset
+    pub fn reset_reason(&self) -> ResetReason {
+        self.reset_reason
     }
 
     pub fn heater_on(&self) -> bool {
@@ -93,15 +150,144 @@ impl State {
         self.kd = kd;
     }
 
-    pub fn in_coldstart(&self) -> bool {
-        self.coldstart
+    pub fn set_machine_state(&mut self, machine_state: MachineState) {
+        self.machine_state = machine_state;
     }
 
-    pub fn disable_coldstart(&mut self) {
-        self.coldstart = false;
+    pub fn machine_state(&self) -> MachineState {
+        self.machine_state
     }
 
     pub fn last_pid_out(&self) -> f32 {
         self.last_pid_out
     }
+
+    pub fn set_cutoff(&mut self, cutoff: Option<CutoffReason>) {
+        self.cutoff = cutoff;
+    }
+
+    pub fn cutoff(&self) -> Option<CutoffReason> {
+        self.cutoff
+    }
+
+    pub fn set_sensor_health(&mut self, sensor_health: SensorHealth) {
+        self.sensor_health = sensor_health;
+    }
+
+    pub fn sensor_health(&self) -> SensorHealth {
+        self.sensor_health
+    }
+
+    pub fn set_last_reading_at(&mut self, last_reading_at: Option<Millis>) {
+        self.last_reading_at = last_reading_at;
+    }
+
+    /// The uptime of the last good reading, `None` if there has not been one yet.
+    pub fn last_reading_at(&self) -> Option<Millis> {
+        self.last_reading_at
+    }
+
+    pub fn set_shot_elapsed(&mut self, shot_elapsed: Option<Millis>) {
+        self.shot_elapsed = shot_elapsed;
+    }
+
+    /// How long the current shot has been running, `None` if no shot is running.
+    pub fn shot_elapsed(&self) -> Option<Millis> {
+        self.shot_elapsed
+    }
+
+    pub fn set_last_shot(&mut self, shot: Shot) {
+        self.last_shot = Some(shot);
+    }
+
+    pub fn last_shot(&self) -> Option<Shot> {
+        self.last_shot
+    }
+
+    pub fn set_ready(&mut self, ready: bool) {
+        self.ready = ready;
+    }
+
+    /// Stable at the setpoint long enough to pull a shot.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    pub fn set_ready_eta(&mut self, ready_eta: Option<Millis>) {
+        self.ready_eta = ready_eta;
+    }
+
+    /// The estimated time until ready, `None` if it cannot be estimated.
+    pub fn ready_eta(&self) -> Option<Millis> {
+        self.ready_eta
+    }
+
+    pub fn set_config_warning(&mut self, warning: DefaultsReason) {
+        self.config_warning = Some(warning);
+    }
+
+    /// Set if stored settings existed, but had to be replaced by the defaults.
+    pub fn config_warning(&self) -> Option<DefaultsReason> {
+        self.config_warning
+    }
+
+    pub fn set_last_crash(&mut self, crash: CrashReport) {
+        self.last_crash = Some(crash);
+    }
+
+    pub fn last_crash(&self) -> Option<&CrashReport> {
+        self.last_crash.as_ref()
+    }
+
+    pub fn set_self_test(&mut self, report: SelfTestReport) {
+        self.self_test = report;
+    }
+
+    pub fn self_test(&self) -> SelfTestReport {
+        self.self_test
+    }
+
+    /// The part of the state which is shown to remotes.
+    pub fn status(&self) -> Status {
+        let mut faults = Faults::empty();
+        match self.cutoff {
+            Some(CutoffReason::OverTemperature) => faults.insert(Faults::CUTOFF_OVER_TEMPERATURE),
+            Some(CutoffReason::RateOfRise) => faults.insert(Faults::CUTOFF_RATE_OF_RISE),
+            Some(CutoffReason::StuckSensor) => faults.insert(Faults::CUTOFF_STUCK_SENSOR),
+            None => {}
+        }
+        match self.sensor_health {
+            SensorHealth::Degraded => faults.insert(Faults::SENSOR_DEGRADED),
+            SensorHealth::Failed => faults.insert(Faults::SENSOR_FAILED),
+            SensorHealth::Ok => {}
+        }
+        if self.self_test.failed() {
+            faults.insert(Faults::SELF_TEST_FAILED);
+        }
+        if self.config_warning.is_some() {
+            faults.insert(Faults::CONFIG_RESET);
+        }
+
+        Status {
+            current_temp: self.current_boiler_temp,
+            target_temp: self.target_boiler_temp,
+            heater_on: self.heater_on,
+            pid_output: self.last_pid_out,
+            kp: self.kp,
+            ki: self.ki,
+            kd: self.kd,
+            mode: self.machine_state.into(),
+            faults,
+        }
+    }
+
+    pub fn telemetry(&self, uptime: Millis) -> Telemetry {
+        Telemetry {
+            uptime,
+            status: self.status(),
+            raw_temp: self.raw_boiler_temp,
+            brew_temp: self.brew_temp_estimate,
+            ready: self.ready,
+        }
+    }
 }
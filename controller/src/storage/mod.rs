@@ -0,0 +1,9 @@
+//! Persistent storage in the internal flash of the nRF52840.
+//!
+//! The record layouts live in `controller_core::storage`, this adds the NVMC underneath.
+
+pub mod nvmc;
+
+pub use controller_core::storage::{
+    config_store, crc32, event_log, Flash, CONFIG_PAGES, PAGE_SIZE,
+};
@@ -0,0 +1,68 @@
+//! The flash of the nRF52840, written through the NVMC.
+
+use super::{Flash, PAGE_SIZE};
+use core::ptr;
+use nrf52840_hal::pac::NVMC;
+
+/// Errors which can happen when accessing the flash.
+pub enum NvmcError {
+    /// The address is not word aligned.
+    Unaligned,
+    /// The address is not on a page boundary.
+    NotPageAligned,
+}
+
+pub struct NvmcFlash {
+    nvmc: NVMC,
+}
+
+impl NvmcFlash {
+    pub fn new(nvmc: NVMC) -> Self {
+        Self { nvmc }
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().is_busy() {}
+    }
+}
+
+impl Flash for NvmcFlash {
+    type Error = NvmcError;
+
+    fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // Safety: the flash is memory mapped and always readable.
+            *byte = unsafe { ptr::read_volatile((address as usize + i) as *const u8) };
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u32]) -> Result<(), Self::Error> {
+        if address % 4 != 0 {
+            return Err(NvmcError::Unaligned);
+        }
+
+        self.nvmc.config.write(|w| w.wen().wen());
+        for (i, word) in data.iter().enumerate() {
+            // Safety: writes are enabled and the address is word aligned.
+            unsafe { ptr::write_volatile((address as usize + i * 4) as *mut u32, *word) };
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+
+    fn erase_page(&mut self, address: u32) -> Result<(), Self::Error> {
+        if address % PAGE_SIZE != 0 {
+            return Err(NvmcError::NotPageAligned);
+        }
+
+        self.nvmc.config.write(|w| w.wen().een());
+        self.nvmc
+            .erasepage()
+            .write(|w| unsafe { w.erasepage().bits(address) });
+        self.wait_ready();
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! The part of the controller configuration which can be changed from the outside.
+
+use crate::wire::{Decode, Encode, Error, Reader, Writer};
+
+/// One set of PID gains.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+/// What [`Config::target_temp`] is the temperature of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetKind {
+    /// The boiler, used as the PID setpoint directly.
+    Boiler = 0,
+    /// The water at the group, the controller picks the boiler setpoint through its brew model.
+    Brew = 1,
+}
+
+impl TargetKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(TargetKind::Boiler),
+            1 => Some(TargetKind::Brew),
+            _ => None,
+        }
+    }
+}
+
+/// How the controller corrects the sensor reading, see [`Config::calibration`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Calibration {
+    /// The reading is used as is.
+    None,
+    /// A fixed offset (°C) is added to every reading.
+    Offset(f32),
+    /// A line through two (sensor reading, reference temperature) pairs.
+    TwoPoint {
+        raw_low: f32,
+        reference_low: f32,
+        raw_high: f32,
+        reference_high: f32,
+    },
+}
+
+/// The filter chain the sensor readings go through, see [`Config::filter`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    /// Readings the median is taken over, 1 turns the median off.
+    pub median_len: u8,
+    /// Smoothing factor of the EMA in (0, 1], 1 turns the EMA off.
+    pub ema_alpha: f32,
+    /// The largest plausible change (°C) between two readings, 0 turns the gate off.
+    pub max_delta: f32,
+    /// Rejected readings in a row after which the gate takes the new level.
+    pub max_rejects: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// The brew target (°C), see [`target_kind`](Self::target_kind).
+    pub target_temp: f32,
+    pub target_kind: TargetKind,
+    /// Gains used while heating up from cold.
+    pub cold_gains: Gains,
+    /// Gains used once the boiler reached the setpoint.
+    pub warm_gains: Gains,
+    /// The boiler setpoint (°C) in steam mode.
+    pub steam_temp: f32,
+    /// Gains used in steam mode.
+    pub steam_gains: Gains,
+    /// Steam mode ends on its own after this long (in ms), `None` keeps it on.
+    pub steam_timeout: Option<u32>,
+    /// The PID window in ms.
+    pub window_size: u32,
+    /// The correction of the sensor reading.
+    pub calibration: Calibration,
+    /// Smooths the corrected reading and drops implausible jumps.
+    pub filter: Filter,
+    /// Standby starts after this long (in ms) without brewing or steaming, `None` never.
+    pub idle_timeout: Option<u32>,
+    /// The boiler temperature (°C) kept in standby, `None` turns the heater off.
+    pub standby_temp: Option<f32>,
+    /// The time of day (seconds since midnight) to wake up from standby, which needs the
+    /// controller to know the time, see [`Request::SetTime`](crate::message::Request::SetTime).
+    pub wake_at: Option<u32>,
+}
+
+impl Encode for Gains {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.f32(self.kp)?;
+        writer.f32(self.ki)?;
+        writer.f32(self.kd)
+    }
+}
+
+impl Decode for Gains {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            kp: reader.f32()?,
+            ki: reader.f32()?,
+            kd: reader.f32()?,
+        })
+    }
+}
+
+impl Encode for TargetKind {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for TargetKind {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let raw = reader.varint()?;
+        if raw > u64::from(u8::MAX) {
+            return Err(Error::Invalid);
+        }
+        TargetKind::from_u8(raw as u8).ok_or(Error::Invalid)
+    }
+}
+
+impl Encode for Calibration {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Calibration::None => writer.varint(0),
+            Calibration::Offset(offset) => {
+                writer.varint(1)?;
+                writer.f32(*offset)
+            }
+            Calibration::TwoPoint {
+                raw_low,
+                reference_low,
+                raw_high,
+                reference_high,
+            } => {
+                writer.varint(2)?;
+                writer.f32(*raw_low)?;
+                writer.f32(*reference_low)?;
+                writer.f32(*raw_high)?;
+                writer.f32(*reference_high)
+            }
+        }
+    }
+}
+
+impl Decode for Calibration {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => Calibration::None,
+            1 => Calibration::Offset(reader.f32()?),
+            2 => Calibration::TwoPoint {
+                raw_low: reader.f32()?,
+                reference_low: reader.f32()?,
+                raw_high: reader.f32()?,
+                reference_high: reader.f32()?,
+            },
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+impl Encode for Filter {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.u8(self.median_len)?;
+        writer.f32(self.ema_alpha)?;
+        writer.f32(self.max_delta)?;
+        writer.u8(self.max_rejects)
+    }
+}
+
+impl Decode for Filter {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            median_len: reader.u8()?,
+            ema_alpha: reader.f32()?,
+            max_delta: reader.f32()?,
+            max_rejects: reader.u8()?,
+        })
+    }
+}
+
+impl Encode for Config {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.f32(self.target_temp)?;
+        self.target_kind.encode(writer)?;
+        self.cold_gains.encode(writer)?;
+        self.warm_gains.encode(writer)?;
+        writer.f32(self.steam_temp)?;
+        self.steam_gains.encode(writer)?;
+        put_option(writer, self.steam_timeout, |writer, timeout| {
+            writer.varint(u64::from(timeout))
+        })?;
+        writer.varint(u64::from(self.window_size))?;
+        self.calibration.encode(writer)?;
+        self.filter.encode(writer)?;
+        put_option(writer, self.idle_timeout, |writer, timeout| {
+            writer.varint(u64::from(timeout))
+        })?;
+        put_option(writer, self.standby_temp, Writer::f32)?;
+        put_option(writer, self.wake_at, |writer, at| {
+            writer.varint(u64::from(at))
+        })
+    }
+}
+
+impl Decode for Config {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            target_temp: reader.f32()?,
+            target_kind: TargetKind::decode(reader)?,
+            cold_gains: Gains::decode(reader)?,
+            warm_gains: Gains::decode(reader)?,
+            steam_temp: reader.f32()?,
+            steam_gains: Gains::decode(reader)?,
+            steam_timeout: get_option(reader, Reader::u32)?,
+            window_size: reader.u32()?,
+            calibration: Calibration::decode(reader)?,
+            filter: Filter::decode(reader)?,
+            idle_timeout: get_option(reader, Reader::u32)?,
+            standby_temp: get_option(reader, Reader::f32)?,
+            wake_at: get_option(reader, Reader::u32)?,
+        })
+    }
+}
+
+fn put_option<'a, T>(
+    writer: &mut Writer<'a>,
+    value: Option<T>,
+    put: impl FnOnce(&mut Writer<'a>, T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match value {
+        Some(value) => {
+            writer.bool(true)?;
+            put(writer, value)
+        }
+        None => writer.bool(false),
+    }
+}
+
+fn get_option<'a, T>(
+    reader: &mut Reader<'a>,
+    get: impl FnOnce(&mut Reader<'a>) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    if reader.bool()? {
+        get(reader).map(Some)
+    } else {
+        Ok(None)
+    }
+}
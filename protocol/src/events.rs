@@ -0,0 +1,336 @@
+//! The entries of the controller's event log.
+
+use crate::status::Mode;
+use crate::wire::{Decode, Encode, Error, Reader, Writer};
+use core::str;
+
+/// The longest [`Text`].
+pub const MAX_TEXT: usize = 12;
+
+/// A short bit of text, like the name of an error or the end of a file path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Text {
+    len: u8,
+    bytes: [u8; MAX_TEXT],
+}
+
+impl Text {
+    /// Takes over `s`, cut off at the last character which fits.
+    pub fn new(s: &str) -> Self {
+        let mut len = s.len().min(MAX_TEXT);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; MAX_TEXT];
+        bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
+        Self {
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("?")
+    }
+}
+
+/// Why the heater got cut off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cutoff {
+    OverTemperature,
+    RateOfRise,
+    StuckSensor,
+}
+
+/// Why the controller runs on the default settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DefaultsReason {
+    Empty,
+    Corrupt,
+    UnknownVersion(u16),
+}
+
+/// A task watched by the supervisor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    Measure,
+    HeaterDrive,
+    Display,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The controller started, with the raw `RESETREAS` register telling why.
+    Boot {
+        resetreas: u32,
+    },
+    SensorFailure {
+        error: Text,
+    },
+    ModeChange {
+        from: Mode,
+        to: Mode,
+    },
+    FaultLatched(Cutoff),
+    FaultCleared,
+    /// The settings changed at runtime, without being stored yet.
+    ConfigChanged,
+    ConfigSaved {
+        version: u16,
+    },
+    ConfigDefaults(DefaultsReason),
+    Panic {
+        file: Text,
+        line: u16,
+    },
+    HardFault {
+        pc: u32,
+        lr: u32,
+    },
+    TaskStuck(Task),
+}
+
+/// An entry of the event log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoggedEvent {
+    pub seq: u32,
+    /// Milliseconds since the boot the event was logged in.
+    pub at: u64,
+    pub event: Event,
+}
+
+impl Encode for Text {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.bytes(self.as_str().as_bytes())
+    }
+}
+
+impl Decode for Text {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let bytes = reader.bytes()?;
+        if bytes.len() > MAX_TEXT {
+            return Err(Error::Invalid);
+        }
+        let text = str::from_utf8(bytes).map_err(|_| Error::Invalid)?;
+        Ok(Text::new(text))
+    }
+}
+
+impl Encode for Cutoff {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for Cutoff {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        match reader.varint()? {
+            0 => Ok(Cutoff::OverTemperature),
+            1 => Ok(Cutoff::RateOfRise),
+            2 => Ok(Cutoff::StuckSensor),
+            _ => Err(Error::Invalid),
+        }
+    }
+}
+
+impl Encode for DefaultsReason {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            DefaultsReason::Empty => writer.varint(0),
+            DefaultsReason::Corrupt => writer.varint(1),
+            DefaultsReason::UnknownVersion(version) => {
+                writer.varint(2)?;
+                writer.varint(u64::from(*version))
+            }
+        }
+    }
+}
+
+impl Decode for DefaultsReason {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        match reader.varint()? {
+            0 => Ok(DefaultsReason::Empty),
+            1 => Ok(DefaultsReason::Corrupt),
+            2 => Ok(DefaultsReason::UnknownVersion(reader.u16()?)),
+            _ => Err(Error::Invalid),
+        }
+    }
+}
+
+impl Encode for Task {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for Task {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        match reader.varint()? {
+            0 => Ok(Task::Measure),
+            1 => Ok(Task::HeaterDrive),
+            2 => Ok(Task::Display),
+            _ => Err(Error::Invalid),
+        }
+    }
+}
+
+impl Encode for Event {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Event::Boot { resetreas } => {
+                writer.varint(0)?;
+                writer.varint(u64::from(*resetreas))
+            }
+            Event::SensorFailure { error } => {
+                writer.varint(1)?;
+                error.encode(writer)
+            }
+            Event::ModeChange { from, to } => {
+                writer.varint(2)?;
+                from.encode(writer)?;
+                to.encode(writer)
+            }
+            Event::FaultLatched(cutoff) => {
+                writer.varint(3)?;
+                cutoff.encode(writer)
+            }
+            Event::FaultCleared => writer.varint(4),
+            Event::ConfigSaved { version } => {
+                writer.varint(5)?;
+                writer.varint(u64::from(*version))
+            }
+            Event::ConfigDefaults(reason) => {
+                writer.varint(6)?;
+                reason.encode(writer)
+            }
+            Event::Panic { file, line } => {
+                writer.varint(7)?;
+                file.encode(writer)?;
+                writer.varint(u64::from(*line))
+            }
+            Event::HardFault { pc, lr } => {
+                writer.varint(8)?;
+                writer.varint(u64::from(*pc))?;
+                writer.varint(u64::from(*lr))
+            }
+            Event::TaskStuck(task) => {
+                writer.varint(9)?;
+                task.encode(writer)
+            }
+            Event::ConfigChanged => writer.varint(10),
+        }
+    }
+}
+
+impl Decode for Event {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => Event::Boot {
+                resetreas: reader.u32()?,
+            },
+            1 => Event::SensorFailure {
+                error: Text::decode(reader)?,
+            },
+            2 => Event::ModeChange {
+                from: Mode::decode(reader)?,
+                to: Mode::decode(reader)?,
+            },
+            3 => Event::FaultLatched(Cutoff::decode(reader)?),
+            4 => Event::FaultCleared,
+            5 => Event::ConfigSaved {
+                version: reader.u16()?,
+            },
+            6 => Event::ConfigDefaults(DefaultsReason::decode(reader)?),
+            7 => Event::Panic {
+                file: Text::decode(reader)?,
+                line: reader.u16()?,
+            },
+            8 => Event::HardFault {
+                pc: reader.u32()?,
+                lr: reader.u32()?,
+            },
+            9 => Event::TaskStuck(Task::decode(reader)?),
+            10 => Event::ConfigChanged,
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+impl Encode for LoggedEvent {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(u64::from(self.seq))?;
+        writer.varint(self.at)?;
+        self.event.encode(writer)
+    }
+}
+
+impl Decode for LoggedEvent {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            seq: reader.u32()?,
+            at: reader.varint()?,
+            event: Event::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{events, through_frame, unused_tag};
+    use crate::wire;
+
+    #[test]
+    fn events_round_trip() {
+        let events = events();
+        for (i, event) in events.iter().enumerate() {
+            let logged = LoggedEvent {
+                seq: u32::MAX - i as u32,
+                at: u64::MAX,
+                event: *event,
+            };
+            assert_eq!(through_frame(&logged), logged);
+        }
+
+        let unused = unused_tag(&events);
+        assert_eq!(wire::from_slice::<Event>(&[unused]), Err(Error::Invalid));
+        assert_eq!(wire::from_slice::<Event>(&[3, 3]), Err(Error::Invalid));
+        assert_eq!(wire::from_slice::<Event>(&[6, 3]), Err(Error::Invalid));
+        assert_eq!(wire::from_slice::<Event>(&[9, 3]), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn text_is_cut_at_a_character() {
+        assert_eq!(Text::new("").as_str(), "");
+        assert_eq!(Text::new("ls/boiler.rs").as_str(), "ls/boiler.rs");
+        assert_eq!(Text::new("als/boiler.rs").as_str(), "als/boiler.r");
+        // Only the first byte of the last ä fits.
+        assert_eq!(Text::new("aääääää").as_str(), "aäääää");
+    }
+
+    #[test]
+    fn text_rejects_what_does_not_fit() {
+        let mut buf = [0; 32];
+        let len = wire::to_slice(&Text::new("ls/boiler.rs"), &mut buf).unwrap();
+        assert_eq!(
+            wire::from_slice::<Text>(&buf[..len]).unwrap().as_str(),
+            "ls/boiler.rs"
+        );
+
+        let mut writer = wire::Writer::new(&mut buf);
+        writer.bytes(b"als/boiler.rs").unwrap();
+        let len = writer.len();
+        assert_eq!(wire::from_slice::<Text>(&buf[..len]), Err(Error::Invalid));
+
+        // Not UTF-8.
+        assert_eq!(
+            wire::from_slice::<Text>(&[2, 0xC3, 0x28]),
+            Err(Error::Invalid)
+        );
+        // Shorter than its length says.
+        assert_eq!(
+            wire::from_slice::<Text>(&[5, b'a', b'b']),
+            Err(Error::UnexpectedEnd)
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Frames messages for byte streams like a serial port.
+//!
+//! A frame is the encoded message followed by its CRC-16 (little endian), COBS encoded so it
+//! contains no zero byte, and terminated by a zero. A receiver which got out of sync, e.g. after
+//! plugging the cable in mid frame, drops everything up to the next zero and carries on.
+
+use crate::wire::{self, Decode, Encode};
+
+/// The longest encoded message.
+pub const MAX_MESSAGE: usize = 160;
+/// The longest frame, delimiter included.
+pub const MAX_FRAME: usize = cobs_max_len(MAX_MESSAGE + 2) + 1;
+
+const DELIMITER: u8 = 0;
+
+/// Errors which can happen while framing or unframing a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameError {
+    /// The message could not be encoded or decoded.
+    Wire(wire::Error),
+    /// The frame is not valid COBS.
+    Cobs,
+    /// The checksum does not match, the frame got corrupted.
+    Crc,
+    /// The frame is longer than [`MAX_FRAME`].
+    TooLong,
+}
+
+impl From<wire::Error> for FrameError {
+    fn from(error: wire::Error) -> Self {
+        FrameError::Wire(error)
+    }
+}
+
+/// The most bytes COBS turns `len` bytes into.
+pub const fn cobs_max_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// CRC-16/CCITT-FALSE.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// COBS encodes `data` into `out`, returning the length. The delimiter is not added.
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    if out.len() < cobs_max_len(data.len()) {
+        return Err(FrameError::TooLong);
+    }
+
+    let mut code_pos = 0;
+    let mut pos = 1;
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            out[pos] = byte;
+            pos += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_pos] = code;
+            code_pos = pos;
+            pos += 1;
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+    Ok(pos)
+}
+
+/// Decodes COBS `data` (without the delimiter) into `out`, returning the length.
+pub fn cobs_decode(data: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    let mut pos = 0;
+    let mut len = 0;
+    while pos < data.len() {
+        let code = data[pos] as usize;
+        if code == 0 {
+            return Err(FrameError::Cobs);
+        }
+        pos += 1;
+
+        let run = data.get(pos..pos + code - 1).ok_or(FrameError::Cobs)?;
+        if run.contains(&0) {
+            return Err(FrameError::Cobs);
+        }
+        out.get_mut(len..len + run.len())
+            .ok_or(FrameError::TooLong)?
+            .copy_from_slice(run);
+        len += run.len();
+        pos += run.len();
+
+        if code != 0xFF && pos < data.len() {
+            *out.get_mut(len).ok_or(FrameError::TooLong)? = 0;
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+/// Frames `message` into `out`, returning the length including the delimiter.
+pub fn encode<T: Encode>(message: &T, out: &mut [u8]) -> Result<usize, FrameError> {
+    let mut payload = [0; MAX_MESSAGE + 2];
+    let len = wire::to_slice(message, &mut payload[..MAX_MESSAGE])?;
+    let crc = crc16(&payload[..len]);
+    payload[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+    let encoded = cobs_encode(&payload[..len + 2], out)?;
+    *out.get_mut(encoded).ok_or(FrameError::TooLong)? = DELIMITER;
+    Ok(encoded + 1)
+}
+
+/// Unframes a message, `frame` is what [`Receiver::push`] returned.
+pub fn decode<T: Decode>(frame: &[u8]) -> Result<T, FrameError> {
+    let mut payload = [0; MAX_MESSAGE + 2];
+    let len = cobs_decode(frame, &mut payload)?;
+    if len < 2 {
+        return Err(FrameError::Crc);
+    }
+    let (message, crc) = payload[..len].split_at(len - 2);
+    if crc16(message).to_le_bytes() != [crc[0], crc[1]] {
+        return Err(FrameError::Crc);
+    }
+    Ok(wire::from_slice(message)?)
+}
+
+/// Frames `message` into a new vector.
+#[cfg(any(test, feature = "std"))]
+pub fn encode_vec<T: Encode>(message: &T) -> Result<Vec<u8>, FrameError> {
+    let mut out = [0; MAX_FRAME];
+    let len = encode(message, &mut out)?;
+    Ok(out[..len].to_vec())
+}
+
+/// Collects received bytes until a frame is complete.
+pub struct Receiver {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    /// Set when the current frame did not fit, it is dropped at the next delimiter.
+    overflow: bool,
+}
+
+impl Receiver {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Takes the next byte, returns the frame (without the delimiter) once it is complete.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == DELIMITER {
+            let len = self.len;
+            let overflow = self.overflow;
+            self.len = 0;
+            self.overflow = false;
+            return if overflow || len == 0 {
+                None
+            } else {
+                Some(&self.buf[..len])
+            };
+        }
+
+        match self.buf.get_mut(self.len) {
+            Some(slot) => {
+                *slot = byte;
+                self.len += 1;
+            }
+            None => self.overflow = true,
+        }
+        None
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FrameError::Wire(error) => write!(f, "{}", error),
+            FrameError::Cobs => f.write_str("invalid COBS"),
+            FrameError::Crc => f.write_str("checksum mismatch"),
+            FrameError::TooLong => f.write_str("frame too long"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Request;
+    use crate::testing::{config, through_frame};
+
+    /// A xorshift generator, so the fuzz tests see the same bytes on every run.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            self.next() as usize % n
+        }
+
+        fn fill(&mut self, buf: &mut [u8]) {
+            for byte in buf {
+                *byte = self.next() as u8;
+            }
+        }
+    }
+
+    fn set_config() -> Request {
+        Request::SetConfig {
+            config: config(),
+            save: true,
+        }
+    }
+
+    /// The frame of `message` without its delimiter.
+    fn frame_of(message: &Request) -> Vec<u8> {
+        let mut frame = encode_vec(message).unwrap();
+        assert_eq!(frame.pop(), Some(DELIMITER));
+        frame
+    }
+
+    #[test]
+    fn cobs_round_trips() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+
+        let cases: [(&[u8], &[u8]); 4] = [
+            (&[], &[0x01]),
+            (&[0x00], &[0x01, 0x01]),
+            (&[0x11, 0x22, 0x00, 0x33], &[0x03, 0x11, 0x22, 0x02, 0x33]),
+            (&[0x11, 0x00, 0x00], &[0x02, 0x11, 0x01, 0x01]),
+        ];
+        for (data, encoded) in cases.iter() {
+            let mut out = [0; 8];
+            let len = cobs_encode(data, &mut out).unwrap();
+            assert_eq!(&out[..len], *encoded);
+            let len = cobs_decode(encoded, &mut out).unwrap();
+            assert_eq!(&out[..len], *data);
+        }
+
+        // Runs around the longest a single code byte covers.
+        let mut rng = Rng(1);
+        for len in 250..=260 {
+            for zeros in [false, true].iter() {
+                let mut data = vec![0; len];
+                rng.fill(&mut data);
+                for byte in data.iter_mut() {
+                    if !*zeros && *byte == 0 {
+                        *byte = 1;
+                    }
+                }
+                let mut encoded = vec![0; cobs_max_len(len)];
+                let encoded_len = cobs_encode(&data, &mut encoded).unwrap();
+                assert!(!encoded[..encoded_len].contains(&0));
+                let mut decoded = vec![0; len];
+                let decoded_len = cobs_decode(&encoded[..encoded_len], &mut decoded).unwrap();
+                assert_eq!(decoded[..decoded_len], data[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_rejects_invalid_data() {
+        let mut out = [0; 8];
+        assert_eq!(cobs_decode(&[0x00], &mut out), Err(FrameError::Cobs));
+        assert_eq!(cobs_decode(&[0x03, 0x11], &mut out), Err(FrameError::Cobs));
+        assert_eq!(
+            cobs_decode(&[0x03, 0x11, 0x00], &mut out),
+            Err(FrameError::Cobs)
+        );
+        assert_eq!(cobs_decode(&[0x0A; 10], &mut out), Err(FrameError::TooLong));
+        assert_eq!(cobs_encode(&[1; 8], &mut out), Err(FrameError::TooLong));
+    }
+
+    #[test]
+    fn the_largest_message_fits() {
+        let frame = encode_vec(&set_config()).unwrap();
+        assert!(frame.len() <= MAX_FRAME);
+        assert_eq!(through_frame(&set_config()), set_config());
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = frame_of(&set_config());
+        for len in 0..frame.len() {
+            assert!(
+                decode::<Request>(&frame[..len]).is_err(),
+                "{} of {} bytes",
+                len,
+                frame.len()
+            );
+        }
+        assert_eq!(decode::<Request>(&[]), Err(FrameError::Crc));
+        assert_eq!(decode::<Request>(&[0x02, 0x01]), Err(FrameError::Crc));
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        let request = Request::SetTime {
+            seconds_of_day: 21_600,
+        };
+        let frame = frame_of(&request);
+        for i in 0..frame.len() {
+            for bit in 0..8 {
+                let mut corrupted = frame.clone();
+                corrupted[i] ^= 1 << bit;
+                assert!(
+                    decode::<Request>(&corrupted).is_err(),
+                    "bit {} of byte {}",
+                    bit,
+                    i
+                );
+            }
+        }
+
+        // A valid COBS frame whose message does not match the checksum.
+        let mut payload = [0u8; 8];
+        let len = wire::to_slice(&request, &mut payload).unwrap();
+        let crc = crc16(&payload[..len]).to_le_bytes();
+        payload[len..len + 2].copy_from_slice(&crc);
+        payload[1] ^= 0x01;
+        let mut corrupted = [0; 16];
+        let encoded = cobs_encode(&payload[..len + 2], &mut corrupted).unwrap();
+        assert_eq!(
+            decode::<Request>(&corrupted[..encoded]),
+            Err(FrameError::Crc)
+        );
+    }
+
+    #[test]
+    fn messages_which_do_not_decode_are_wire_errors() {
+        let mut payload = [9, 0x80, 0x80, 0x80, 0x80, 0x10, 0, 0];
+        let crc = crc16(&payload[..6]).to_le_bytes();
+        payload[6..].copy_from_slice(&crc);
+        let mut frame = [0; 16];
+        let len = cobs_encode(&payload, &mut frame).unwrap();
+        assert_eq!(
+            decode::<Request>(&frame[..len]),
+            Err(FrameError::Wire(wire::Error::Invalid))
+        );
+    }
+
+    #[test]
+    fn receiver_resynchronises() {
+        let frame = encode_vec(&Request::GetStatus).unwrap();
+        let mut receiver = Receiver::new();
+
+        // The end of a frame which started before we listened, an empty one and a whole one.
+        let mut received = Vec::new();
+        for &byte in [0x42, 0x13, DELIMITER, DELIMITER]
+            .iter()
+            .chain(frame.iter())
+        {
+            if let Some(frame) = receiver.push(byte) {
+                received.push(decode::<Request>(frame));
+            }
+        }
+        assert_eq!(received, [Err(FrameError::Cobs), Ok(Request::GetStatus)]);
+
+        // A frame which does not fit is dropped as a whole, the next one comes through.
+        for _ in 0..MAX_FRAME * 2 {
+            assert!(receiver.push(0x01).is_none());
+        }
+        assert!(receiver.push(DELIMITER).is_none());
+        let (last, rest) = frame.split_last().unwrap();
+        for &byte in rest {
+            assert!(receiver.push(byte).is_none());
+        }
+        assert_eq!(
+            receiver.push(*last).map(decode::<Request>),
+            Some(Ok(Request::GetStatus))
+        );
+    }
+
+    #[test]
+    fn fuzz_arbitrary_bytes() {
+        let mut rng = Rng(0x2545_F491);
+        let mut receiver = Receiver::new();
+        for _ in 0..20_000 {
+            let mut data = vec![0; rng.below(MAX_FRAME + 16)];
+            rng.fill(&mut data);
+            let _ = decode::<Request>(&data);
+            for &byte in data.iter() {
+                if let Some(frame) = receiver.push(byte) {
+                    let _ = decode::<Request>(frame);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_checksummed_messages() {
+        // Random messages behind a valid checksum get past the framing, into the decoding.
+        let mut rng = Rng(0x9E37_79B9);
+        let mut decoded = 0;
+        for _ in 0..50_000 {
+            // Mostly short, most requests are.
+            let longest = if rng.below(4) == 0 { MAX_MESSAGE } else { 8 };
+            let mut payload = vec![0; rng.below(longest) + 1];
+            rng.fill(&mut payload);
+            // A known request, so the decoding gets further than the first byte.
+            payload[0] = rng.below(11) as u8;
+            let crc = crc16(&payload).to_le_bytes();
+            payload.extend_from_slice(&crc);
+
+            let mut frame = [0; MAX_FRAME];
+            let len = cobs_encode(&payload, &mut frame).unwrap();
+            if let Ok(request) = decode::<Request>(&frame[..len]) {
+                decoded += 1;
+                // Compared encoded, as a NaN is not equal to itself.
+                assert_eq!(
+                    encode_vec(&through_frame(&request)).unwrap(),
+                    encode_vec(&request).unwrap()
+                );
+            }
+        }
+        assert!(decoded > 1000, "only {} decoded", decoded);
+    }
+}
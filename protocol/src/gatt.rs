@@ -0,0 +1,698 @@
+//! The custom GATT service of the controller.
+//!
+//! Every value of the [`Status`] has its own characteristic, which can be read and notifies once
+//! per measurement. The [`Setting`]s can be written as well, [`Command`]s change the mode. How a
+//! write went is reported through the write result characteristic, see [`WriteResult`]. All
+//! numbers are little endian, temperatures and gains are `f32`.
+
+use crate::config::{Calibration, Config, Filter, Gains, TargetKind};
+use crate::status::{Faults, Mode, Status};
+use crate::wire::{Decode, Encode, Error, Reader, Writer};
+
+/// Turns the short id into a full UUID of the form `8f1a<id>-5a3c-4d6e-9b2f-7c0e1d2a3b4c`.
+///
+/// The bytes are in the order they are written, BLE sends them reversed.
+pub const fn uuid(id: u16) -> [u8; 16] {
+    [
+        0x8f,
+        0x1a,
+        (id >> 8) as u8,
+        id as u8,
+        0x5a,
+        0x3c,
+        0x4d,
+        0x6e,
+        0x9b,
+        0x2f,
+        0x7c,
+        0x0e,
+        0x1d,
+        0x2a,
+        0x3b,
+        0x4c,
+    ]
+}
+
+pub const SERVICE_UUID: [u8; 16] = uuid(0x0001);
+/// Write only, takes a single [`Command`] byte.
+pub const COMMAND_ID: u16 = 0x0300;
+/// Read and notify, see [`WriteResult::encode`].
+pub const WRITE_RESULT_ID: u16 = 0x0301;
+
+/// The longest value of any characteristic.
+pub const MAX_VALUE_LEN: usize = 17;
+
+/// The read only characteristics of the service, in the order of their handles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Characteristic {
+    /// `f32`, °C.
+    CurrentTemp,
+    /// `f32`, °C.
+    TargetTemp,
+    /// `u8`, 0 or 1.
+    HeaterOn,
+    /// `f32`, ms per window.
+    PidOutput,
+    /// Three `f32`: kp, ki and kd.
+    Gains,
+    /// `u8`, see [`Mode`].
+    Mode,
+    /// `u16`, see [`Faults`].
+    Faults,
+}
+
+/// Errors which can happen when decoding a characteristic value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The value does not have the length of the characteristic.
+    Length,
+    /// The value is out of the range of the characteristic.
+    Invalid,
+}
+
+impl Characteristic {
+    pub const ALL: [Characteristic; 7] = [
+        Characteristic::CurrentTemp,
+        Characteristic::TargetTemp,
+        Characteristic::HeaterOn,
+        Characteristic::PidOutput,
+        Characteristic::Gains,
+        Characteristic::Mode,
+        Characteristic::Faults,
+    ];
+
+    /// The short id within the UUID.
+    pub fn id(self) -> u16 {
+        0x0100 + self as u16
+    }
+
+    pub fn uuid(self) -> [u8; 16] {
+        uuid(self.id())
+    }
+
+    /// The length of the encoded value.
+    pub fn value_len(self) -> usize {
+        match self {
+            Characteristic::HeaterOn | Characteristic::Mode => 1,
+            Characteristic::Faults => 2,
+            Characteristic::Gains => 12,
+            Characteristic::CurrentTemp
+            | Characteristic::TargetTemp
+            | Characteristic::PidOutput => 4,
+        }
+    }
+
+    /// Encodes the value of this characteristic, returning its length.
+    ///
+    /// `buf` has to hold at least [`MAX_VALUE_LEN`] bytes.
+    pub fn encode(self, status: &Status, buf: &mut [u8]) -> usize {
+        match self {
+            Characteristic::CurrentTemp => put_f32(buf, status.current_temp),
+            Characteristic::TargetTemp => put_f32(buf, status.target_temp),
+            Characteristic::HeaterOn => buf[0] = status.heater_on as u8,
+            Characteristic::PidOutput => put_f32(buf, status.pid_output),
+            Characteristic::Gains => {
+                put_f32(&mut buf[0..4], status.kp);
+                put_f32(&mut buf[4..8], status.ki);
+                put_f32(&mut buf[8..12], status.kd);
+            }
+            Characteristic::Mode => buf[0] = status.mode as u8,
+            Characteristic::Faults => buf[..2].copy_from_slice(&status.faults.0.to_le_bytes()),
+        }
+        self.value_len()
+    }
+
+    /// Applies a received value of this characteristic to `status`.
+    pub fn decode(self, data: &[u8], status: &mut Status) -> Result<(), DecodeError> {
+        if data.len() != self.value_len() {
+            return Err(DecodeError::Length);
+        }
+
+        match self {
+            Characteristic::CurrentTemp => status.current_temp = get_f32(data),
+            Characteristic::TargetTemp => status.target_temp = get_f32(data),
+            Characteristic::HeaterOn => {
+                status.heater_on = match data[0] {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(DecodeError::Invalid),
+                }
+            }
+            Characteristic::PidOutput => status.pid_output = get_f32(data),
+            Characteristic::Gains => {
+                status.kp = get_f32(&data[0..4]);
+                status.ki = get_f32(&data[4..8]);
+                status.kd = get_f32(&data[8..12]);
+            }
+            Characteristic::Mode => {
+                status.mode = Mode::from_u8(data[0]).ok_or(DecodeError::Invalid)?
+            }
+            Characteristic::Faults => {
+                status.faults = Faults(u16::from_le_bytes([data[0], data[1]]))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The characteristics which can be read and written, in the order of their handles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    /// `f32`, °C, see [`Config::target_temp`].
+    TargetTemp,
+    /// Three `f32`: kp, ki and kd.
+    ColdGains,
+    /// Three `f32`: kp, ki and kd.
+    WarmGains,
+    /// Three `f32`: kp, ki and kd.
+    SteamGains,
+    /// `u32`, ms.
+    WindowSize,
+    /// `u8` kind (0 none, 1 offset, 2 two-point) followed by four `f32`: the offset or
+    /// raw low, reference low, raw high and reference high. Unused values are 0.
+    Calibration,
+    /// `u8`, see [`TargetKind`].
+    TargetKind,
+    /// `f32`, °C, see [`Config::steam_temp`].
+    SteamTemp,
+    /// `u32`, ms, 0 keeps steam mode on until it is turned off.
+    SteamTimeout,
+    /// `u32`, ms without brewing or steaming until standby, 0 never goes to standby.
+    IdleTimeout,
+    /// `f32`, °C kept in standby, 0 turns the heater off.
+    StandbyTemp,
+    /// `u32`, seconds since midnight to wake up at, [`NEVER`] does not wake up on its own.
+    WakeAt,
+    /// `u8` median length, `f32` EMA alpha, `f32` max delta (°C) and `u8` max rejects, see
+    /// [`Filter`].
+    Filter,
+}
+
+/// The value of [`Setting::WakeAt`] without a wake-up time.
+pub const NEVER: u32 = u32::MAX;
+
+impl Setting {
+    pub const ALL: [Setting; 13] = [
+        Setting::TargetTemp,
+        Setting::ColdGains,
+        Setting::WarmGains,
+        Setting::SteamGains,
+        Setting::WindowSize,
+        Setting::Calibration,
+        Setting::TargetKind,
+        Setting::SteamTemp,
+        Setting::SteamTimeout,
+        Setting::IdleTimeout,
+        Setting::StandbyTemp,
+        Setting::WakeAt,
+        Setting::Filter,
+    ];
+
+    /// The short id within the UUID.
+    pub fn id(self) -> u16 {
+        0x0200 + self as u16
+    }
+
+    pub fn uuid(self) -> [u8; 16] {
+        uuid(self.id())
+    }
+
+    /// The length of the encoded value.
+    pub fn value_len(self) -> usize {
+        match self {
+            Setting::TargetTemp
+            | Setting::WindowSize
+            | Setting::SteamTemp
+            | Setting::SteamTimeout
+            | Setting::IdleTimeout
+            | Setting::StandbyTemp
+            | Setting::WakeAt => 4,
+            Setting::ColdGains | Setting::WarmGains | Setting::SteamGains => 12,
+            Setting::Calibration => 17,
+            Setting::Filter => 10,
+            Setting::TargetKind => 1,
+        }
+    }
+
+    /// Encodes the value of this setting, returning its length.
+    ///
+    /// `buf` has to hold at least [`MAX_VALUE_LEN`] bytes.
+    pub fn encode(self, config: &Config, buf: &mut [u8]) -> usize {
+        match self {
+            Setting::TargetTemp => put_f32(buf, config.target_temp),
+            Setting::ColdGains => put_gains(buf, &config.cold_gains),
+            Setting::WarmGains => put_gains(buf, &config.warm_gains),
+            Setting::SteamGains => put_gains(buf, &config.steam_gains),
+            Setting::WindowSize => put_u32(buf, config.window_size),
+            Setting::Calibration => put_calibration(buf, &config.calibration),
+            Setting::TargetKind => buf[0] = config.target_kind as u8,
+            Setting::SteamTemp => put_f32(buf, config.steam_temp),
+            Setting::SteamTimeout => put_u32(buf, config.steam_timeout.unwrap_or(0)),
+            Setting::IdleTimeout => put_u32(buf, config.idle_timeout.unwrap_or(0)),
+            Setting::StandbyTemp => put_f32(buf, config.standby_temp.unwrap_or(0.0)),
+            Setting::WakeAt => put_u32(buf, config.wake_at.unwrap_or(NEVER)),
+            Setting::Filter => put_filter(buf, &config.filter),
+        }
+        self.value_len()
+    }
+
+    /// Applies a written value of this setting to `config`.
+    ///
+    /// Only the encoding is checked, the ranges are up to the controller.
+    pub fn decode(self, data: &[u8], config: &mut Config) -> Result<(), DecodeError> {
+        if data.len() != self.value_len() {
+            return Err(DecodeError::Length);
+        }
+
+        match self {
+            Setting::TargetTemp => config.target_temp = get_f32(data),
+            Setting::ColdGains => config.cold_gains = get_gains(data),
+            Setting::WarmGains => config.warm_gains = get_gains(data),
+            Setting::SteamGains => config.steam_gains = get_gains(data),
+            Setting::WindowSize => config.window_size = get_u32(data),
+            Setting::Calibration => config.calibration = get_calibration(data)?,
+            Setting::TargetKind => {
+                config.target_kind = TargetKind::from_u8(data[0]).ok_or(DecodeError::Invalid)?
+            }
+            Setting::SteamTemp => config.steam_temp = get_f32(data),
+            Setting::SteamTimeout => config.steam_timeout = unless(get_u32(data), 0),
+            Setting::IdleTimeout => config.idle_timeout = unless(get_u32(data), 0),
+            Setting::StandbyTemp => {
+                let temp = get_f32(data);
+                config.standby_temp = if temp == 0.0 { None } else { Some(temp) };
+            }
+            Setting::WakeAt => config.wake_at = unless(get_u32(data), NEVER),
+            Setting::Filter => config.filter = get_filter(data),
+        }
+        Ok(())
+    }
+}
+
+/// Asks the controller to do something, written as a single byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Standby = 0,
+    /// Leaves standby.
+    Wake = 1,
+    SteamOn = 2,
+    SteamOff = 3,
+    // 4 was autotune, which never got implemented, and is not reused.
+    /// Stores the current settings, so they survive a reset.
+    Save = 5,
+}
+
+impl Command {
+    /// Decodes the value written to the command characteristic.
+    pub fn from_value(data: &[u8]) -> Result<Self, DecodeError> {
+        match data {
+            [0] => Ok(Command::Standby),
+            [1] => Ok(Command::Wake),
+            [2] => Ok(Command::SteamOn),
+            [3] => Ok(Command::SteamOff),
+            [5] => Ok(Command::Save),
+            [_] => Err(DecodeError::Invalid),
+            _ => Err(DecodeError::Length),
+        }
+    }
+}
+
+/// The outcome of the last write.
+///
+/// Anything but `Ok` means the write was rejected and nothing changed, except for
+/// `StorageFailed` where the settings are active but could not be stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteResult {
+    Ok = 0,
+    /// The value does not have the length of the characteristic.
+    Length = 1,
+    /// The value could not be decoded.
+    Invalid = 2,
+    /// The value is outside of the range the controller accepts.
+    OutOfRange = 3,
+    /// The command does not apply in the current mode.
+    NotAllowed = 4,
+    /// The controller does not support the command.
+    Unsupported = 5,
+    /// Too many writes at once, try again.
+    Busy = 6,
+    /// Saving the settings to flash failed.
+    StorageFailed = 7,
+}
+
+impl WriteResult {
+    /// The value of the write result characteristic: the short id of the written
+    /// characteristic (`u16`) followed by the result (`u8`).
+    pub fn encode(self, id: u16) -> [u8; 3] {
+        let id = id.to_le_bytes();
+        [id[0], id[1], self as u8]
+    }
+}
+
+impl From<DecodeError> for WriteResult {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::Length => WriteResult::Length,
+            DecodeError::Invalid => WriteResult::Invalid,
+        }
+    }
+}
+
+fn put_gains(buf: &mut [u8], gains: &Gains) {
+    put_f32(&mut buf[0..4], gains.kp);
+    put_f32(&mut buf[4..8], gains.ki);
+    put_f32(&mut buf[8..12], gains.kd);
+}
+
+fn get_gains(data: &[u8]) -> Gains {
+    Gains {
+        kp: get_f32(&data[0..4]),
+        ki: get_f32(&data[4..8]),
+        kd: get_f32(&data[8..12]),
+    }
+}
+
+fn put_calibration(buf: &mut [u8], calibration: &Calibration) {
+    let (kind, values) = match *calibration {
+        Calibration::None => (0, [0.0; 4]),
+        Calibration::Offset(offset) => (1, [offset, 0.0, 0.0, 0.0]),
+        Calibration::TwoPoint {
+            raw_low,
+            reference_low,
+            raw_high,
+            reference_high,
+        } => (2, [raw_low, reference_low, raw_high, reference_high]),
+    };
+    buf[0] = kind;
+    for (i, value) in values.iter().enumerate() {
+        put_f32(&mut buf[1 + i * 4..], *value);
+    }
+}
+
+fn get_calibration(data: &[u8]) -> Result<Calibration, DecodeError> {
+    let value = |i: usize| get_f32(&data[1 + i * 4..]);
+    Ok(match data[0] {
+        0 => Calibration::None,
+        1 => Calibration::Offset(value(0)),
+        2 => Calibration::TwoPoint {
+            raw_low: value(0),
+            reference_low: value(1),
+            raw_high: value(2),
+            reference_high: value(3),
+        },
+        _ => return Err(DecodeError::Invalid),
+    })
+}
+
+fn put_filter(buf: &mut [u8], filter: &Filter) {
+    buf[0] = filter.median_len;
+    put_f32(&mut buf[1..5], filter.ema_alpha);
+    put_f32(&mut buf[5..9], filter.max_delta);
+    buf[9] = filter.max_rejects;
+}
+
+fn get_filter(data: &[u8]) -> Filter {
+    Filter {
+        median_len: data[0],
+        ema_alpha: get_f32(&data[1..5]),
+        max_delta: get_f32(&data[5..9]),
+        max_rejects: data[9],
+    }
+}
+
+/// `None` if `value` is the one standing for nothing.
+fn unless(value: u32, nothing: u32) -> Option<u32> {
+    if value == nothing {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn put_u32(buf: &mut [u8], value: u32) {
+    buf[..4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn put_f32(buf: &mut [u8], value: f32) {
+    buf[..4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_f32(data: &[u8]) -> f32 {
+    f32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+impl Encode for Command {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for Command {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let raw = reader.varint()?;
+        if raw > u64::from(u8::MAX) {
+            return Err(Error::Invalid);
+        }
+        Command::from_value(&[raw as u8]).map_err(|_| Error::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bare_config, config, status};
+    use crate::wire;
+
+    /// A status sharing no value with [`status`].
+    fn other_status() -> Status {
+        Status {
+            current_temp: 0.0,
+            target_temp: 0.0,
+            heater_on: false,
+            pid_output: 0.0,
+            kp: 0.0,
+            ki: 0.0,
+            kd: 0.0,
+            mode: Mode::Booting,
+            faults: Faults(0),
+        }
+    }
+
+    #[test]
+    fn ids_are_unique_and_values_fit() {
+        let mut ids: Vec<u16> = Characteristic::ALL.iter().map(|c| c.id()).collect();
+        ids.extend(Setting::ALL.iter().map(|s| s.id()));
+        ids.push(COMMAND_ID);
+        ids.push(WRITE_RESULT_ID);
+        let count = ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+
+        let longest = Characteristic::ALL
+            .iter()
+            .map(|c| c.value_len())
+            .chain(Setting::ALL.iter().map(|s| s.value_len()))
+            .max();
+        assert_eq!(longest, Some(MAX_VALUE_LEN));
+
+        assert_eq!(
+            Setting::WakeAt.uuid(),
+            [
+                0x8f, 0x1a, 0x02, 0x0b, 0x5a, 0x3c, 0x4d, 0x6e, 0x9b, 0x2f, 0x7c, 0x0e, 0x1d, 0x2a,
+                0x3b, 0x4c
+            ]
+        );
+    }
+
+    #[test]
+    fn characteristics_round_trip() {
+        let mut decoded = other_status();
+        for characteristic in Characteristic::ALL.iter() {
+            let mut buf = [0u8; MAX_VALUE_LEN];
+            let len = characteristic.encode(&status(), &mut buf);
+            assert_eq!(len, characteristic.value_len(), "{:?}", characteristic);
+            characteristic.decode(&buf[..len], &mut decoded).unwrap();
+        }
+        assert_eq!(decoded, status());
+    }
+
+    #[test]
+    fn characteristics_check_the_length() {
+        for characteristic in Characteristic::ALL.iter() {
+            let buf = [0u8; MAX_VALUE_LEN + 1];
+            let len = characteristic.value_len();
+            let mut decoded = status();
+            for wrong in [0, len - 1, len + 1].iter() {
+                assert_eq!(
+                    characteristic.decode(&buf[..*wrong], &mut decoded),
+                    Err(DecodeError::Length),
+                    "{:?} with {} bytes",
+                    characteristic,
+                    wrong
+                );
+            }
+            assert_eq!(decoded, status());
+        }
+    }
+
+    #[test]
+    fn characteristics_reject_invalid_values() {
+        let mut decoded = status();
+        assert_eq!(
+            Characteristic::HeaterOn.decode(&[2], &mut decoded),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(
+            Characteristic::Mode.decode(&[8], &mut decoded),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(
+            Characteristic::Mode.decode(&[0xFF], &mut decoded),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(decoded, status());
+
+        Characteristic::Mode.decode(&[7], &mut decoded).unwrap();
+        assert_eq!(decoded.mode, Mode::Fault);
+    }
+
+    #[test]
+    fn characteristics_take_edge_values() {
+        let mut decoded = status();
+        Characteristic::HeaterOn.decode(&[0], &mut decoded).unwrap();
+        assert!(!decoded.heater_on);
+        Characteristic::Mode.decode(&[0], &mut decoded).unwrap();
+        assert_eq!(decoded.mode, Mode::Booting);
+        Characteristic::Faults
+            .decode(&[0xFF, 0xFF], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded.faults, Faults(0xFFFF));
+
+        // Temperatures are taken as they are, judging them is up to the reader.
+        for value in [f32::NEG_INFINITY, f32::MAX, -0.0, f32::NAN].iter() {
+            Characteristic::CurrentTemp
+                .decode(&value.to_le_bytes(), &mut decoded)
+                .unwrap();
+            assert_eq!(decoded.current_temp.to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    fn commands_take_a_single_known_byte() {
+        for value in [0, 1, 2, 3, 5].iter() {
+            let command = Command::from_value(&[*value]).unwrap();
+            assert_eq!(command as u8, *value);
+
+            let mut buf = [0u8; 4];
+            let len = wire::to_slice(&command, &mut buf).unwrap();
+            assert_eq!(wire::from_slice(&buf[..len]), Ok(command));
+        }
+
+        assert_eq!(Command::from_value(&[4]), Err(DecodeError::Invalid));
+        assert_eq!(Command::from_value(&[6]), Err(DecodeError::Invalid));
+        assert_eq!(Command::from_value(&[0xFF]), Err(DecodeError::Invalid));
+        assert_eq!(Command::from_value(&[]), Err(DecodeError::Length));
+        assert_eq!(Command::from_value(&[0, 0]), Err(DecodeError::Length));
+        assert_eq!(Command::from_value(&[5, 0xFF]), Err(DecodeError::Length));
+
+        // A varint which would wrap around to a valid command as a byte.
+        assert_eq!(
+            wire::from_slice::<Command>(&[0x80, 0x02]),
+            Err(Error::Invalid)
+        );
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        // Every setting written over a configuration which differs in all of them.
+        for (expected, start) in [(config(), bare_config()), (bare_config(), config())].iter() {
+            let mut decoded = *start;
+            for setting in Setting::ALL.iter() {
+                let mut buf = [0u8; MAX_VALUE_LEN];
+                let len = setting.encode(expected, &mut buf);
+                assert_eq!(len, setting.value_len(), "{:?}", setting);
+                setting.decode(&buf[..len], &mut decoded).unwrap();
+            }
+            assert_eq!(decoded, *expected);
+        }
+
+        let none = Config {
+            calibration: Calibration::None,
+            ..config()
+        };
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        let len = Setting::Calibration.encode(&none, &mut buf);
+        let mut decoded = config();
+        Setting::Calibration
+            .decode(&buf[..len], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, none);
+    }
+
+    #[test]
+    fn settings_map_nothing_to_its_value() {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        Setting::WakeAt.encode(&bare_config(), &mut buf);
+        assert_eq!(buf[..4], NEVER.to_le_bytes());
+        Setting::SteamTimeout.encode(&bare_config(), &mut buf);
+        assert_eq!(buf[..4], [0; 4]);
+
+        // Midnight is a time, not the lack of one.
+        let mut decoded = bare_config();
+        Setting::WakeAt.decode(&[0; 4], &mut decoded).unwrap();
+        assert_eq!(decoded.wake_at, Some(0));
+    }
+
+    #[test]
+    fn settings_check_the_length() {
+        for setting in Setting::ALL.iter() {
+            let buf = [0u8; MAX_VALUE_LEN + 1];
+            let len = setting.value_len();
+            let mut decoded = config();
+            for wrong in [0, len - 1, len + 1].iter() {
+                assert_eq!(
+                    setting.decode(&buf[..*wrong], &mut decoded),
+                    Err(DecodeError::Length),
+                    "{:?} with {} bytes",
+                    setting,
+                    wrong
+                );
+            }
+            assert_eq!(decoded, config());
+        }
+    }
+
+    #[test]
+    fn settings_reject_invalid_values() {
+        let mut decoded = config();
+        assert_eq!(
+            Setting::TargetKind.decode(&[2], &mut decoded),
+            Err(DecodeError::Invalid)
+        );
+        let mut calibration = [0u8; 17];
+        calibration[0] = 3;
+        assert_eq!(
+            Setting::Calibration.decode(&calibration, &mut decoded),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(decoded, config());
+    }
+
+    #[test]
+    fn write_results_carry_the_id() {
+        assert_eq!(WriteResult::Ok.encode(0x0204), [0x04, 0x02, 0]);
+        assert_eq!(
+            WriteResult::StorageFailed.encode(COMMAND_ID),
+            [0x00, 0x03, 7]
+        );
+        assert_eq!(WriteResult::from(DecodeError::Length), WriteResult::Length);
+        assert_eq!(
+            WriteResult::from(DecodeError::Invalid),
+            WriteResult::Invalid
+        );
+    }
+}
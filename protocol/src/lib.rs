@@ -0,0 +1,21 @@
+//! The language the controller speaks with the outside world.
+//!
+//! Shared between the firmware and the host tools, so both sides agree on every byte:
+//!
+//! - [`gatt`] lays out the values of the BLE service.
+//! - [`message`] holds the requests, responses and telemetry sent over a byte stream, encoded with
+//!   [`wire`] and framed with [`frame`].
+//!
+//! Without the `std` feature the crate is `no_std` and never allocates.
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+pub mod config;
+pub mod events;
+pub mod frame;
+pub mod gatt;
+pub mod message;
+pub mod status;
+#[cfg(test)]
+mod testing;
+pub mod wire;
@@ -0,0 +1,469 @@
+//! The messages exchanged over a byte stream like the serial console.
+//!
+//! The host sends [`Request`]s, the controller answers each of them with one or more
+//! [`Response`]s and, once asked to, sends [`Telemetry`] on its own. Every session starts with a
+//! [`Request::Hello`], see [`negotiate`].
+
+use crate::config::Config;
+use crate::events::LoggedEvent;
+use crate::gatt::Command;
+use crate::status::Status;
+use crate::wire::{Decode, Encode, Error, Reader, Writer};
+
+/// The version of the messages, bumped on every incompatible change.
+pub const PROTOCOL_VERSION: u16 = 7;
+/// The oldest version this side still speaks.
+pub const MIN_PROTOCOL_VERSION: u16 = 7;
+
+/// Picks the version to talk in with a peer speaking `peer_version`, `None` if there is none.
+///
+/// The newer side has to step down, so both run this and the answer is the same on either end.
+pub fn negotiate(peer_version: u16) -> Option<u16> {
+    if peer_version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+    Some(peer_version.min(PROTOCOL_VERSION))
+}
+
+/// Sent by the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    /// Has to come first, answered by [`Response::Hello`].
+    Hello {
+        version: u16,
+    },
+    /// Answered by [`Response::Status`].
+    GetStatus,
+    /// Answered by [`Response::Config`].
+    GetConfig,
+    /// Replaces the configuration after validating it, `save` stores it in flash as well.
+    SetConfig {
+        config: Config,
+        save: bool,
+    },
+    Command(Command),
+    /// Turns the periodic [`Telemetry`] on or off.
+    Stream(bool),
+    /// Asks for up to `count` events of the log, starting at the sequence number `from`.
+    ///
+    /// Answered by a [`Response::Event`] per event and a [`Response::EventsEnd`].
+    ReadEvents {
+        from: u32,
+        count: u8,
+    },
+    /// Pairs the brew temperature (°C) measured at the group with the current boiler
+    /// temperature, for [`Request::FitBrewModel`]. Only taken while the machine is ready.
+    BrewSample {
+        brew_temp: f32,
+    },
+    /// Fits the brew model through the samples taken so far and applies it, the samples are
+    /// dropped afterwards. Needs samples at two different boiler temperatures.
+    FitBrewModel,
+    /// Sets the local time of day (seconds since midnight), which the standby wake-up needs.
+    SetTime {
+        seconds_of_day: u32,
+    },
+}
+
+/// Why a request was rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    /// The versions do not overlap.
+    Incompatible,
+    /// The handshake has not happened yet.
+    NoHandshake,
+    /// The request could not be decoded.
+    Malformed,
+    /// A value is outside of the range the controller accepts.
+    OutOfRange,
+    /// The command does not apply in the current mode.
+    NotAllowed,
+    /// The controller does not support the request.
+    Unsupported,
+    /// Saving to flash failed, the new configuration is active nevertheless.
+    StorageFailed,
+}
+
+/// Sent by the controller as an answer to a [`Request`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    /// The version both sides use from here on.
+    Hello {
+        version: u16,
+    },
+    Status(Telemetry),
+    Config(Config),
+    /// The request has been carried out.
+    Ok,
+    Error(ErrorCode),
+    Event(LoggedEvent),
+    /// No more events follow, `next` is the sequence number to continue at.
+    EventsEnd {
+        next: u32,
+    },
+}
+
+/// A snapshot of the controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Telemetry {
+    /// Milliseconds since boot.
+    pub uptime: u64,
+    pub status: Status,
+    /// The last reading before filtering (°C).
+    pub raw_temp: f32,
+    /// The estimated water temperature at the group (°C).
+    pub brew_temp: f32,
+    pub ready: bool,
+}
+
+/// Everything the controller sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Message {
+    Response(Response),
+    Telemetry(Telemetry),
+}
+
+impl Encode for Request {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Request::Hello { version } => {
+                writer.varint(0)?;
+                writer.varint(u64::from(*version))
+            }
+            Request::GetStatus => writer.varint(1),
+            Request::GetConfig => writer.varint(2),
+            Request::SetConfig { config, save } => {
+                writer.varint(3)?;
+                config.encode(writer)?;
+                writer.bool(*save)
+            }
+            Request::Command(command) => {
+                writer.varint(4)?;
+                command.encode(writer)
+            }
+            Request::Stream(on) => {
+                writer.varint(5)?;
+                writer.bool(*on)
+            }
+            Request::ReadEvents { from, count } => {
+                writer.varint(6)?;
+                writer.varint(u64::from(*from))?;
+                writer.u8(*count)
+            }
+            Request::BrewSample { brew_temp } => {
+                writer.varint(7)?;
+                writer.f32(*brew_temp)
+            }
+            Request::FitBrewModel => writer.varint(8),
+            Request::SetTime { seconds_of_day } => {
+                writer.varint(9)?;
+                writer.varint(u64::from(*seconds_of_day))
+            }
+        }
+    }
+}
+
+impl Decode for Request {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => Request::Hello {
+                version: reader.u16()?,
+            },
+            1 => Request::GetStatus,
+            2 => Request::GetConfig,
+            3 => Request::SetConfig {
+                config: Config::decode(reader)?,
+                save: reader.bool()?,
+            },
+            4 => Request::Command(Command::decode(reader)?),
+            5 => Request::Stream(reader.bool()?),
+            6 => Request::ReadEvents {
+                from: reader.u32()?,
+                count: reader.u8()?,
+            },
+            7 => Request::BrewSample {
+                brew_temp: reader.f32()?,
+            },
+            8 => Request::FitBrewModel,
+            9 => Request::SetTime {
+                seconds_of_day: reader.u32()?,
+            },
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+impl Encode for ErrorCode {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for ErrorCode {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => ErrorCode::Incompatible,
+            1 => ErrorCode::NoHandshake,
+            2 => ErrorCode::Malformed,
+            3 => ErrorCode::OutOfRange,
+            4 => ErrorCode::NotAllowed,
+            5 => ErrorCode::Unsupported,
+            6 => ErrorCode::StorageFailed,
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+impl Encode for Response {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Response::Hello { version } => {
+                writer.varint(0)?;
+                writer.varint(u64::from(*version))
+            }
+            Response::Status(telemetry) => {
+                writer.varint(1)?;
+                telemetry.encode(writer)
+            }
+            Response::Config(config) => {
+                writer.varint(2)?;
+                config.encode(writer)
+            }
+            Response::Ok => writer.varint(3),
+            Response::Error(code) => {
+                writer.varint(4)?;
+                code.encode(writer)
+            }
+            Response::Event(event) => {
+                writer.varint(5)?;
+                event.encode(writer)
+            }
+            Response::EventsEnd { next } => {
+                writer.varint(6)?;
+                writer.varint(u64::from(*next))
+            }
+        }
+    }
+}
+
+impl Decode for Response {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => Response::Hello {
+                version: reader.u16()?,
+            },
+            1 => Response::Status(Telemetry::decode(reader)?),
+            2 => Response::Config(Config::decode(reader)?),
+            3 => Response::Ok,
+            4 => Response::Error(ErrorCode::decode(reader)?),
+            5 => Response::Event(LoggedEvent::decode(reader)?),
+            6 => Response::EventsEnd {
+                next: reader.u32()?,
+            },
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+impl Encode for Telemetry {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(self.uptime)?;
+        self.status.encode(writer)?;
+        writer.f32(self.raw_temp)?;
+        writer.f32(self.brew_temp)?;
+        writer.bool(self.ready)
+    }
+}
+
+impl Decode for Telemetry {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            uptime: reader.varint()?,
+            status: Status::decode(reader)?,
+            raw_temp: reader.f32()?,
+            brew_temp: reader.f32()?,
+            ready: reader.bool()?,
+        })
+    }
+}
+
+impl Encode for Message {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Message::Response(response) => {
+                writer.varint(0)?;
+                response.encode(writer)
+            }
+            Message::Telemetry(telemetry) => {
+                writer.varint(1)?;
+                telemetry.encode(writer)
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(match reader.varint()? {
+            0 => Message::Response(Response::decode(reader)?),
+            1 => Message::Telemetry(Telemetry::decode(reader)?),
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{bare_config, config, events, telemetry, through_frame, unused_tag};
+    use crate::wire;
+
+    fn requests() -> Vec<Request> {
+        let mut requests = vec![
+            Request::Hello { version: u16::MAX },
+            Request::GetStatus,
+            Request::GetConfig,
+            Request::SetConfig {
+                config: config(),
+                save: true,
+            },
+            Request::SetConfig {
+                config: bare_config(),
+                save: false,
+            },
+            Request::Stream(true),
+            Request::Stream(false),
+            Request::ReadEvents {
+                from: u32::MAX,
+                count: u8::MAX,
+            },
+            Request::BrewSample { brew_temp: 93.5 },
+            Request::FitBrewModel,
+            Request::SetTime {
+                seconds_of_day: 24 * 60 * 60 - 1,
+            },
+        ];
+        requests.extend(
+            [
+                Command::Standby,
+                Command::Wake,
+                Command::SteamOn,
+                Command::SteamOff,
+                Command::Save,
+            ]
+            .iter()
+            .map(|command| Request::Command(*command)),
+        );
+        requests
+    }
+
+    fn responses() -> Vec<Response> {
+        let mut responses = vec![
+            Response::Hello { version: 0 },
+            Response::Status(telemetry()),
+            Response::Config(config()),
+            Response::Config(bare_config()),
+            Response::Ok,
+            Response::EventsEnd { next: u32::MAX },
+        ];
+        responses.extend(
+            [
+                ErrorCode::Incompatible,
+                ErrorCode::NoHandshake,
+                ErrorCode::Malformed,
+                ErrorCode::OutOfRange,
+                ErrorCode::NotAllowed,
+                ErrorCode::Unsupported,
+                ErrorCode::StorageFailed,
+            ]
+            .iter()
+            .map(|code| Response::Error(*code)),
+        );
+        responses.extend(events().into_iter().enumerate().map(|(i, event)| {
+            Response::Event(LoggedEvent {
+                seq: i as u32,
+                at: u64::MAX - i as u64,
+                event,
+            })
+        }));
+        responses
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = requests();
+        for request in requests.iter() {
+            assert_eq!(through_frame(request), *request);
+        }
+
+        let unused = unused_tag(&requests);
+        assert_eq!(wire::from_slice::<Request>(&[unused]), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let responses = responses();
+        for response in responses.iter() {
+            assert_eq!(through_frame(response), *response);
+        }
+
+        let unused = unused_tag(&responses);
+        assert_eq!(wire::from_slice::<Response>(&[unused]), Err(Error::Invalid));
+
+        let codes: Vec<ErrorCode> = responses
+            .iter()
+            .filter_map(|response| match response {
+                Response::Error(code) => Some(*code),
+                _ => None,
+            })
+            .collect();
+        let unused = unused_tag(&codes);
+        assert_eq!(
+            wire::from_slice::<ErrorCode>(&[unused]),
+            Err(Error::Invalid)
+        );
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let mut messages: Vec<Message> = responses().into_iter().map(Message::Response).collect();
+        messages.push(Message::Telemetry(telemetry()));
+        for message in messages.iter() {
+            assert_eq!(through_frame(message), *message);
+        }
+
+        let unused = unused_tag(&messages);
+        assert_eq!(wire::from_slice::<Message>(&[unused]), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn requests_reject_out_of_range_values() {
+        // Hello with a version above u16::MAX.
+        assert_eq!(
+            wire::from_slice::<Request>(&[0, 0x80, 0x80, 0x04]),
+            Err(Error::Invalid)
+        );
+        // Stream with something else than a bool.
+        assert_eq!(wire::from_slice::<Request>(&[5, 2]), Err(Error::Invalid));
+        // A command which does not exist.
+        assert_eq!(wire::from_slice::<Request>(&[4, 6]), Err(Error::Invalid));
+        // ReadEvents without its count.
+        assert_eq!(
+            wire::from_slice::<Request>(&[6, 1]),
+            Err(Error::UnexpectedEnd)
+        );
+        assert_eq!(
+            wire::from_slice::<Request>(&[1, 0]),
+            Err(Error::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn negotiate_picks_the_older_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(u16::MAX), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION), Some(MIN_PROTOCOL_VERSION));
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION - 1), None);
+        assert_eq!(negotiate(0), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! The live values of the controller, as seen from the outside.
+
+use crate::wire::{Decode, Encode, Error, Reader, Writer};
+
+/// What the machine is doing, mirrors the state machine of the firmware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Booting = 0,
+    Coldstart = 1,
+    Stabilizing = 2,
+    Ready = 3,
+    Brewing = 4,
+    Steam = 5,
+    Standby = 6,
+    Fault = 7,
+}
+
+impl Mode {
+    pub fn from_u8(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0 => Mode::Booting,
+            1 => Mode::Coldstart,
+            2 => Mode::Stabilizing,
+            3 => Mode::Ready,
+            4 => Mode::Brewing,
+            5 => Mode::Steam,
+            6 => Mode::Standby,
+            7 => Mode::Fault,
+            _ => return None,
+        })
+    }
+}
+
+/// Everything which is currently wrong, as a set of bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults(pub u16);
+
+impl Faults {
+    pub const CUTOFF_OVER_TEMPERATURE: Faults = Faults(1 << 0);
+    pub const CUTOFF_RATE_OF_RISE: Faults = Faults(1 << 1);
+    pub const CUTOFF_STUCK_SENSOR: Faults = Faults(1 << 2);
+    pub const SENSOR_DEGRADED: Faults = Faults(1 << 3);
+    pub const SENSOR_FAILED: Faults = Faults(1 << 4);
+    pub const SELF_TEST_FAILED: Faults = Faults(1 << 5);
+    pub const CONFIG_RESET: Faults = Faults(1 << 6);
+
+    pub const fn empty() -> Self {
+        Faults(0)
+    }
+
+    pub fn insert(&mut self, other: Faults) {
+        self.0 |= other.0;
+    }
+
+    pub fn contains(self, other: Faults) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// A snapshot of the controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    /// Filtered boiler temperature (°C).
+    pub current_temp: f32,
+    /// The setpoint the heater currently works towards (°C).
+    pub target_temp: f32,
+    pub heater_on: bool,
+    /// The PID output, the heater on-time (ms) per window.
+    pub pid_output: f32,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub mode: Mode,
+    pub faults: Faults,
+}
+
+impl Encode for Mode {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.varint(*self as u64)
+    }
+}
+
+impl Decode for Mode {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        let raw = reader.varint()?;
+        if raw > u64::from(u8::MAX) {
+            return Err(Error::Invalid);
+        }
+        Mode::from_u8(raw as u8).ok_or(Error::Invalid)
+    }
+}
+
+impl Encode for Status {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error> {
+        writer.f32(self.current_temp)?;
+        writer.f32(self.target_temp)?;
+        writer.bool(self.heater_on)?;
+        writer.f32(self.pid_output)?;
+        writer.f32(self.kp)?;
+        writer.f32(self.ki)?;
+        writer.f32(self.kd)?;
+        self.mode.encode(writer)?;
+        writer.varint(u64::from(self.faults.0))
+    }
+}
+
+impl Decode for Status {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            current_temp: reader.f32()?,
+            target_temp: reader.f32()?,
+            heater_on: reader.bool()?,
+            pid_output: reader.f32()?,
+            kp: reader.f32()?,
+            ki: reader.f32()?,
+            kd: reader.f32()?,
+            mode: Mode::decode(reader)?,
+            faults: Faults(reader.u16()?),
+        })
+    }
+}
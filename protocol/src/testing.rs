@@ -0,0 +1,165 @@
+//! Values for the tests, with every field set to something which stands out.
+
+use crate::config::{Calibration, Config, Filter, Gains, TargetKind};
+use crate::events::{Cutoff, DefaultsReason, Event, Task, Text};
+use crate::frame::{self, Receiver, MAX_FRAME};
+use crate::message::Telemetry;
+use crate::status::{Faults, Mode, Status};
+use crate::wire::{self, Decode, Encode};
+
+pub fn status() -> Status {
+    Status {
+        current_temp: 93.25,
+        target_temp: 95.5,
+        heater_on: true,
+        pid_output: 412.5,
+        kp: 69.0,
+        ki: 0.17,
+        kd: 1.5,
+        mode: Mode::Stabilizing,
+        faults: Faults(0x0105),
+    }
+}
+
+/// A configuration with everything optional set.
+pub fn config() -> Config {
+    Config {
+        target_temp: 92.5,
+        target_kind: TargetKind::Brew,
+        cold_gains: Gains {
+            kp: 250.0,
+            ki: 0.03,
+            kd: 0.0,
+        },
+        warm_gains: Gains {
+            kp: 69.0,
+            ki: 0.17,
+            kd: 0.5,
+        },
+        steam_temp: 125.0,
+        steam_gains: Gains {
+            kp: 80.0,
+            ki: 0.2,
+            kd: 1.0,
+        },
+        steam_timeout: Some(300_000),
+        window_size: 1000,
+        calibration: Calibration::TwoPoint {
+            raw_low: 99.0,
+            reference_low: 100.0,
+            raw_high: 90.0,
+            reference_high: 92.0,
+        },
+        filter: Filter {
+            median_len: 5,
+            ema_alpha: 0.25,
+            max_delta: 2.5,
+            max_rejects: 7,
+        },
+        idle_timeout: Some(1_800_000),
+        standby_temp: Some(70.0),
+        wake_at: Some(6 * 60 * 60),
+    }
+}
+
+/// A configuration with everything optional left out and every filter stage off.
+pub fn bare_config() -> Config {
+    Config {
+        target_kind: TargetKind::Boiler,
+        steam_timeout: None,
+        calibration: Calibration::Offset(-1.5),
+        filter: Filter {
+            median_len: 1,
+            ema_alpha: 1.0,
+            max_delta: 0.0,
+            max_rejects: 0,
+        },
+        idle_timeout: None,
+        standby_temp: None,
+        wake_at: None,
+        ..config()
+    }
+}
+
+pub fn telemetry() -> Telemetry {
+    Telemetry {
+        uptime: u64::MAX,
+        status: status(),
+        raw_temp: 99.75,
+        brew_temp: 93.0,
+        ready: true,
+    }
+}
+
+/// One of every event, with the values at their edges where there are any.
+pub fn events() -> Vec<Event> {
+    vec![
+        Event::Boot {
+            resetreas: 0x0004_0001,
+        },
+        Event::SensorFailure {
+            error: Text::new("Fault(Open)"),
+        },
+        Event::ModeChange {
+            from: Mode::Coldstart,
+            to: Mode::Ready,
+        },
+        Event::FaultLatched(Cutoff::OverTemperature),
+        Event::FaultLatched(Cutoff::RateOfRise),
+        Event::FaultLatched(Cutoff::StuckSensor),
+        Event::FaultCleared,
+        Event::ConfigSaved { version: 3 },
+        Event::ConfigDefaults(DefaultsReason::Empty),
+        Event::ConfigDefaults(DefaultsReason::Corrupt),
+        Event::ConfigDefaults(DefaultsReason::UnknownVersion(u16::MAX)),
+        Event::Panic {
+            file: Text::new("ls/boiler.rs"),
+            line: u16::MAX,
+        },
+        Event::HardFault {
+            pc: 0x0002_6A4C,
+            lr: u32::MAX,
+        },
+        Event::TaskStuck(Task::Measure),
+        Event::TaskStuck(Task::HeaterDrive),
+        Event::TaskStuck(Task::Display),
+        Event::ConfigChanged,
+    ]
+}
+
+/// Sends `message` the way it goes over the serial port: framed, byte by byte through a
+/// [`Receiver`] and unframed again.
+pub fn through_frame<T: Encode + Decode>(message: &T) -> T {
+    let mut buf = [0; MAX_FRAME];
+    let len = frame::encode(message, &mut buf).unwrap();
+    let (delimiter, bytes) = buf[..len].split_last().unwrap();
+
+    let mut receiver = Receiver::new();
+    for &byte in bytes {
+        assert!(receiver.push(byte).is_none(), "frame ended early");
+    }
+    let frame = receiver.push(*delimiter).expect("frame not complete");
+    frame::decode(frame).unwrap()
+}
+
+/// Checks the variant tags of `values` run from 0 up without a gap, returning the first one
+/// which is not used.
+pub fn unused_tag<T: Encode>(values: &[T]) -> u8 {
+    let mut tags: Vec<u8> = values
+        .iter()
+        .map(|value| {
+            let mut buf = [0; MAX_FRAME];
+            wire::to_slice(value, &mut buf).unwrap();
+            buf[0]
+        })
+        .collect();
+    tags.sort_unstable();
+    tags.dedup();
+    let unused = tags.len() as u8;
+    assert_eq!(
+        tags,
+        (0..unused).collect::<Vec<_>>(),
+        "a variant is missing"
+    );
+    unused
+}
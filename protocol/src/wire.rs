@@ -0,0 +1,304 @@
+//! A compact binary encoding in the style of postcard.
+//!
+//! Unsigned integers are LEB128 varints, floats four bytes little endian, `bool`s a single byte.
+//! Enums start with their variant as a varint, an `Option` with a 0 or 1 byte. Nothing describes
+//! itself, both sides have to use the same types, which the version handshake makes sure of.
+
+/// Errors which can happen while encoding or decoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The buffer is too small for the encoded value.
+    BufferFull,
+    /// The data ended in the middle of a value.
+    UnexpectedEnd,
+    /// A value is out of range, e.g. an unknown enum variant.
+    Invalid,
+    /// The data continues after the value.
+    TrailingBytes,
+}
+
+/// Encodes into a fixed buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// The number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    pub fn u8(&mut self, value: u8) -> Result<(), Error> {
+        let slot = self.buf.get_mut(self.pos).ok_or(Error::BufferFull)?;
+        *slot = value;
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<(), Error> {
+        self.u8(value as u8)
+    }
+
+    pub fn varint(&mut self, mut value: u64) -> Result<(), Error> {
+        while value >= 0x80 {
+            self.u8(value as u8 | 0x80)?;
+            value >>= 7;
+        }
+        self.u8(value as u8)
+    }
+
+    pub fn f32(&mut self, value: f32) -> Result<(), Error> {
+        self.raw(&value.to_le_bytes())
+    }
+
+    /// Writes the bytes with their length in front.
+    pub fn bytes(&mut self, value: &[u8]) -> Result<(), Error> {
+        self.varint(value.len() as u64)?;
+        self.raw(value)
+    }
+
+    fn raw(&mut self, value: &[u8]) -> Result<(), Error> {
+        let end = self.pos + value.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(Error::BufferFull)?
+            .copy_from_slice(value);
+        self.pos = end;
+        Ok(())
+    }
+}
+
+/// Decodes from a slice.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        let value = *self.data.get(self.pos).ok_or(Error::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(value)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    pub fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Invalid)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let value = self.varint()?;
+        if value > u64::from(u16::MAX) {
+            return Err(Error::Invalid);
+        }
+        Ok(value as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let value = self.varint()?;
+        if value > u64::from(u32::MAX) {
+            return Err(Error::Invalid);
+        }
+        Ok(value as u32)
+    }
+
+    pub fn f32(&mut self) -> Result<f32, Error> {
+        let raw = self.raw(4)?;
+        Ok(f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+    }
+
+    /// Reads bytes written by [`Writer::bytes`].
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.varint()? as usize;
+        self.raw(len)
+    }
+
+    /// Makes sure all of the data has been read.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.pos == self.data.len() {
+            Ok(())
+        } else {
+            Err(Error::TrailingBytes)
+        }
+    }
+
+    fn raw(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(len).ok_or(Error::UnexpectedEnd)?;
+        let raw = self.data.get(self.pos..end).ok_or(Error::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(raw)
+    }
+}
+
+/// A type which can be written in the wire encoding.
+pub trait Encode {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), Error>;
+}
+
+/// A type which can be read from the wire encoding.
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, Error>;
+}
+
+/// Encodes `value` into `buf`, returning the length.
+pub fn to_slice<T: Encode>(value: &T, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut writer = Writer::new(buf);
+    value.encode(&mut writer)?;
+    Ok(writer.len())
+}
+
+/// Decodes a value which has to take up all of `data`.
+pub fn from_slice<T: Decode>(data: &[u8]) -> Result<T, Error> {
+    let mut reader = Reader::new(data);
+    let value = T::decode(&mut reader)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::BufferFull => "buffer too small",
+            Error::UnexpectedEnd => "unexpected end of data",
+            Error::Invalid => "invalid value",
+            Error::TrailingBytes => "trailing bytes",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(value: u64) -> ([u8; 16], usize) {
+        let mut buf = [0; 16];
+        let mut writer = Writer::new(&mut buf);
+        writer.varint(value).unwrap();
+        let len = writer.len();
+        (buf, len)
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for &(value, len) in [
+            (0, 1),
+            (0x7F, 1),
+            (0x80, 2),
+            (u64::from(u16::MAX), 3),
+            (u64::from(u32::MAX), 5),
+            (u64::MAX, 10),
+        ]
+        .iter()
+        {
+            let (buf, written) = varint(value);
+            assert_eq!(written, len, "{:#x}", value);
+            let mut reader = Reader::new(&buf[..written]);
+            assert_eq!(reader.varint(), Ok(value));
+            reader.finish().unwrap();
+        }
+        assert_eq!(varint(300).0[..2], [0xAC, 0x02]);
+    }
+
+    #[test]
+    fn varints_longer_than_a_u64_are_invalid() {
+        let mut data = [0x80; 11];
+        data[10] = 0x01;
+        assert_eq!(Reader::new(&data).varint(), Err(Error::Invalid));
+        assert_eq!(Reader::new(&[0xFF; 10]).varint(), Err(Error::Invalid));
+        assert_eq!(Reader::new(&[0xFF; 9]).varint(), Err(Error::UnexpectedEnd));
+        assert_eq!(Reader::new(&[]).varint(), Err(Error::UnexpectedEnd));
+    }
+
+    #[test]
+    fn narrow_integers_check_their_range() {
+        let (buf, len) = varint(u64::from(u16::MAX));
+        assert_eq!(Reader::new(&buf[..len]).u16(), Ok(u16::MAX));
+        let (buf, len) = varint(u64::from(u16::MAX) + 1);
+        assert_eq!(Reader::new(&buf[..len]).u16(), Err(Error::Invalid));
+
+        let (buf, len) = varint(u64::from(u32::MAX));
+        assert_eq!(Reader::new(&buf[..len]).u32(), Ok(u32::MAX));
+        let (buf, len) = varint(u64::from(u32::MAX) + 1);
+        assert_eq!(Reader::new(&buf[..len]).u32(), Err(Error::Invalid));
+
+        assert_eq!(Reader::new(&[1]).bool(), Ok(true));
+        assert_eq!(Reader::new(&[2]).bool(), Err(Error::Invalid));
+        assert_eq!(Reader::new(&[0; 3]).f32(), Err(Error::UnexpectedEnd));
+    }
+
+    #[test]
+    fn bytes_check_their_length() {
+        let mut buf = [0; 8];
+        let mut writer = Writer::new(&mut buf);
+        writer.bytes(b"abc").unwrap();
+        assert_eq!(writer.len(), 4);
+        assert_eq!(Reader::new(&buf[..4]).bytes(), Ok(&b"abc"[..]));
+        assert_eq!(Reader::new(&buf[..3]).bytes(), Err(Error::UnexpectedEnd));
+
+        // A length which does not fit a usize, let alone the data.
+        let (len, written) = varint(u64::MAX);
+        assert_eq!(
+            Reader::new(&len[..written]).bytes(),
+            Err(Error::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn writer_stops_at_the_end_of_the_buffer() {
+        let mut buf = [0; 4];
+        let mut writer = Writer::new(&mut buf);
+        assert!(writer.is_empty());
+        assert_eq!(writer.varint(u64::from(u32::MAX)), Err(Error::BufferFull));
+        let mut writer = Writer::new(&mut buf);
+        writer.u8(1).unwrap();
+        assert_eq!(writer.f32(1.0), Err(Error::BufferFull));
+        assert_eq!(writer.bytes(b"abc"), Err(Error::BufferFull));
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        struct Byte(u8);
+        impl Decode for Byte {
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, Error> {
+                reader.u8().map(Byte)
+            }
+        }
+
+        assert_eq!(from_slice::<Byte>(&[7]).map(|b| b.0), Ok(7));
+        assert_eq!(
+            from_slice::<Byte>(&[7, 0]).map(|b| b.0),
+            Err(Error::TrailingBytes)
+        );
+    }
+}